@@ -0,0 +1,576 @@
+//! Analysis of connection dump data.
+//!
+//! Reads [dump data](aggligator::dump) recorded by a connection,
+//! computes summary statistics, exports it as CSV and renders SVG plots.
+//!
+
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufReader, Write},
+    path::Path,
+};
+
+use aggligator::dump::{ConnDump, DumpFormat, LinkDump};
+
+/// Reads all dump records from a file.
+pub fn read_dump_file(path: impl AsRef<Path>, format: DumpFormat) -> io::Result<Vec<ConnDump>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut dumps = Vec::new();
+    while let Some(dump) = ConnDump::read_from(format, &mut reader)? {
+        dumps.push(dump);
+    }
+    Ok(dumps)
+}
+
+/// Dump data of one connection.
+#[derive(Debug, Clone)]
+pub struct ConnData {
+    /// Connection id.
+    pub conn_id: u128,
+    /// Dump records in the order they were recorded.
+    pub dumps: Vec<ConnDump>,
+}
+
+/// Splits dump records by connection, in the order the connections first appear.
+pub fn split_connections(dumps: impl IntoIterator<Item = ConnDump>) -> Vec<ConnData> {
+    let mut conns: Vec<ConnData> = Vec::new();
+    for dump in dumps {
+        match conns.iter_mut().find(|conn| conn.conn_id == dump.conn_id) {
+            Some(conn) => conn.dumps.push(dump),
+            None => conns.push(ConnData { conn_id: dump.conn_id, dumps: vec![dump] }),
+        }
+    }
+    conns
+}
+
+/// Percentiles of a set of samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Percentiles {
+    /// Minimum.
+    pub min: f32,
+    /// Median.
+    pub p50: f32,
+    /// 90th percentile.
+    pub p90: f32,
+    /// 99th percentile.
+    pub p99: f32,
+    /// Maximum.
+    pub max: f32,
+}
+
+impl Percentiles {
+    /// Computes the percentiles of the samples using the nearest-rank method.
+    ///
+    /// Returns `None` if there are no samples.
+    pub fn new(mut samples: Vec<f32>) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_by(|a, b| a.total_cmp(b));
+
+        let rank = |p: f32| {
+            let idx = (p / 100.0 * samples.len() as f32).ceil() as usize;
+            samples[idx.clamp(1, samples.len()) - 1]
+        };
+        Some(Self { min: samples[0], p50: rank(50.0), p90: rank(90.0), p99: rank(99.0), max: rank(100.0) })
+    }
+}
+
+/// Summary statistics of a link.
+#[derive(Debug, Clone)]
+pub struct LinkSummary {
+    /// Link id.
+    pub link_id: u128,
+    /// Link tag.
+    pub tag: String,
+    /// Running time of the connection when the link first appeared in seconds.
+    pub first_seen: f32,
+    /// Running time of the connection when the link last appeared in seconds.
+    pub last_seen: f32,
+    /// Bytes sent while the link was recorded.
+    pub sent: u64,
+    /// Bytes received while the link was recorded.
+    pub recved: u64,
+    /// Average send throughput in bytes per second.
+    pub send_speed: f64,
+    /// Average receive throughput in bytes per second.
+    pub recv_speed: f64,
+    /// Percentiles of the smoothed roundtrip time in seconds.
+    pub roundtrip: Option<Percentiles>,
+    /// Lowest limit of unacknowledged data.
+    pub unacked_limit_min: usize,
+    /// Highest limit of unacknowledged data.
+    pub unacked_limit_max: usize,
+    /// Last limit of unacknowledged data.
+    pub unacked_limit_last: usize,
+    /// Number of times the limit of unacknowledged data was increased.
+    pub unacked_limit_increases: usize,
+    /// Number of times the limit of unacknowledged data was decreased.
+    pub unacked_limit_decreases: usize,
+    /// Fraction of records in which the link was unconfirmed.
+    pub unconfirmed_fraction: f32,
+}
+
+/// Summary statistics of a connection.
+#[derive(Debug, Clone)]
+pub struct ConnSummary {
+    /// Connection id.
+    pub conn_id: u128,
+    /// Number of dump records.
+    pub records: usize,
+    /// Recorded duration in seconds.
+    pub duration: f32,
+    /// Peak amount of sent, unacknowledged data.
+    pub peak_txed_unacked: usize,
+    /// Peak amount of sent, unconsumable data.
+    pub peak_txed_unconsumable: usize,
+    /// Peak length of the resend queue.
+    pub peak_resend_queue: usize,
+    /// Runtime when the resend queue peaked in seconds.
+    pub peak_resend_queue_at: f32,
+    /// Peak amount of received, unconsumable data.
+    pub peak_rxed_reliable_size: usize,
+    /// Links in the order they first appeared.
+    pub links: Vec<LinkSummary>,
+}
+
+impl ConnData {
+    /// Records of each link in the order the links first appeared.
+    ///
+    /// Each record is paired with the running time of the connection.
+    pub fn link_records(&self) -> Vec<(u128, Vec<(f32, &LinkDump)>)> {
+        let mut order = Vec::new();
+        let mut records: HashMap<u128, Vec<(f32, &LinkDump)>> = HashMap::new();
+        for dump in &self.dumps {
+            for link in &dump.links {
+                records
+                    .entry(link.link_id)
+                    .or_insert_with(|| {
+                        order.push(link.link_id);
+                        Vec::new()
+                    })
+                    .push((dump.runtime, link));
+            }
+        }
+        order.into_iter().map(|id| (id, records.remove(&id).unwrap())).collect()
+    }
+
+    /// Computes summary statistics.
+    pub fn summary(&self) -> ConnSummary {
+        let first = self.dumps.first().map(|dump| dump.runtime).unwrap_or_default();
+        let last = self.dumps.last().map(|dump| dump.runtime).unwrap_or_default();
+        let resend_peak = self.dumps.iter().max_by_key(|dump| dump.resend_queue);
+
+        ConnSummary {
+            conn_id: self.conn_id,
+            records: self.dumps.len(),
+            duration: last - first,
+            peak_txed_unacked: self.dumps.iter().map(|dump| dump.txed_unacked).max().unwrap_or_default(),
+            peak_txed_unconsumable: self
+                .dumps
+                .iter()
+                .map(|dump| dump.txed_unconsumable)
+                .max()
+                .unwrap_or_default(),
+            peak_resend_queue: resend_peak.map(|dump| dump.resend_queue).unwrap_or_default(),
+            peak_resend_queue_at: resend_peak.map(|dump| dump.runtime).unwrap_or_default(),
+            peak_rxed_reliable_size: self
+                .dumps
+                .iter()
+                .map(|dump| dump.rxed_reliable_size)
+                .max()
+                .unwrap_or_default(),
+            links: self.link_records().into_iter().map(|(id, records)| link_summary(id, &records)).collect(),
+        }
+    }
+
+    /// Writes connection-level data as CSV with one row per dump record.
+    pub fn write_csv(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "conn_id,runtime,txed_unacked,txed_unconsumed,txed_unconsumable,send_buffer,remote_receive_buffer,\
+             tx_seq,txed_packets,resend_queue,rx_seq,rxed_reliable,rxed_reliable_consumable,rxed_reliable_size,\
+             rxed_reliable_consumed_since_last_ack,sent_redundant,recved_duplicates,sent_datagrams,\
+             recved_datagrams,links"
+        )?;
+        for d in &self.dumps {
+            writeln!(
+                writer,
+                "{:032x},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                d.conn_id,
+                d.runtime,
+                d.txed_unacked,
+                d.txed_unconsumed,
+                d.txed_unconsumable,
+                d.send_buffer,
+                d.remote_receive_buffer,
+                d.tx_seq,
+                d.txed_packets,
+                d.resend_queue,
+                d.rx_seq,
+                d.rxed_reliable,
+                d.rxed_reliable_consumable,
+                d.rxed_reliable_size,
+                d.rxed_reliable_consumed_since_last_ack,
+                d.sent_redundant,
+                d.recved_duplicates,
+                d.sent_datagrams,
+                d.recved_datagrams,
+                d.links.len()
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes link-level data as CSV with one row per link and dump record.
+    pub fn write_links_csv(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "conn_id,runtime,link_id,tag,unconfirmed,blocked,tx_idle,tx_pending,tx_flushing,tx_flushed,\
+             tx_ack_queue,txed_unacked_data,txed_unacked_data_limit,\
+             txed_unacked_data_limit_increased_consecutively,total_sent,total_recved,roundtrip,min_roundtrip,\
+             roundtrip_var,jitter"
+        )?;
+        for d in &self.dumps {
+            for l in &d.links {
+                writeln!(
+                    writer,
+                    "{:032x},{},{:032x},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                    d.conn_id,
+                    d.runtime,
+                    l.link_id,
+                    csv_escape(&l.tag),
+                    l.unconfirmed,
+                    l.blocked,
+                    l.tx_idle,
+                    l.tx_pending,
+                    l.tx_flushing,
+                    l.tx_flushed,
+                    l.tx_ack_queue,
+                    l.txed_unacked_data,
+                    l.txed_unacked_data_limit,
+                    l.txed_unacked_data_limit_increased_consecutively,
+                    l.total_sent,
+                    l.total_recved,
+                    l.roundtrip,
+                    l.min_roundtrip,
+                    l.roundtrip_var,
+                    l.jitter
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Time-series plots of the connection and its links.
+    ///
+    /// Returns pairs of a file name stem and the plot.
+    pub fn plots(&self) -> Vec<(String, Plot)> {
+        let conn_series = |name: &str, f: fn(&ConnDump) -> f64| Series {
+            name: name.to_string(),
+            points: self.dumps.iter().map(|dump| (dump.runtime as f64, f(dump))).collect(),
+        };
+        let link_records = self.link_records();
+        let link_series = |f: fn(&LinkDump) -> f64| -> Vec<Series> {
+            link_records
+                .iter()
+                .map(|(_, records)| Series {
+                    name: records[0].1.tag.clone(),
+                    points: records.iter().map(|(runtime, link)| (*runtime as f64, f(link))).collect(),
+                })
+                .collect()
+        };
+
+        let mut plots = vec![
+            (
+                "connection".to_string(),
+                Plot {
+                    title: "Connection send buffer".to_string(),
+                    y_label: "bytes".to_string(),
+                    series: vec![
+                        conn_series("txed_unacked", |d| d.txed_unacked as f64),
+                        conn_series("txed_unconsumed", |d| d.txed_unconsumed as f64),
+                        conn_series("txed_unconsumable", |d| d.txed_unconsumable as f64),
+                        conn_series("remote_receive_buffer", |d| d.remote_receive_buffer as f64),
+                    ],
+                },
+            ),
+            (
+                "queues".to_string(),
+                Plot {
+                    title: "Reliable queues".to_string(),
+                    y_label: "packets".to_string(),
+                    series: vec![
+                        conn_series("txed_packets", |d| d.txed_packets as f64),
+                        conn_series("resend_queue", |d| d.resend_queue as f64),
+                        conn_series("rxed_reliable", |d| d.rxed_reliable as f64),
+                    ],
+                },
+            ),
+            (
+                "total_sent".to_string(),
+                Plot {
+                    title: "Total sent per link".to_string(),
+                    y_label: "bytes".to_string(),
+                    series: link_series(|l| l.total_sent as f64),
+                },
+            ),
+            (
+                "roundtrip".to_string(),
+                Plot {
+                    title: "Smoothed roundtrip time per link".to_string(),
+                    y_label: "ms".to_string(),
+                    series: link_series(|l| l.roundtrip as f64 * 1000.0),
+                },
+            ),
+        ];
+
+        for (n, (_, records)) in link_records.iter().enumerate() {
+            let series = |name: &str, f: fn(&LinkDump) -> f64| Series {
+                name: name.to_string(),
+                points: records.iter().map(|(runtime, link)| (*runtime as f64, f(link))).collect(),
+            };
+            plots.push((
+                format!("link{n}_unacked"),
+                Plot {
+                    title: format!("Unacknowledged data of link {}", records[0].1.tag),
+                    y_label: "bytes".to_string(),
+                    series: vec![
+                        series("txed_unacked_data", |l| l.txed_unacked_data as f64),
+                        series("txed_unacked_data_limit", |l| l.txed_unacked_data_limit as f64),
+                    ],
+                },
+            ));
+        }
+
+        plots
+    }
+}
+
+/// Computes the summary of a link from its records.
+fn link_summary(link_id: u128, records: &[(f32, &LinkDump)]) -> LinkSummary {
+    let (first_seen, first) = records[0];
+    let (last_seen, last) = records[records.len() - 1];
+    let duration = (last_seen - first_seen) as f64;
+    let sent = last.total_sent.saturating_sub(first.total_sent);
+    let recved = last.total_recved.saturating_sub(first.total_recved);
+    let speed = |bytes: u64| if duration > 0.0 { bytes as f64 / duration } else { 0.0 };
+
+    let limits = records.iter().map(|(_, link)| link.txed_unacked_data_limit);
+    let changes: Vec<_> =
+        records.windows(2).map(|w| (w[0].1.txed_unacked_data_limit, w[1].1.txed_unacked_data_limit)).collect();
+
+    LinkSummary {
+        link_id,
+        tag: first.tag.clone(),
+        first_seen,
+        last_seen,
+        sent,
+        recved,
+        send_speed: speed(sent),
+        recv_speed: speed(recved),
+        roundtrip: Percentiles::new(
+            records.iter().filter(|(_, link)| !link.unconfirmed).map(|(_, link)| link.roundtrip).collect(),
+        ),
+        unacked_limit_min: limits.clone().min().unwrap_or_default(),
+        unacked_limit_max: limits.max().unwrap_or_default(),
+        unacked_limit_last: last.txed_unacked_data_limit,
+        unacked_limit_increases: changes.iter().filter(|(a, b)| b > a).count(),
+        unacked_limit_decreases: changes.iter().filter(|(a, b)| b < a).count(),
+        unconfirmed_fraction: records.iter().filter(|(_, link)| link.unconfirmed).count() as f32
+            / records.len() as f32,
+    }
+}
+
+/// Quotes a CSV field if necessary.
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// A data series of a [plot](Plot).
+#[derive(Debug, Clone)]
+pub struct Series {
+    /// Name shown in the legend.
+    pub name: String,
+    /// Data points as x and y values.
+    pub points: Vec<(f64, f64)>,
+}
+
+/// A time-series line plot.
+#[derive(Debug, Clone)]
+pub struct Plot {
+    /// Title.
+    pub title: String,
+    /// Label of the y axis.
+    pub y_label: String,
+    /// Data series.
+    pub series: Vec<Series>,
+}
+
+impl Plot {
+    const WIDTH: f64 = 1000.0;
+    const HEIGHT: f64 = 500.0;
+    const LEFT: f64 = 90.0;
+    const RIGHT: f64 = 220.0;
+    const TOP: f64 = 40.0;
+    const BOTTOM: f64 = 50.0;
+    const TICKS: usize = 5;
+    const COLORS: [&'static str; 10] = [
+        "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f", "#bcbd22",
+        "#17becf",
+    ];
+
+    /// Renders the plot as an SVG image.
+    pub fn to_svg(&self) -> String {
+        let points =
+            || self.series.iter().flat_map(|s| s.points.iter()).filter(|(x, y)| x.is_finite() && y.is_finite());
+        let (x_min, x_max) = bounds(points().map(|(x, _)| *x));
+        let (y_min, y_max) = bounds(points().map(|(_, y)| *y).chain([0.0]));
+        let (y_min, y_max, y_step) = nice_range(y_min, y_max);
+        let (x_min, x_max, x_step) = nice_range(x_min, x_max);
+
+        let plot_w = Self::WIDTH - Self::LEFT - Self::RIGHT;
+        let plot_h = Self::HEIGHT - Self::TOP - Self::BOTTOM;
+        let px = |x: f64| Self::LEFT + (x - x_min) / (x_max - x_min) * plot_w;
+        let py = |y: f64| Self::TOP + plot_h - (y - y_min) / (y_max - y_min) * plot_h;
+
+        let mut svg = String::new();
+        let _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="sans-serif" font-size="12">"#,
+            w = Self::WIDTH,
+            h = Self::HEIGHT
+        );
+        let _ = writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#);
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="24" text-anchor="middle" font-size="16">{}</text>"#,
+            Self::LEFT + plot_w / 2.0,
+            xml_escape(&self.title)
+        );
+
+        // Grid and axis labels.
+        let mut y = y_min;
+        while y <= y_max + y_step / 2.0 {
+            let _ = writeln!(
+                svg,
+                r##"<line x1="{l}" y1="{p}" x2="{r}" y2="{p}" stroke="#ddd"/><text x="{t}" y="{p}" text-anchor="end" dominant-baseline="middle">{v}</text>"##,
+                l = Self::LEFT,
+                r = Self::LEFT + plot_w,
+                t = Self::LEFT - 6.0,
+                p = py(y),
+                v = format_tick(y)
+            );
+            y += y_step;
+        }
+        let mut x = x_min;
+        while x <= x_max + x_step / 2.0 {
+            let _ = writeln!(
+                svg,
+                r##"<line x1="{p}" y1="{t}" x2="{p}" y2="{b}" stroke="#ddd"/><text x="{p}" y="{l}" text-anchor="middle">{v}</text>"##,
+                t = Self::TOP,
+                b = Self::TOP + plot_h,
+                l = Self::TOP + plot_h + 18.0,
+                p = px(x),
+                v = format_tick(x)
+            );
+            x += x_step;
+        }
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{}" y="{}" width="{plot_w}" height="{plot_h}" fill="none" stroke="black"/>"#,
+            Self::LEFT,
+            Self::TOP
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" text-anchor="middle">runtime [s]</text>"#,
+            Self::LEFT + plot_w / 2.0,
+            Self::HEIGHT - 10.0
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text x="16" y="{y}" text-anchor="middle" transform="rotate(-90 16 {y})">{}</text>"#,
+            xml_escape(&self.y_label),
+            y = Self::TOP + plot_h / 2.0
+        );
+
+        // Series and legend.
+        for (n, series) in self.series.iter().enumerate() {
+            let color = Self::COLORS[n % Self::COLORS.len()];
+            let mut path = String::new();
+            for (x, y) in series.points.iter().filter(|(x, y)| x.is_finite() && y.is_finite()) {
+                let _ = write!(path, "{:.1},{:.1} ", px(*x), py(*y));
+            }
+            let _ = writeln!(
+                svg,
+                r#"<polyline points="{}" fill="none" stroke="{color}" stroke-width="1.5"/>"#,
+                path.trim_end()
+            );
+
+            let ly = Self::TOP + 10.0 + n as f64 * 18.0;
+            let lx = Self::LEFT + plot_w + 12.0;
+            let _ = writeln!(
+                svg,
+                r#"<line x1="{lx}" y1="{ly}" x2="{}" y2="{ly}" stroke="{color}" stroke-width="3"/><text x="{}" y="{ly}" dominant-baseline="middle">{}</text>"#,
+                lx + 20.0,
+                lx + 26.0,
+                xml_escape(&series.name)
+            );
+        }
+
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+/// Minimum and maximum of the values.
+fn bounds(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    values.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| (min.min(v), max.max(v)))
+}
+
+/// Extends the range to round tick values and returns it together with the tick step.
+fn nice_range(min: f64, max: f64) -> (f64, f64, f64) {
+    let (min, max) = match (min.is_finite(), max.is_finite()) {
+        (true, true) if max > min => (min, max),
+        (true, true) => (min, min + 1.0),
+        _ => (0.0, 1.0),
+    };
+
+    let raw_step = (max - min) / Plot::TICKS as f64;
+    let magnitude = 10f64.powf(raw_step.log10().floor());
+    let step = [1.0, 2.0, 2.5, 5.0, 10.0]
+        .into_iter()
+        .map(|f| f * magnitude)
+        .find(|step| *step >= raw_step)
+        .unwrap_or(10.0 * magnitude);
+
+    ((min / step).floor() * step, (max / step).ceil() * step, step)
+}
+
+/// Formats a tick value compactly.
+fn format_tick(v: f64) -> String {
+    let abs = v.abs();
+    if abs >= 1e9 {
+        format!("{:.1}G", v / 1e9)
+    } else if abs >= 1e6 {
+        format!("{:.1}M", v / 1e6)
+    } else if abs >= 1e3 {
+        format!("{:.1}k", v / 1e3)
+    } else if abs == 0.0 || abs >= 1.0 {
+        format!("{}", (v * 10.0).round() / 10.0)
+    } else {
+        format!("{v:.3}")
+    }
+}
+
+/// Escapes text for inclusion in XML.
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
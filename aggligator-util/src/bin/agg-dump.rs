@@ -0,0 +1,204 @@
+//! Analysis of connection dump data.
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::{
+    fs,
+    fs::File,
+    io::{stdout, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use aggligator::dump::DumpFormat;
+use aggligator_util::analysis::{read_dump_file, split_connections, ConnData, ConnSummary};
+
+/// Analyze connection dump data.
+///
+/// Reads dump data recorded using the `--dump` option of `agg-speed` or `agg-tunnel`
+/// and prints a summary, exports it as CSV or renders time-series plots.
+#[derive(Parser)]
+#[command(author, version)]
+pub struct DumpCli {
+    /// Format of the dump file.
+    #[arg(long, short = 'f', value_enum, default_value_t = Format::Json)]
+    format: Format,
+    /// Command.
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Prints summary statistics of each connection and its links.
+    Summary {
+        /// Dump file.
+        file: PathBuf,
+    },
+    /// Exports dump data as CSV.
+    Csv {
+        /// Dump file.
+        file: PathBuf,
+        /// Export link data with one row per link and record instead of connection data.
+        #[arg(long, short = 'l')]
+        links: bool,
+        /// Output file; standard output if not specified.
+        #[arg(long, short = 'o')]
+        output: Option<PathBuf>,
+    },
+    /// Renders time-series plots as SVG images.
+    Plot {
+        /// Dump file.
+        file: PathBuf,
+        /// Output directory.
+        #[arg(long, short = 'o', default_value = ".")]
+        output: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// JSON lines.
+    Json,
+    /// Compact binary format.
+    Binary,
+}
+
+impl From<Format> for DumpFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Json => DumpFormat::JsonLines,
+            Format::Binary => DumpFormat::Binary,
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let cli = DumpCli::parse();
+    let load = |file: &Path| -> Result<Vec<ConnData>> {
+        let dumps = read_dump_file(file, cli.format.into())
+            .with_context(|| format!("cannot read dump file {}", file.display()))?;
+        if dumps.is_empty() {
+            bail!("dump file {} contains no data", file.display());
+        }
+        Ok(split_connections(dumps))
+    };
+
+    match &cli.command {
+        Commands::Summary { file } => {
+            for conn in load(file)? {
+                print_summary(&conn.summary());
+            }
+        }
+        Commands::Csv { file, links, output } => {
+            let conns = load(file)?;
+            let mut writer: BufWriter<Box<dyn Write>> = BufWriter::new(match output {
+                Some(output) => Box::new(File::create(output)?),
+                None => Box::new(stdout()),
+            });
+            for (n, conn) in conns.iter().enumerate() {
+                let mut data = Vec::new();
+                if *links {
+                    conn.write_links_csv(&mut data)?;
+                } else {
+                    conn.write_csv(&mut data)?;
+                }
+
+                // Write the header only once.
+                let data = if n > 0 { data.splitn(2, |b| *b == b'\n').nth(1).unwrap_or_default() } else { &data };
+                writer.write_all(data)?;
+            }
+            writer.flush()?;
+        }
+        Commands::Plot { file, output } => {
+            let conns = load(file)?;
+            fs::create_dir_all(output)?;
+            for conn in &conns {
+                for (name, plot) in conn.plots() {
+                    let name = match conns.len() {
+                        1 => format!("{name}.svg"),
+                        _ => format!("{:032x}_{name}.svg", conn.conn_id),
+                    };
+                    let path = output.join(name);
+                    fs::write(&path, plot.to_svg())?;
+                    println!("{}", path.display());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_summary(summary: &ConnSummary) {
+    println!("Connection {:032x}", summary.conn_id);
+    println!("  records:               {}", summary.records);
+    println!("  duration:              {:.1} s", summary.duration);
+    println!("  peak unacked:          {}", format_bytes(summary.peak_txed_unacked as f64));
+    println!("  peak unconsumable:     {}", format_bytes(summary.peak_txed_unconsumable as f64));
+    println!(
+        "  peak resend queue:     {} packets at {:.1} s",
+        summary.peak_resend_queue, summary.peak_resend_queue_at
+    );
+    println!("  peak receive buffer:   {}", format_bytes(summary.peak_rxed_reliable_size as f64));
+
+    for link in &summary.links {
+        println!();
+        println!("  Link {} ({:032x})", link.tag, link.link_id);
+        println!("    seen:                {:.1} s - {:.1} s", link.first_seen, link.last_seen);
+        println!("    unconfirmed:         {:.1} %", link.unconfirmed_fraction * 100.0);
+        println!(
+            "    sent:                {} ({})",
+            format_bytes(link.sent as f64),
+            format_speed(link.send_speed)
+        );
+        println!(
+            "    received:            {} ({})",
+            format_bytes(link.recved as f64),
+            format_speed(link.recv_speed)
+        );
+        match &link.roundtrip {
+            Some(rtt) => println!(
+                "    roundtrip:           min {:.1} / p50 {:.1} / p90 {:.1} / p99 {:.1} / max {:.1} ms",
+                rtt.min * 1000.0,
+                rtt.p50 * 1000.0,
+                rtt.p90 * 1000.0,
+                rtt.p99 * 1000.0,
+                rtt.max * 1000.0
+            ),
+            None => println!("    roundtrip:           n/a"),
+        }
+        println!(
+            "    unacked limit:       min {} / max {} / last {}",
+            format_bytes(link.unacked_limit_min as f64),
+            format_bytes(link.unacked_limit_max as f64),
+            format_bytes(link.unacked_limit_last as f64)
+        );
+        println!(
+            "    limit changes:       {} increases, {} decreases",
+            link.unacked_limit_increases, link.unacked_limit_decreases
+        );
+    }
+    println!();
+}
+
+/// Formats a byte count without styling.
+fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{value:.0} {}", UNITS[unit]),
+        _ => format!("{value:.1} {}", UNITS[unit]),
+    }
+}
+
+/// Formats a speed without styling.
+fn format_speed(speed: f64) -> String {
+    format!("{}/s", format_bytes(speed))
+}
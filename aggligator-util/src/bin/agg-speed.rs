@@ -26,6 +26,7 @@ use tokio::{
 use aggligator::{cfg::Cfg, dump::dump_to_json_line_file};
 use aggligator_util::{
     cli::{init_log, load_cfg, print_default_cfg},
+    metrics::Metrics,
     monitor::{format_speed, interactive_monitor},
     speed::{speed_test, INTERVAL},
     transport::{
@@ -46,6 +47,7 @@ const DUMP_BUFFER: usize = 8192;
 
 const WEBSOCKET_PORT: u16 = 8080;
 const WEBSOCKET_PATH: &str = "/agg-speed";
+const METRICS_PATH: &str = "/metrics";
 
 #[cfg(any(feature = "usb-host", feature = "usb-device"))]
 mod usb {
@@ -472,6 +474,9 @@ pub struct ServerCli {
     /// WebSocket (HTTP) port to listen on.
     #[arg(long, default_value_t = WEBSOCKET_PORT)]
     websocket: u16,
+    /// Serve Prometheus/OpenMetrics metrics at /metrics on the WebSocket (HTTP) port.
+    #[arg(long)]
+    metrics: bool,
 }
 
 impl ServerCli {
@@ -571,8 +576,12 @@ impl ServerCli {
             None
         };
 
-        let (wsa, router) = WebSocketAcceptor::new(WEBSOCKET_PATH);
+        let metrics = self.metrics.then(Metrics::new);
+        let (wsa, mut router) = WebSocketAcceptor::new(WEBSOCKET_PATH);
         acceptor.add(wsa);
+        if let Some(metrics) = &metrics {
+            router = router.merge(metrics.router(METRICS_PATH));
+        }
         ports.push(format!("WebSocket {}", self.websocket));
         let websocket_addr = SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), self.websocket);
         tokio::spawn(async move {
@@ -598,6 +607,9 @@ impl ServerCli {
         let task = async move {
             loop {
                 let (ch, control) = acceptor.accept().await?;
+                if let Some(metrics) = &metrics {
+                    metrics.register(&control);
+                }
                 let _ = control_tx.send((control, String::new()));
 
                 tokio::spawn(async move {
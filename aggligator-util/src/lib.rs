@@ -33,22 +33,31 @@
 //!   * [transport implementations](transport) for TCP, Bluetooth RFCOMM sockets, USB and WebSockets,
 //!   * optional TLS link authentication and encryption,
 //!   * a text-based, interactive [connection and link montor](monitor),
-//!   * a [speed test](speed).
+//!   * a [speed test](speed),
+//!   * [analysis](analysis) of connection dump data,
+//!   * a Prometheus/OpenMetrics [exporter](metrics) for connections and links.
 //!
 //! The following command line tools are included:
 //!   * `agg-speed` — performs a speed test over a connection of aggregated TCP links,
-//!   * `agg-tunnel` — forwards arbitrary TCP ports over a connection of aggregated TCP links.
+//!   * `agg-tunnel` — forwards arbitrary TCP ports over a connection of aggregated TCP links,
+//!   * `agg-dump` — summarizes, exports and plots connection dump data.
 //!
-//! Both tools display a text-based, interactive connection and link monitor.
+//! The first two tools display a text-based, interactive connection and link monitor.
 //!
 //! #### Simple aggregation of TCP links
 //! Use the [tcp_connect](net::tcp_connect) and [tcp_server](net::tcp_server) functions
 //! from the [net module](net).
 //!
 
+#[cfg(feature = "dump")]
+#[cfg_attr(docsrs, doc(cfg(feature = "dump")))]
+pub mod analysis;
 #[cfg(feature = "cli")]
 #[doc(hidden)]
 pub mod cli;
+#[cfg(feature = "metrics")]
+#[cfg_attr(docsrs, doc(cfg(feature = "metrics")))]
+pub mod metrics;
 #[cfg(feature = "monitor")]
 #[cfg_attr(docsrs, doc(cfg(feature = "monitor")))]
 pub mod monitor;
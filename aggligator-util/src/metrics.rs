@@ -0,0 +1,422 @@
+//! Prometheus/OpenMetrics exporter for connections and links.
+//!
+//! Register the [control handles](Control) of connections with [`Metrics`] and
+//! serve the metrics over HTTP using the [Axum router](Metrics::router) or
+//! [`Metrics::serve`].
+//! Terminated connections are removed automatically.
+//!
+//! All metrics are prefixed with `aggligator_`.
+//! Connection metrics are labeled by the connection id and direction,
+//! link metrics are labeled by connection id, link id and link tag.
+//!
+
+use axum::{
+    http::{header::CONTENT_TYPE, HeaderValue},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use futures::StreamExt;
+use std::{
+    collections::HashMap,
+    fmt,
+    fmt::Write,
+    io::{Error, ErrorKind, Result},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use aggligator::{
+    control::{Control, DisconnectReason, EventKind, Stats},
+    id::ConnId,
+};
+
+/// Content type of the OpenMetrics text format.
+pub const CONTENT_TYPE_OPENMETRICS: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Source of metrics of one connection.
+trait Source: Send + Sync {
+    /// Whether the connection has terminated.
+    fn is_terminated(&self) -> bool;
+
+    /// Current metrics of the connection.
+    fn snapshot(&self) -> ConnSnapshot;
+}
+
+/// Registered connection.
+struct Registered {
+    conn_id: ConnId,
+    source: Box<dyn Source>,
+    disconnects: Arc<Mutex<HashMap<(String, &'static str), u64>>>,
+}
+
+impl<TX, RX, TAG> Source for Control<TX, RX, TAG>
+where
+    TX: Send + 'static,
+    RX: Send + 'static,
+    TAG: fmt::Display + Send + Sync + 'static,
+{
+    fn is_terminated(&self) -> bool {
+        Control::is_terminated(self)
+    }
+
+    fn snapshot(&self) -> ConnSnapshot {
+        ConnSnapshot {
+            conn_id: self.id().to_string(),
+            direction: self.direction().to_string(),
+            stats: self.stats(),
+            links: self
+                .links()
+                .into_iter()
+                .map(|link| LinkSnapshot {
+                    link_id: link.id().to_string(),
+                    tag: link.tag().to_string(),
+                    stats: link.stats(),
+                    working: link.is_working(),
+                    blocked: link.is_blocked(),
+                    remotely_blocked: link.is_remotely_blocked(),
+                })
+                .collect(),
+            disconnects: Vec::new(),
+        }
+    }
+}
+
+/// Metrics of a connection at one point in time.
+struct ConnSnapshot {
+    conn_id: String,
+    direction: String,
+    stats: Stats,
+    links: Vec<LinkSnapshot>,
+    disconnects: Vec<(String, &'static str, u64)>,
+}
+
+/// Metrics of a link at one point in time.
+struct LinkSnapshot {
+    link_id: String,
+    tag: String,
+    stats: aggligator::control::LinkStats,
+    working: bool,
+    blocked: bool,
+    remotely_blocked: bool,
+}
+
+const GAUGE: &str = "gauge";
+const COUNTER: &str = "counter";
+
+/// Name, type, help and value of a metric.
+type Metric<T> = (&'static str, &'static str, &'static str, fn(&T) -> f64);
+
+/// Connection metrics.
+const CONN_METRICS: &[Metric<ConnSnapshot>] = &[
+    ("aggligator_conn_working", GAUGE, "Whether any link of the connection is working.", |c| {
+        c.stats.not_working_since.is_none() as u8 as f64
+    }),
+    ("aggligator_conn_links", GAUGE, "Number of links.", |c| c.links.len() as f64),
+    ("aggligator_conn_uptime_seconds", GAUGE, "Time since establishment.", |c| {
+        c.stats.established.map(|t| t.elapsed().as_secs_f64()).unwrap_or_default()
+    }),
+    ("aggligator_conn_send_space_bytes", GAUGE, "Available send buffer space.", |c| c.stats.send_space as f64),
+    ("aggligator_conn_sent_unacked_bytes", GAUGE, "Data sent and not yet acknowledged.", |c| {
+        c.stats.sent_unacked as f64
+    }),
+    ("aggligator_conn_sent_unconsumed_bytes", GAUGE, "Data sent and not yet consumed.", |c| {
+        c.stats.sent_unconsumed as f64
+    }),
+    (
+        "aggligator_conn_sent_unconsumable_bytes",
+        GAUGE,
+        "Data received by the remote endpoint that cannot yet be consumed.",
+        |c| c.stats.sent_unconsumable as f64,
+    ),
+    ("aggligator_conn_resend_queue_packets", GAUGE, "Length of the resend queue.", |c| {
+        c.stats.resend_queue_len as f64
+    }),
+    ("aggligator_conn_recved_unconsumed_bytes", GAUGE, "Data received and not yet consumed.", |c| {
+        c.stats.recved_unconsumed as f64
+    }),
+    ("aggligator_conn_sent_redundant_packets", COUNTER, "Redundant copies of packets sent.", |c| {
+        c.stats.sent_redundant as f64
+    }),
+    ("aggligator_conn_sent_redundant_bytes", COUNTER, "Size of redundant copies of packets sent.", |c| {
+        c.stats.sent_redundant_bytes as f64
+    }),
+    ("aggligator_conn_recved_duplicate_packets", COUNTER, "Duplicate packets received.", |c| {
+        c.stats.recved_duplicates as f64
+    }),
+    ("aggligator_conn_recved_duplicate_bytes", COUNTER, "Size of duplicate packets received.", |c| {
+        c.stats.recved_duplicate_bytes as f64
+    }),
+    ("aggligator_conn_sent_datagrams", COUNTER, "Datagrams sent.", |c| c.stats.sent_datagrams as f64),
+    ("aggligator_conn_sent_datagrams_dropped", COUNTER, "Datagrams dropped before sending.", |c| {
+        c.stats.sent_datagrams_dropped as f64
+    }),
+    ("aggligator_conn_recved_datagrams", COUNTER, "Datagrams received.", |c| c.stats.recved_datagrams as f64),
+    ("aggligator_conn_recved_datagrams_dropped", COUNTER, "Received datagrams dropped.", |c| {
+        c.stats.recved_datagrams_dropped as f64
+    }),
+    ("aggligator_conn_sent_uncompressed_bytes", COUNTER, "Compressed data sent, before compression.", |c| {
+        c.stats.sent_uncompressed_bytes as f64
+    }),
+    ("aggligator_conn_sent_compressed_bytes", COUNTER, "Compressed data sent, after compression.", |c| {
+        c.stats.sent_compressed_bytes as f64
+    }),
+    (
+        "aggligator_conn_recved_compressed_bytes",
+        COUNTER,
+        "Compressed data received, before decompression.",
+        |c| c.stats.recved_compressed_bytes as f64,
+    ),
+    (
+        "aggligator_conn_recved_uncompressed_bytes",
+        COUNTER,
+        "Compressed data received, after decompression.",
+        |c| c.stats.recved_uncompressed_bytes as f64,
+    ),
+];
+
+/// Link metrics.
+const LINK_METRICS: &[Metric<LinkSnapshot>] = &[
+    ("aggligator_link_working", GAUGE, "Whether the link is working.", |l| l.working as u8 as f64),
+    ("aggligator_link_blocked", GAUGE, "Whether the link is blocked locally.", |l| l.blocked as u8 as f64),
+    ("aggligator_link_remotely_blocked", GAUGE, "Whether the link is blocked remotely.", |l| {
+        l.remotely_blocked as u8 as f64
+    }),
+    ("aggligator_link_sent_bytes", COUNTER, "Data sent.", |l| l.stats.total_sent as f64),
+    ("aggligator_link_recved_bytes", COUNTER, "Data received.", |l| l.stats.total_recved as f64),
+    ("aggligator_link_sent_unacked_bytes", GAUGE, "Data sent and not yet acknowledged.", |l| {
+        l.stats.sent_unacked as f64
+    }),
+    ("aggligator_link_unacked_limit_bytes", GAUGE, "Limit of data sent and not yet acknowledged.", |l| {
+        l.stats.unacked_limit as f64
+    }),
+    ("aggligator_link_roundtrip_seconds", GAUGE, "Smoothed round trip time.", |l| {
+        l.stats.roundtrip.as_secs_f64()
+    }),
+    ("aggligator_link_min_roundtrip_seconds", GAUGE, "Lowest observed round trip time.", |l| {
+        l.stats.min_roundtrip.as_secs_f64()
+    }),
+    ("aggligator_link_roundtrip_var_seconds", GAUGE, "Variation of the round trip time.", |l| {
+        l.stats.roundtrip_var.as_secs_f64()
+    }),
+    ("aggligator_link_jitter_seconds", GAUGE, "Jitter of the round trip time.", |l| l.stats.jitter.as_secs_f64()),
+    ("aggligator_link_hangs", COUNTER, "Number of times the link exceeded the timeout.", |l| {
+        l.stats.hangs as f64
+    }),
+];
+
+/// Registry of connections for exporting metrics.
+///
+/// Clones of this refer to the same registry.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    conns: Arc<Mutex<Vec<Registered>>>,
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Metrics").field("connections", &self.conns.lock().unwrap().len()).finish()
+    }
+}
+
+impl Metrics {
+    /// Creates a new, empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a connection for exporting its metrics.
+    ///
+    /// Link disconnections are counted from the time of registration.
+    /// The connection is removed from the registry once it has terminated.
+    /// Registering a connection twice has no effect.
+    pub fn register<TX, RX, TAG>(&self, control: &Control<TX, RX, TAG>)
+    where
+        TX: Send + 'static,
+        RX: Send + 'static,
+        TAG: fmt::Display + Send + Sync + 'static,
+    {
+        let mut conns = self.conns.lock().unwrap();
+        if conns.iter().any(|conn| conn.conn_id == control.id()) {
+            return;
+        }
+
+        let disconnects = Arc::new(Mutex::new(HashMap::new()));
+        let mut events = control.events();
+        let task_disconnects = disconnects.clone();
+        tokio::spawn(async move {
+            while let Some(event) = events.next().await {
+                if let EventKind::LinkDisconnected { link, reason } = event.kind {
+                    let mut disconnects = task_disconnects.lock().unwrap();
+                    *disconnects.entry((link.tag().to_string(), reason_label(&reason))).or_default() += 1;
+                }
+            }
+        });
+
+        conns.push(Registered { conn_id: control.id(), source: Box::new(control.clone()), disconnects });
+    }
+
+    /// Number of registered connections that have not yet terminated.
+    pub fn len(&self) -> usize {
+        self.conns.lock().unwrap().iter().filter(|conn| !conn.source.is_terminated()).count()
+    }
+
+    /// Whether no connections are registered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Renders the current metrics in the OpenMetrics text format.
+    pub fn render(&self) -> String {
+        let snapshots: Vec<_> = {
+            let mut conns = self.conns.lock().unwrap();
+            conns.retain(|conn| !conn.source.is_terminated());
+            conns
+                .iter()
+                .map(|conn| {
+                    let mut snapshot = conn.source.snapshot();
+                    let mut disconnects: Vec<_> = conn
+                        .disconnects
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .map(|((tag, reason), count)| (tag.clone(), *reason, *count))
+                        .collect();
+                    disconnects.sort();
+                    snapshot.disconnects = disconnects;
+                    snapshot
+                })
+                .collect()
+        };
+
+        let mut out = Encoder::default();
+        out.family("aggligator_connections", GAUGE, "Number of connections.", |m| {
+            m.sample(&[], snapshots.len() as f64)
+        });
+
+        for (name, kind, help, value) in CONN_METRICS {
+            out.family(name, kind, help, |m| {
+                for conn in &snapshots {
+                    m.sample(&[("conn_id", &conn.conn_id), ("direction", &conn.direction)], value(conn));
+                }
+            });
+        }
+
+        for (name, kind, help, value) in LINK_METRICS {
+            out.family(name, kind, help, |m| {
+                for conn in &snapshots {
+                    for link in &conn.links {
+                        m.sample(
+                            &[("conn_id", &conn.conn_id), ("link_id", &link.link_id), ("tag", &link.tag)],
+                            value(link),
+                        );
+                    }
+                }
+            });
+        }
+
+        out.family("aggligator_link_disconnects", COUNTER, "Link disconnections by reason.", |m| {
+            for conn in &snapshots {
+                for (tag, reason, count) in &conn.disconnects {
+                    m.sample(&[("conn_id", &conn.conn_id), ("tag", tag), ("reason", reason)], *count as f64);
+                }
+            }
+        });
+
+        out.finish()
+    }
+
+    /// Creates an Axum router that serves the metrics at the specified `path`.
+    ///
+    /// It can be merged into an existing router, for example the one
+    /// used for accepting [WebSocket links](crate::transport::websocket).
+    pub fn router(&self, path: &str) -> Router {
+        let this = self.clone();
+        Router::new().route(
+            path,
+            get(move || async move {
+                let mut response: Response = this.render().into_response();
+                response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static(CONTENT_TYPE_OPENMETRICS));
+                response
+            }),
+        )
+    }
+
+    /// Serves the metrics over HTTP at path `/metrics` on the specified address.
+    ///
+    /// This function returns only when an error occurs.
+    pub async fn serve(self, addr: SocketAddr) -> Result<()> {
+        axum::Server::try_bind(&addr)
+            .map_err(|err| Error::new(ErrorKind::AddrInUse, err))?
+            .serve(self.router("/metrics").into_make_service())
+            .await
+            .map_err(|err| Error::new(ErrorKind::Other, err))
+    }
+}
+
+/// Label value of a disconnect reason.
+fn reason_label(reason: &DisconnectReason) -> &'static str {
+    match reason {
+        DisconnectReason::SendTimeout => "send_timeout",
+        DisconnectReason::PingTimeout => "ping_timeout",
+        DisconnectReason::UnconfirmedTimeout => "unconfirmed_timeout",
+        DisconnectReason::AllUnconfirmedTimeout => "all_unconfirmed_timeout",
+        DisconnectReason::IoError(_) => "io_error",
+        DisconnectReason::LocallyRequested => "locally_requested",
+        DisconnectReason::RemotelyRequested => "remotely_requested",
+        DisconnectReason::ConnectionClosed => "connection_closed",
+        DisconnectReason::LinkFilter => "link_filter",
+        DisconnectReason::ServerIdMismatch => "server_id_mismatch",
+        DisconnectReason::ProtocolError(_) => "protocol_error",
+        DisconnectReason::TaskTerminated => "task_terminated",
+    }
+}
+
+/// Encoder for the OpenMetrics text format.
+#[derive(Default)]
+struct Encoder {
+    out: String,
+}
+
+/// Writes the samples of one metric family.
+struct FamilyEncoder<'a> {
+    out: &'a mut String,
+    name: &'a str,
+    suffix: &'a str,
+}
+
+impl Encoder {
+    /// Writes a metric family.
+    ///
+    /// Counter samples get the `_total` suffix.
+    fn family(&mut self, name: &str, kind: &str, help: &str, samples: impl FnOnce(&mut FamilyEncoder)) {
+        let _ = writeln!(self.out, "# TYPE {name} {kind}");
+        let _ = writeln!(self.out, "# HELP {name} {help}");
+        let suffix = if kind == COUNTER { "_total" } else { "" };
+        samples(&mut FamilyEncoder { out: &mut self.out, name, suffix });
+    }
+
+    /// Finishes the exposition.
+    fn finish(mut self) -> String {
+        self.out.push_str("# EOF\n");
+        self.out
+    }
+}
+
+impl<'a> FamilyEncoder<'a> {
+    /// Writes a sample.
+    fn sample(&mut self, labels: &[(&str, &str)], value: f64) {
+        let _ = write!(self.out, "{}{}", self.name, self.suffix);
+        if !labels.is_empty() {
+            let labels: Vec<_> =
+                labels.iter().map(|(name, value)| format!("{name}=\"{}\"", escape_label(value))).collect();
+            let _ = write!(self.out, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.out, " {value}");
+    }
+}
+
+/// Escapes a label value.
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
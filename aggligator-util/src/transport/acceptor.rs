@@ -388,7 +388,11 @@ impl Acceptor {
                 tracing::debug!("adding link for tag {tag} to connection");
                 let user_data = tag.user_data();
                 let TxRxBox { tx, rx } = stream_box.into_tx_rx();
-                let link = match server.add_incoming(tx, rx, tag.clone(), &user_data).await {
+                let res = match tag.remote_addr() {
+                    Some(remote) => server.add_incoming_from(tx, rx, tag.clone(), &user_data, remote).await,
+                    None => server.add_incoming(tx, rx, tag.clone(), &user_data).await,
+                };
+                let link = match res {
                     Ok(link) => link,
                     Err(err) => {
                         tracing::debug!("adding link for tag {tag} to connection failed: {err}");
@@ -63,6 +63,7 @@ use std::{
     hash::{Hash, Hasher},
     io,
     io::Result,
+    net::IpAddr,
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
@@ -134,6 +135,13 @@ pub trait LinkTag: Debug + Display + Send + Sync + 'static {
     /// User data to send to the remote endpoint when connecting.
     fn user_data(&self) -> Vec<u8>;
 
+    /// Address of the remote endpoint, if the transport is IP-based.
+    ///
+    /// It is used to enforce the incoming link rate limit of the server.
+    fn remote_addr(&self) -> Option<IpAddr> {
+        None
+    }
+
     /// Cast this type as [`Any`].
     fn as_any(&self) -> &dyn Any;
 
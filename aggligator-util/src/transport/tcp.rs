@@ -101,6 +101,10 @@ impl LinkTag for TcpLinkTag {
         self.interface.clone()
     }
 
+    fn remote_addr(&self) -> Option<IpAddr> {
+        Some(self.remote.ip())
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -361,6 +361,10 @@ impl LinkTag for IncomingWebSocketLinkTag {
         }
     }
 
+    fn remote_addr(&self) -> Option<IpAddr> {
+        Some(self.remote.ip())
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
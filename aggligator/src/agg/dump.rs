@@ -3,27 +3,46 @@
 //! The purpose of the data is to debug connection performance issues
 //! and to help with the development of Aggligator.
 //!
-//! The data can be visualized using the `PlotDump.ipynb` script
-//! from the repository.
+//! Dump data can be written as JSON lines or in a compact binary format,
+//! either to a file using [`dump_to_file`] or to any writer, for example
+//! a network socket, using [`dump_to_writer`].
+//!
+//! The data can be summarized, exported as CSV and plotted using the
+//! `agg-dump` tool from the `aggligator-util` crate or visualized using
+//! the `PlotDump.ipynb` script from the repository.
 //!
 
 use serde::{Deserialize, Serialize};
-use std::{io::Error, path::Path};
+use std::{
+    io::{BufRead, Error, ErrorKind, Result},
+    path::Path,
+};
 use tokio::{
     fs::File,
-    io::{AsyncWriteExt, BufWriter},
+    io::{AsyncWrite, AsyncWriteExt, BufWriter},
     sync::mpsc,
 };
 
+/// Version of the dump data schema.
+///
+/// Version 1 had a fixed number of link fields and no version field.
+pub const DUMP_VERSION: u32 = 2;
+
+/// Maximum size of a record in the binary format.
+const MAX_BINARY_RECORD: usize = 16 * 1024 * 1024;
+
 /// Link dump data for analysis.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct LinkDump {
-    /// Whether the link is present.
-    pub present: bool,
     /// The link id.
     pub link_id: u128,
+    /// The link tag in debug formatting.
+    pub tag: String,
     /// Whether the link is currently unconfirmed.
     pub unconfirmed: bool,
+    /// Whether the link is blocked locally or remotely.
+    pub blocked: bool,
     /// Whether the sender is idle.
     pub tx_idle: bool,
     /// Whether the sender is being polled for readiness.
@@ -45,13 +64,22 @@ pub struct LinkDump {
     pub total_sent: u64,
     /// Total bytes received.
     pub total_recved: u64,
-    /// Roundtrip time in milliseconds.
+    /// Smoothed roundtrip time in seconds.
     pub roundtrip: f32,
+    /// Minimum roundtrip time in seconds.
+    pub min_roundtrip: f32,
+    /// Roundtrip time variation in seconds.
+    pub roundtrip_var: f32,
+    /// Jitter of the roundtrip time in seconds.
+    pub jitter: f32,
 }
 
 /// Connection dump data for analysis.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConnDump {
+    /// Version of the dump data schema, see [`DUMP_VERSION`].
+    #[serde(default = "ConnDump::v1")]
+    pub version: u32,
     /// Connection id.
     pub conn_id: u128,
     /// Running time in seconds.
@@ -66,48 +94,228 @@ pub struct ConnDump {
     pub send_buffer: u32,
     /// Receive buffer size of remote endpoint.
     pub remote_receive_buffer: u32,
+    /// Next sequence number for sending.
+    #[serde(default)]
+    pub tx_seq: u32,
+    /// Number of sent packets that have not yet been consumed by the remote endpoint.
+    #[serde(default)]
+    pub txed_packets: usize,
     /// Resend queue length.
     pub resend_queue: usize,
+    /// Next expected received sequence number.
+    #[serde(default)]
+    pub rx_seq: u32,
+    /// Number of received packets waiting for missing packets.
+    #[serde(default)]
+    pub rxed_reliable: usize,
+    /// Number of received packets ready for consumption.
+    #[serde(default)]
+    pub rxed_reliable_consumable: usize,
     /// Amount of received, unconsumable data.
     pub rxed_reliable_size: usize,
     /// Amount of received data consumed since sending last
     /// Consumed message.
     pub rxed_reliable_consumed_since_last_ack: usize,
-    /// Link 0.
-    pub link0: LinkDump,
-    /// Link 1.
-    pub link1: LinkDump,
-    /// Link 2.
-    pub link2: LinkDump,
-    /// Link 3.
-    pub link3: LinkDump,
-    /// Link 4.
-    pub link4: LinkDump,
-    /// Link 5.
-    pub link5: LinkDump,
-    /// Link 6.
-    pub link6: LinkDump,
-    /// Link 7.
-    pub link7: LinkDump,
-    /// Link 8.
-    pub link8: LinkDump,
-    /// Link 9.
-    pub link9: LinkDump,
+    /// Packets sent redundantly.
+    #[serde(default)]
+    pub sent_redundant: u64,
+    /// Duplicate packets received.
+    #[serde(default)]
+    pub recved_duplicates: u64,
+    /// Datagrams sent.
+    #[serde(default)]
+    pub sent_datagrams: u64,
+    /// Datagrams received.
+    #[serde(default)]
+    pub recved_datagrams: u64,
+    /// Links of the connection.
+    #[serde(default)]
+    pub links: Vec<LinkDump>,
+}
+
+impl ConnDump {
+    /// Version of dump data without a version field.
+    fn v1() -> u32 {
+        1
+    }
+
+    /// Encodes the dump data in the specified format and appends it to `buf`.
+    ///
+    /// Each encoded record is self-delimiting.
+    pub fn encode(&self, format: DumpFormat, buf: &mut Vec<u8>) {
+        match format {
+            DumpFormat::JsonLines => {
+                serde_json::to_writer(&mut *buf, self).unwrap();
+                buf.push(b'\n');
+            }
+            DumpFormat::Binary => {
+                let len = bincode::serialized_size(self).unwrap() as u32;
+                buf.extend_from_slice(&len.to_le_bytes());
+                bincode::serialize_into(&mut *buf, self).unwrap();
+            }
+        }
+    }
+
+    /// Reads the next record in the specified format.
+    ///
+    /// JSON records of version 1 of the schema are converted to the current version;
+    /// fields missing from them are set to their default values.
+    ///
+    /// Returns `None` when the end of the data has been reached.
+    pub fn read_from(format: DumpFormat, mut reader: impl BufRead) -> Result<Option<Self>> {
+        match format {
+            DumpFormat::JsonLines => loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+                if !line.trim().is_empty() {
+                    let mut dump: Self = serde_json::from_str(&line)?;
+                    if dump.version == 1 {
+                        dump.links = serde_json::from_str::<LinksV1>(&line)?.into();
+                    }
+                    return Ok(Some(dump));
+                }
+            },
+            DumpFormat::Binary => {
+                if reader.fill_buf()?.is_empty() {
+                    return Ok(None);
+                }
+
+                let mut len = [0; 4];
+                reader.read_exact(&mut len)?;
+                let len = u32::from_le_bytes(len) as usize;
+                if len > MAX_BINARY_RECORD {
+                    return Err(Error::new(ErrorKind::InvalidData, "dump record too large"));
+                }
+
+                let mut record = vec![0; len];
+                reader.read_exact(&mut record)?;
+                bincode::deserialize(&record).map(Some).map_err(|err| Error::new(ErrorKind::InvalidData, err))
+            }
+        }
+    }
+}
+
+/// Link dump data of version 1 of the schema.
+#[derive(Deserialize)]
+struct LinkDumpV1 {
+    present: bool,
+    link_id: u128,
+    unconfirmed: bool,
+    tx_idle: bool,
+    tx_pending: bool,
+    tx_flushing: bool,
+    tx_flushed: bool,
+    tx_ack_queue: usize,
+    txed_unacked_data: usize,
+    txed_unacked_data_limit: usize,
+    txed_unacked_data_limit_increased_consecutively: usize,
+    total_sent: u64,
+    total_recved: u64,
+    roundtrip: f32,
+}
+
+impl From<LinkDumpV1> for LinkDump {
+    fn from(link: LinkDumpV1) -> Self {
+        Self {
+            link_id: link.link_id,
+            unconfirmed: link.unconfirmed,
+            tx_idle: link.tx_idle,
+            tx_pending: link.tx_pending,
+            tx_flushing: link.tx_flushing,
+            tx_flushed: link.tx_flushed,
+            tx_ack_queue: link.tx_ack_queue,
+            txed_unacked_data: link.txed_unacked_data,
+            txed_unacked_data_limit: link.txed_unacked_data_limit,
+            txed_unacked_data_limit_increased_consecutively: link.txed_unacked_data_limit_increased_consecutively,
+            total_sent: link.total_sent,
+            total_recved: link.total_recved,
+            roundtrip: link.roundtrip,
+            ..Default::default()
+        }
+    }
+}
+
+/// Fixed link fields of version 1 of the schema.
+#[derive(Deserialize)]
+struct LinksV1 {
+    link0: LinkDumpV1,
+    link1: LinkDumpV1,
+    link2: LinkDumpV1,
+    link3: LinkDumpV1,
+    link4: LinkDumpV1,
+    link5: LinkDumpV1,
+    link6: LinkDumpV1,
+    link7: LinkDumpV1,
+    link8: LinkDumpV1,
+    link9: LinkDumpV1,
+}
+
+impl From<LinksV1> for Vec<LinkDump> {
+    fn from(links: LinksV1) -> Self {
+        let LinksV1 { link0, link1, link2, link3, link4, link5, link6, link7, link8, link9 } = links;
+        [link0, link1, link2, link3, link4, link5, link6, link7, link8, link9]
+            .into_iter()
+            .filter(|link| link.present)
+            .map(LinkDump::from)
+            .collect()
+    }
+}
+
+/// Encoding of dump data.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum DumpFormat {
+    /// One JSON object per line.
+    #[default]
+    JsonLines,
+    /// Compact binary encoding.
+    ///
+    /// Each record consists of its length as a 32-bit little endian integer
+    /// followed by the record encoded using [bincode](https://docs.rs/bincode/1).
+    Binary,
 }
 
 /// Dumps analysis data from the channel to a JSON line file.
 ///
 /// The file has one JSON object per line.
-pub async fn dump_to_json_line_file(
-    path: impl AsRef<Path>, mut rx: mpsc::Receiver<ConnDump>,
-) -> Result<(), Error> {
+pub async fn dump_to_json_line_file(path: impl AsRef<Path>, rx: mpsc::Receiver<ConnDump>) -> Result<()> {
+    dump_to_file(path, DumpFormat::JsonLines, rx).await
+}
+
+/// Dumps analysis data from the channel to a file using the specified format.
+pub async fn dump_to_file(
+    path: impl AsRef<Path>, format: DumpFormat, rx: mpsc::Receiver<ConnDump>,
+) -> Result<()> {
     let file = File::create(path).await?;
-    let mut writer = BufWriter::new(file);
+    dump_to_writer(file, format, rx).await
+}
+
+/// Dumps analysis data from the channel to a writer, for example a network socket,
+/// using the specified format.
+///
+/// The writer is flushed whenever no more dump data is immediately available,
+/// so that the receiving end obtains the data in a timely manner.
+pub async fn dump_to_writer(
+    writer: impl AsyncWrite + Unpin, format: DumpFormat, mut rx: mpsc::Receiver<ConnDump>,
+) -> Result<()> {
+    let mut writer = BufWriter::new(writer);
+    let mut buf = Vec::new();
+
+    while let Some(mut dump) = rx.recv().await {
+        loop {
+            buf.clear();
+            dump.encode(format, &mut buf);
+            writer.write_all(&buf).await?;
+
+            match rx.try_recv() {
+                Ok(next) => dump = next,
+                Err(_) => break,
+            }
+        }
 
-    while let Some(dump) = rx.recv().await {
-        let line = serde_json::to_vec(&dump).unwrap();
-        writer.write_all(&line).await?;
-        writer.write_u8(b'\n').await?;
+        writer.flush().await?;
     }
 
     writer.flush().await?;
@@ -5,9 +5,10 @@ use futures::{future, future::poll_fn, FutureExt, Sink, SinkExt, Stream, StreamE
 use std::{
     collections::VecDeque,
     fmt, io, mem,
+    num::NonZeroU64,
     sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Arc, Mutex as StdMutex,
     },
     task::Poll,
     time::Duration,
@@ -17,13 +18,24 @@ use tokio::{
     sync::{mpsc, watch},
     time::{sleep_until, Instant},
 };
+use x25519_dalek::PublicKey;
 
 use crate::{
-    cfg::{Cfg, ExchangedCfg},
-    control::{Direction, DisconnectReason, Link, LinkIntervalStats, LinkStats, NotWorkingReason},
-    id::{ConnId, LinkId},
-    msg::LinkMsg,
+    agg::rtt::RttEstimator,
+    auth::AuthMac,
+    cc::{AckSample, CongestionController},
+    cfg::{Cfg, ExchangedCfg, Quota, RateLimit},
+    control::{
+        Direction, DisconnectReason, Link, LinkIntervalStats, LinkStats, NotWorkingReason, QuotaState,
+        QuotaStatus,
+    },
+    crypto::DataKeys,
+    id::{ConnId, EncryptedResumeTicket, LinkId},
+    msg::{LinkMsg, MsgBuf},
+    protocol_err,
+    sched::LinkState,
     seq::Seq,
+    shaper::TokenBucket,
 };
 
 /// Link event.
@@ -48,8 +60,14 @@ pub(crate) enum LinkIntEvent {
     FlushDelayPassed,
     /// Local disconnection request.
     Disconnect,
+    /// Local drain request.
+    Drain,
     /// Link blocked status has changed.
     BlockedChanged,
+    /// Link priority has changed.
+    PriorityChanged,
+    /// Link quota has been set.
+    QuotaSet,
 }
 
 /// Link test status.
@@ -86,12 +104,26 @@ pub(crate) struct LinkInt<TX, RX, TAG> {
     cfg: Arc<Cfg>,
     /// Configuration of remote endpoint.
     remote_cfg: Arc<ExchangedCfg>,
+    /// Protocol version agreed with the remote endpoint.
+    pub(crate) version: u8,
     /// Whether the Accepeted message needs to be sent.
     pub(crate) needs_tx_accepted: bool,
+    /// Encrypted resumption ticket sent to the remote endpoint when accepting the link.
+    pub(crate) resume_ticket: Option<EncryptedResumeTicket>,
+    /// Flag set once the resumption ticket has been sent to the remote endpoint.
+    pub(crate) resume_ticket_sent: Option<Arc<AtomicBool>>,
+    /// Authentication code sent to the remote endpoint when accepting the link.
+    pub(crate) auth_mac: Option<AuthMac>,
+    /// Encryption key sent to the remote endpoint when accepting the link.
+    pub(crate) encryption_key: Option<PublicKey>,
+    /// Keys for end-to-end encryption of data of the connection.
+    pub(crate) data_keys: Option<DataKeys>,
     /// Transmit sink.
     tx: TX,
     /// Data to transmit next.
     tx_data: Option<Bytes>,
+    /// Buffer for encoding messages.
+    tx_buf: MsgBuf,
     /// Last transmit error.
     tx_error: Option<io::Error>,
     /// Since when sink `tx` is being polled for readyness.
@@ -110,6 +142,9 @@ pub(crate) struct LinkInt<TX, RX, TAG> {
     tx_flushed: bool,
     /// Number of bytes sent for which no acknowledgement has been received yet.
     pub(crate) txed_unacked_data: usize,
+    /// Sequence numbers and sizes of copies of data packets sent over this link
+    /// and not yet acknowledged.
+    pub(crate) txed_unacked_copies: VecDeque<(Seq, usize)>,
     /// Limit of sent unacknowledged bytes.
     pub(crate) txed_unacked_data_limit: usize,
     /// Sequence number when limit of sent unacknowledged bytes was last increased.
@@ -130,6 +165,12 @@ pub(crate) struct LinkInt<TX, RX, TAG> {
     disconnect_tx: mpsc::Sender<()>,
     /// Graceful disconnect request receiver.
     disconnect_rx: mpsc::Receiver<()>,
+    /// Drain request sender.
+    drain_tx: mpsc::Sender<()>,
+    /// Drain request receiver.
+    drain_rx: mpsc::Receiver<()>,
+    /// Since when the link is being drained.
+    pub(crate) draining: Option<Instant>,
     /// Link blocked by user.
     pub(crate) blocked: Arc<AtomicBool>,
     /// Blocked status last sent to remote endpoint.
@@ -144,6 +185,32 @@ pub(crate) struct LinkInt<TX, RX, TAG> {
     blocked_changed_out_rx: watch::Receiver<()>,
     /// Link blocked by remote endpoint.
     pub(crate) remotely_blocked: Arc<AtomicBool>,
+    /// Local and remote blocked status last reported as event.
+    pub(crate) reported_blocked: (bool, bool),
+    /// Link priority set by user.
+    priority: Arc<AtomicU8>,
+    /// Link priority changed.
+    priority_changed_tx: mpsc::Sender<()>,
+    /// Link priority changed receiver.
+    priority_changed_rx: mpsc::Receiver<()>,
+    /// Byte quota set by user.
+    quota: Arc<StdMutex<Quota>>,
+    /// Link quota set.
+    quota_set_tx: mpsc::Sender<()>,
+    /// Link quota set receiver.
+    quota_set_rx: mpsc::Receiver<()>,
+    /// Quota state, considering the quota of the link and of its tag.
+    pub(crate) quota_state: QuotaState,
+    /// Channel for publishing quota status and state.
+    quota_tx: watch::Sender<(QuotaStatus, QuotaState)>,
+    /// Channel for publishing quota status and state.
+    quota_rx: watch::Receiver<(QuotaStatus, QuotaState)>,
+    /// Token bucket limiting the send rate.
+    shaper: Arc<StdMutex<TokenBucket>>,
+    /// Congestion controller; `None` if the adaptive unacked data limit is used.
+    pub(crate) cc: Option<Box<dyn CongestionController>>,
+    /// Pacing of sent data as requested by the congestion controller.
+    pacer: TokenBucket,
     /// Since when the link is unconfirmed, i.e. it has not been tested or message
     /// acknowledgement timed out.
     pub(crate) unconfirmed: Option<(Instant, NotWorkingReason)>,
@@ -153,8 +220,10 @@ pub(crate) struct LinkInt<TX, RX, TAG> {
     unconfirmed_rx: watch::Receiver<Option<(Instant, NotWorkingReason)>>,
     /// Link test status.
     pub(crate) test: LinkTest,
-    /// Last measured roundtrip duration.
-    pub(crate) roundtrip: Duration,
+    /// Roundtrip time estimator fed by acknowledgements and pings.
+    pub(crate) rtt: RttEstimator,
+    /// Roundtrip time estimator fed by pings only.
+    pub(crate) ping_rtt: RttEstimator,
     /// When last ping has been performed.
     pub(crate) last_ping: Option<Instant>,
     /// When current (not yet answered) ping has been sent.
@@ -206,17 +275,23 @@ where
     TX: Sink<Bytes, Error = io::Error> + Unpin,
 {
     /// Creates new internal link data.
+    ///
+    /// The configuration of the remote endpoint is restricted to the agreed protocol version.
     #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
-        tag: TAG, conn_id: ConnId, tx: TX, rx: RX, cfg: Arc<Cfg>, remote_cfg: ExchangedCfg, direction: Direction,
-        roundtrip: Duration, remote_user_data: Vec<u8>,
+        tag: TAG, conn_id: ConnId, tx: TX, rx: RX, cfg: Arc<Cfg>, version: u8, remote_cfg: ExchangedCfg,
+        direction: Direction, roundtrip: Duration, remote_user_data: Vec<u8>,
     ) -> Self {
         let (disconnected_tx, _) = watch::channel(DisconnectReason::TaskTerminated);
         let (disconnect_tx, disconnect_rx) = mpsc::channel(1);
+        let (drain_tx, drain_rx) = mpsc::channel(1);
         let (blocked_changed_tx, blocked_changed_rx) = mpsc::channel(2);
         let stats = LinkStatistican::new(&cfg.stats_intervals, roundtrip);
         let (unconfirmed_tx, unconfirmed_rx) = watch::channel(None);
         let (blocked_changed_out_tx, blocked_changed_out_rx) = watch::channel(());
+        let (priority_changed_tx, priority_changed_rx) = mpsc::channel(1);
+        let (quota_set_tx, quota_set_rx) = mpsc::channel(1);
+        let (quota_tx, quota_rx) = watch::channel((QuotaStatus::new(cfg.link_quota, 0), QuotaState::Within));
 
         Self {
             tag: Arc::new(tag),
@@ -225,13 +300,23 @@ where
             direction,
             tx,
             tx_data: None,
+            tx_buf: MsgBuf::default(),
             tx_error: None,
             rx,
-            remote_cfg: Arc::new(remote_cfg),
+            remote_cfg: Arc::new(remote_cfg.for_version(version)),
+            version,
             needs_tx_accepted: direction == Direction::Incoming,
+            resume_ticket: None,
+            resume_ticket_sent: None,
+            auth_mac: None,
+            encryption_key: None,
+            data_keys: None,
             disconnected_tx,
             disconnect_tx,
             disconnect_rx,
+            drain_tx,
+            drain_rx,
+            draining: None,
             stats,
             goodbye_sent: false,
             tx_polling: None,
@@ -242,6 +327,19 @@ where
             blocked_changed_out_tx,
             blocked_changed_out_rx,
             remotely_blocked: Arc::new(AtomicBool::new(false)),
+            reported_blocked: (false, false),
+            priority: Arc::new(AtomicU8::new(0)),
+            priority_changed_tx,
+            priority_changed_rx,
+            quota: Arc::new(StdMutex::new(cfg.link_quota)),
+            quota_set_tx,
+            quota_set_rx,
+            quota_state: QuotaState::Within,
+            quota_tx,
+            quota_rx,
+            shaper: Arc::new(StdMutex::new(TokenBucket::new(cfg.link_rate_limit))),
+            cc: None,
+            pacer: TokenBucket::new(None),
             unconfirmed: None,
             unconfirmed_tx,
             unconfirmed_rx,
@@ -255,9 +353,11 @@ where
             current_ping_sent: None,
             send_ping: false,
             send_pong: false,
-            roundtrip,
+            rtt: RttEstimator::new(roundtrip),
+            ping_rtt: RttEstimator::new(roundtrip),
             disconnecting: None,
             txed_unacked_data: 0,
+            txed_unacked_copies: VecDeque::new(),
             txed_unacked_data_limit: cfg.link_unacked_init.get(),
             txed_unacked_data_limit_increased: None,
             txed_unacked_data_limit_increased_consecutively: 45,
@@ -352,6 +452,12 @@ where
                             None => {
                                 let cursor = io::Cursor::new(buf);
                                 match LinkMsg::read(cursor) {
+                                    Ok(msg) if msg.min_version() > self.version => {
+                                        break LinkIntEvent::RxError(protocol_err!(
+                                            "received message not supported by protocol version {}",
+                                            self.version
+                                        ));
+                                    }
                                     Ok(msg) => {
                                         match (&msg, self.txed_unacked) {
                                             (LinkMsg::Ack { received }, Some(sent)) if *received >= sent => {
@@ -360,7 +466,7 @@ where
                                             _ => (),
                                         }
 
-                                        if let LinkMsg::Data { .. } = &msg {
+                                        if let LinkMsg::Data { .. } | LinkMsg::Datagram = &msg {
                                             self.rxed_data_msg = Some(msg);
                                         } else {
                                             break LinkIntEvent::Rx { msg, data: None };
@@ -395,14 +501,28 @@ where
             rx_event = rx_task => rx_event,
             () = flush_req_task => LinkIntEvent::FlushDelayPassed,
             Some(()) = self.disconnect_rx.recv() => LinkIntEvent::Disconnect,
+            Some(()) = self.drain_rx.recv() => LinkIntEvent::Drain,
             Some(()) = self.blocked_changed_rx.recv() => LinkIntEvent::BlockedChanged,
+            Some(()) = self.priority_changed_rx.recv() => LinkIntEvent::PriorityChanged,
+            Some(()) = self.quota_set_rx.recv() => LinkIntEvent::QuotaSet,
         }
     }
 
+    /// Checks that the message is supported by the protocol version used on the link.
+    fn check_version(&self, msg: &LinkMsg) -> Result<(), io::Error> {
+        if msg.min_version() > self.version {
+            return Err(protocol_err!("message not supported by protocol version {}", self.version));
+        }
+        Ok(())
+    }
+
     /// Waits for the link to become ready, sends a message and flushes it.
     pub(crate) async fn send_msg_and_flush(&mut self, msg: LinkMsg) -> Result<(), io::Error> {
         self.tx_polling = Some(Instant::now());
-        self.tx.send(msg.encode()).await?;
+        let msg = msg.for_version(self.version);
+        self.check_version(&msg)?;
+        let encoded = self.tx_buf.encode(&msg)?;
+        self.tx.send(encoded).await?;
         self.tx_flushed = true;
         Ok(())
     }
@@ -418,17 +538,27 @@ where
         self.tx_flushed = false;
         self.tx_idle_since = None;
 
-        let encoded = msg.encode();
-        let msg_len = encoded.len();
+        let msg = msg.for_version(self.version);
         let data_len = data.as_ref().map(|data| data.len()).unwrap_or_default();
+        let res = self.check_version(&msg).and_then(|()| self.tx_buf.encode(&msg)).and_then(|encoded| {
+            let msg_len = encoded.len();
+            self.tx.start_send_unpin(encoded).map(|()| msg_len)
+        });
 
-        if let Err(err) = self.tx.start_send_unpin(encoded) {
-            tracing::debug!("link send failure: {}", err);
-            self.tx_error = Some(err);
-            return;
-        }
+        let msg_len = match res {
+            Ok(msg_len) => msg_len,
+            Err(err) => {
+                tracing::debug!("link send failure: {}", err);
+                self.tx_error = Some(err);
+                return;
+            }
+        };
 
         self.stats.record(msg_len + data_len, 0);
+        if let LinkMsg::Data { .. } | LinkMsg::Datagram = &msg {
+            self.shaper.lock().unwrap().consume(msg_len + data_len);
+            self.pacer.consume(msg_len + data_len);
+        }
 
         self.tx_data = data;
         self.tx_last_msg = Some(Instant::now());
@@ -439,7 +569,7 @@ where
                 Some(txed_unacked) if txed_unacked > *seq => (),
                 _ => self.txed_unacked = Some(*seq),
             },
-            LinkMsg::Accepted
+            LinkMsg::Accepted { .. }
             | LinkMsg::Ping
             | LinkMsg::Pong
             | LinkMsg::SendFinish { .. }
@@ -498,13 +628,16 @@ where
             }
 
             let size = packet_size.min(data_limit - sent);
-            if let Err(err) = self.tx.start_send_unpin(LinkMsg::TestData { size }.encode()) {
+            let res = self.tx_buf.encode(&LinkMsg::TestData { size });
+            if let Err(err) = res.and_then(|encoded| self.tx.start_send_unpin(encoded)) {
                 self.tx_error = Some(err);
                 break;
             }
             sent += size;
         }
 
+        self.shaper.lock().unwrap().consume(sent);
+        self.pacer.consume(sent);
         sent
     }
 
@@ -516,13 +649,29 @@ where
 
     /// Marks the send part of the link as idle.
     pub(crate) fn mark_idle(&mut self) {
-        self.tx_idle_since = Some(Instant::now());
+        let now = Instant::now();
+        self.tx_idle_since = Some(now);
         self.stats.mark_idle();
+
+        // The link could send more data, thus it is limited by the application.
+        if self.is_sendable() {
+            if let Some(cc) = &mut self.cc {
+                cc.on_app_limited(now);
+            }
+        }
     }
 
-    /// Returns whether unacknowledged sent data is under the limit.
+    /// Returns whether unacknowledged sent data is under the limit and sending of data
+    /// is not delayed by rate limiting or pacing.
     pub(crate) fn is_sendable(&self) -> bool {
-        self.txed_unacked_data < self.txed_unacked_data_limit
+        self.txed_unacked_data < self.txed_unacked_data_limit && self.tx_shaped_until().is_none()
+    }
+
+    /// Time until which sending of data is delayed by rate limiting or pacing.
+    ///
+    /// Control messages are sent regardless.
+    pub(crate) fn tx_shaped_until(&self) -> Option<Instant> {
+        self.shaper.lock().unwrap().ready_at().max(self.pacer.ready_at())
     }
 
     /// Since when transmitter is being polled for readyness.
@@ -539,20 +688,137 @@ where
         self.txed_unacked_data_limit = self.txed_unacked_data_limit.clamp(128, self.cfg.link_unacked_init.get());
         self.txed_unacked_data_limit_increased = None;
         self.txed_unacked_data_limit_increased_consecutively = 0;
+
+        if let Some(cc) = &mut self.cc {
+            cc.on_timeout(Instant::now());
+            self.apply_cc();
+        }
+    }
+
+    /// Notifies the congestion controller of sent data.
+    pub(crate) fn cc_sent(&mut self, size: usize) {
+        if let Some(cc) = &mut self.cc {
+            cc.on_sent(Instant::now(), size, self.txed_unacked_data);
+            self.apply_cc();
+        }
+    }
+
+    /// Notifies the congestion controller of acknowledged data.
+    pub(crate) fn cc_acked(&mut self, size: usize, rtt: Option<Duration>) {
+        if let Some(cc) = &mut self.cc {
+            cc.on_ack(&AckSample { now: Instant::now(), size, rtt, unacked: self.txed_unacked_data });
+            self.apply_cc();
+        }
+    }
+
+    /// Applies the congestion window and pacing rate of the congestion controller.
+    pub(crate) fn apply_cc(&mut self) {
+        let Some(cc) = &self.cc else { return };
+
+        self.txed_unacked_data_limit = cc.window().clamp(128, self.cfg.link_unacked_limit.get());
+
+        // Allow bursts of a few packets or of the data of a few milliseconds.
+        let pacing = cc.pacing_rate().and_then(NonZeroU64::new).map(|rate| RateLimit {
+            rate,
+            burst: (rate.get() / 200).max(4 * self.cfg.io_write_size.get() as u64),
+        });
+        if pacing != self.pacer.limit() {
+            self.pacer.set_limit(pacing);
+        }
     }
 
     /// Whether link is blocked locally or remotely.
     pub(crate) fn is_blocked(&self) -> bool {
-        self.blocked.load(Ordering::SeqCst) || self.remotely_blocked.load(Ordering::SeqCst)
+        self.is_locally_blocked() || self.remotely_blocked.load(Ordering::SeqCst)
+    }
+
+    /// Whether link is blocked locally by the user or because its quota is exceeded.
+    pub(crate) fn is_locally_blocked(&self) -> bool {
+        self.blocked.load(Ordering::SeqCst) || self.quota_state == QuotaState::HardExceeded
+    }
+
+    /// Bytes sent and received over the link.
+    pub(crate) fn quota_used(&self) -> u64 {
+        self.stats.current.total_sent.wrapping_add(self.stats.current.total_recved)
+    }
+
+    /// Byte quota of the link.
+    pub(crate) fn quota(&self) -> Quota {
+        *self.quota.lock().unwrap()
+    }
+
+    /// Status of the byte quota of the link.
+    pub(crate) fn quota_status(&self) -> QuotaStatus {
+        QuotaStatus::new(self.quota(), self.quota_used())
+    }
+
+    /// Publishes the quota status and state.
+    pub(crate) fn publish_quota(&self) {
+        self.quota_tx.send_replace((self.quota_status(), self.quota_state));
+    }
+
+    /// Whether the link is confirmed, not blocked and not being drained or disconnected.
+    pub(crate) fn is_usable(&self) -> bool {
+        self.unconfirmed.is_none() && !self.is_closing() && !self.is_blocked()
+    }
+
+    /// Whether the link is being drained or disconnected.
+    pub(crate) fn is_closing(&self) -> bool {
+        self.draining.is_some() || self.disconnecting.is_some()
+    }
+
+    /// Whether the link may carry data given the priority of links carrying data.
+    pub(crate) fn carries_data(&self, data_priority: Option<u8>) -> bool {
+        data_priority.map(|priority| self.priority() == priority).unwrap_or(true)
+    }
+
+    /// Priority of the link; lower values denote higher priority.
+    pub(crate) fn priority(&self) -> u8 {
+        self.priority.load(Ordering::SeqCst)
+    }
+
+    /// Smoothed roundtrip time.
+    pub(crate) fn roundtrip(&self) -> Duration {
+        self.rtt.smoothed()
+    }
+
+    /// Smoothed roundtrip time of pings, which is checked against the maximum ping.
+    ///
+    /// Unlike [`roundtrip`](Self::roundtrip) it does not follow the queueing delay of
+    /// individual data packets.
+    pub(crate) fn ping(&self) -> Duration {
+        self.ping_rtt.smoothed()
+    }
+
+    /// State of the link for the link scheduler.
+    pub(crate) fn sched_state(&self, ready: bool) -> LinkState<'_, TAG> {
+        LinkState {
+            id: self.link_id,
+            tag: &self.tag,
+            ready,
+            idle_since: self.tx_idle_since.or_else(|| ready.then(Instant::now)),
+            unconfirmed: self.unconfirmed.is_some(),
+            blocked: self.is_blocked(),
+            priority: self.priority(),
+            quota_state: self.quota_state,
+            roundtrip: self.roundtrip(),
+            unacked: self.txed_unacked_data,
+            unacked_limit: self.txed_unacked_data_limit,
+            throughput: self.stats.current.time_stats.first().map(|ts| ts.send_speed()).unwrap_or_default(),
+        }
     }
 
     /// Publishes link statistics.
     pub(crate) fn publish_stats(&mut self) {
         self.stats.current.sent_unacked = self.txed_unacked_data as _;
         self.stats.current.unacked_limit = self.txed_unacked_data_limit as _;
-        self.stats.current.roundtrip = self.roundtrip;
+        self.stats.current.roundtrip = self.rtt.smoothed();
+        self.stats.current.min_roundtrip = self.rtt.min();
+        self.stats.current.roundtrip_var = self.rtt.var();
+        self.stats.current.jitter = self.rtt.jitter();
 
         self.stats.publish();
+        self.publish_quota();
     }
 }
 
@@ -562,10 +828,12 @@ impl<TX, RX, TAG> From<&LinkInt<TX, RX, TAG>> for Link<TAG> {
             conn_id: link_int.conn_id,
             link_id: link_int.link_id,
             direction: link_int.direction,
+            version: link_int.version,
             tag: link_int.tag.clone(),
             cfg: link_int.cfg.clone(),
             disconnected_rx: link_int.disconnected_tx.subscribe(),
             disconnect_tx: link_int.disconnect_tx.clone(),
+            drain_tx: link_int.drain_tx.clone(),
             stats_rx: link_int.stats.subscribe(),
             remote_user_data: link_int.remote_user_data.clone(),
             blocked: link_int.blocked.clone(),
@@ -573,6 +841,12 @@ impl<TX, RX, TAG> From<&LinkInt<TX, RX, TAG>> for Link<TAG> {
             blocked_changed_rx: link_int.blocked_changed_out_rx.clone(),
             not_working_rx: link_int.unconfirmed_rx.clone(),
             remotely_blocked: link_int.remotely_blocked.clone(),
+            priority: link_int.priority.clone(),
+            priority_changed_tx: link_int.priority_changed_tx.clone(),
+            quota: link_int.quota.clone(),
+            quota_set_tx: link_int.quota_set_tx.clone(),
+            quota_rx: link_int.quota_rx.clone(),
+            shaper: link_int.shaper.clone(),
         }
     }
 }
@@ -599,6 +873,9 @@ impl LinkStatistican {
             sent_unacked: 0,
             unacked_limit: 0,
             roundtrip,
+            min_roundtrip: roundtrip,
+            roundtrip_var: roundtrip / 2,
+            jitter: Duration::ZERO,
             hangs: 0,
             time_stats: running_stats.clone(),
         };
@@ -650,23 +927,30 @@ impl LinkStatistican {
 }
 
 #[cfg(feature = "dump")]
-impl<TX, RX, TAG> From<&LinkInt<TX, RX, TAG>> for super::dump::LinkDump {
-    fn from(link: &LinkInt<TX, RX, TAG>) -> Self {
-        Self {
-            present: true,
-            link_id: link.link_id.0,
-            unconfirmed: link.unconfirmed.is_some(),
-            tx_flushing: link.tx_flushing,
-            tx_flushed: link.tx_flushed,
-            roundtrip: link.roundtrip.as_secs_f32(),
-            tx_ack_queue: link.tx_ack_queue.len(),
-            txed_unacked_data: link.txed_unacked_data,
-            txed_unacked_data_limit: link.txed_unacked_data_limit,
-            txed_unacked_data_limit_increased_consecutively: link.txed_unacked_data_limit_increased_consecutively,
-            tx_idle: link.tx_idle_since.is_some(),
-            tx_pending: link.tx_pending,
-            total_sent: link.stats.current.total_sent,
-            total_recved: link.stats.current.total_recved,
+impl<TX, RX, TAG> LinkInt<TX, RX, TAG> {
+    /// Dump data for analysis using the specified function to format the link tag.
+    pub(crate) fn dump(&self, fmt_tag: fn(&TAG) -> String) -> super::dump::LinkDump {
+        super::dump::LinkDump {
+            link_id: self.link_id.0,
+            tag: fmt_tag(&self.tag),
+            unconfirmed: self.unconfirmed.is_some(),
+            blocked: self.blocked.load(Ordering::SeqCst)
+                || self.quota_state == QuotaState::HardExceeded
+                || self.remotely_blocked.load(Ordering::SeqCst),
+            tx_flushing: self.tx_flushing,
+            tx_flushed: self.tx_flushed,
+            tx_ack_queue: self.tx_ack_queue.len(),
+            txed_unacked_data: self.txed_unacked_data,
+            txed_unacked_data_limit: self.txed_unacked_data_limit,
+            txed_unacked_data_limit_increased_consecutively: self.txed_unacked_data_limit_increased_consecutively,
+            tx_idle: self.tx_idle_since.is_some(),
+            tx_pending: self.tx_pending,
+            total_sent: self.stats.current.total_sent,
+            total_recved: self.stats.current.total_recved,
+            roundtrip: self.rtt.smoothed().as_secs_f32(),
+            min_roundtrip: self.rtt.min().as_secs_f32(),
+            roundtrip_var: self.rtt.var().as_secs_f32(),
+            jitter: self.rtt.jitter().as_secs_f32(),
         }
     }
 }
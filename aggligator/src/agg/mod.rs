@@ -4,7 +4,7 @@ use bytes::Bytes;
 use futures::{Sink, Stream};
 use std::{
     io,
-    sync::{atomic::AtomicBool, Arc},
+    sync::{atomic::AtomicBool, Arc, Mutex as StdMutex},
 };
 use tokio::sync::{mpsc, oneshot, watch, Mutex};
 
@@ -12,8 +12,12 @@ use crate::{
     agg::{link_int::LinkInt, task::Task},
     alc::{Channel, RecvError, SendError},
     cfg::{Cfg, ExchangedCfg},
-    control::{Control, Direction, Link},
+    connect::Refusal,
+    control::{Control, Direction, EventTxs, Link, Metadata},
+    crypto::ClientKeyExchange,
+    ext::Extensions,
     id::{OwnedConnId, ServerId},
+    shaper::TokenBucket,
     TaskError,
 };
 
@@ -21,6 +25,7 @@ use crate::{
 #[cfg_attr(docsrs, doc(cfg(feature = "dump")))]
 pub mod dump;
 pub(crate) mod link_int;
+pub(crate) mod rtt;
 pub(crate) mod task;
 
 /// Link aggregator parts.
@@ -29,6 +34,7 @@ pub(crate) struct AggParts<TX, RX, TAG> {
     pub channel: Channel,
     pub control: Control<TX, RX, TAG>,
     pub connected_rx: oneshot::Receiver<Arc<ExchangedCfg>>,
+    pub refused_rx: watch::Receiver<Option<Refusal>>,
 }
 
 impl<TX, RX, TAG> AggParts<TX, RX, TAG>
@@ -38,25 +44,37 @@ where
     TAG: Send + Sync + 'static,
 {
     /// Creates a new aggregated connection and returns its parts.
-    #[allow(clippy::type_complexity)]
+    #[allow(clippy::type_complexity, clippy::too_many_arguments)]
     pub(crate) fn new(
         cfg: Arc<Cfg>, conn_id: OwnedConnId, direction: Direction, server_id: Option<ServerId>,
         remote_server_id: Option<ServerId>, links: Vec<LinkInt<TX, RX, TAG>>,
         link_tx_rx: Option<(mpsc::Sender<LinkInt<TX, RX, TAG>>, mpsc::Receiver<LinkInt<TX, RX, TAG>>)>,
+        metadata: Metadata,
     ) -> Self {
         let (read_tx, read_rx) = mpsc::channel(cfg.recv_queue.get());
         let (write_tx, write_rx) = mpsc::channel(cfg.send_queue.get());
+        let (datagram_read_tx, datagram_read_rx) = mpsc::channel(cfg.datagram_queue.get());
+        let (datagram_write_tx, datagram_write_rx) = mpsc::channel(cfg.datagram_queue.get());
         let (read_error_tx, read_error_rx) = watch::channel(Some(RecvError::TaskTerminated));
         let (write_error_tx, write_error_rx) = watch::channel(SendError::TaskTerminated);
         let (read_closed_tx, read_closed_rx) = mpsc::channel(1);
         let (links_tx, links_rx) = watch::channel(links.iter().map(Link::from).collect());
         let (link_tx, link_rx) = link_tx_rx.unwrap_or_else(|| mpsc::channel(cfg.connect_queue.get()));
         let (connected_tx, connected_rx) = oneshot::channel();
+        let (refused_tx, refused_rx) = watch::channel(None);
         let (stats_tx, stats_rx) = watch::channel(Default::default());
         let (server_changed_tx, server_changed_rx) = mpsc::channel(1);
         let (result_tx, result_rx) = watch::channel(Err(TaskError::Terminated));
+        let (quota_changed_tx, quota_changed_rx) = watch::channel(());
+        let (quota_set_tx, quota_set_rx) = mpsc::channel(1);
+        let tag_quotas = Arc::new(StdMutex::new(Vec::new()));
+        let event_txs = EventTxs::new(cfg.event_queue.get());
         let remote_cfg = links.first().as_ref().map(|link| link.remote_cfg());
+        let extensions = remote_cfg.as_ref().map(|remote_cfg| Extensions::agreed(remote_cfg.extensions));
         let connected = Arc::new(AtomicBool::new(!links.is_empty()));
+        let send_shaper = Arc::new(StdMutex::new(TokenBucket::new(cfg.send_rate_limit)));
+        let key_exchange =
+            (direction == Direction::Outgoing && cfg.data_encryption).then(|| Arc::new(ClientKeyExchange::new()));
 
         Self {
             task: Task::new(
@@ -70,11 +88,17 @@ where
                 read_tx,
                 read_closed_rx,
                 write_rx,
+                datagram_read_tx,
+                datagram_write_rx,
                 read_error_tx,
                 write_error_tx,
                 stats_tx,
                 server_changed_rx,
                 result_tx,
+                tag_quotas.clone(),
+                quota_changed_tx,
+                quota_set_rx,
+                event_txs.clone(),
                 links,
             ),
             channel: Channel::new(
@@ -86,6 +110,8 @@ where
                 read_rx,
                 read_closed_tx,
                 read_error_rx,
+                send_shaper.clone(),
+                (datagram_write_tx, datagram_read_rx),
             ),
             control: Control {
                 cfg,
@@ -99,8 +125,19 @@ where
                 stats_rx,
                 server_changed_tx,
                 result_rx,
+                tag_quotas,
+                quota_changed_rx,
+                quota_set_tx,
+                send_shaper,
+                resume_ticket: Arc::new(StdMutex::new(None)),
+                key_exchange,
+                extensions: Arc::new(StdMutex::new(extensions)),
+                refused_tx: Arc::new(refused_tx),
+                metadata: Arc::new(StdMutex::new(metadata)),
+                event_txs,
             },
             connected_rx,
+            refused_rx,
         }
     }
 }
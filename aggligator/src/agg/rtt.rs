@@ -0,0 +1,81 @@
+//! Roundtrip time estimation.
+
+use std::time::Duration;
+
+/// Roundtrip time estimator of a link.
+///
+/// The smoothed roundtrip time and its variance are tracked as specified in RFC 6298.
+/// Jitter is the smoothed difference between consecutive samples as specified in RFC 3550.
+#[derive(Debug, Clone)]
+pub(crate) struct RttEstimator {
+    /// Lowest observed roundtrip time.
+    min: Duration,
+    /// Smoothed roundtrip time (SRTT).
+    smoothed: Duration,
+    /// Roundtrip time variation (RTTVAR).
+    var: Duration,
+    /// Smoothed difference between consecutive samples.
+    jitter: Duration,
+    /// Last sample.
+    last: Duration,
+}
+
+impl RttEstimator {
+    /// Creates a new estimator from the first measured roundtrip time.
+    pub fn new(first: Duration) -> Self {
+        Self { min: first, smoothed: first, var: first / 2, jitter: Duration::ZERO, last: first }
+    }
+
+    /// Updates the estimate with a new sample.
+    pub fn sample(&mut self, rtt: Duration) {
+        let delta = abs_diff(self.smoothed, rtt);
+        self.var = (3 * self.var + delta) / 4;
+        self.smoothed = (7 * self.smoothed + rtt) / 8;
+
+        let diff = abs_diff(self.last, rtt);
+        self.jitter = (15 * self.jitter + diff) / 16;
+
+        self.min = self.min.min(rtt);
+        self.last = rtt;
+    }
+
+    /// Most recent sample.
+    pub fn latest(&self) -> Duration {
+        self.last
+    }
+
+    /// Lowest observed roundtrip time.
+    pub fn min(&self) -> Duration {
+        self.min
+    }
+
+    /// Smoothed roundtrip time.
+    pub fn smoothed(&self) -> Duration {
+        self.smoothed
+    }
+
+    /// Roundtrip time variation.
+    pub fn var(&self) -> Duration {
+        self.var
+    }
+
+    /// Jitter of the roundtrip time.
+    pub fn jitter(&self) -> Duration {
+        self.jitter
+    }
+
+    /// Acknowledgement timeout given by the smoothed roundtrip time times `factor`
+    /// plus four times the variation.
+    pub fn ack_timeout(&self, factor: u32) -> Duration {
+        self.smoothed * factor + 4 * self.var
+    }
+}
+
+/// Absolute difference of two durations.
+fn abs_diff(a: Duration, b: Duration) -> Duration {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
@@ -12,10 +12,11 @@ use std::{
     error::Error,
     fmt,
     future::IntoFuture,
-    io,
+    io, mem,
+    num::NonZeroUsize,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, Mutex as StdMutex,
     },
     time::Duration,
 };
@@ -29,12 +30,19 @@ use tokio_stream::wrappers::IntervalStream;
 use crate::{
     agg::link_int::{DisconnectInitiator, LinkInt, LinkIntEvent, LinkTest},
     alc::{RecvError, SendError},
-    cfg::{Cfg, ExchangedCfg, LinkPing},
-    control::{Direction, DisconnectReason, Link, NotWorkingReason, Stats},
+    cc::{Bbr, CongestionController},
+    cfg::{BondingMode, Cfg, CongestionControl, ExchangedCfg, LinkPing},
+    compress::{self, Compressor},
+    control::{
+        Direction, DisconnectReason, EventKind, EventTxs, Link, NotWorkingReason, QuotaState, QuotaStatus, Stats,
+        TagQuota,
+    },
+    crypto::{self, DataCipher},
     id::{ConnId, LinkId, OwnedConnId},
     msg::{LinkMsg, RefusedReason, ReliableMsg},
     peekable_mpsc::{PeekableReceiver, RecvIfError},
     protocol_err,
+    sched::{FirstReady, LinkScheduler, LinkState},
     seq::Seq,
 };
 
@@ -83,8 +91,8 @@ impl From<TaskError> for std::io::Error {
 /// A send request to the link aggregator task.
 #[derive(Debug)]
 pub(crate) enum SendReq {
-    /// Send data.
-    Send(Bytes),
+    /// Send data over the specified number of links.
+    Send(Bytes, NonZeroUsize),
     /// Flush.
     Flush(oneshot::Sender<()>),
 }
@@ -151,22 +159,30 @@ struct ReceivedReliableMsg {
     seq: Seq,
     /// Message.
     msg: ReliableMsg,
+    /// Size of the data as received, used for flow control.
+    size: usize,
 }
 
 /// Link aggregator task event.
 enum TaskEvent<TX, RX, TAG> {
     /// A new link has been established.
-    NewLink(LinkInt<TX, RX, TAG>),
+    NewLink(Box<LinkInt<TX, RX, TAG>>),
     /// No new links will be established.
     NoNewLinks,
     /// A link event occurred.
     LinkEvent { id: usize, event: LinkIntEvent },
     /// Data to send over an idle link has been received.
-    WriteRx { id: usize, data: Bytes },
+    WriteRx { id: usize, data: Bytes, redundancy: NonZeroUsize },
+    /// Data to send has been queued and requires link scheduling.
+    WriteQueued,
     /// No more data to send will be received.
     WriteEnd,
     /// Flush.
     Flush(oneshot::Sender<()>),
+    /// Datagram to send has been received.
+    DatagramRx(Bytes),
+    /// No more datagrams to send will be received.
+    DatagramEnd,
     /// Confirmation of sent packet over specified link timed out.
     ConfirmTimedOut(usize),
     /// Resend packet over an idle link.
@@ -185,6 +201,8 @@ enum TaskEvent<TX, RX, TAG> {
     LinkUnconfirmedTimeout(usize),
     /// Sending over link timed out.
     LinkSendTimeout(usize),
+    /// Draining of link timed out.
+    LinkDrainTimeout(usize),
     /// Timeout waiting for ping reply over link.
     LinkPingTimeout(usize),
     /// A link requires testing.
@@ -193,15 +211,26 @@ enum TaskEvent<TX, RX, TAG> {
     NoLinksTimeout,
     /// Publish link statistics.
     PublishLinkStats,
+    /// Rate limiting or pacing of an idle link has ended.
+    LinkUnshaped,
     /// A refused link task completed.
     RefusedLinkTask,
     /// The server id changed.
     ServerChanged,
+    /// A tag quota has been set or removed.
+    QuotaSet,
 }
 
 /// Link filter function type.
 type LinkFilterFn<TAG> = Box<dyn FnMut(Link<TAG>, Vec<Link<TAG>>) -> BoxFuture<'static, bool> + Send>;
 
+/// Analysis data sender and link tag formatter.
+#[cfg(feature = "dump")]
+type DumpTx<TAG> = (mpsc::Sender<super::dump::ConnDump>, fn(&TAG) -> String);
+
+/// Congestion controller function type.
+type CongestionControllerFn<TAG> = Box<dyn FnMut(&Link<TAG>) -> Option<Box<dyn CongestionController>> + Send>;
+
 /// Task managing a connection of aggregated links.
 ///
 /// This manages a connection of aggregated links and must be executed
@@ -248,6 +277,18 @@ pub struct Task<TX, RX, TAG> {
     read_error_tx: watch::Sender<Option<RecvError>>,
     /// Error for writing.
     write_error_tx: watch::Sender<SendError>,
+    /// Channel for forwarding received datagrams to user.
+    datagram_tx: Option<mpsc::Sender<Bytes>>,
+    /// Channel for receiving datagrams to send from user.
+    datagram_rx: Option<mpsc::Receiver<Bytes>>,
+    /// Number of datagrams sent.
+    txed_datagrams: u64,
+    /// Number of datagrams dropped because no link was ready for sending.
+    txed_datagrams_dropped: u64,
+    /// Number of datagrams received.
+    rxed_datagrams: u64,
+    /// Number of received datagrams dropped because the receive queue was full.
+    rxed_datagrams_dropped: u64,
     /// Next data sequence number for sending.
     tx_seq: Seq,
     /// Send overrun handling.
@@ -262,6 +303,14 @@ pub struct Task<TX, RX, TAG> {
     txed_unconsumed: usize,
     /// Size of data received by remote endpoint that cannot yet be consumed.
     txed_unconsumable: usize,
+    /// Number of redundant copies of data packets sent.
+    txed_redundant: u64,
+    /// Size of redundant copies of data packets sent.
+    txed_redundant_bytes: u64,
+    /// Number of data packets sent over fewer links than requested by their redundancy.
+    txed_redundancy_shortfall: u64,
+    /// Copies of data packets queued for sending over the specified links.
+    tx_copy_queue: VecDeque<(usize, Seq, Bytes)>,
     /// Sequence number of last packet consumed by the remote endpoint.
     txed_last_consumed: Seq,
     /// Queue of packets that have been declared lost and must be send again.
@@ -274,8 +323,26 @@ pub struct Task<TX, RX, TAG> {
     rxed_reliable: VecDeque<Option<ReceivedReliableMsg>>,
     /// Received data message parts, ready for consumption.
     rxed_reliable_consumable: VecDeque<ReceivedReliableMsg>,
+    /// Cipher for end-to-end encryption of sent data.
+    tx_cipher: Option<DataCipher>,
+    /// Cipher for end-to-end decryption of received data.
+    rx_cipher: Option<DataCipher>,
+    /// Compressor of sent data.
+    compressor: Option<Compressor>,
+    /// Size of sent data before compression.
+    txed_uncompressed_bytes: u64,
+    /// Size of sent data after compression.
+    txed_compressed_bytes: u64,
+    /// Size of received data before decompression.
+    rxed_compressed_bytes: u64,
+    /// Size of received data after decompression.
+    rxed_uncompressed_bytes: u64,
     /// Sum of size of all buffers in `rxed_reliable` and `rxed_reliable_consumable`.
     rxed_reliable_size: usize,
+    /// Number of duplicate data packets received.
+    rxed_duplicates: u64,
+    /// Size of duplicate data packets received.
+    rxed_duplicate_bytes: u64,
     /// Size of that that has been consumed since last acknowledgement.
     rxed_reliable_consumed_since_last_ack: usize,
     /// Forces acking consumed data.
@@ -296,6 +363,19 @@ pub struct Task<TX, RX, TAG> {
     stats_last_sent: Instant,
     /// Filter function for new links.
     link_filter: LinkFilterFn<TAG>,
+    /// Scheduler selecting the link for sending data.
+    link_scheduler: Box<dyn LinkScheduler<TAG>>,
+    /// Creates the congestion controller of a new link.
+    congestion_controller: CongestionControllerFn<TAG>,
+    /// Whether the link scheduler deferred sending of data to a link that is not ready.
+    tx_deferred: bool,
+    /// Buffer for the ids of links provided to the link scheduler.
+    sched_ids: Vec<usize>,
+    /// Buffer for the states of links provided to the link scheduler.
+    ///
+    /// It is empty between uses and only keeps the allocation,
+    /// thus its lifetime and tag type are irrelevant.
+    sched_states: Vec<LinkState<'static, ()>>,
     /// Links provided at creation of this task.
     init_links: VecDeque<LinkInt<TX, RX, TAG>>,
     /// Tasks handling refused links.
@@ -304,9 +384,23 @@ pub struct Task<TX, RX, TAG> {
     server_changed_rx: mpsc::Receiver<()>,
     /// Result of task sender.
     result_tx: watch::Sender<Result<(), TaskError>>,
+    /// Byte quotas of link tags.
+    tag_quotas: Arc<StdMutex<Vec<TagQuota<TAG>>>>,
+    /// Quota state changed notification.
+    quota_changed_tx: watch::Sender<()>,
+    /// Tag quota set notification.
+    quota_set_rx: mpsc::Receiver<()>,
+    /// Whether quota states must be recomputed.
+    quotas_dirty: bool,
+    /// Total bytes used by all links when quota states were last computed.
+    quotas_used: u64,
+    /// Bytes that can be used by all links together before any quota threshold can be reached.
+    quotas_remaining: u64,
+    /// Connection event subscribers.
+    event_txs: EventTxs<TAG>,
     /// Channel for sending analysis data.
     #[cfg(feature = "dump")]
-    dump_tx: Option<mpsc::Sender<super::dump::ConnDump>>,
+    dump_tx: Option<DumpTx<TAG>>,
 }
 
 impl<TX, RX, TAG> fmt::Debug for Task<TX, RX, TAG> {
@@ -326,11 +420,22 @@ where
         cfg: Arc<Cfg>, remote_cfg: Option<Arc<ExchangedCfg>>, conn_id: OwnedConnId, direction: Direction,
         links_tx: watch::Sender<Vec<Link<TAG>>>, link_rx: mpsc::Receiver<LinkInt<TX, RX, TAG>>,
         connected_tx: oneshot::Sender<Arc<ExchangedCfg>>, read_tx: mpsc::Sender<Bytes>,
-        read_closed_rx: mpsc::Receiver<()>, write_rx: mpsc::Receiver<SendReq>,
-        read_error_tx: watch::Sender<Option<RecvError>>, write_error_tx: watch::Sender<SendError>,
-        stats_tx: watch::Sender<Stats>, server_changed_rx: mpsc::Receiver<()>,
-        result_tx: watch::Sender<Result<(), TaskError>>, links: Vec<LinkInt<TX, RX, TAG>>,
+        read_closed_rx: mpsc::Receiver<()>, write_rx: mpsc::Receiver<SendReq>, datagram_tx: mpsc::Sender<Bytes>,
+        datagram_rx: mpsc::Receiver<Bytes>, read_error_tx: watch::Sender<Option<RecvError>>,
+        write_error_tx: watch::Sender<SendError>, stats_tx: watch::Sender<Stats>,
+        server_changed_rx: mpsc::Receiver<()>, result_tx: watch::Sender<Result<(), TaskError>>,
+        tag_quotas: Arc<StdMutex<Vec<TagQuota<TAG>>>>, quota_changed_tx: watch::Sender<()>,
+        quota_set_rx: mpsc::Receiver<()>, event_txs: EventTxs<TAG>, links: Vec<LinkInt<TX, RX, TAG>>,
     ) -> Self {
+        let compressor =
+            remote_cfg.as_ref().and_then(|remote_cfg| Compressor::new(cfg.compression, remote_cfg.extensions));
+        let congestion_controller: CongestionControllerFn<TAG> = match cfg.congestion_control {
+            CongestionControl::Adaptive => Box::new(|_| None),
+            CongestionControl::Bbr => {
+                let (init_window, min_window) = (cfg.link_unacked_init.get(), 4 * cfg.io_write_size.get());
+                Box::new(move |_| Some(Box::new(Bbr::new(init_window, min_window))))
+            }
+        };
         Self {
             cfg,
             remote_cfg,
@@ -350,6 +455,12 @@ where
             send_finish_sent: false,
             read_error_tx,
             write_error_tx,
+            datagram_tx: Some(datagram_tx),
+            datagram_rx: Some(datagram_rx),
+            txed_datagrams: 0,
+            txed_datagrams_dropped: 0,
+            rxed_datagrams: 0,
+            rxed_datagrams_dropped: 0,
             tx_seq: Seq::ZERO,
             tx_overrun: SendOverrun::Armed,
             tx_overrun_since: None,
@@ -360,11 +471,24 @@ where
             rx_seq: Seq::ZERO,
             rxed_reliable: VecDeque::new(),
             rxed_reliable_consumable: VecDeque::new(),
+            tx_cipher: None,
+            rx_cipher: None,
+            compressor,
+            txed_uncompressed_bytes: 0,
+            txed_compressed_bytes: 0,
+            rxed_compressed_bytes: 0,
+            rxed_uncompressed_bytes: 0,
             rxed_reliable_consumed_since_last_ack: 0,
             txed_unconsumed: 0,
             txed_unconsumable: 0,
+            txed_redundant: 0,
+            txed_redundant_bytes: 0,
+            txed_redundancy_shortfall: 0,
+            tx_copy_queue: VecDeque::new(),
             txed_last_consumed: Seq::MINUS_ONE,
             rxed_reliable_size: 0,
+            rxed_duplicates: 0,
+            rxed_duplicate_bytes: 0,
             rxed_reliable_consumed_force_ack: false,
             unflushed_links: HashSet::new(),
             flushed_tx: None,
@@ -374,10 +498,22 @@ where
             stats_tx,
             stats_last_sent: Instant::now(),
             link_filter: Box::new(|_, _| async { true }.boxed()),
+            link_scheduler: Box::new(FirstReady),
+            congestion_controller,
+            tx_deferred: false,
+            sched_ids: Vec::new(),
+            sched_states: Vec::new(),
             init_links: links.into(),
             refused_links_tasks: FuturesUnordered::new(),
             server_changed_rx,
             result_tx,
+            tag_quotas,
+            quota_changed_tx,
+            quota_set_rx,
+            quotas_dirty: true,
+            quotas_used: 0,
+            quotas_remaining: 0,
+            event_txs,
             #[cfg(feature = "dump")]
             dump_tx: None,
         }
@@ -457,6 +593,7 @@ where
                     tracing::debug!("sending connection established notification");
                     let _ = connected_tx.send(self.remote_cfg.clone().unwrap());
                     self.established = Some(Instant::now());
+                    self.event_txs.emit(|| EventKind::Established);
                 }
             }
 
@@ -468,12 +605,26 @@ where
                 }
             }
 
+            // Update quota states of links, if a threshold may have been reached.
+            let quotas_used = self.quotas_used();
+            if self.quotas_dirty || quotas_used.wrapping_sub(self.quotas_used) >= self.quotas_remaining {
+                self.update_quotas();
+            }
+
             // Adjust link transmit buffer limits.
             self.adjust_link_tx_limits();
 
+            // Schedule link for sending queued data.
+            let tx_data_size = self.tx_data_size();
+            let scheduled_link_id = match tx_data_size {
+                Some(size) if tx_seq_avail && !resending && size <= tx_space => self.schedule_link(size, None),
+                _ => None,
+            };
+            let data_priority = self.data_priority();
+
             // Timeout for no working links.
             let no_link_since = self.links_not_working_since();
-            let no_link_timeout = self.cfg.no_link_timeout;
+            let no_link_timeout = self.cfg.resume_window.unwrap_or(self.cfg.no_link_timeout);
             let links_timeout = async move {
                 match no_link_since {
                     Some(since) => sleep_until(since + no_link_timeout).await,
@@ -507,6 +658,23 @@ where
             let next_send_timeout =
                 self.earliest_link_specific_timeout(self.cfg.link_ping_timeout, |link| link.tx_polling());
 
+            // Timeout for disconnecting a link that is being drained.
+            let next_drain_timeout =
+                self.earliest_link_specific_timeout(self.cfg.link_drain_timeout, |link| link.draining);
+
+            // Timeout for end of rate limiting or pacing of an idle link.
+            let next_unshaped = self
+                .idle_links
+                .iter()
+                .filter_map(|&id| self.links[id].as_ref().and_then(|link| link.tx_shaped_until()))
+                .min();
+            let unshaped_timeout = async move {
+                match next_unshaped {
+                    Some(timeout) => sleep_until(timeout).await,
+                    None => future::pending().await,
+                }
+            };
+
             // Timeout for next link testing step.
             let next_link_testing = (0..self.links.len()).filter_map(|id| self.link_testing_step(id)).min();
             let link_testing_timeout = async move {
@@ -531,9 +699,11 @@ where
             // Task for receiving a new link.
             let new_link_task = async {
                 match &mut self.link_rx {
-                    _ if !self.init_links.is_empty() => TaskEvent::NewLink(self.init_links.pop_front().unwrap()),
+                    _ if !self.init_links.is_empty() => {
+                        TaskEvent::NewLink(Box::new(self.init_links.pop_front().unwrap()))
+                    }
                     Some(link_rx) => match link_rx.recv().await {
-                        Some(link) => TaskEvent::NewLink(link),
+                        Some(link) => TaskEvent::NewLink(Box::new(link)),
                         None => TaskEvent::NoNewLinks,
                     },
                     None => future::pending().await,
@@ -541,36 +711,46 @@ where
             };
 
             // Task for receiving requests from sender.
-            let sendable_idle_link_id =
-                self.idle_links.iter().rev().cloned().find(|id| self.links[*id].as_ref().unwrap().is_sendable());
+            let sendable_idle_link_id = self.idle_links.iter().rev().cloned().find(|id| {
+                let link = self.links[*id].as_ref().unwrap();
+                link.is_sendable() && link.carries_data(data_priority)
+            });
             let write_rx_task = async {
                 if links_idling && is_consume_ack_required {
                     TaskEvent::SendConsumed
                 } else {
                     match &mut self.write_rx {
-                        Some(write_rx) if tx_seq_avail && !resending => {
-                            match write_rx
-                                .recv_if(|msg| match msg {
-                                    SendReq::Send(data) => {
-                                        data.len() <= tx_space && sendable_idle_link_id.is_some()
-                                    }
-                                    SendReq::Flush(_) => true,
-                                })
-                                .await
-                            {
-                                Ok(SendReq::Send(data)) => {
-                                    TaskEvent::WriteRx { id: sendable_idle_link_id.unwrap(), data }
+                        Some(write_rx) if tx_seq_avail && !resending => match scheduled_link_id {
+                            Some(id) => match write_rx.try_recv() {
+                                Ok(SendReq::Send(data, redundancy)) => {
+                                    TaskEvent::WriteRx { id, data, redundancy }
                                 }
+                                _ => unreachable!("scheduled data disappeared"),
+                            },
+                            None => match write_rx.recv_if(|msg| matches!(msg, SendReq::Flush(_))).await {
                                 Ok(SendReq::Flush(flushed_tx)) => TaskEvent::Flush(flushed_tx),
+                                Ok(SendReq::Send(..)) => unreachable!(),
+                                Err(RecvIfError::NoMatch) if tx_data_size.is_none() => TaskEvent::WriteQueued,
                                 Err(RecvIfError::NoMatch) => future::pending().await,
                                 Err(RecvIfError::Disconnected) => TaskEvent::WriteEnd,
-                            }
-                        }
+                            },
+                        },
                         _ => future::pending().await,
                     }
                 }
             };
 
+            // Task for receiving datagrams to send from user.
+            let datagram_rx_task = async {
+                match &mut self.datagram_rx {
+                    Some(datagram_rx) => match datagram_rx.recv().await {
+                        Some(data) => TaskEvent::DatagramRx(data),
+                        None => TaskEvent::DatagramEnd,
+                    },
+                    None => future::pending().await,
+                }
+            };
+
             // Task for receiving link events.
             let link_task = async {
                 if self.links.is_empty() {
@@ -635,30 +815,44 @@ where
                 new_link_event = new_link_task => new_link_event,
                 ((id, event), _, _) = link_task => TaskEvent::LinkEvent { id, event },
                 write_event = write_rx_task => write_event,
+                datagram_event = datagram_rx_task => datagram_event,
                 link_id = recv_confirm_timeout => TaskEvent::ConfirmTimedOut(link_id),
                 link_id = next_ping_timeout => TaskEvent::PingLink(link_id),
                 link_id = next_pong_timeout => TaskEvent::LinkPingTimeout(link_id),
                 link_id = next_unconfirmed_timeout => TaskEvent::LinkUnconfirmedTimeout(link_id),
                 link_id = next_send_timeout => TaskEvent::LinkSendTimeout(link_id),
+                link_id = next_drain_timeout => TaskEvent::LinkDrainTimeout(link_id),
                 packet = resend_task => TaskEvent::Resend (packet),
                 consume_event = consume_task => consume_event,
                 event = read_closed_task => event,
                 () = link_testing_timeout => TaskEvent::LinkTesting,
+                () = unshaped_timeout => TaskEvent::LinkUnshaped,
                 () = links_timeout => TaskEvent::NoLinksTimeout,
                 Some(_) = stat_timers.next() => TaskEvent::PublishLinkStats,
                 Some(()) = self.refused_links_tasks.next(), if !self.refused_links_tasks.is_empty()
                     => TaskEvent::RefusedLinkTask,
                 Some(()) = self.server_changed_rx.recv() => TaskEvent::ServerChanged,
+                Some(()) = self.quota_set_rx.recv() => TaskEvent::QuotaSet,
             };
 
             // Handle event.
             match event {
-                TaskEvent::NewLink(mut link) => {
+                TaskEvent::NewLink(link) => {
+                    let mut link = *link;
                     if self.remote_cfg.is_none() {
                         let remote_cfg = link.remote_cfg();
                         tracing::debug!("obtained remote configuration: {remote_cfg:?}");
+                        self.compressor = Compressor::new(self.cfg.compression, remote_cfg.extensions);
                         self.remote_cfg = Some(remote_cfg);
                     }
+                    if self.tx_cipher.is_none() {
+                        if let Some(data_keys) = link.data_keys.take() {
+                            tracing::debug!("enabling end-to-end encryption of data");
+                            let (tx_cipher, rx_cipher) = data_keys.ciphers();
+                            self.tx_cipher = Some(tx_cipher);
+                            self.rx_cipher = Some(rx_cipher);
+                        }
+                    }
                     let others =
                         self.links.iter().filter_map(|link_opt| link_opt.as_ref().map(Link::from)).collect();
                     if (self.link_filter)(Link::from(&link), others).await {
@@ -694,12 +888,32 @@ where
                     match event {
                         LinkIntEvent::TxReady => {
                             // Link is ready to send more data.
+                            let send_data = match tx_data_size {
+                                Some(size) if tx_seq_avail && size <= tx_space => {
+                                    self.schedule_link(size, Some(id)) == Some(id)
+                                }
+                                _ => false,
+                            };
+                            let data_priority = self.data_priority();
+                            let tx_overhead = self.tx_overhead();
                             let link = self.links[id].as_mut().unwrap();
-                            let link_blocked = link.blocked.load(Ordering::SeqCst);
+                            let link_blocked = link.is_locally_blocked();
                             if link.needs_tx_accepted {
                                 tracing::debug!("sending Accepted over link {id}");
                                 self.idle_links.retain(|&idle_id| idle_id != id);
-                                link.start_send_msg(LinkMsg::Accepted, None);
+                                link.start_send_msg(
+                                    LinkMsg::Accepted {
+                                        version: (link.version >= LinkMsg::NEGOTIATION_VERSION)
+                                            .then_some(link.version),
+                                        resume_ticket: link.resume_ticket,
+                                        auth_mac: link.auth_mac,
+                                        encryption_key: link.encryption_key,
+                                    },
+                                    None,
+                                );
+                                if let Some(resume_ticket_sent) = &link.resume_ticket_sent {
+                                    resume_ticket_sent.store(true, Ordering::Release);
+                                }
                                 link.needs_tx_accepted = false;
                             } else if link.send_pong {
                                 tracing::trace!("sending Pong over link {id}");
@@ -733,7 +947,8 @@ where
                                 tracing::trace!("acking sequence {recved_seq} over non-idle link {id}");
                                 self.idle_links.retain(|&idle_id| idle_id != id);
                                 link.start_send_msg(LinkMsg::Ack { received: recved_seq }, None);
-                            } else if link.unconfirmed.is_none() && !link.is_blocked() {
+                            } else if link.unconfirmed.is_none() && link.draining.is_none() && !link.is_blocked()
+                            {
                                 // This is a link that is believed to be working, so we can submit
                                 // reliable messages over it. Do so by priority.
                                 if is_consume_ack_required {
@@ -743,11 +958,20 @@ where
                                     self.send_reliable_over_link(id, ReliableMsg::Consumed(consumed));
                                     self.rxed_reliable_consumed_since_last_ack = 0;
                                     self.rxed_reliable_consumed_force_ack = false;
-                                } else if resending && link.is_sendable() {
+                                } else if resending && link.is_sendable() && link.carries_data(data_priority) {
                                     let packet = self.resend_queue.pop_front().unwrap();
                                     tracing::trace!("resending packet {} over non-idle link {id}", packet.seq);
                                     self.idle_links.retain(|idle_id| *idle_id != id);
                                     self.resend_reliable_over_link(id, packet);
+                                } else if let Some(idx) = self
+                                    .tx_copy_queue
+                                    .iter()
+                                    .position(|(copy_id, _, _)| *copy_id == id)
+                                    .filter(|_| link.is_sendable())
+                                {
+                                    let (_, seq, data) = self.tx_copy_queue.remove(idx).unwrap();
+                                    self.idle_links.retain(|&idle_id| idle_id != id);
+                                    self.send_copy_over_link(id, seq, data);
                                 } else if self.read_closed_rx.is_none() && !self.receive_close_sent {
                                     tracing::trace!("sending ReceiveClose over non-idle link {id}");
                                     self.idle_links.retain(|&idle_id| idle_id != id);
@@ -763,23 +987,22 @@ where
                                     self.idle_links.retain(|&idle_id| idle_id != id);
                                     self.send_reliable_over_link(id, ReliableMsg::SendFinish);
                                     self.send_finish_sent = true;
-                                } else if let Some(SendReq::Send(data)) = self
-                                    .write_rx
-                                    .as_mut()
-                                    .filter(|_| tx_seq_avail && link.is_sendable())
-                                    .and_then(|rx| {
-                                        rx.try_recv_if(
-                                            |msg| matches!(msg, SendReq::Send(data) if data.len() <= tx_space),
+                                } else if let Some(SendReq::Send(data, redundancy)) =
+                                    self.write_rx.as_mut().filter(|_| send_data && link.is_sendable()).and_then(
+                                        |rx| {
+                                            rx.try_recv_if(
+                                            |msg| matches!(msg, SendReq::Send(data, _) if data.len() + tx_overhead <= tx_space),
                                         )
                                         .ok()
-                                    })
+                                        },
+                                    )
                                 {
                                     tracing::trace!(
                                         "sending data of size {} over non-idle link {id}",
                                         data.len()
                                     );
                                     self.idle_links.retain(|idle_id| *idle_id != id);
-                                    self.send_reliable_over_link(id, ReliableMsg::Data(data));
+                                    self.send_data_over_link(id, data, redundancy);
                                 } else if link.need_ack_flush() {
                                     tracing::trace!("flushing link {id} due to sent acks");
                                     self.idle_links.retain(|&idle_id| idle_id != id);
@@ -795,7 +1018,7 @@ where
                                     self.idle_links.push(id);
                                 }
                             } else {
-                                // Link is unconfirmed, make sure it is flushed.
+                                // Link is unconfirmed or being drained, make sure it is flushed.
                                 if link.needs_flush() || link.need_ack_flush() {
                                     tracing::trace!("flushing link {id} because it is not unconfirmed");
                                     self.idle_links.retain(|&idle_id| idle_id != id);
@@ -844,23 +1067,64 @@ where
                             self.idle_links.retain(|&idle_id| idle_id != id);
                             link.report_ready();
                             link.blocked_changed_out_tx.send_replace(());
+                            self.emit_blocked_events(id);
+                        }
+                        LinkIntEvent::QuotaSet => self.quotas_dirty = true,
+                        LinkIntEvent::PriorityChanged => {
+                            // Link priority has changed.
+                            let link = self.links[id].as_mut().unwrap();
+                            tracing::debug!("priority of link {id} has become {}", link.priority());
+                            self.idle_links.retain(|&idle_id| idle_id != id);
+                            link.report_ready();
                         }
                         LinkIntEvent::Disconnect => {
                             // Local request to disconnect link.
                             let link = self.links[id].as_mut().unwrap();
                             if link.disconnecting.is_none() {
                                 tracing::info!("starting disconnection of link {id} by local request");
-                                link.disconnecting = Some(DisconnectInitiator::Local);
+                                self.start_link_disconnect(id);
+                            }
+                        }
+                        LinkIntEvent::Drain => {
+                            // Local request to drain link.
+                            let link = self.links[id].as_mut().unwrap();
+                            if link.disconnecting.is_none() && link.draining.is_none() {
+                                tracing::info!("starting drain of link {id} by local request");
+                                link.draining = Some(Instant::now());
                                 self.idle_links.retain(|&idle_id| idle_id != id);
-                                link.start_flush();
+                                link.report_ready();
                             }
                         }
                     }
                 }
-                TaskEvent::WriteRx { id, data } => {
+                TaskEvent::WriteRx { id, data, redundancy } => {
                     tracing::trace!("sending data of size {} over idle link {id}", data.len());
                     self.idle_links.retain(|&idle_id| idle_id != id);
-                    self.send_reliable_over_link(id, ReliableMsg::Data(data));
+                    self.send_data_over_link(id, data, redundancy);
+                }
+                TaskEvent::WriteQueued => {
+                    tracing::trace!("data to send has been queued");
+                }
+                TaskEvent::DatagramRx(data) => match self.datagram_link() {
+                    Some(id) => {
+                        tracing::trace!("sending datagram of size {} over idle link {id}", data.len());
+                        self.idle_links.retain(|&idle_id| idle_id != id);
+                        let data = match &mut self.tx_cipher {
+                            Some(tx_cipher) => tx_cipher.seal_datagram(&data),
+                            None => data,
+                        };
+                        let link = self.links[id].as_mut().unwrap();
+                        link.start_send_msg(LinkMsg::Datagram, Some(data));
+                        self.txed_datagrams += 1;
+                    }
+                    None => {
+                        tracing::trace!("dropping datagram of size {} since no link is ready", data.len());
+                        self.txed_datagrams_dropped += 1;
+                    }
+                },
+                TaskEvent::DatagramEnd => {
+                    tracing::debug!("datagram sender was dropped");
+                    self.datagram_rx = None;
                 }
                 TaskEvent::SendConsumed => {
                     let id = self.idle_links.pop().unwrap();
@@ -935,8 +1199,10 @@ where
                     tracing::trace!("consuming received data message {:?}", &received.msg);
                     match received.msg {
                         ReliableMsg::Data(data) => {
-                            self.rxed_reliable_size -= data.len();
-                            self.rxed_reliable_consumed_since_last_ack += data.len();
+                            // Flow control accounts for the size of the data as received
+                            // or after decompression, whichever is larger.
+                            self.rxed_reliable_size -= received.size;
+                            self.rxed_reliable_consumed_since_last_ack += received.size;
                             if let Some(permit) = permit {
                                 permit.send(data);
                             }
@@ -971,7 +1237,12 @@ where
                     tracing::warn!("removing link {id} due to send timeout");
                     self.remove_link(id, DisconnectReason::SendTimeout);
                 }
+                TaskEvent::LinkDrainTimeout(id) => {
+                    tracing::warn!("disconnecting link {id} with unacknowledged data due to drain timeout");
+                    self.start_link_disconnect(id);
+                }
                 TaskEvent::LinkTesting => (),
+                TaskEvent::LinkUnshaped => (),
                 TaskEvent::NoLinksTimeout => {
                     tracing::warn!("disconnecting because no links are available for too long");
                     result = Err(TaskError::NoLinksTimeout);
@@ -986,6 +1257,7 @@ where
                             link.publish_stats();
                         }
                     }
+                    self.quotas_dirty = true;
                 }
                 TaskEvent::RefusedLinkTask => (),
                 TaskEvent::ServerChanged => {
@@ -996,6 +1268,14 @@ where
                     link_term = DisconnectReason::ServerIdMismatch;
                     break;
                 }
+                TaskEvent::QuotaSet => self.quotas_dirty = true,
+            }
+
+            // Disconnect drained links.
+            let drained: Vec<_> = (0..self.links.len()).filter(|&id| self.is_link_drained(id)).collect();
+            for id in drained {
+                tracing::info!("disconnecting link {id} because it has been drained");
+                self.start_link_disconnect(id);
             }
 
             // Check for link ping exceeding configured limit.
@@ -1003,7 +1283,7 @@ where
                 let all_links_slow = self.links.iter().all(|link_opt| {
                     link_opt
                         .as_ref()
-                        .map(|link| link.unconfirmed.is_some() || link.is_blocked() || link.roundtrip > max_ping)
+                        .map(|link| link.unconfirmed.is_some() || link.is_blocked() || link.ping() > max_ping)
                         .unwrap_or(true)
                 });
 
@@ -1013,10 +1293,10 @@ where
                         .iter()
                         .enumerate()
                         .filter_map(|(id, link_opt)| match link_opt {
-                            Some(link) if link.unconfirmed.is_none() && link.roundtrip > max_ping => {
+                            Some(link) if link.unconfirmed.is_none() && link.ping() > max_ping => {
                                 tracing::warn!(
                                     "unconfirming link {id} due to slow ping of {} ms",
-                                    link.roundtrip.as_millis()
+                                    link.ping().as_millis()
                                 );
                                 Some(id)
                             }
@@ -1041,9 +1321,13 @@ where
         }
         for link in &mut self.links {
             if let Some(link) = link.take() {
+                self.event_txs
+                    .emit(|| EventKind::LinkDisconnected { link: Link::from(&link), reason: link_term.clone() });
                 link.notify_disconnected(link_term.clone());
             }
         }
+        self.event_txs.emit(|| EventKind::Terminated(result.clone()));
+        self.event_txs.close();
 
         match &result {
             Ok(()) => tracing::debug!("link aggregator task exiting"),
@@ -1056,6 +1340,11 @@ where
     fn add_link(&mut self, mut link: LinkInt<TX, RX, TAG>) -> usize {
         link.report_ready();
         link.unconfirmed = Some((Instant::now(), NotWorkingReason::New));
+        link.cc = (self.congestion_controller)(&Link::from(&link));
+        link.apply_cc();
+        link.reported_blocked = (link.is_locally_blocked(), link.remotely_blocked.load(Ordering::SeqCst));
+        self.event_txs.emit(|| EventKind::LinkAdded(Link::from(&link)));
+        self.quotas_dirty = true;
 
         for (id, link_opt) in self.links.iter_mut().enumerate() {
             if link_opt.is_none() {
@@ -1078,8 +1367,20 @@ where
         // Queue unconfirmed packets for resending.
         self.unconfirm_link(id, NotWorkingReason::Disconnecting);
 
+        // Account used bytes to quotas of link tag.
+        {
+            let link = self.links[id].as_ref().unwrap();
+            for tq in self.tag_quotas.lock().unwrap().iter_mut() {
+                if (tq.matches)(link.tag()) {
+                    tq.disconnected_used = tq.disconnected_used.saturating_add(link.quota_used());
+                }
+            }
+        }
+        self.quotas_dirty = true;
+
         // Send disconnect reason.
         let link = self.links[id].take().unwrap();
+        self.event_txs.emit(|| EventKind::LinkDisconnected { link: Link::from(&link), reason: reason.clone() });
         link.notify_disconnected(reason);
 
         // Cleanup and publish links.
@@ -1089,6 +1390,27 @@ where
         self.publish_links();
     }
 
+    /// Starts locally requested disconnection of a link.
+    fn start_link_disconnect(&mut self, id: usize) {
+        let link = self.links[id].as_mut().unwrap();
+        link.draining = None;
+        link.disconnecting = Some(DisconnectInitiator::Local);
+        self.idle_links.retain(|&idle_id| idle_id != id);
+        link.start_flush();
+    }
+
+    /// Returns whether the link is being drained and all packets sent over it have
+    /// been acknowledged.
+    fn is_link_drained(&self, id: usize) -> bool {
+        match &self.links[id] {
+            Some(link) if link.draining.is_some() => link.txed_unacked_copies.is_empty()
+                && !self.txed_packets.iter().any(
+                    |p| matches!(&*p.status.borrow(), SentReliableStatus::Sent { link_id, .. } if *link_id == id),
+                ),
+            _ => false,
+        }
+    }
+
     /// Publishes the currently connected links.
     fn publish_links(&self) {
         let links = self.links.iter().filter_map(|link_opt| link_opt.as_ref().map(Link::from)).collect();
@@ -1144,16 +1466,22 @@ where
 
         // If too much data is unconsumable, decrease unacked data limit of guilty link,
         // which is most probably the link used to send the oldest still unconfirmed data.
+        // Links managed by a congestion controller are left alone.
         if (soft_overrun && self.tx_overrun == SendOverrun::Armed)
             || (hard_overrun && self.tx_overrun != SendOverrun::Hard)
         {
-            if let Some(id) = self.txed_packets.iter().find_map(|p| {
-                if let SentReliableStatus::Sent { link_id, .. } = &*p.status.borrow() {
-                    Some(*link_id)
-                } else {
-                    None
-                }
-            }) {
+            if let Some(id) = self
+                .txed_packets
+                .iter()
+                .find_map(|p| {
+                    if let SentReliableStatus::Sent { link_id, .. } = &*p.status.borrow() {
+                        Some(*link_id)
+                    } else {
+                        None
+                    }
+                })
+                .filter(|&id| self.links[id].as_ref().map(|link| link.cc.is_none()).unwrap_or_default())
+            {
                 let link = self.links[id].as_mut().unwrap();
 
                 // Decrease limit.
@@ -1198,9 +1526,7 @@ where
                 all_links_slow = self.links.iter().all(|link_opt| {
                     link_opt
                         .as_ref()
-                        .map(|link| {
-                            link.unconfirmed.is_some() || link.is_blocked() || link.roundtrip > max_ping / 2
-                        })
+                        .map(|link| link.unconfirmed.is_some() || link.is_blocked() || link.ping() > max_ping / 2)
                         .unwrap_or(true)
                 });
 
@@ -1208,9 +1534,10 @@ where
                     for (id, link_opt) in self.links.iter_mut().enumerate() {
                         match link_opt {
                             Some(link)
-                                if link.unconfirmed.is_none()
+                                if link.cc.is_none()
+                                    && link.unconfirmed.is_none()
                                     && link.txed_unacked_data_limit_increased.is_none()
-                                    && link.roundtrip > max_ping * 3 / 4 =>
+                                    && link.ping() > max_ping * 3 / 4 =>
                             {
                                 // Decrease limit.
                                 let current = link.txed_unacked_data.min(link.txed_unacked_data_limit);
@@ -1237,6 +1564,7 @@ where
         // Check if data is available for sending but no link is available.
         let send_data_avail = self.write_rx.as_mut().map(|rx| rx.try_peek().is_ok()).unwrap_or_default()
             || !self.resend_queue.is_empty();
+        let data_priority = self.data_priority();
         let sendable_link_avail = self.links.iter().any(|link_opt| {
             link_opt
                 .as_ref()
@@ -1244,26 +1572,29 @@ where
                     !link.tx_pending
                         && link.unconfirmed.is_none()
                         && !link.is_blocked()
+                        && link.carries_data(data_priority)
                         && link.txed_unacked_data < link.txed_unacked_data_limit
                 })
                 .unwrap_or_default()
         });
 
         // Increase the unacked data limits of links that are currently blocked by it.
-        if send_data_avail && !sendable_link_avail {
+        if send_data_avail && (!sendable_link_avail || self.tx_deferred) {
             for (id, link_opt) in self.links.iter_mut().enumerate() {
                 match link_opt {
                     Some(link)
-                        if !link.tx_pending
+                        if link.cc.is_none()
+                            && !link.tx_pending
                             && link.unconfirmed.is_none()
                             && !link.is_blocked()
+                            && link.carries_data(data_priority)
                             && link.txed_unacked_data >= link.txed_unacked_data_limit
                             && link.txed_unacked_data_limit_increased.is_none()
                             && link.txed_unacked_data_limit < self.cfg.link_unacked_limit.get()
                             && self
                                 .cfg
                                 .link_max_ping
-                                .map(|max_ping| link.roundtrip <= max_ping / 2 || all_links_slow)
+                                .map(|max_ping| link.ping() <= max_ping / 2 || all_links_slow)
                                 .unwrap_or(true) =>
                     {
                         // Increase limit, faster if done many times consecutively.
@@ -1337,7 +1668,7 @@ where
             if let SentReliableStatus::Sent { link_id, sent, resent, .. } = &*p.status.borrow() {
                 let link = self.links[*link_id].as_ref().unwrap();
                 let dur_factor = if *resent { 3 } else { 1 };
-                let dur = (link.roundtrip * self.cfg.link_ack_timeout_roundtrip_factor.get() * dur_factor)
+                let dur = (link.rtt.ack_timeout(self.cfg.link_ack_timeout_roundtrip_factor.get()) * dur_factor)
                     .clamp(self.cfg.link_ack_timeout_min, self.cfg.link_ack_timeout_max);
                 return Some((*link_id, *sent + dur));
             }
@@ -1389,6 +1720,8 @@ where
             self.txed_unacked += data.len();
             self.txed_unconsumed += data.len();
             link.txed_unacked_data += data.len();
+            link.cc_sent(data.len());
+            self.link_scheduler.sent(&link.sched_state(false), data.len());
         }
 
         // Store sent message until confirmation to be able to resend it should the link fail.
@@ -1406,6 +1739,294 @@ where
         seq
     }
 
+    /// Sends data over the specified link.
+    ///
+    /// In broadcast bonding mode copies of the data are sent over all other usable links.
+    /// Otherwise, if `redundancy` is greater than one, copies are sent over the other usable
+    /// links with the lowest roundtrip time, so that `redundancy` links carry the data in total.
+    /// Copies for links that are busy are queued until they become ready.
+    fn send_data_over_link(&mut self, id: usize, data: Bytes, redundancy: NonZeroUsize) {
+        let uncompressed_len = data.len();
+        let data = match &self.compressor {
+            Some(compressor) => {
+                let compressed = compressor.compress(&data);
+                self.txed_uncompressed_bytes += data.len() as u64;
+                self.txed_compressed_bytes += compressed.len() as u64;
+                compressed
+            }
+            None => data,
+        };
+        let data = match &mut self.tx_cipher {
+            Some(tx_cipher) => tx_cipher.seal(&data),
+            None => data,
+        };
+        let seq = self.send_reliable_over_link(id, ReliableMsg::Data(data.clone()));
+
+        // Flow control accounts for the size of compressed data after decompression,
+        // since this is what the remote endpoint has to buffer.
+        if self.compressor.is_some() {
+            self.txed_unconsumed += uncompressed_len.saturating_sub(data.len());
+        }
+
+        let broadcast = self.cfg.bonding_mode == BondingMode::Broadcast;
+        if !broadcast && redundancy.get() == 1 {
+            return;
+        }
+
+        let data_priority = if broadcast { None } else { self.data_priority() };
+        let data_quota_state = self.data_quota_state(data_priority);
+        let mut copy_ids: Vec<_> = self
+            .links
+            .iter()
+            .enumerate()
+            .filter_map(|(copy_id, link_opt)| {
+                let link = link_opt.as_ref()?;
+                (copy_id != id
+                    && link.is_usable()
+                    && link.carries_data(data_priority)
+                    && link.quota_state <= data_quota_state)
+                    .then_some(copy_id)
+            })
+            .collect();
+        if !broadcast {
+            copy_ids.sort_by_key(|&copy_id| self.links[copy_id].as_ref().unwrap().roundtrip());
+            if copy_ids.len() < redundancy.get() - 1 {
+                tracing::debug!(
+                    "only {} of {redundancy} links are usable for sending reliable message {seq}",
+                    copy_ids.len() + 1
+                );
+                self.txed_redundancy_shortfall += 1;
+            }
+            copy_ids.truncate(redundancy.get() - 1);
+        }
+
+        // Copies are sent immediately over idle links and queued for the busy ones.
+        for copy_id in copy_ids {
+            if self.idle_links.contains(&copy_id) {
+                self.idle_links.retain(|&idle_id| idle_id != copy_id);
+                self.send_copy_over_link(copy_id, seq, data.clone());
+            } else {
+                tracing::trace!("queueing copy of reliable message {seq} for link {copy_id}");
+                self.tx_copy_queue.push_back((copy_id, seq, data.clone()));
+            }
+        }
+    }
+
+    /// Sends a copy of a data packet over the specified link.
+    ///
+    /// The copy is accounted as unacknowledged data of the link until the link receives
+    /// its acknowledgement.
+    fn send_copy_over_link(&mut self, id: usize, seq: Seq, data: Bytes) {
+        tracing::trace!("sending copy of reliable message {seq} over link {id}");
+        let link = self.links[id].as_mut().unwrap();
+        link.txed_unacked_data += data.len();
+        link.txed_unacked_copies.push_back((seq, data.len()));
+        link.cc_sent(data.len());
+        self.txed_redundant += 1;
+        self.txed_redundant_bytes += data.len() as u64;
+        let (msg, copy) = ReliableMsg::Data(data).to_link_msg(seq);
+        link.start_send_msg(msg, copy);
+    }
+
+    /// Selects an idle link for sending a datagram.
+    ///
+    /// Prefers the link with the lowest roundtrip time.
+    fn datagram_link(&self) -> Option<usize> {
+        let data_priority = self.data_priority();
+        let data_quota_state = self.data_quota_state(data_priority);
+        self.idle_links
+            .iter()
+            .copied()
+            .filter(|&id| {
+                let link = self.links[id].as_ref().unwrap();
+                link.is_usable()
+                    && link.is_sendable()
+                    && link.version >= LinkMsg::Datagram.min_version()
+                    && link.carries_data(data_priority)
+                    && link.quota_state <= data_quota_state
+            })
+            .min_by_key(|&id| self.links[id].as_ref().unwrap().roundtrip())
+    }
+
+    /// Updates the statistics of received duplicate data packets.
+    fn count_duplicate(&mut self, msg: &ReliableMsg) {
+        if let ReliableMsg::Data(data) = msg {
+            self.rxed_duplicates += 1;
+            self.rxed_duplicate_bytes += data.len() as u64;
+        }
+    }
+
+    /// Priority of the links that may carry data.
+    ///
+    /// In active-backup bonding mode this is the highest priority of all usable links.
+    /// `None` if all links may carry data.
+    fn data_priority(&self) -> Option<u8> {
+        match self.cfg.bonding_mode {
+            BondingMode::ActiveBackup => {
+                self.links.iter().flatten().filter(|link| link.is_usable()).map(|link| link.priority()).min()
+            }
+            _ => None,
+        }
+    }
+
+    /// Quota state of the links that may carry data.
+    ///
+    /// Links that exceeded their soft quota only carry data if no other usable link is available.
+    fn data_quota_state(&self, data_priority: Option<u8>) -> QuotaState {
+        self.links
+            .iter()
+            .flatten()
+            .filter(|link| link.is_usable() && link.carries_data(data_priority))
+            .map(|link| link.quota_state)
+            .min()
+            .unwrap_or(QuotaState::SoftExceeded)
+    }
+
+    /// Total bytes used by all links.
+    fn quotas_used(&self) -> u64 {
+        self.links.iter().flatten().fold(0, |used, link| used.wrapping_add(link.quota_used()))
+    }
+
+    /// Updates the quota states of links and link tags.
+    ///
+    /// This is necessary when links have been added or removed, quotas have been set
+    /// or the bytes used since the last update may have reached a quota threshold.
+    fn update_quotas(&mut self) {
+        let mut tag_quotas = self.tag_quotas.lock().unwrap();
+        let mut changed = false;
+        let mut blocked_changed = Vec::new();
+        let mut remaining = u64::MAX;
+
+        for tq in tag_quotas.iter_mut() {
+            let used = self
+                .links
+                .iter()
+                .flatten()
+                .filter(|link| (tq.matches)(link.tag()))
+                .fold(tq.disconnected_used, |used, link| used.saturating_add(link.quota_used()));
+            let status = QuotaStatus::new(tq.status.quota, used);
+            remaining = remaining.min(tq.status.quota.remaining(used));
+            changed |= status.state != tq.status.state;
+            tq.status = status;
+        }
+
+        for (id, link_opt) in self.links.iter_mut().enumerate() {
+            let Some(link) = link_opt else { continue };
+            remaining = remaining.min(link.quota().remaining(link.quota_used()));
+
+            let tag_state =
+                tag_quotas.iter().filter(|tq| (tq.matches)(link.tag())).map(|tq| tq.status.state).max();
+            let state = link.quota_status().state.max(tag_state.unwrap_or_default());
+            if state != link.quota_state {
+                tracing::info!("quota state of link {id} has become {state}");
+                let was_blocked = link.is_locally_blocked();
+                link.quota_state = state;
+                if link.is_locally_blocked() != was_blocked {
+                    self.idle_links.retain(|&idle_id| idle_id != id);
+                    link.report_ready();
+                    link.blocked_changed_out_tx.send_replace(());
+                    blocked_changed.push(id);
+                }
+                link.publish_quota();
+                changed = true;
+            }
+        }
+
+        drop(tag_quotas);
+        self.quotas_dirty = false;
+        self.quotas_used = self.quotas_used();
+        self.quotas_remaining = remaining;
+
+        for id in blocked_changed {
+            self.emit_blocked_events(id);
+        }
+
+        if changed {
+            self.quota_changed_tx.send_replace(());
+        }
+    }
+
+    /// Emits events for changes of the local and remote blocked status of a link.
+    fn emit_blocked_events(&mut self, id: usize) {
+        let link = self.links[id].as_mut().unwrap();
+        let blocked = (link.is_locally_blocked(), link.remotely_blocked.load(Ordering::SeqCst));
+        let reported = mem::replace(&mut link.reported_blocked, blocked);
+
+        for (remote, reported, blocked) in [(false, reported.0, blocked.0), (true, reported.1, blocked.1)] {
+            if reported != blocked {
+                self.event_txs.emit(|| {
+                    let link = Link::from(&*link);
+                    if blocked {
+                        EventKind::LinkBlocked { link, remote }
+                    } else {
+                        EventKind::LinkUnblocked { link, remote }
+                    }
+                });
+            }
+        }
+    }
+
+    /// Size of the data queued for sending next.
+    fn tx_data_size(&mut self) -> Option<usize> {
+        let overhead = self.tx_overhead();
+        match self.write_rx.as_mut()?.try_peek() {
+            Ok(SendReq::Send(data, _)) => Some(data.len() + overhead),
+            _ => None,
+        }
+    }
+
+    /// Maximum size added to sent data packets by compression and end-to-end encryption.
+    fn tx_overhead(&self) -> usize {
+        let mut overhead = 0;
+        if self.compressor.is_some() {
+            overhead += compress::HEADER_SIZE;
+        }
+        if self.tx_cipher.is_some() {
+            overhead += crypto::TAG_SIZE;
+        }
+        overhead
+    }
+
+    /// Uses the link scheduler to select the link for sending data of the specified size.
+    ///
+    /// The link `ready_id` is treated as ready, in addition to the idle links.
+    /// Returns the id of the selected link, if it is ready for sending.
+    fn schedule_link(&mut self, size: usize, ready_id: Option<usize>) -> Option<usize> {
+        let data_priority = self.data_priority();
+        let data_quota_state = self.data_quota_state(data_priority);
+        let mut all_idle = true;
+        let mut ids = mem::take(&mut self.sched_ids);
+        let mut states = recycle_vec(mem::take(&mut self.sched_states));
+        for (id, link) in self.links.iter().enumerate() {
+            let Some(link) = link else { continue };
+            if link.is_closing() || !link.carries_data(data_priority) || link.quota_state > data_quota_state {
+                continue;
+            }
+            let idle = ready_id == Some(id) || self.idle_links.contains(&id);
+            all_idle &= idle || !link.is_usable();
+            let ready = idle && link.is_usable() && link.is_sendable();
+            ids.push(id);
+            states.push(link.sched_state(ready));
+        }
+
+        // In broadcast mode data is sent over all usable links at once.
+        let broadcast_ready = self.cfg.bonding_mode != BondingMode::Broadcast || all_idle;
+
+        let selected = match self.link_scheduler.select(&states, size) {
+            Some(idx) if broadcast_ready && states.get(idx).map(|state| state.ready).unwrap_or_default() => {
+                Some(ids[idx])
+            }
+            _ => None,
+        };
+        self.tx_deferred = selected.is_none();
+
+        ids.clear();
+        self.sched_ids = ids;
+        self.sched_states = recycle_vec(states);
+
+        selected
+    }
+
     /// Resends a packet over the specified link.
     fn resend_reliable_over_link(&mut self, id: usize, packet: Arc<SentReliable>) {
         let link = self.links[id].as_mut().unwrap();
@@ -1424,6 +2045,7 @@ where
         // Update link statistics.
         if let ReliableMsg::Data(data) = reliable_msg {
             link.txed_unacked_data += data.len();
+            link.cc_sent(data.len());
         }
 
         // Adjust last buffer increase sequence number if necessary.
@@ -1447,9 +2069,14 @@ where
     fn unconfirm_link(&mut self, id: usize, reason: NotWorkingReason) {
         // Mark link as unconfirmed.
         let link = self.links[id].as_mut().unwrap();
+        if !matches!(&link.unconfirmed, Some((_, r)) if *r == reason) {
+            self.event_txs
+                .emit(|| EventKind::LinkUnconfirmed { link: Link::from(&*link), reason: reason.clone() });
+        }
         link.unconfirmed = Some((Instant::now(), reason));
         self.idle_links.retain(|&idle_id| idle_id != id);
         self.unflushed_links.remove(&id);
+        self.tx_copy_queue.retain(|(copy_id, _, _)| *copy_id != id);
 
         // Flush link.
         link.start_flush();
@@ -1475,6 +2102,12 @@ where
             };
         }
 
+        // Copies sent over the link are lost.
+        let link = self.links[id].as_mut().unwrap();
+        for (_, size) in link.txed_unacked_copies.drain(..) {
+            link.txed_unacked_data -= size;
+        }
+
         // Sort resend queue, so that oldest packets are resend first.
         self.resend_queue.make_contiguous().sort_by_key(|packet| packet.seq);
 
@@ -1495,10 +2128,7 @@ where
                 link_opt
                     .as_ref()
                     .map(|link| {
-                        link_id == id
-                            || link.unconfirmed.is_some()
-                            || link.is_blocked()
-                            || link.roundtrip > max_ping
+                        link_id == id || link.unconfirmed.is_some() || link.is_blocked() || link.ping() > max_ping
                     })
                     .unwrap_or(true)
             }),
@@ -1537,21 +2167,24 @@ where
                 LinkTest::InProgress => {
                     if link.current_ping_sent.is_none() && !link.send_ping {
                         // Ping has completed.
-
-                        if link.roundtrip <= self.cfg.link_ack_timeout_max / 2
+                        // Its roundtrip time is judged on its own, since the smoothed roundtrip
+                        // time may still reflect the conditions that made the link fail.
+                        let ping = link.ping_rtt.latest();
+                        if ping <= self.cfg.link_ack_timeout_max / 2
                             && self
                                 .cfg
                                 .link_max_ping
-                                .map(|max_ping| link.roundtrip <= max_ping || others_slow)
+                                .map(|max_ping| ping <= max_ping || others_slow)
                                 .unwrap_or(true)
                         {
                             // Ping response arrived quickly enough, thus mark link as confirmed.
                             tracing::debug!(
                                 "link {id} successfully completed test with ping {} ms",
-                                link.roundtrip.as_millis()
+                                ping.as_millis()
                             );
                             link.unconfirmed = None;
                             link.test = LinkTest::Inactive;
+                            self.event_txs.emit(|| EventKind::LinkConfirmed(Link::from(&*link)));
 
                             self.idle_links.retain(|&idle_id| idle_id != id);
                             link.report_ready();
@@ -1561,11 +2194,17 @@ where
                             // Link is too slow, schedule retest.
                             tracing::debug!(
                                 "link {id} failed test with ping {} ms, retrying in {} s",
-                                link.roundtrip.as_millis(),
+                                ping.as_millis(),
                                 self.cfg.link_retest_interval.as_secs()
                             );
                             let when = Instant::now();
                             link.test = LinkTest::Failed(when);
+                            if !matches!(&link.unconfirmed, Some((_, NotWorkingReason::TestFailed))) {
+                                self.event_txs.emit(|| EventKind::LinkUnconfirmed {
+                                    link: Link::from(&*link),
+                                    reason: NotWorkingReason::TestFailed,
+                                });
+                            }
                             match &mut link.unconfirmed {
                                 Some((_since, reason)) => *reason = NotWorkingReason::TestFailed,
                                 None => link.unconfirmed = Some((Instant::now(), NotWorkingReason::TestFailed)),
@@ -1612,7 +2251,8 @@ where
                 if let Some(current_ping_sent) = link.current_ping_sent.take() {
                     let elapsed = current_ping_sent.elapsed();
                     tracing::trace!("ping round-trip time is {} ms", elapsed.as_millis());
-                    link.roundtrip = elapsed;
+                    link.rtt.sample(elapsed);
+                    link.ping_rtt.sample(elapsed);
                     link.last_ping = Some(Instant::now());
                     self.link_testing_step(id);
                 }
@@ -1626,6 +2266,26 @@ where
                 tracing::trace!("received reliable message {seq}: {reliable_msg:?}");
                 self.handle_received_reliable_msg(id, seq, reliable_msg)?;
             }
+            LinkMsg::Datagram => {
+                let mut data = data.unwrap();
+                tracing::trace!("received datagram of size {}", data.len());
+                if let Some(rx_cipher) = &self.rx_cipher {
+                    data = rx_cipher.open_datagram(&data)?;
+                }
+                if data.len() > self.cfg.datagram_max_size as usize {
+                    return Err(protocol_err!("datagram exceeds maximum size"));
+                }
+
+                self.rxed_datagrams += 1;
+                let delivered = match &self.datagram_tx {
+                    Some(datagram_tx) => datagram_tx.try_send(data).is_ok(),
+                    None => false,
+                };
+                if !delivered {
+                    tracing::trace!("dropping received datagram since receive queue is not available");
+                    self.rxed_datagrams_dropped += 1;
+                }
+            }
             LinkMsg::Ack { received } => {
                 tracing::trace!("link {id} acked reception up to {received}");
                 self.handle_ack(id, received);
@@ -1639,6 +2299,7 @@ where
                 self.idle_links.retain(|&idle_id| idle_id != id);
                 link.report_ready();
                 link.blocked_changed_out_tx.send_replace(());
+                self.emit_blocked_events(id);
             }
             LinkMsg::Goodbye => {
                 match link.disconnecting {
@@ -1660,9 +2321,10 @@ where
                     }
                 }
             }
-            LinkMsg::Welcome { .. } | LinkMsg::Connect { .. } | LinkMsg::Accepted | LinkMsg::Refused { .. } => {
-                return Err(protocol_err!("received unexpected message"))
-            }
+            LinkMsg::Welcome { .. }
+            | LinkMsg::Connect { .. }
+            | LinkMsg::Accepted { .. }
+            | LinkMsg::Refused { .. } => return Err(protocol_err!("received unexpected message")),
         }
 
         Ok(())
@@ -1681,6 +2343,7 @@ where
             // received and consumed. Thus the acknowledgement has been
             // lost and must be resend.
             tracing::trace!("rereceived consumed reliable message {}", seq);
+            self.count_duplicate(&msg);
         } else {
             let offset = (seq - self.rx_seq) as usize;
             if self.rxed_reliable.len() <= offset {
@@ -1720,21 +2383,46 @@ where
                     }
                 }
 
-                self.rxed_reliable[offset] = Some(ReceivedReliableMsg { seq, msg });
+                let size = match &msg {
+                    ReliableMsg::Data(data) => data.len(),
+                    _ => 0,
+                };
+                self.rxed_reliable[offset] = Some(ReceivedReliableMsg { seq, msg, size });
             } else {
                 // The sequence number belongs to a packet that has alredy been
                 // received. Thus the acknowledgement has been lost and must be resend.
                 tracing::trace!("rereceived unconsumed reliable message {}", seq);
+                self.count_duplicate(&msg);
             }
         }
 
         // Forward received messages that are ready for consumption.
         while let Some(Some(_)) = self.rxed_reliable.front().as_ref() {
-            let msg = self.rxed_reliable.pop_front().unwrap().unwrap();
+            let mut msg = self.rxed_reliable.pop_front().unwrap().unwrap();
 
             assert_eq!(msg.seq, self.rx_seq);
             self.rx_seq += 1;
 
+            if let ReliableMsg::Data(data) = &mut msg.msg {
+                if let Some(rx_cipher) = &mut self.rx_cipher {
+                    *data = rx_cipher.open(data)?;
+                }
+                if self.remote_cfg.as_ref().map(|remote_cfg| remote_cfg.compressed).unwrap_or_default() {
+                    self.rxed_compressed_bytes += data.len() as u64;
+                    let max_size = (self.cfg.recv_buffer.get() as usize).saturating_sub(self.rxed_reliable_size);
+                    *data = compress::decompress(data, msg.size + max_size)?;
+                    self.rxed_uncompressed_bytes += data.len() as u64;
+
+                    // Flow control accounts for the size of the data after decompression.
+                    let size = msg.size.max(data.len());
+                    self.rxed_reliable_size += size - msg.size;
+                    if self.rxed_reliable_size > self.cfg.recv_buffer.get() as usize {
+                        return Err(protocol_err!("receive buffer overflow"));
+                    }
+                    msg.size = size;
+                }
+            }
+
             if matches!(&msg.msg, ReliableMsg::Data(_) | ReliableMsg::SendFinish) {
                 self.rxed_reliable_consumable.push_back(msg);
             }
@@ -1764,6 +2452,17 @@ where
             _ => (),
         }
 
+        // Release copy of packet sent over the link.
+        let acked_copy = match link.txed_unacked_copies.iter().position(|(seq, _)| *seq == rxed_seq) {
+            Some(idx) => {
+                let (_, size) = link.txed_unacked_copies.remove(idx).unwrap();
+                link.txed_unacked_data -= size;
+                link.cc_acked(size, None);
+                true
+            }
+            None => false,
+        };
+
         // Remove packet that has been received by remote endpoint.
         let back_idx = self.tx_seq - rxed_seq;
         if 0 < back_idx && (back_idx as usize) <= self.txed_packets.len() {
@@ -1773,14 +2472,21 @@ where
 
             let mut status = packet.status.borrow_mut();
             match &*status {
-                SentReliableStatus::Sent { sent, link_id, msg, .. } if *link_id == id => {
+                SentReliableStatus::Sent { sent, link_id, msg, resent } if *link_id == id || acked_copy => {
                     let size = if let ReliableMsg::Data(data) = &msg { data.len() } else { 0 };
 
-                    link.txed_unacked_data -= size;
+                    // The acknowledgement may arrive over a link carrying a copy of the packet.
+                    let sent_link = self.links[*link_id].as_mut().unwrap();
+                    sent_link.txed_unacked_data -= size;
                     self.txed_unacked -= size;
                     self.txed_unconsumable += size;
 
-                    link.roundtrip = (99 * link.roundtrip + sent.elapsed()) / 100;
+                    // Acknowledgements of resent packets are ambiguous and thus not sampled.
+                    let rtt = (*link_id == id && !*resent).then(|| sent.elapsed());
+                    if let Some(rtt) = rtt {
+                        sent_link.rtt.sample(rtt);
+                    }
+                    sent_link.cc_acked(size, rtt);
 
                     *status = SentReliableStatus::Received { size };
                 }
@@ -1795,6 +2501,11 @@ where
                 }
                 _ => (),
             }
+
+            // Queued copies are no longer needed.
+            if !self.tx_copy_queue.is_empty() {
+                self.tx_copy_queue.retain(|(_, seq, _)| *seq != rxed_seq);
+            }
         }
 
         // Swipe front of unconfirmed queue.
@@ -1830,6 +2541,19 @@ where
                 resend_queue_len: self.resend_queue.len(),
                 recved_unconsumed: self.rxed_reliable_size,
                 recved_unconsumed_count: self.rxed_reliable.len(),
+                sent_redundant: self.txed_redundant,
+                sent_redundant_bytes: self.txed_redundant_bytes,
+                sent_redundancy_shortfall: self.txed_redundancy_shortfall,
+                recved_duplicates: self.rxed_duplicates,
+                recved_duplicate_bytes: self.rxed_duplicate_bytes,
+                sent_datagrams: self.txed_datagrams,
+                sent_datagrams_dropped: self.txed_datagrams_dropped,
+                recved_datagrams: self.rxed_datagrams,
+                recved_datagrams_dropped: self.rxed_datagrams_dropped,
+                sent_uncompressed_bytes: self.txed_uncompressed_bytes,
+                sent_compressed_bytes: self.txed_compressed_bytes,
+                recved_compressed_bytes: self.rxed_compressed_bytes,
+                recved_uncompressed_bytes: self.rxed_uncompressed_bytes,
             });
         }
     }
@@ -1862,6 +2586,26 @@ where
         self.link_filter = Box::new(move |link, others| link_filter(link, others).boxed());
     }
 
+    /// Sets the function creating the congestion controller of each link.
+    ///
+    /// The function is called for each link when it is added to the connection.
+    /// If it returns `None`, the adaptive unacked data limit is used for the link.
+    /// By default the controller is chosen by [`Cfg::congestion_control`].
+    pub fn set_congestion_controller<F>(&mut self, congestion_controller: F)
+    where
+        F: FnMut(&Link<TAG>) -> Option<Box<dyn CongestionController>> + Send + 'static,
+    {
+        self.congestion_controller = Box::new(congestion_controller);
+    }
+
+    /// Sets the link scheduler.
+    ///
+    /// The link scheduler selects the link that carries the next data packet.
+    /// By default the [FirstReady](crate::sched::FirstReady) scheduler is used.
+    pub fn set_link_scheduler(&mut self, link_scheduler: impl LinkScheduler<TAG> + 'static) {
+        self.link_scheduler = Box::new(link_scheduler);
+    }
+
     /// Enables dumping of analysis data over the provided channel while the aggregator task is running.
     ///
     /// The purpose of the dumped data is to debug connection performance issues
@@ -1870,20 +2614,53 @@ where
     ///
     /// Sending over the channel is performed without blocking,
     /// i.e. if no sufficient send space is available the dump data is discarded.
+    ///
+    /// Link tags are included in the dump data using their debug formatting.
     #[cfg(feature = "dump")]
     #[cfg_attr(docsrs, doc(cfg(feature = "dump")))]
-    pub fn dump(&mut self, tx: mpsc::Sender<super::dump::ConnDump>) {
-        self.dump_tx = Some(tx);
+    pub fn dump(&mut self, tx: mpsc::Sender<super::dump::ConnDump>)
+    where
+        TAG: fmt::Debug,
+    {
+        self.dump_tx = Some((tx, |tag| format!("{tag:?}")));
+    }
+
+    /// Dump data for analysis using the specified function to format link tags.
+    #[cfg(feature = "dump")]
+    fn dump_data(&self, fmt_tag: fn(&TAG) -> String) -> super::dump::ConnDump {
+        super::dump::ConnDump {
+            version: super::dump::DUMP_VERSION,
+            conn_id: self.conn_id.get().0,
+            runtime: self.start_time.elapsed().as_secs_f32(),
+            txed_unacked: self.txed_unacked,
+            txed_unconsumable: self.txed_unconsumable,
+            txed_unconsumed: self.txed_unconsumed,
+            send_buffer: self.cfg.send_buffer.get(),
+            remote_receive_buffer: self.remote_cfg.as_ref().map(|cfg| cfg.recv_buffer.get()).unwrap_or_default(),
+            tx_seq: self.tx_seq.into(),
+            txed_packets: self.txed_packets.len(),
+            resend_queue: self.resend_queue.len(),
+            rx_seq: self.rx_seq.into(),
+            rxed_reliable: self.rxed_reliable.len(),
+            rxed_reliable_consumable: self.rxed_reliable_consumable.len(),
+            rxed_reliable_size: self.rxed_reliable_size,
+            rxed_reliable_consumed_since_last_ack: self.rxed_reliable_consumed_since_last_ack,
+            sent_redundant: self.txed_redundant,
+            recved_duplicates: self.rxed_duplicates,
+            sent_datagrams: self.txed_datagrams,
+            recved_datagrams: self.rxed_datagrams,
+            links: self.links.iter().flatten().map(|link| link.dump(fmt_tag)).collect(),
+        }
     }
 
     /// Sends dump data.
     #[cfg(feature = "dump")]
     fn send_dump(&mut self) {
-        if let Some(tx) = &self.dump_tx {
+        if let Some((tx, fmt_tag)) = &self.dump_tx {
             let mut closed = false;
 
             match tx.try_reserve() {
-                Ok(permit) => permit.send(super::dump::ConnDump::from(&*self)),
+                Ok(permit) => permit.send(self.dump_data(*fmt_tag)),
                 Err(mpsc::error::TrySendError::Full(_)) => (),
                 Err(mpsc::error::TrySendError::Closed(_)) => closed = true,
             }
@@ -1926,34 +2703,8 @@ where
     }
 }
 
-#[cfg(feature = "dump")]
-impl<TX, RX, TAG> From<&Task<TX, RX, TAG>> for super::dump::ConnDump {
-    fn from(task: &Task<TX, RX, TAG>) -> Self {
-        use super::dump::LinkDump;
-
-        let mut links: Vec<_> = task.links.iter().map(|opt| opt.as_ref().map(LinkDump::from)).collect();
-
-        Self {
-            conn_id: task.conn_id.get().0,
-            runtime: task.start_time.elapsed().as_secs_f32(),
-            txed_unacked: task.txed_unacked,
-            txed_unconsumable: task.txed_unconsumable,
-            txed_unconsumed: task.txed_unconsumed,
-            send_buffer: task.cfg.send_buffer.get(),
-            remote_receive_buffer: task.remote_cfg.as_ref().map(|cfg| cfg.recv_buffer.get()).unwrap_or_default(),
-            resend_queue: task.resend_queue.len(),
-            rxed_reliable_size: task.rxed_reliable_size,
-            rxed_reliable_consumed_since_last_ack: task.rxed_reliable_consumed_since_last_ack,
-            link0: links.get_mut(0).and_then(Option::take).unwrap_or_default(),
-            link1: links.get_mut(1).and_then(Option::take).unwrap_or_default(),
-            link2: links.get_mut(2).and_then(Option::take).unwrap_or_default(),
-            link3: links.get_mut(3).and_then(Option::take).unwrap_or_default(),
-            link4: links.get_mut(4).and_then(Option::take).unwrap_or_default(),
-            link5: links.get_mut(5).and_then(Option::take).unwrap_or_default(),
-            link6: links.get_mut(6).and_then(Option::take).unwrap_or_default(),
-            link7: links.get_mut(7).and_then(Option::take).unwrap_or_default(),
-            link8: links.get_mut(8).and_then(Option::take).unwrap_or_default(),
-            link9: links.get_mut(9).and_then(Option::take).unwrap_or_default(),
-        }
-    }
+/// Clears the vector and reuses its allocation for elements of a different lifetime.
+fn recycle_vec<'a, 'b, T: 'a, U: 'b>(mut vec: Vec<T>) -> Vec<U> {
+    vec.clear();
+    vec.into_iter().map(|_| unreachable!()).collect()
 }
@@ -4,7 +4,7 @@ use bytes::Bytes;
 use std::{
     io,
     pin::Pin,
-    sync::Arc,
+    sync::{Arc, Mutex as StdMutex},
     task::{Context, Poll},
 };
 use tokio::{
@@ -12,11 +12,15 @@ use tokio::{
     sync::{mpsc, watch},
 };
 
-use super::{Receiver, ReceiverStream, RecvError, SendError, Sender, SenderSink};
+use super::{
+    mux, DatagramReceiver, DatagramSender, Mux, MuxCfg, MuxTask, Receiver, ReceiverStream, RecvError, SendError,
+    Sender, SenderSink,
+};
 use crate::{
     agg::task::SendReq,
     cfg::{Cfg, ExchangedCfg},
     id::ConnId,
+    shaper::TokenBucket,
 };
 
 /// A bi-directional channel backed by a connection of aggregated links.
@@ -35,6 +39,8 @@ pub struct Channel {
     rx: mpsc::Receiver<Bytes>,
     rx_closed: mpsc::Sender<()>,
     rx_error: watch::Receiver<Option<RecvError>>,
+    shaper: Arc<StdMutex<TokenBucket>>,
+    datagrams: Option<(mpsc::Sender<Bytes>, mpsc::Receiver<Bytes>)>,
 }
 
 impl Channel {
@@ -42,9 +48,21 @@ impl Channel {
     pub(crate) fn new(
         cfg: Arc<Cfg>, remote_cfg: Option<Arc<ExchangedCfg>>, conn_id: ConnId, tx: mpsc::Sender<SendReq>,
         tx_error: watch::Receiver<SendError>, rx: mpsc::Receiver<Bytes>, rx_closed: mpsc::Sender<()>,
-        rx_error: watch::Receiver<Option<RecvError>>,
+        rx_error: watch::Receiver<Option<RecvError>>, shaper: Arc<StdMutex<TokenBucket>>,
+        datagrams: (mpsc::Sender<Bytes>, mpsc::Receiver<Bytes>),
     ) -> Self {
-        Self { cfg, remote_cfg, conn_id, tx, tx_error, rx, rx_closed, rx_error }
+        Self {
+            cfg,
+            remote_cfg,
+            conn_id,
+            tx,
+            tx_error,
+            rx,
+            rx_closed,
+            rx_error,
+            shaper,
+            datagrams: Some(datagrams),
+        }
     }
 
     /// Connection id.
@@ -58,13 +76,28 @@ impl Channel {
         self.remote_cfg = Some(remote_cfg);
     }
 
+    /// Takes the sender and receiver for unreliable datagrams.
+    ///
+    /// Datagrams are exchanged over the same links as the reliable data, but they are not resent
+    /// when lost and may arrive out of order.
+    /// If the datagram channel is not taken before this channel is converted, datagrams
+    /// are not available for this connection.
+    ///
+    /// Returns `None` if the datagram channel has already been taken or if the
+    /// configuration of the remote endpoint is not yet known.
+    pub fn datagrams(&mut self) -> Option<(DatagramSender, DatagramReceiver)> {
+        let max_size = self.remote_cfg.as_ref()?.datagram_max_size as usize;
+        let (tx, rx) = self.datagrams.take()?;
+        Some((DatagramSender::new(self.conn_id, max_size, tx), DatagramReceiver::new(self.conn_id, rx)))
+    }
+
     /// Splits this into sender and receiver for messages.
     ///
     /// Note that the local sender is connected to the receiver *of the remote endpoint* and vice versa.
     pub fn into_tx_rx(self) -> (Sender, Receiver) {
-        let Self { cfg, remote_cfg, conn_id, tx, tx_error, rx, rx_closed, rx_error } = self;
+        let Self { cfg, remote_cfg, conn_id, tx, tx_error, rx, rx_closed, rx_error, shaper, .. } = self;
 
-        let tx = Sender::new(cfg, remote_cfg.unwrap(), conn_id, tx, tx_error);
+        let tx = Sender::new(cfg, remote_cfg.unwrap(), conn_id, tx, tx_error, shaper);
         let rx = Receiver::new(conn_id, rx, rx_closed, rx_error);
 
         (tx, rx)
@@ -75,6 +108,16 @@ impl Channel {
         let (tx, rx) = self.into_tx_rx();
         Stream { tx: tx.into_sink(), rx: rx.into_stream() }
     }
+
+    /// Converts this into a [stream multiplexer](Mux) carrying many independent streams.
+    ///
+    /// The remote endpoint must convert its channel into a multiplexer, too.
+    /// The returned [MuxTask] must be executed for the multiplexer to work.
+    pub fn into_mux(self, cfg: MuxCfg) -> (MuxTask, Mux) {
+        let io_write_size = self.cfg.io_write_size.get();
+        let (tx, rx) = self.into_tx_rx();
+        mux::new(tx, rx, io_write_size, cfg)
+    }
 }
 
 /// A bi-directional IO stream backed by a connection of aggregated links,
@@ -0,0 +1,140 @@
+//! Unreliable datagrams over aggregated links.
+
+use bytes::Bytes;
+use std::{
+    fmt, io,
+    task::{Context, Poll},
+};
+use tokio::sync::mpsc;
+
+use crate::id::ConnId;
+
+/// Error sending a datagram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DatagramError {
+    /// The remote endpoint does not support datagrams.
+    Unsupported,
+    /// Datagram size exceeds the maximum datagram size of the remote endpoint.
+    TooBig,
+    /// The send queue is full.
+    QueueFull,
+    /// The connection task was terminated.
+    TaskTerminated,
+}
+
+impl fmt::Display for DatagramError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unsupported => write!(f, "datagrams not supported by remote endpoint"),
+            Self::TooBig => write!(f, "datagram too big for remote endpoint"),
+            Self::QueueFull => write!(f, "datagram send queue is full"),
+            Self::TaskTerminated => write!(f, "task terminated"),
+        }
+    }
+}
+
+impl std::error::Error for DatagramError {}
+
+impl From<DatagramError> for io::Error {
+    fn from(err: DatagramError) -> Self {
+        let kind = match &err {
+            DatagramError::Unsupported => io::ErrorKind::Unsupported,
+            DatagramError::TooBig => io::ErrorKind::InvalidData,
+            DatagramError::QueueFull => io::ErrorKind::WouldBlock,
+            DatagramError::TaskTerminated => io::ErrorKind::ConnectionAborted,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/// The sending half of the unreliable datagram channel of a connection.
+///
+/// Datagrams are sent over the same links as the reliable data stream,
+/// but they are neither acknowledged nor resent and may arrive out of order.
+/// A datagram is dropped if no link is ready for sending when it is processed.
+#[derive(Clone)]
+pub struct DatagramSender {
+    conn_id: ConnId,
+    max_size: usize,
+    tx: mpsc::Sender<Bytes>,
+}
+
+impl fmt::Debug for DatagramSender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DatagramSender").field("id", &self.conn_id).field("max_size", &self.max_size).finish()
+    }
+}
+
+impl DatagramSender {
+    pub(crate) fn new(conn_id: ConnId, max_size: usize, tx: mpsc::Sender<Bytes>) -> Self {
+        Self { conn_id, max_size, tx }
+    }
+
+    /// Connection id.
+    pub fn id(&self) -> ConnId {
+        self.conn_id
+    }
+
+    /// Maximum datagram size, as negotiated with the remote endpoint.
+    ///
+    /// Zero if the remote endpoint does not support datagrams.
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Queues a datagram for sending.
+    ///
+    /// This never waits.
+    /// Success does not imply that the datagram will be delivered.
+    pub fn send(&self, data: Bytes) -> Result<(), DatagramError> {
+        if self.max_size == 0 {
+            return Err(DatagramError::Unsupported);
+        }
+
+        if data.len() > self.max_size {
+            return Err(DatagramError::TooBig);
+        }
+
+        self.tx.try_send(data).map_err(|err| match err {
+            mpsc::error::TrySendError::Full(_) => DatagramError::QueueFull,
+            mpsc::error::TrySendError::Closed(_) => DatagramError::TaskTerminated,
+        })
+    }
+}
+
+/// The receiving half of the unreliable datagram channel of a connection.
+///
+/// Received datagrams are dropped if they are not received fast enough.
+pub struct DatagramReceiver {
+    conn_id: ConnId,
+    rx: mpsc::Receiver<Bytes>,
+}
+
+impl fmt::Debug for DatagramReceiver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DatagramReceiver").field("id", &self.conn_id).finish()
+    }
+}
+
+impl DatagramReceiver {
+    pub(crate) fn new(conn_id: ConnId, rx: mpsc::Receiver<Bytes>) -> Self {
+        Self { conn_id, rx }
+    }
+
+    /// Connection id.
+    pub fn id(&self) -> ConnId {
+        self.conn_id
+    }
+
+    /// Receives the next datagram.
+    ///
+    /// Returns `None` when the connection has been terminated.
+    pub async fn recv(&mut self) -> Option<Bytes> {
+        self.rx.recv().await
+    }
+
+    /// Polls to receive the next datagram.
+    pub fn poll_recv(&mut self, cx: &mut Context) -> Poll<Option<Bytes>> {
+        self.rx.poll_recv(cx)
+    }
+}
@@ -4,12 +4,18 @@
 //!
 //! An [aggregated link channel](Channel) supports both message-based communication,
 //! using a [Sender] and [Receiver], and [stream-based IO](Stream).
+//! It can also be converted into a [stream multiplexer](Mux) carrying many independent streams.
+//! Additionally, [unreliable datagrams](Channel::datagrams) can be exchanged over the same links.
 //!
 
 mod channel;
+mod datagram;
+mod mux;
 pub(crate) mod receiver;
 pub(crate) mod sender;
 
 pub use channel::{Channel, Stream};
+pub use datagram::{DatagramError, DatagramReceiver, DatagramSender};
+pub use mux::{Mux, MuxCfg, MuxError, MuxStream, MuxTask, StreamId};
 pub use receiver::{Receiver, ReceiverStream, RecvError};
 pub use sender::{SendError, Sender, SenderSink};
@@ -0,0 +1,952 @@
+//! Stream multiplexer on top of an aggregated link channel.
+//!
+//! A [Mux] carries many independent, bi-directional [MuxStream]s over a single [Channel](super::Channel).
+//! Each stream has its own flow control window, thus a stream whose data is not being read
+//! does not stall the other streams.
+//! A stream can be half-closed by shutting down its sending direction and aborted by resetting it.
+//! When more data is queued for sending than the channel accepts, the available
+//! bandwidth is shared between the streams in proportion to their priority weights.
+//!
+//! Both endpoints of the channel must use a multiplexer.
+//!
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{
+    future::{BoxFuture, OptionFuture},
+    FutureExt,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    future::IntoFuture,
+    io,
+    num::{NonZeroU32, NonZeroU8, NonZeroUsize},
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    select,
+    sync::{mpsc, Notify},
+};
+
+use super::{Receiver, RecvError, SendError, Sender};
+use crate::id::ConnId;
+
+/// Flow control window of a newly opened stream, assumed by both endpoints.
+const INITIAL_WINDOW: u32 = 65_536;
+
+/// Size of frame header.
+const HEADER_SIZE: usize = 5;
+
+/// Stream id bit that is set when the stream was opened by the sender of the frame.
+const OPENER_BIT: u32 = 1 << 31;
+
+/// Pass increment of a stream sending one byte with priority weight one.
+const STRIDE: u64 = 256;
+
+const FRAME_OPEN: u8 = 1;
+const FRAME_DATA: u8 = 2;
+const FRAME_WINDOW: u8 = 3;
+const FRAME_FIN: u8 = 4;
+const FRAME_STOP: u8 = 5;
+const FRAME_RESET: u8 = 6;
+
+/// Multiplexer configuration.
+#[derive(Debug, Clone)]
+#[allow(clippy::manual_non_exhaustive)]
+pub struct MuxCfg {
+    /// Receive window of each stream in bytes.
+    ///
+    /// This is the amount of data the remote endpoint may send on a stream
+    /// before it must wait for the data to be read locally.
+    /// Values below 64 kB are raised to 64 kB.
+    pub stream_window: NonZeroU32,
+    /// Length of queue for incoming streams that have not been accepted yet.
+    ///
+    /// Further streams opened by the remote endpoint are reset.
+    pub accept_queue: NonZeroUsize,
+    /// Priority weight of newly opened and accepted streams.
+    pub default_priority: NonZeroU8,
+    #[doc(hidden)]
+    pub _non_exhaustive: (),
+}
+
+impl Default for MuxCfg {
+    fn default() -> Self {
+        Self {
+            stream_window: NonZeroU32::new(1_048_576).unwrap(),
+            accept_queue: NonZeroUsize::new(32).unwrap(),
+            default_priority: NonZeroU8::new(16).unwrap(),
+            _non_exhaustive: (),
+        }
+    }
+}
+
+impl MuxCfg {
+    /// Receive window of each stream.
+    fn window(&self) -> u32 {
+        self.stream_window.get().max(INITIAL_WINDOW)
+    }
+}
+
+/// Multiplexer error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MuxError {
+    /// The remote endpoint closed the channel.
+    Closed,
+    /// The multiplexer task was terminated.
+    TaskTerminated,
+    /// Sending over the channel failed.
+    Send(SendError),
+    /// Receiving from the channel failed.
+    Recv(RecvError),
+    /// The remote endpoint sent an invalid frame.
+    ProtocolError,
+    /// All stream ids have been used.
+    StreamIdsExhausted,
+}
+
+impl fmt::Display for MuxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Closed => write!(f, "closed by remote endpoint"),
+            Self::TaskTerminated => write!(f, "multiplexer task terminated"),
+            Self::Send(err) => write!(f, "send error: {err}"),
+            Self::Recv(err) => write!(f, "receive error: {err}"),
+            Self::ProtocolError => write!(f, "multiplexer protocol error"),
+            Self::StreamIdsExhausted => write!(f, "stream ids exhausted"),
+        }
+    }
+}
+
+impl std::error::Error for MuxError {}
+
+impl From<MuxError> for io::Error {
+    fn from(err: MuxError) -> Self {
+        let kind = match &err {
+            MuxError::Closed => io::ErrorKind::ConnectionReset,
+            MuxError::ProtocolError => io::ErrorKind::InvalidData,
+            MuxError::StreamIdsExhausted => io::ErrorKind::Other,
+            MuxError::TaskTerminated | MuxError::Send(_) | MuxError::Recv(_) => io::ErrorKind::ConnectionAborted,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/// Identifier of a multiplexed stream.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StreamId {
+    num: u32,
+    local: bool,
+}
+
+impl StreamId {
+    /// Whether the stream was opened by this endpoint.
+    pub fn is_local(&self) -> bool {
+        self.local
+    }
+
+    /// Stream number, unique among the streams opened by the same endpoint.
+    pub fn num(&self) -> u32 {
+        self.num
+    }
+
+    /// Encodes the id from the point of view of the sender of a frame.
+    fn encode(self) -> u32 {
+        if self.local {
+            self.num | OPENER_BIT
+        } else {
+            self.num
+        }
+    }
+
+    /// Decodes the id of a received frame.
+    fn decode(raw: u32) -> Self {
+        Self { num: raw & !OPENER_BIT, local: raw & OPENER_BIT == 0 }
+    }
+}
+
+impl fmt::Debug for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", if self.local { "L" } else { "R" }, self.num)
+    }
+}
+
+/// Frame exchanged between multiplexers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Frame {
+    /// Opens a stream.
+    Open { id: StreamId },
+    /// Stream data.
+    Data { id: StreamId, data: Bytes },
+    /// Grants additional flow control window.
+    Window { id: StreamId, increment: u32 },
+    /// Sender has finished sending on the stream.
+    Fin { id: StreamId },
+    /// Receiver will not read further data from the stream.
+    Stop { id: StreamId },
+    /// Stream is aborted in both directions.
+    Reset { id: StreamId },
+}
+
+impl Frame {
+    fn encode(&self) -> Bytes {
+        let (kind, id) = match self {
+            Self::Open { id } => (FRAME_OPEN, id),
+            Self::Data { id, .. } => (FRAME_DATA, id),
+            Self::Window { id, .. } => (FRAME_WINDOW, id),
+            Self::Fin { id } => (FRAME_FIN, id),
+            Self::Stop { id } => (FRAME_STOP, id),
+            Self::Reset { id } => (FRAME_RESET, id),
+        };
+
+        let size = match self {
+            Self::Data { data, .. } => data.len(),
+            Self::Window { .. } => 4,
+            _ => 0,
+        };
+
+        let mut buf = BytesMut::with_capacity(HEADER_SIZE + size);
+        buf.put_u8(kind);
+        buf.put_u32(id.encode());
+
+        match self {
+            Self::Data { data, .. } => buf.extend_from_slice(data),
+            Self::Window { increment, .. } => buf.put_u32(*increment),
+            Self::Open { .. } | Self::Fin { .. } | Self::Stop { .. } | Self::Reset { .. } => (),
+        }
+
+        buf.freeze()
+    }
+
+    fn decode(mut data: Bytes) -> Result<Self, MuxError> {
+        if data.len() < HEADER_SIZE {
+            return Err(MuxError::ProtocolError);
+        }
+
+        let kind = data.get_u8();
+        let id = StreamId::decode(data.get_u32());
+
+        let frame = match kind {
+            FRAME_OPEN => Self::Open { id },
+            FRAME_DATA => return Ok(Self::Data { id, data }),
+            FRAME_WINDOW => {
+                if data.len() < 4 {
+                    return Err(MuxError::ProtocolError);
+                }
+                Self::Window { id, increment: data.get_u32() }
+            }
+            FRAME_FIN => Self::Fin { id },
+            FRAME_STOP => Self::Stop { id },
+            FRAME_RESET => Self::Reset { id },
+            _ => return Err(MuxError::ProtocolError),
+        };
+
+        Ok(frame)
+    }
+}
+
+/// Item queued for sending on a stream.
+enum TxItem {
+    Data(Bytes),
+    Fin,
+}
+
+/// State of a multiplexed stream.
+struct StreamState {
+    /// Priority weight.
+    priority: NonZeroU8,
+    /// Scheduling pass, the stream with the lowest pass sends next.
+    pass: u64,
+    /// Items queued for sending.
+    tx_queue: VecDeque<TxItem>,
+    /// Remaining flow control window of remote endpoint.
+    tx_credit: u64,
+    /// Waker of writer waiting for credit or for the queue to become empty.
+    tx_waker: Option<Waker>,
+    /// Sending direction has been shut down locally.
+    tx_fin: bool,
+    /// Remote endpoint will not read further data.
+    tx_stopped: bool,
+    /// Received data not yet read.
+    rx_queue: VecDeque<Bytes>,
+    /// Remaining flow control window granted to remote endpoint.
+    rx_window: u64,
+    /// Bytes read since last window grant.
+    rx_consumed: u64,
+    /// Waker of reader waiting for data.
+    rx_waker: Option<Waker>,
+    /// Remote endpoint has finished sending.
+    rx_fin: bool,
+    /// Remote endpoint was told to stop sending.
+    rx_stopped: bool,
+    /// Stream was reset by either endpoint.
+    reset: bool,
+    /// Stream handle has been dropped.
+    dropped: bool,
+}
+
+impl StreamState {
+    fn new(priority: NonZeroU8, pass: u64, rx_window: u32) -> Self {
+        Self {
+            priority,
+            pass,
+            tx_queue: VecDeque::new(),
+            tx_credit: INITIAL_WINDOW.into(),
+            tx_waker: None,
+            tx_fin: false,
+            tx_stopped: false,
+            rx_queue: VecDeque::new(),
+            rx_window: rx_window.into(),
+            rx_consumed: 0,
+            rx_waker: None,
+            rx_fin: false,
+            rx_stopped: false,
+            reset: false,
+            dropped: false,
+        }
+    }
+
+    fn wake_tx(&mut self) {
+        if let Some(waker) = self.tx_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn wake_rx(&mut self) {
+        if let Some(waker) = self.rx_waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Marks the stream as reset and discards all queued data.
+    fn reset(&mut self) {
+        self.reset = true;
+        self.tx_queue.clear();
+        self.rx_queue.clear();
+        self.wake_tx();
+        self.wake_rx();
+    }
+
+    /// Whether the stream state can be released.
+    fn is_released(&self) -> bool {
+        self.dropped && self.tx_queue.is_empty()
+    }
+}
+
+/// Multiplexer state shared between task and handles.
+struct Inner {
+    /// Number of existing multiplexer and stream handles.
+    handles: usize,
+    /// Number of next locally opened stream.
+    next_num: u32,
+    /// Streams.
+    streams: HashMap<StreamId, StreamState>,
+    /// Queued control frames, sent before stream data.
+    control: VecDeque<Frame>,
+    /// Pass of most recently scheduled stream.
+    pass: u64,
+    /// Last requested channel flush.
+    flush_req: u64,
+    /// Last completed channel flush.
+    flush_done: u64,
+    /// Wakers waiting for channel flush.
+    flush_wakers: Vec<Waker>,
+    /// Error that terminated the multiplexer.
+    error: Option<MuxError>,
+}
+
+impl Inner {
+    /// Schedules the next frame for sending.
+    fn next_frame(&mut self) -> Option<Frame> {
+        if let Some(frame) = self.control.pop_front() {
+            return Some(frame);
+        }
+
+        let (&id, stream) = self
+            .streams
+            .iter_mut()
+            .filter(|(_, stream)| !stream.tx_queue.is_empty())
+            .min_by_key(|(id, stream)| (stream.pass, **id))?;
+
+        let frame = match stream.tx_queue.pop_front().unwrap() {
+            TxItem::Data(data) => Frame::Data { id, data },
+            TxItem::Fin => Frame::Fin { id },
+        };
+
+        let size = match &frame {
+            Frame::Data { data, .. } => data.len().max(1) as u64,
+            _ => 1,
+        };
+        self.pass = stream.pass;
+        stream.pass += size * STRIDE / u64::from(stream.priority.get());
+
+        if stream.tx_queue.is_empty() {
+            stream.wake_tx();
+            if stream.is_released() {
+                self.streams.remove(&id);
+            }
+        }
+
+        Some(frame)
+    }
+
+    /// Terminates all streams with the specified error.
+    fn terminate(&mut self, err: MuxError) {
+        if self.error.is_none() {
+            self.error = Some(err);
+        }
+
+        for stream in self.streams.values_mut() {
+            stream.wake_tx();
+            stream.wake_rx();
+        }
+        for waker in self.flush_wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// Shared multiplexer state.
+struct Shared {
+    conn_id: ConnId,
+    cfg: MuxCfg,
+    max_data_size: usize,
+    inner: Mutex<Inner>,
+    /// Notifies the task of queued frames and dropped handles.
+    notify: Notify,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap()
+    }
+
+    /// Locks the shared state and checks that the specified stream has not been reset.
+    fn lock_stream(&self, id: StreamId) -> Result<MutexGuard<'_, Inner>, io::Error> {
+        let inner = self.lock();
+        if inner.streams[&id].reset {
+            return Err(io::Error::new(io::ErrorKind::ConnectionReset, "stream was reset"));
+        }
+        Ok(inner)
+    }
+
+    /// Registers a new stream.
+    fn register(&self, inner: &mut Inner, id: StreamId) {
+        let window = self.cfg.window();
+        let stream = StreamState::new(self.cfg.default_priority, inner.pass, window);
+        inner.streams.insert(id, stream);
+
+        if window > INITIAL_WINDOW {
+            inner.control.push_back(Frame::Window { id, increment: window - INITIAL_WINDOW });
+        }
+    }
+}
+
+/// Creates a stream multiplexer using the specified channel sender and receiver.
+pub(super) fn new(tx: Sender, rx: Receiver, io_write_size: usize, cfg: MuxCfg) -> (MuxTask, Mux) {
+    let max_data_size = tx.max_size().saturating_sub(HEADER_SIZE).min(io_write_size).max(1);
+
+    let shared = Arc::new(Shared {
+        conn_id: tx.id(),
+        max_data_size,
+        inner: Mutex::new(Inner {
+            handles: 1,
+            next_num: 0,
+            streams: HashMap::new(),
+            control: VecDeque::new(),
+            pass: 0,
+            flush_req: 0,
+            flush_done: 0,
+            flush_wakers: Vec::new(),
+            error: None,
+        }),
+        notify: Notify::new(),
+        cfg,
+    });
+
+    let (accept_tx, accept_rx) = mpsc::channel(shared.cfg.accept_queue.get());
+
+    let task = MuxTask { shared: shared.clone(), tx, rx, accept_tx };
+    let mux = Mux { shared, accept_rx };
+    (task, mux)
+}
+
+/// Task running a stream multiplexer.
+///
+/// This must be executed (for example using [`tokio::spawn`]) for the multiplexer to work.
+///
+/// It returns when the multiplexer and all its streams have been dropped or the channel
+/// has been closed.
+#[must_use = "the multiplexer task must be run for the multiplexer to work"]
+pub struct MuxTask {
+    shared: Arc<Shared>,
+    tx: Sender,
+    rx: Receiver,
+    accept_tx: mpsc::Sender<MuxStream>,
+}
+
+impl fmt::Debug for MuxTask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MuxTask").field("id", &self.shared.conn_id).finish()
+    }
+}
+
+/// Operation in progress on the channel sender.
+struct Sending {
+    fut: BoxFuture<'static, Result<(), SendError>>,
+    flush: Option<u64>,
+}
+
+impl MuxTask {
+    /// Connection id.
+    pub fn id(&self) -> ConnId {
+        self.shared.conn_id
+    }
+
+    /// Runs the multiplexer.
+    ///
+    /// This returns when the multiplexer and all its streams have been dropped or the channel
+    /// has been closed.
+    pub async fn run(self) -> Result<(), MuxError> {
+        let Self { shared, tx, mut rx, accept_tx } = self;
+        let tx = Arc::new(tx);
+        let mut sending: Option<Sending> = None;
+
+        let result = loop {
+            if sending.is_none() {
+                let mut inner = shared.lock();
+                if let Some(frame) = inner.next_frame() {
+                    let tx = tx.clone();
+                    let data = frame.encode();
+                    sending = Some(Sending { fut: async move { tx.send(data).await }.boxed(), flush: None });
+                } else if inner.flush_req > inner.flush_done {
+                    let tx = tx.clone();
+                    sending = Some(Sending {
+                        fut: async move { tx.flush().await }.boxed(),
+                        flush: Some(inner.flush_req),
+                    });
+                } else if inner.handles == 0 {
+                    break Ok(());
+                }
+            }
+
+            select! {
+                res = OptionFuture::from(sending.as_mut().map(|s| &mut s.fut)), if sending.is_some() => {
+                    let Sending { flush, .. } = sending.take().unwrap();
+                    if let Err(err) = res.unwrap() {
+                        break Err(MuxError::Send(err));
+                    }
+                    if let Some(flush) = flush {
+                        let mut inner = shared.lock();
+                        inner.flush_done = flush;
+                        for waker in inner.flush_wakers.drain(..) {
+                            waker.wake();
+                        }
+                    }
+                }
+                res = rx.recv() => {
+                    match res {
+                        Ok(Some(data)) => {
+                            if let Err(err) = Self::handle_frame(&shared, &accept_tx, data) {
+                                break Err(err);
+                            }
+                        }
+                        Ok(None) => break Err(MuxError::Closed),
+                        Err(err) => break Err(MuxError::Recv(err)),
+                    }
+                }
+                () = shared.notify.notified() => (),
+            }
+        };
+
+        tracing::debug!("multiplexer terminated: {result:?}");
+        shared.lock().terminate(result.clone().err().unwrap_or(MuxError::TaskTerminated));
+
+        match result {
+            Err(MuxError::Closed) => Ok(()),
+            other => other,
+        }
+    }
+
+    /// Handles a frame received from the remote endpoint.
+    fn handle_frame(
+        shared: &Arc<Shared>, accept_tx: &mpsc::Sender<MuxStream>, data: Bytes,
+    ) -> Result<(), MuxError> {
+        let frame = Frame::decode(data)?;
+        tracing::trace!("received frame {frame:?}");
+
+        let mut inner = shared.lock();
+        let inner = &mut *inner;
+
+        match frame {
+            Frame::Open { id } => {
+                if id.local || inner.streams.contains_key(&id) {
+                    return Err(MuxError::ProtocolError);
+                }
+
+                match accept_tx.try_reserve() {
+                    Ok(permit) => {
+                        shared.register(inner, id);
+                        permit.send(MuxStream::new(shared.clone(), inner, id));
+                    }
+                    Err(_) => {
+                        tracing::debug!("refusing incoming stream {id}");
+                        inner.control.push_back(Frame::Reset { id });
+                    }
+                }
+            }
+            Frame::Data { id, data } => {
+                let Some(stream) = inner.streams.get_mut(&id) else { return Ok(()) };
+                if stream.reset || stream.rx_stopped || stream.dropped {
+                    return Ok(());
+                }
+                if stream.rx_fin || data.len() as u64 > stream.rx_window {
+                    tracing::debug!("resetting stream {id} due to flow control violation");
+                    stream.reset();
+                    inner.control.push_back(Frame::Reset { id });
+                    return Ok(());
+                }
+
+                stream.rx_window -= data.len() as u64;
+                if data.is_empty() {
+                    return Ok(());
+                }
+                stream.rx_queue.push_back(data);
+                stream.wake_rx();
+            }
+            Frame::Window { id, increment } => {
+                if let Some(stream) = inner.streams.get_mut(&id) {
+                    stream.tx_credit += u64::from(increment);
+                    stream.wake_tx();
+                }
+            }
+            Frame::Fin { id } => {
+                if let Some(stream) = inner.streams.get_mut(&id) {
+                    stream.rx_fin = true;
+                    stream.wake_rx();
+                }
+            }
+            Frame::Stop { id } => {
+                if let Some(stream) = inner.streams.get_mut(&id) {
+                    stream.tx_stopped = true;
+                    stream.tx_queue.clear();
+                    stream.wake_tx();
+                    if stream.is_released() {
+                        inner.streams.remove(&id);
+                    }
+                }
+            }
+            Frame::Reset { id } => {
+                if let Some(stream) = inner.streams.get_mut(&id) {
+                    stream.reset();
+                    if stream.is_released() {
+                        inner.streams.remove(&id);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl IntoFuture for MuxTask {
+    type Output = Result<(), MuxError>;
+    type IntoFuture = BoxFuture<'static, Result<(), MuxError>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        self.run().boxed()
+    }
+}
+
+/// Stream multiplexer on top of an aggregated link channel.
+///
+/// Use [Channel::into_mux](super::Channel::into_mux) to create a multiplexer.
+pub struct Mux {
+    shared: Arc<Shared>,
+    accept_rx: mpsc::Receiver<MuxStream>,
+}
+
+impl fmt::Debug for Mux {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Mux").field("id", &self.shared.conn_id).finish()
+    }
+}
+
+impl Drop for Mux {
+    fn drop(&mut self) {
+        self.shared.lock().handles -= 1;
+        self.shared.notify.notify_one();
+    }
+}
+
+impl Mux {
+    /// Connection id.
+    pub fn id(&self) -> ConnId {
+        self.shared.conn_id
+    }
+
+    /// Opens a new stream.
+    ///
+    /// The remote endpoint is notified of the stream and data can be sent immediately.
+    pub fn open(&self) -> Result<MuxStream, MuxError> {
+        let mut inner = self.shared.lock();
+        if let Some(err) = &inner.error {
+            return Err(err.clone());
+        }
+
+        if inner.next_num & OPENER_BIT != 0 {
+            return Err(MuxError::StreamIdsExhausted);
+        }
+        let id = StreamId { num: inner.next_num, local: true };
+        inner.next_num += 1;
+
+        inner.control.push_back(Frame::Open { id });
+        self.shared.register(&mut inner, id);
+        let stream = MuxStream::new(self.shared.clone(), &mut inner, id);
+
+        self.shared.notify.notify_one();
+        Ok(stream)
+    }
+
+    /// Accepts the next stream opened by the remote endpoint.
+    pub async fn accept(&mut self) -> Result<MuxStream, MuxError> {
+        match self.accept_rx.recv().await {
+            Some(stream) => Ok(stream),
+            None => Err(self.shared.lock().error.clone().unwrap_or(MuxError::TaskTerminated)),
+        }
+    }
+}
+
+/// A bi-directional stream of a [stream multiplexer](Mux), implementing [AsyncRead] and [AsyncWrite].
+///
+/// Shutting down the stream finishes its sending direction, while data can still be received.
+/// Dropping the stream finishes the sending direction and tells the remote endpoint to stop sending.
+pub struct MuxStream {
+    shared: Arc<Shared>,
+    id: StreamId,
+    flush: Option<u64>,
+}
+
+impl fmt::Debug for MuxStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MuxStream").field("conn_id", &self.shared.conn_id).field("id", &self.id).finish()
+    }
+}
+
+impl MuxStream {
+    fn new(shared: Arc<Shared>, inner: &mut Inner, id: StreamId) -> Self {
+        inner.handles += 1;
+        Self { shared, id, flush: None }
+    }
+
+    /// Connection id.
+    pub fn conn_id(&self) -> ConnId {
+        self.shared.conn_id
+    }
+
+    /// Stream id.
+    pub fn id(&self) -> StreamId {
+        self.id
+    }
+
+    /// Priority weight of the stream.
+    pub fn priority(&self) -> NonZeroU8 {
+        self.shared.lock().streams[&self.id].priority
+    }
+
+    /// Sets the priority weight of the stream.
+    ///
+    /// When multiple streams have data queued for sending, each stream receives
+    /// a share of the bandwidth proportional to its priority weight.
+    pub fn set_priority(&self, priority: NonZeroU8) {
+        self.shared.lock().streams.get_mut(&self.id).unwrap().priority = priority;
+    }
+
+    /// Aborts the stream in both directions.
+    ///
+    /// Queued data is discarded and the remote endpoint is notified.
+    pub fn reset(self) {
+        {
+            let mut inner = self.shared.lock();
+            let stream = inner.streams.get_mut(&self.id).unwrap();
+            if !stream.reset {
+                stream.reset();
+                inner.control.push_back(Frame::Reset { id: self.id });
+            }
+        }
+        self.shared.notify.notify_one();
+    }
+}
+
+impl Drop for MuxStream {
+    fn drop(&mut self) {
+        let mut inner = self.shared.lock();
+        let inner = &mut *inner;
+
+        inner.handles -= 1;
+
+        let stream = inner.streams.get_mut(&self.id).unwrap();
+        stream.dropped = true;
+
+        if !stream.reset {
+            if !stream.tx_fin && !stream.tx_stopped {
+                if stream.tx_queue.is_empty() {
+                    stream.pass = stream.pass.max(inner.pass);
+                }
+                stream.tx_queue.push_back(TxItem::Fin);
+                stream.tx_fin = true;
+            }
+
+            if !stream.rx_fin && !stream.rx_stopped {
+                stream.rx_stopped = true;
+                stream.rx_queue.clear();
+                inner.control.push_back(Frame::Stop { id: self.id });
+            }
+        }
+
+        if stream.is_released() {
+            inner.streams.remove(&self.id);
+        }
+
+        self.shared.notify.notify_one();
+    }
+}
+
+impl AsyncRead for MuxStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut ReadBuf) -> Poll<io::Result<()>> {
+        let this = Pin::into_inner(self);
+        let mut inner = this.shared.lock_stream(this.id)?;
+        let inner = &mut *inner;
+        let stream = inner.streams.get_mut(&this.id).unwrap();
+
+        if let Some(data) = stream.rx_queue.front_mut() {
+            let len = buf.remaining().min(data.len());
+            buf.put_slice(&data.split_to(len));
+            if data.is_empty() {
+                stream.rx_queue.pop_front();
+            }
+
+            stream.rx_consumed += len as u64;
+            if !stream.rx_fin && stream.rx_consumed >= u64::from(this.shared.cfg.window() / 2) {
+                let increment = stream.rx_consumed as u32;
+                stream.rx_window += stream.rx_consumed;
+                stream.rx_consumed = 0;
+                inner.control.push_back(Frame::Window { id: this.id, increment });
+                this.shared.notify.notify_one();
+            }
+
+            return Poll::Ready(Ok(()));
+        }
+
+        if stream.rx_fin {
+            return Poll::Ready(Ok(()));
+        }
+
+        if let Some(err) = &inner.error {
+            return Poll::Ready(Err(err.clone().into()));
+        }
+
+        stream.rx_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl AsyncWrite for MuxStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<Result<usize, io::Error>> {
+        let this = Pin::into_inner(self);
+        let mut inner = this.shared.lock_stream(this.id)?;
+        let inner = &mut *inner;
+
+        if let Some(err) = &inner.error {
+            return Poll::Ready(Err(err.clone().into()));
+        }
+
+        let stream = inner.streams.get_mut(&this.id).unwrap();
+        if stream.tx_stopped {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "stream was stopped by remote endpoint",
+            )));
+        }
+        if stream.tx_fin {
+            return Poll::Ready(Err(io::Error::new(io::ErrorKind::BrokenPipe, "stream was shut down")));
+        }
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        if stream.tx_credit == 0 {
+            stream.tx_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let len = buf.len().min(this.shared.max_data_size).min(stream.tx_credit.try_into().unwrap_or(usize::MAX));
+        if stream.tx_queue.is_empty() {
+            stream.pass = stream.pass.max(inner.pass);
+        }
+        stream.tx_queue.push_back(TxItem::Data(Bytes::copy_from_slice(&buf[..len])));
+        stream.tx_credit -= len as u64;
+
+        this.shared.notify.notify_one();
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        let this = Pin::into_inner(self);
+        let mut inner = this.shared.lock_stream(this.id)?;
+        let inner = &mut *inner;
+
+        if let Some(err) = &inner.error {
+            return Poll::Ready(Err(err.clone().into()));
+        }
+
+        let stream = inner.streams.get_mut(&this.id).unwrap();
+        if !stream.tx_queue.is_empty() {
+            stream.tx_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let flush = *this.flush.get_or_insert_with(|| {
+            inner.flush_req += 1;
+            this.shared.notify.notify_one();
+            inner.flush_req
+        });
+
+        if inner.flush_done >= flush {
+            this.flush = None;
+            Poll::Ready(Ok(()))
+        } else {
+            inner.flush_wakers.push(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        {
+            let this = &mut *self;
+            let mut inner = this.shared.lock_stream(this.id)?;
+            let inner = &mut *inner;
+
+            let stream = inner.streams.get_mut(&this.id).unwrap();
+            if !stream.tx_fin && !stream.tx_stopped {
+                if stream.tx_queue.is_empty() {
+                    stream.pass = stream.pass.max(inner.pass);
+                }
+                stream.tx_queue.push_back(TxItem::Fin);
+                stream.tx_fin = true;
+                this.shared.notify.notify_one();
+            }
+        }
+
+        self.poll_flush(cx)
+    }
+}
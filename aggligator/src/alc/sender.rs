@@ -4,13 +4,15 @@ use bytes::Bytes;
 use futures::{ready, FutureExt, Sink, SinkExt};
 use std::{
     fmt, io,
+    num::NonZeroUsize,
     pin::Pin,
-    sync::Arc,
+    sync::{Arc, Mutex as StdMutex},
     task::{Context, Poll},
 };
 use tokio::{
     io::AsyncWrite,
     sync::{mpsc, oneshot, watch},
+    time::{sleep_until, Sleep},
 };
 use tokio_util::sync;
 
@@ -18,6 +20,7 @@ use crate::{
     agg::task::SendReq,
     cfg::{Cfg, ExchangedCfg},
     id::ConnId,
+    shaper::TokenBucket,
 };
 
 /// Error sending to an aggregated link channel.
@@ -84,6 +87,7 @@ pub struct Sender {
     conn_id: ConnId,
     tx: mpsc::Sender<SendReq>,
     error_rx: watch::Receiver<SendError>,
+    shaper: Arc<StdMutex<TokenBucket>>,
 }
 
 impl fmt::Debug for Sender {
@@ -95,9 +99,9 @@ impl fmt::Debug for Sender {
 impl Sender {
     pub(crate) fn new(
         cfg: Arc<Cfg>, remote_cfg: Arc<ExchangedCfg>, conn_id: ConnId, tx: mpsc::Sender<SendReq>,
-        error_rx: watch::Receiver<SendError>,
+        error_rx: watch::Receiver<SendError>, shaper: Arc<StdMutex<TokenBucket>>,
     ) -> Self {
-        Self { cfg, remote_cfg, conn_id, tx, error_rx }
+        Self { cfg, remote_cfg, conn_id, tx, error_rx, shaper }
     }
 
     /// Connection id.
@@ -106,13 +110,38 @@ impl Sender {
     }
 
     /// Enqueues data for sending.
+    ///
+    /// Waits if the [send rate limit](crate::cfg::Cfg::send_rate_limit) has been reached.
     #[inline]
     pub async fn send(&self, data: Bytes) -> Result<(), SendError> {
+        self.send_redundant(data, self.cfg.redundancy).await
+    }
+
+    /// Enqueues data for sending over the specified number of links.
+    ///
+    /// This overrides the [redundancy](crate::cfg::Cfg::redundancy) of the connection
+    /// for this data.
+    pub async fn send_redundant(&self, data: Bytes, redundancy: NonZeroUsize) -> Result<(), SendError> {
         if data.len() > self.max_size() {
             return Err(SendError::DataTooBig);
         }
 
-        self.tx.send(SendReq::Send(data)).await.map_err(|_| self.error_rx.borrow().clone())
+        loop {
+            let ready_at = {
+                let mut shaper = self.shaper.lock().unwrap();
+                let ready_at = shaper.ready_at();
+                if ready_at.is_none() {
+                    shaper.consume(data.len());
+                }
+                ready_at
+            };
+            match ready_at {
+                Some(ready_at) => sleep_until(ready_at).await,
+                None => break,
+            }
+        }
+
+        self.tx.send(SendReq::Send(data, redundancy)).await.map_err(|_| self.error_rx.borrow().clone())
     }
 
     /// Flushes data queued for sending.
@@ -131,14 +160,17 @@ impl Sender {
 
     /// Converts this sender into a [SenderSink], that implements the [Sink] and [AsyncWrite] traits.
     pub fn into_sink(self) -> SenderSink {
-        let Self { cfg, remote_cfg, conn_id, tx, error_rx } = self;
+        let Self { cfg, remote_cfg, conn_id, tx, error_rx, shaper } = self;
         SenderSink {
+            redundancy: cfg.redundancy,
             cfg,
             remote_cfg,
             conn_id,
             tx: sync::PollSender::new(tx),
             flushed_rx: None,
             error_rx,
+            shaper,
+            shaper_sleep: None,
             closed: false,
         }
     }
@@ -154,6 +186,9 @@ pub struct SenderSink {
     tx: sync::PollSender<SendReq>,
     flushed_rx: Option<oneshot::Receiver<()>>,
     error_rx: watch::Receiver<SendError>,
+    shaper: Arc<StdMutex<TokenBucket>>,
+    shaper_sleep: Option<Pin<Box<Sleep>>>,
+    redundancy: NonZeroUsize,
     closed: bool,
 }
 
@@ -179,6 +214,18 @@ impl SenderSink {
     pub fn max_size(&self) -> usize {
         max_send_size(&self.remote_cfg)
     }
+
+    /// Number of links data is sent over.
+    pub fn redundancy(&self) -> NonZeroUsize {
+        self.redundancy
+    }
+
+    /// Sets the number of links data is sent over.
+    ///
+    /// The default is specified by the [redundancy](crate::cfg::Cfg::redundancy) of the connection.
+    pub fn set_redundancy(&mut self, redundancy: NonZeroUsize) {
+        self.redundancy = redundancy;
+    }
 }
 
 impl Sink<Bytes> for SenderSink {
@@ -192,6 +239,18 @@ impl Sink<Bytes> for SenderSink {
             return Poll::Ready(Err(SendError::Shutdown));
         }
 
+        loop {
+            if let Some(sleep) = &mut this.shaper_sleep {
+                ready!(sleep.poll_unpin(cx));
+                this.shaper_sleep = None;
+            }
+
+            match this.shaper.lock().unwrap().ready_at() {
+                Some(ready_at) => this.shaper_sleep = Some(Box::pin(sleep_until(ready_at))),
+                None => break,
+            }
+        }
+
         this.tx.poll_ready_unpin(cx).map_err(|_| this.error_rx.borrow().clone())
     }
 
@@ -207,7 +266,8 @@ impl Sink<Bytes> for SenderSink {
             return Err(SendError::DataTooBig);
         }
 
-        this.tx.start_send_unpin(SendReq::Send(item)).map_err(|_| this.error_rx.borrow().clone())
+        this.shaper.lock().unwrap().consume(item.len());
+        this.tx.start_send_unpin(SendReq::Send(item, this.redundancy)).map_err(|_| this.error_rx.borrow().clone())
     }
 
     #[inline]
@@ -0,0 +1,125 @@
+//! Link authentication using pre-shared keys or tokens.
+//!
+//! Both endpoints prove knowledge of the key by sending an HMAC over the
+//! Diffie-Hellman transcript of the link handshake.
+//! Thus a proof cannot be replayed on another link.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, SharedSecret};
+
+use crate::{cfg::LinkAuth, id::ServerId};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Size of an authentication code.
+pub(crate) const MAC_SIZE: usize = 32;
+
+/// Authentication code.
+pub(crate) type AuthMac = [u8; MAC_SIZE];
+
+/// Proof of key knowledge sent by the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AuthProof {
+    /// Identifier of the token used; empty for a pre-shared key.
+    pub token_id: Vec<u8>,
+    /// Authentication code.
+    pub mac: AuthMac,
+}
+
+/// Role of endpoint proving its knowledge of the key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Role {
+    Client,
+    Server,
+}
+
+impl Role {
+    fn label(self) -> &'static [u8] {
+        match self {
+            Self::Client => b"aggligator link auth client",
+            Self::Server => b"aggligator link auth server",
+        }
+    }
+}
+
+/// Handshake transcript the authentication is bound to.
+pub(crate) struct Transcript<'a> {
+    /// Server id.
+    pub server_id: ServerId,
+    /// Diffie-Hellman public key of server.
+    pub server_public_key: &'a PublicKey,
+    /// Diffie-Hellman public key of client.
+    pub client_public_key: &'a PublicKey,
+    /// Diffie-Hellman shared secret.
+    pub shared_secret: &'a SharedSecret,
+}
+
+impl Transcript<'_> {
+    fn hmac(&self, key: &[u8], role: Role, token_id: &[u8]) -> HmacSha256 {
+        let mut hmac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+        hmac.update(role.label());
+        hmac.update(&self.server_id.0.get().to_be_bytes());
+        hmac.update(self.server_public_key.as_bytes());
+        hmac.update(self.client_public_key.as_bytes());
+        hmac.update(self.shared_secret.as_bytes());
+        hmac.update(&(token_id.len() as u16).to_be_bytes());
+        hmac.update(token_id);
+        hmac
+    }
+
+    /// Calculates the authentication code of the specified role.
+    pub fn mac(&self, key: &[u8], role: Role, token_id: &[u8]) -> AuthMac {
+        self.hmac(key, role, token_id).finalize().into_bytes().into()
+    }
+
+    /// Verifies the authentication code of the specified role in constant time.
+    pub fn verify(&self, key: &[u8], role: Role, token_id: &[u8], mac: &AuthMac) -> bool {
+        self.hmac(key, role, token_id).verify_slice(mac).is_ok()
+    }
+}
+
+impl LinkAuth {
+    /// Key and token id used by a client.
+    pub(crate) fn client_key(&self) -> Option<(&[u8], &[u8])> {
+        match self {
+            Self::PreSharedKey(key) => Some((key, &[])),
+            Self::Tokens(tokens) => tokens.first().map(|token| (&token.key[..], token.id.as_bytes())),
+        }
+    }
+
+    /// Key for the specified token id accepted by a server.
+    pub(crate) fn server_key(&self, token_id: &[u8]) -> Option<&[u8]> {
+        match self {
+            Self::PreSharedKey(key) if token_id.is_empty() => Some(key),
+            Self::PreSharedKey(_) => None,
+            Self::Tokens(tokens) => {
+                tokens.iter().find(|token| token.id.as_bytes() == token_id).map(|token| &token.key[..])
+            }
+        }
+    }
+
+    /// Creates the proof of the client.
+    pub(crate) fn client_proof(&self, transcript: &Transcript) -> Option<AuthProof> {
+        let (key, token_id) = self.client_key()?;
+        Some(AuthProof { token_id: token_id.to_vec(), mac: transcript.mac(key, Role::Client, token_id) })
+    }
+
+    /// Verifies the proof of the client and returns the authentication code of the server.
+    pub(crate) fn verify_client(&self, transcript: &Transcript, proof: Option<&AuthProof>) -> Option<AuthMac> {
+        let proof = proof?;
+        let key = self.server_key(&proof.token_id)?;
+        if !transcript.verify(key, Role::Client, &proof.token_id, &proof.mac) {
+            return None;
+        }
+        Some(transcript.mac(key, Role::Server, &proof.token_id))
+    }
+
+    /// Verifies the authentication code of the server.
+    pub(crate) fn verify_server(&self, transcript: &Transcript, mac: Option<&AuthMac>) -> bool {
+        match (self.client_key(), mac) {
+            (Some((key, token_id)), Some(mac)) => transcript.verify(key, Role::Server, token_id, mac),
+            _ => false,
+        }
+    }
+}
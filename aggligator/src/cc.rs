@@ -0,0 +1,306 @@
+//! Per-link congestion control.
+//!
+//! A [congestion controller](CongestionController) limits the amount of unacknowledged data
+//! of a link, i.e. its congestion window, and may pace sending over the link.
+//! Each link has its own controller instance.
+//!
+//! The controller is selected using [`Cfg::congestion_control`](crate::cfg::Cfg::congestion_control)
+//! or by setting a custom controller on the connection task using
+//! [`Task::set_congestion_controller`](crate::Task::set_congestion_controller).
+//!
+//! Besides the adaptive heuristic, which is the default and has no controller type,
+//! the following controllers are provided:
+//!
+//!   * [Bbr] estimates the bottleneck bandwidth and minimum round trip time of the link
+//!     from acknowledgement timing and keeps the link's buffers short.
+//!
+
+use std::{collections::VecDeque, mem, time::Duration};
+use tokio::time::Instant;
+
+/// Acknowledgement of data sent over a link, provided to a [congestion controller](CongestionController).
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct AckSample {
+    /// Time the acknowledgement was received.
+    pub now: Instant,
+    /// Size of the acknowledged data in bytes.
+    pub size: usize,
+    /// Round trip time of the acknowledged packet.
+    ///
+    /// `None` if the packet has been resent, since its acknowledgement is ambiguous then.
+    pub rtt: Option<Duration>,
+    /// Data of the link that is still unacknowledged in bytes.
+    pub unacked: usize,
+}
+
+/// Congestion controller of a link.
+///
+/// The controller is notified of sent and acknowledged data and determines the
+/// congestion window and pacing rate of the link.
+pub trait CongestionController: Send {
+    /// Notifies the controller that data of the specified size has been sent.
+    ///
+    /// `unacked` is the data of the link that is unacknowledged after sending in bytes.
+    fn on_sent(&mut self, _now: Instant, _size: usize, _unacked: usize) {}
+
+    /// Notifies the controller that sent data has been acknowledged.
+    fn on_ack(&mut self, sample: &AckSample);
+
+    /// Notifies the controller that the link could send more data, but none is available.
+    ///
+    /// The delivery rate measured until the sent data has been acknowledged is thus
+    /// limited by the application and not by the link.
+    fn on_app_limited(&mut self, _now: Instant) {}
+
+    /// Notifies the controller that acknowledgement of sent data timed out.
+    ///
+    /// The link is tested before it is used again.
+    fn on_timeout(&mut self, _now: Instant) {}
+
+    /// Congestion window, i.e. the limit of unacknowledged data in bytes.
+    ///
+    /// It is clamped to [`Cfg::link_unacked_limit`](crate::cfg::Cfg::link_unacked_limit).
+    fn window(&self) -> usize;
+
+    /// Pacing rate in bytes per second.
+    ///
+    /// `None` if data is sent as fast as the congestion window allows.
+    fn pacing_rate(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Mode of the [Bbr] congestion controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BbrMode {
+    /// Exponential growth to find the bottleneck bandwidth.
+    Startup,
+    /// Drains the queue built up during startup.
+    Drain,
+    /// Cycles the pacing rate around the bottleneck bandwidth.
+    ProbeBw,
+    /// Reduces the data in flight to measure the minimum round trip time.
+    ProbeRtt,
+}
+
+/// Delay-based congestion controller modelled after BBR.
+///
+/// The bottleneck bandwidth is estimated as the maximum delivery rate over the last
+/// rounds and the propagation delay as the minimum round trip time over the last
+/// ten seconds.
+/// Rounds during which the sender ran out of data only raise the bandwidth estimate.
+/// The congestion window is kept at twice their product and sending is paced at
+/// the bottleneck bandwidth, so that links with deep buffers do not build up
+/// queues and thus latency under load.
+#[derive(Debug, Clone)]
+pub struct Bbr {
+    mode: BbrMode,
+    /// Window used until the bandwidth has been estimated.
+    init_window: usize,
+    /// Lowest congestion window.
+    min_window: usize,
+    /// Delivery rate samples of the last rounds in bytes per second.
+    bw_samples: VecDeque<(u64, f64)>,
+    /// Estimated bottleneck bandwidth in bytes per second.
+    btl_bw: f64,
+    /// Estimated minimum round trip time and when it was measured.
+    min_rtt: Option<(Duration, Instant)>,
+    /// Current round.
+    round: u64,
+    /// Start of current round.
+    round_start: Instant,
+    /// Data delivered during current round.
+    round_delivered: usize,
+    /// Whether the sender ran out of data during current round.
+    round_app_limited: bool,
+    /// Bandwidth when startup last made progress.
+    full_bw: f64,
+    /// Rounds without startup progress.
+    full_bw_rounds: u32,
+    /// Position within the pacing gain cycle.
+    cycle_idx: usize,
+    /// End of probing the round trip time.
+    probe_rtt_end: Option<Instant>,
+    /// Unacknowledged data.
+    unacked: usize,
+}
+
+impl Bbr {
+    /// Gain of startup.
+    const STARTUP_GAIN: f64 = 2.885;
+    /// Gain of the congestion window after startup.
+    const CWND_GAIN: f64 = 2.0;
+    /// Pacing gains cycled through while probing the bandwidth.
+    const PACING_GAINS: [f64; 8] = [1.25, 0.75, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0];
+    /// Number of rounds delivery rate samples are kept.
+    const BW_WINDOW_ROUNDS: u64 = 10;
+    /// Time after which the minimum round trip time is measured again.
+    const MIN_RTT_WINDOW: Duration = Duration::from_secs(10);
+    /// Minimum duration of probing the round trip time.
+    const PROBE_RTT_DURATION: Duration = Duration::from_millis(200);
+    /// Round length used until the round trip time is known.
+    const DEFAULT_ROUND: Duration = Duration::from_millis(100);
+
+    /// Creates a new controller using the specified initial and minimum congestion windows.
+    pub fn new(init_window: usize, min_window: usize) -> Self {
+        Self {
+            mode: BbrMode::Startup,
+            init_window,
+            min_window,
+            bw_samples: VecDeque::new(),
+            btl_bw: 0.0,
+            min_rtt: None,
+            round: 0,
+            round_start: Instant::now(),
+            round_delivered: 0,
+            round_app_limited: false,
+            full_bw: 0.0,
+            full_bw_rounds: 0,
+            cycle_idx: 0,
+            probe_rtt_end: None,
+            unacked: 0,
+        }
+    }
+
+    /// Estimated bottleneck bandwidth in bytes per second.
+    pub fn bandwidth(&self) -> f64 {
+        self.btl_bw
+    }
+
+    /// Estimated minimum round trip time.
+    pub fn min_rtt(&self) -> Option<Duration> {
+        self.min_rtt.map(|(rtt, _)| rtt)
+    }
+
+    /// Estimated bandwidth-delay product in bytes.
+    fn bdp(&self) -> Option<f64> {
+        let min_rtt = self.min_rtt()?;
+        (self.btl_bw > 0.0).then_some(self.btl_bw * min_rtt.as_secs_f64())
+    }
+
+    /// Updates the minimum round trip time and enters round trip probing when it expired.
+    fn update_min_rtt(&mut self, now: Instant, rtt: Duration) {
+        match self.min_rtt {
+            Some((min_rtt, stamp)) if now.duration_since(stamp) > Self::MIN_RTT_WINDOW => {
+                if self.mode != BbrMode::ProbeRtt && self.mode != BbrMode::Startup {
+                    self.mode = BbrMode::ProbeRtt;
+                    self.probe_rtt_end = Some(now + Self::PROBE_RTT_DURATION.max(min_rtt));
+                }
+                self.min_rtt = Some((rtt, now));
+            }
+            Some((min_rtt, _)) if rtt > min_rtt => (),
+            _ => self.min_rtt = Some((rtt, now)),
+        }
+    }
+
+    /// Ends the current round when it lasted a round trip and takes a delivery rate sample.
+    fn update_round(&mut self, now: Instant, size: usize) {
+        self.round_delivered += size;
+
+        let elapsed = now.duration_since(self.round_start);
+        if elapsed < self.min_rtt().unwrap_or(Self::DEFAULT_ROUND) || elapsed.is_zero() {
+            return;
+        }
+
+        let rate = self.round_delivered as f64 / elapsed.as_secs_f64();
+        let app_limited = mem::take(&mut self.round_app_limited);
+        self.round += 1;
+        self.round_start = now;
+        self.round_delivered = 0;
+
+        // The delivery rate of an application-limited round includes idle time and thus
+        // underestimates the bandwidth, unless it exceeds the current estimate.
+        if !app_limited || rate > self.btl_bw {
+            self.bw_samples.push_back((self.round, rate));
+            while matches!(self.bw_samples.front(), Some((round, _)) if round + Self::BW_WINDOW_ROUNDS <= self.round)
+            {
+                self.bw_samples.pop_front();
+            }
+            self.btl_bw = self.bw_samples.iter().map(|(_, rate)| *rate).fold(0.0, f64::max);
+        }
+
+        match self.mode {
+            BbrMode::Startup if !app_limited => {
+                if self.btl_bw >= self.full_bw * 1.25 {
+                    self.full_bw = self.btl_bw;
+                    self.full_bw_rounds = 0;
+                } else {
+                    self.full_bw_rounds += 1;
+                    if self.full_bw_rounds >= 3 {
+                        self.mode = BbrMode::Drain;
+                    }
+                }
+            }
+            BbrMode::ProbeBw => self.cycle_idx = (self.cycle_idx + 1) % Self::PACING_GAINS.len(),
+            BbrMode::Startup | BbrMode::Drain | BbrMode::ProbeRtt => (),
+        }
+    }
+}
+
+impl CongestionController for Bbr {
+    fn on_sent(&mut self, _now: Instant, _size: usize, unacked: usize) {
+        self.unacked = unacked;
+    }
+
+    fn on_ack(&mut self, sample: &AckSample) {
+        self.unacked = sample.unacked;
+
+        if let Some(rtt) = sample.rtt {
+            self.update_min_rtt(sample.now, rtt);
+        }
+        self.update_round(sample.now, sample.size);
+
+        match self.mode {
+            BbrMode::Drain if self.bdp().map(|bdp| self.unacked as f64 <= bdp).unwrap_or(true) => {
+                self.mode = BbrMode::ProbeBw;
+                self.cycle_idx = 0;
+            }
+            BbrMode::ProbeRtt if self.probe_rtt_end.map(|end| sample.now >= end).unwrap_or(true) => {
+                self.mode = BbrMode::ProbeBw;
+                self.probe_rtt_end = None;
+            }
+            _ => (),
+        }
+    }
+
+    fn on_app_limited(&mut self, _now: Instant) {
+        self.round_app_limited = true;
+    }
+
+    fn on_timeout(&mut self, now: Instant) {
+        // Link conditions have changed, thus start over estimating the bandwidth.
+        self.mode = BbrMode::Startup;
+        self.bw_samples.clear();
+        self.btl_bw = 0.0;
+        self.full_bw = 0.0;
+        self.full_bw_rounds = 0;
+        self.round_start = now;
+        self.round_delivered = 0;
+        self.round_app_limited = false;
+        self.probe_rtt_end = None;
+    }
+
+    fn window(&self) -> usize {
+        let Some(bdp) = self.bdp() else { return self.init_window };
+        let gain = match self.mode {
+            BbrMode::Startup => Self::STARTUP_GAIN,
+            BbrMode::ProbeRtt => return self.min_window,
+            BbrMode::Drain | BbrMode::ProbeBw => Self::CWND_GAIN,
+        };
+        ((gain * bdp) as usize).max(self.min_window)
+    }
+
+    fn pacing_rate(&self) -> Option<u64> {
+        if self.btl_bw <= 0.0 {
+            return None;
+        }
+        let gain = match self.mode {
+            BbrMode::Startup => Self::STARTUP_GAIN,
+            BbrMode::Drain => 1.0 / Self::STARTUP_GAIN,
+            BbrMode::ProbeBw => Self::PACING_GAINS[self.cycle_idx],
+            BbrMode::ProbeRtt => 1.0,
+        };
+        Some(((gain * self.btl_bw) as u64).max(1))
+    }
+}
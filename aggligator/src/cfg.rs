@@ -2,12 +2,12 @@
 
 use byteorder::{ReadBytesExt, WriteBytesExt, BE};
 use std::{
-    io,
-    num::{NonZeroU32, NonZeroUsize},
+    fmt, io,
+    num::{NonZeroU32, NonZeroU64, NonZeroUsize},
     time::Duration,
 };
 
-use crate::protocol_err;
+use crate::{control::QuotaState, msg::LinkMsg, protocol_err};
 
 /// Link pinging mode.
 #[cfg_attr(feature = "dump", derive(serde::Serialize, serde::Deserialize))]
@@ -22,6 +22,169 @@ pub enum LinkPing {
     WhenTimedOut,
 }
 
+/// Bonding mode, i.e. how the links of a connection are used for sending data.
+#[cfg_attr(feature = "dump", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum BondingMode {
+    /// Data is distributed over all working links by the [link scheduler](crate::sched).
+    #[default]
+    Aggregate,
+    /// Only the working links with the highest [priority](crate::control::Link::set_priority)
+    /// carry data.
+    ///
+    /// Links of lower priority stay connected and are pinged, but carry no data until
+    /// all links of higher priority are unconfirmed, blocked or disconnected.
+    ActiveBackup,
+    /// Each data packet is sent over all working links.
+    ///
+    /// The first copy arriving at the remote endpoint is used and the others are discarded.
+    /// Sending proceeds at the pace of the slowest link.
+    Broadcast,
+}
+
+/// Congestion control of links.
+#[cfg_attr(feature = "dump", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum CongestionControl {
+    /// The unacknowledged data limit of a link is grown while it is exhausted and shrunk
+    /// when the remote endpoint cannot keep up or the link approaches the maximum ping.
+    #[default]
+    Adaptive,
+    /// Delay-based congestion control using the [Bbr](crate::cc::Bbr) controller,
+    /// which also paces sending.
+    Bbr,
+}
+
+/// Compression of data sent over a connection.
+///
+/// Compression is only used if the remote endpoint supports it.
+/// Each data packet is compressed individually and sent uncompressed if it does not shrink.
+#[cfg_attr(feature = "dump", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Compression {
+    /// No compression.
+    #[default]
+    None,
+    /// Fast compression using lz4.
+    Lz4,
+    /// Compression using zstd with the specified compression level.
+    ///
+    /// This requires the `zstd` feature and falls back to lz4 if it is not enabled
+    /// or the remote endpoint does not support zstd.
+    Zstd(i32),
+}
+
+/// Byte quota of a link or of all links with the same tag.
+///
+/// Both sent and received bytes count towards the quota.
+#[cfg_attr(feature = "dump", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Quota {
+    /// Soft threshold in bytes.
+    ///
+    /// When reached, the link is only used for sending data if no other link is usable.
+    pub soft: Option<u64>,
+    /// Hard threshold in bytes.
+    ///
+    /// When reached, the link is blocked.
+    pub hard: Option<u64>,
+}
+
+impl Quota {
+    /// Unlimited quota.
+    pub const UNLIMITED: Self = Self { soft: None, hard: None };
+
+    /// Quota state for the specified amount of used bytes.
+    pub fn state(&self, used: u64) -> QuotaState {
+        if self.hard.map(|hard| used >= hard).unwrap_or_default() {
+            QuotaState::HardExceeded
+        } else if self.soft.map(|soft| used >= soft).unwrap_or_default() {
+            QuotaState::SoftExceeded
+        } else {
+            QuotaState::Within
+        }
+    }
+
+    /// Bytes that can be used until the next threshold is reached, starting
+    /// from the specified amount of used bytes.
+    pub(crate) fn remaining(&self, used: u64) -> u64 {
+        [self.soft, self.hard]
+            .into_iter()
+            .flatten()
+            .filter(|&threshold| threshold > used)
+            .map(|threshold| threshold - used)
+            .min()
+            .unwrap_or(u64::MAX)
+    }
+}
+
+/// Data rate limit enforced by a token bucket.
+#[cfg_attr(feature = "dump", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RateLimit {
+    /// Sustained rate in bytes per second.
+    pub rate: NonZeroU64,
+    /// Maximum burst size in bytes.
+    pub burst: u64,
+}
+
+/// Rate limit for incoming links enforced by a token bucket.
+#[cfg_attr(feature = "dump", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IncomingRateLimit {
+    /// Sustained rate in links per second.
+    pub rate: NonZeroU64,
+    /// Maximum number of links accepted in a burst.
+    pub burst: NonZeroU64,
+}
+
+/// Authentication of links during the handshake.
+///
+/// Both endpoints prove that they know the key by sending an authentication code
+/// over the Diffie-Hellman transcript of the handshake.
+/// The key itself is never sent.
+///
+/// This authenticates links, but does not encrypt data sent over them.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LinkAuth {
+    /// Key known to both endpoints.
+    PreSharedKey(Vec<u8>),
+    /// Named tokens.
+    ///
+    /// A client authenticates using the first token.
+    /// A server accepts links authenticated using any of the tokens.
+    Tokens(Vec<AuthToken>),
+}
+
+impl fmt::Debug for LinkAuth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::PreSharedKey(_) => write!(f, "PreSharedKey(..)"),
+            Self::Tokens(tokens) => f.debug_tuple("Tokens").field(tokens).finish(),
+        }
+    }
+}
+
+/// Named authentication token.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AuthToken {
+    /// Token identifier, which is sent to the server.
+    ///
+    /// Its size must not exceed 64 kB.
+    pub id: String,
+    /// Secret key.
+    pub key: Vec<u8>,
+}
+
+impl fmt::Debug for AuthToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AuthToken").field("id", &self.id).finish_non_exhaustive()
+    }
+}
+
 /// Configuration of a connection consisting of aggregated links.
 ///
 /// For most use cases the default configuration, i.e. [`Cfg::default()`](Self::default),
@@ -44,6 +207,8 @@ pub struct Cfg {
     /// Length of queue for sending data packets.
     pub send_queue: NonZeroUsize,
     /// Maximum number of unacknowledged received bytes.
+    ///
+    /// Compressed data is accounted for by its size after decompression.
     pub recv_buffer: NonZeroU32,
     /// Length of queue for received data packets.
     pub recv_queue: NonZeroUsize,
@@ -51,7 +216,8 @@ pub struct Cfg {
     pub link_ack_timeout_min: Duration,
     /// Factor to calculate acknowledgement timeout from roundtrip time.
     ///
-    /// Timeout is given by current roundtrip time (ping) of the link times this factor.
+    /// Timeout is given by the smoothed roundtrip time (ping) of the link times this factor
+    /// plus four times the variation of the roundtrip time.
     pub link_ack_timeout_roundtrip_factor: NonZeroU32,
     /// Maximum timeout waiting for a packet to be acknowledged.
     pub link_ack_timeout_max: Duration,
@@ -59,6 +225,11 @@ pub struct Cfg {
     pub link_unacked_init: NonZeroUsize,
     /// Maximum amount of sent unacknowledged data per link.
     pub link_unacked_limit: NonZeroUsize,
+    /// Congestion control of links.
+    ///
+    /// A custom [congestion controller](crate::cc::CongestionController) can be set using
+    /// [`Task::set_congestion_controller`](crate::Task::set_congestion_controller).
+    pub congestion_control: CongestionControl,
     /// Link pinging mode.
     pub link_ping: LinkPing,
     /// Timeout for waiting for ping response, which when exceeded leads to removal of the link.
@@ -73,16 +244,124 @@ pub struct Cfg {
     pub link_retest_interval: Duration,
     /// Timeout after which a non-working link is disconnected.
     pub link_non_working_timeout: Duration,
+    /// Timeout for acknowledgement of outstanding data when draining a link.
+    ///
+    /// After it has passed, the link is disconnected and unacknowledged data is resent
+    /// over other links.
+    pub link_drain_timeout: Duration,
     /// Delay before flushing a link when it has become idle.
     pub link_flush_delay: Duration,
     /// Timeout after which connection is closed when no working links are present.
     pub no_link_timeout: Duration,
+    /// Enables connection resumption and specifies how long the connection is kept when
+    /// no working links are present.
+    ///
+    /// This replaces [`no_link_timeout`](Self::no_link_timeout).
+    /// The server issues a resumption ticket to the client, which it must present
+    /// when adding links to the existing connection.
+    /// Data in flight is preserved and resent once new links have been added.
+    pub resume_window: Option<Duration>,
     /// Timeout after which connection is forcefully closed when sender and receiver are closed.
     pub termination_timeout: Duration,
     /// Queue length for establishing connections.
     pub connect_queue: NonZeroUsize,
+    /// Timeout for the handshake of an incoming link on the server.
+    pub handshake_timeout: Duration,
+    /// Maximum number of concurrent connections of a server.
+    ///
+    /// When reached, links starting new connections are refused.
+    pub max_connections: Option<NonZeroUsize>,
+    /// Maximum number of links per connection accepted by a server.
+    ///
+    /// When reached, links joining the connection are refused.
+    pub max_links_per_connection: Option<NonZeroUsize>,
+    /// Maximum number of incoming connections waiting to be accepted by the
+    /// [listener](crate::connect::Listener) of a server.
+    ///
+    /// When reached, links starting new connections are refused instead of
+    /// waiting for the listener.
+    pub max_pending_incoming: Option<NonZeroUsize>,
+    /// Rate limit for incoming links per remote address of a server.
+    ///
+    /// It only applies to links added using
+    /// [`Server::add_incoming_from`](crate::connect::Server::add_incoming_from).
+    /// Links exceeding it are refused before the handshake.
+    /// At most 65,536 remote addresses are tracked at a time; while this limit is reached,
+    /// links from further addresses are refused.
+    pub incoming_rate_limit: Option<IncomingRateLimit>,
     /// Disconnect the aggregated connection when a server id mismatch occurs while connecting a link.
     pub disconnect_on_server_id_mismatch: bool,
+    /// Authentication of links.
+    ///
+    /// If set on the server, links that fail to authenticate are refused.
+    /// If set on the client, links to a server that fails to authenticate are not added.
+    /// It is never included in dumps.
+    #[cfg_attr(feature = "dump", serde(skip))]
+    pub link_auth: Option<LinkAuth>,
+    /// End-to-end encryption of data sent over the connection.
+    ///
+    /// Data is encrypted and authenticated using keys agreed once per connection,
+    /// so that links do not need to be encrypted individually.
+    /// If [`link_auth`](Self::link_auth) uses a pre-shared key, it is mixed into the keys.
+    ///
+    /// If set on the client, encryption is requested and links to servers that do not
+    /// support it are not added.
+    /// If set on the server, links that do not request encryption are refused.
+    /// A server encrypts data if requested by the client, regardless of this setting.
+    ///
+    /// Encryption does not hide the length of data packets, which reveals information
+    /// about their content when [compression](Self::compression) is enabled.
+    pub data_encryption: bool,
+    /// Compression of sent data.
+    ///
+    /// Data is compressed before it is encrypted.
+    ///
+    /// **Warning:** the compressed size of a packet depends on its content.
+    /// If a packet contains both secrets and data influenced by an attacker,
+    /// the attacker can recover the secrets from the lengths of encrypted packets
+    /// (as in the CRIME and BREACH attacks).
+    /// Do not enable compression together with [encryption](Self::data_encryption)
+    /// for such data.
+    pub compression: Compression,
+    /// Bonding mode, i.e. how links are used for sending data.
+    pub bonding_mode: BondingMode,
+    /// Number of links each data packet is sent over.
+    ///
+    /// Copies of a data packet are sent over the usable links with the lowest roundtrip time,
+    /// and the first copy that arrives is used by the remote endpoint.
+    /// If fewer links are usable, the packet is sent over all of them and the shortfall is
+    /// counted in [`Stats::sent_redundancy_shortfall`](crate::control::Stats::sent_redundancy_shortfall).
+    /// This trades bandwidth for latency.
+    /// It can be overridden for individual packets using
+    /// [`Sender::send_redundant`](crate::alc::Sender::send_redundant).
+    pub redundancy: NonZeroUsize,
+    /// Byte quota of each link.
+    ///
+    /// It can be changed for an individual link using [`Link::set_quota`](crate::control::Link::set_quota).
+    /// Quotas shared by all links with the same tag are set using
+    /// [`Control::set_tag_quota`](crate::control::Control::set_tag_quota).
+    pub link_quota: Quota,
+    /// Rate limit for sending data over each link.
+    ///
+    /// Control messages, such as acknowledgements, are neither delayed nor counted.
+    /// It can be changed for an individual link using
+    /// [`Link::set_rate_limit`](crate::control::Link::set_rate_limit).
+    pub link_rate_limit: Option<RateLimit>,
+    /// Rate limit for sending data over the connection.
+    ///
+    /// It is enforced when data is submitted to the [sender](crate::alc::Sender) and
+    /// can be changed using [`Control::set_send_rate_limit`](crate::control::Control::set_send_rate_limit).
+    pub send_rate_limit: Option<RateLimit>,
+    /// Maximum size of a received [datagram](crate::alc::DatagramReceiver).
+    ///
+    /// This limits the size of datagrams the remote endpoint may send.
+    /// Zero disables the reception of datagrams.
+    pub datagram_max_size: u32,
+    /// Length of queues for sending and receiving datagrams.
+    pub datagram_queue: NonZeroUsize,
+    /// Number of connection events buffered for each
+    /// [event stream](crate::control::Control::events).
+    pub event_queue: NonZeroUsize,
     /// Link speed statistics interval durations.
     pub stats_intervals: Vec<Duration>,
     #[doc(hidden)]
@@ -103,17 +382,36 @@ impl Default for Cfg {
             link_ack_timeout_max: Duration::from_secs(30),
             link_unacked_init: NonZeroUsize::new(8192).unwrap(),
             link_unacked_limit: NonZeroUsize::new(33_554_432).unwrap(),
+            congestion_control: CongestionControl::Adaptive,
             link_ping: LinkPing::WhenIdle(Duration::from_secs(15)),
             link_ping_timeout: Duration::from_secs(40),
             link_max_ping: None,
             link_test_data_limit: usize::MAX,
             link_retest_interval: Duration::from_secs(15),
             link_non_working_timeout: Duration::from_secs(600),
+            link_drain_timeout: Duration::from_secs(30),
             link_flush_delay: Duration::from_millis(500),
             no_link_timeout: Duration::from_secs(90),
+            resume_window: None,
             termination_timeout: Duration::from_secs(300),
             connect_queue: NonZeroUsize::new(32).unwrap(),
+            handshake_timeout: Duration::from_secs(40),
+            max_connections: None,
+            max_links_per_connection: None,
+            max_pending_incoming: None,
+            incoming_rate_limit: None,
             disconnect_on_server_id_mismatch: true,
+            link_auth: None,
+            data_encryption: false,
+            compression: Compression::None,
+            bonding_mode: BondingMode::Aggregate,
+            redundancy: NonZeroUsize::new(1).unwrap(),
+            link_quota: Quota::UNLIMITED,
+            link_rate_limit: None,
+            send_rate_limit: None,
+            datagram_max_size: 16_384,
+            datagram_queue: NonZeroUsize::new(64).unwrap(),
+            event_queue: NonZeroUsize::new(256).unwrap(),
             stats_intervals: vec![
                 Duration::from_millis(100),
                 Duration::from_secs(1),
@@ -130,25 +428,155 @@ impl Default for Cfg {
 pub(crate) struct ExchangedCfg {
     /// Maximum number of unacknowledged bytes.
     pub recv_buffer: NonZeroU32,
+    /// Maximum size of a received datagram.
+    ///
+    /// Zero if datagrams are not supported.
+    pub datagram_max_size: u32,
+    /// Whether sent data packets are prefixed by a compression header.
+    ///
+    /// Exchanged in the configuration section.
+    pub compressed: bool,
+    /// Highest supported protocol version.
+    ///
+    /// Exchanged in the configuration section.
+    /// `None` if not transmitted, in which case the protocol version of the
+    /// handshake message is the only supported version.
+    pub max_version: Option<u8>,
+    /// Supported protocol extensions.
+    ///
+    /// This is not transmitted as part of the configuration.
+    pub extensions: u32,
 }
 
 impl ExchangedCfg {
-    pub fn write(&self, mut writer: impl io::Write) -> Result<(), io::Error> {
+    /// Version of the configuration section.
+    ///
+    /// It is increased when new entry types are added.
+    const SECTION_VERSION: u8 = 1;
+
+    /// Configuration section entry: whether sent data packets are compressed.
+    const ENTRY_COMPRESSED: u16 = 1;
+
+    /// Configuration section entry: highest supported protocol version.
+    const ENTRY_MAX_VERSION: u16 = 2;
+
+    /// The configuration as used with the specified protocol version.
+    ///
+    /// Protocol extensions and the configuration section are only used from
+    /// [`LinkMsg::NEGOTIATION_VERSION`] onwards.
+    pub fn for_version(self, version: u8) -> Self {
+        if version >= LinkMsg::NEGOTIATION_VERSION {
+            return self;
+        }
+        Self { datagram_max_size: 0, compressed: false, extensions: 0, ..self }
+    }
+
+    /// Writes the configuration, including the fields of the specified protocol extensions.
+    pub fn write(&self, mut writer: impl io::Write, extensions: u32) -> Result<(), io::Error> {
         writer.write_u32::<BE>(self.recv_buffer.get())?;
+        if extensions & LinkMsg::EXT_DATAGRAM != 0 {
+            writer.write_u32::<BE>(self.datagram_max_size)?;
+        }
+        if extensions & LinkMsg::EXT_CFG_SECTION != 0 {
+            self.write_section(writer)?;
+        }
         Ok(())
     }
 
-    pub fn read(mut reader: impl io::Read) -> Result<Self, io::Error> {
-        let this = Self {
+    /// Reads the configuration, including the fields of the specified protocol extensions.
+    pub fn read(mut reader: impl io::Read, extensions: u32) -> Result<Self, io::Error> {
+        let mut this = Self {
             recv_buffer: NonZeroU32::new(reader.read_u32::<BE>()?)
                 .ok_or_else(|| protocol_err!("recv_buffer must not be zero"))?,
+            datagram_max_size: if extensions & LinkMsg::EXT_DATAGRAM != 0 {
+                reader.read_u32::<BE>()?
+            } else {
+                0
+            },
+            compressed: false,
+            max_version: None,
+            extensions,
         };
+        if extensions & LinkMsg::EXT_CFG_SECTION != 0 {
+            this.read_section(reader)?;
+        }
         Ok(this)
     }
+
+    /// Writes the configuration section.
+    ///
+    /// It consists of the section version, its length and a sequence of entries.
+    /// Each entry is encoded as type, length and value.
+    fn write_section(&self, mut writer: impl io::Write) -> Result<(), io::Error> {
+        let mut entries = Vec::new();
+        if self.compressed {
+            Self::write_entry(&mut entries, Self::ENTRY_COMPRESSED, &[1])?;
+        }
+        if let Some(max_version) = self.max_version {
+            Self::write_entry(&mut entries, Self::ENTRY_MAX_VERSION, &[max_version])?;
+        }
+
+        writer.write_u8(Self::SECTION_VERSION)?;
+        writer.write_u16::<BE>(
+            entries.len().try_into().map_err(|_| protocol_err!("configuration section is too long"))?,
+        )?;
+        writer.write_all(&entries)?;
+        Ok(())
+    }
+
+    /// Writes an entry of the configuration section.
+    fn write_entry(mut writer: impl io::Write, ty: u16, value: &[u8]) -> Result<(), io::Error> {
+        writer.write_u16::<BE>(ty)?;
+        writer.write_u16::<BE>(
+            value.len().try_into().map_err(|_| protocol_err!("configuration entry is too long"))?,
+        )?;
+        writer.write_all(value)?;
+        Ok(())
+    }
+
+    /// Reads the configuration section.
+    ///
+    /// Entries of unknown type are skipped, so that new entries can be added
+    /// without breaking compatibility.
+    fn read_section(&mut self, mut reader: impl io::Read) -> Result<(), io::Error> {
+        let version = reader.read_u8()?;
+        if version == 0 {
+            return Err(protocol_err!("invalid configuration section version"));
+        }
+
+        let len = reader.read_u16::<BE>()?;
+        let mut section = vec![0; len.into()];
+        reader.read_exact(&mut section)?;
+
+        let mut section = &section[..];
+        while !section.is_empty() {
+            let ty = section.read_u16::<BE>()?;
+            let len = section.read_u16::<BE>()?.into();
+            if section.len() < len {
+                return Err(protocol_err!("configuration entry exceeds section"));
+            }
+            let (value, rest) = section.split_at(len);
+            section = rest;
+
+            match ty {
+                Self::ENTRY_COMPRESSED => self.compressed = value.first().copied().unwrap_or_default() != 0,
+                Self::ENTRY_MAX_VERSION => self.max_version = value.first().copied(),
+                _ => tracing::trace!("skipping unknown configuration entry of type {ty}"),
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl From<&Cfg> for ExchangedCfg {
     fn from(cfg: &Cfg) -> Self {
-        Self { recv_buffer: cfg.recv_buffer }
+        Self {
+            recv_buffer: cfg.recv_buffer,
+            datagram_max_size: cfg.datagram_max_size,
+            compressed: cfg.compression != Compression::None,
+            max_version: Some(LinkMsg::PROTOCOL_VERSION),
+            extensions: LinkMsg::EXTENSIONS,
+        }
     }
 }
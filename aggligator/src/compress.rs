@@ -0,0 +1,119 @@
+//! Compression of data.
+//!
+//! When enabled, each data packet is prefixed by a header byte specifying
+//! the compression method used for it.
+//! A packet is sent uncompressed if compression does not reduce its size.
+//! The lz4 method is always supported by endpoints that support compression,
+//! while zstd support is advertised separately by a protocol extension.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use std::io;
+
+use crate::{cfg::Compression, msg::LinkMsg, protocol_err};
+
+/// Size of the header prefixed to each data packet when compression is enabled.
+pub(crate) const HEADER_SIZE: usize = 1;
+
+/// Header: data is not compressed.
+const UNCOMPRESSED: u8 = 0;
+
+/// Header: data is compressed using lz4, prefixed by its uncompressed size.
+const LZ4: u8 = 1;
+
+/// Header: data is compressed using zstd.
+#[cfg(feature = "zstd")]
+const ZSTD: u8 = 2;
+
+/// Compresses data packets for sending.
+#[derive(Debug, Clone)]
+pub(crate) struct Compressor {
+    compression: Compression,
+}
+
+impl Compressor {
+    /// Creates a compressor for the configured compression method.
+    ///
+    /// Returns `None` if compression is disabled or the remote endpoint
+    /// does not support compression.
+    /// Falls back to lz4 if the remote endpoint does not support the configured method.
+    pub fn new(compression: Compression, remote_extensions: u32) -> Option<Self> {
+        if remote_extensions & LinkMsg::EXT_COMPRESS == 0 {
+            return None;
+        }
+
+        let compression = match compression {
+            Compression::None => return None,
+            Compression::Zstd(_) if remote_extensions & LinkMsg::EXT_ZSTD == 0 => Compression::Lz4,
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd(_) => Compression::Lz4,
+            other => other,
+        };
+
+        Some(Self { compression })
+    }
+
+    /// Compresses the data and prefixes it by the header.
+    ///
+    /// Data that does not shrink is sent uncompressed.
+    pub fn compress(&self, data: &[u8]) -> Bytes {
+        let compressed = match self.compression {
+            Compression::Lz4 => {
+                let mut buf = vec![LZ4];
+                buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+                buf.extend_from_slice(&lz4_flex::block::compress(data));
+                Some(buf)
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(level) => zstd::bulk::compress(data, level).ok().map(|compressed| {
+                let mut buf = vec![ZSTD];
+                buf.extend_from_slice(&compressed);
+                buf
+            }),
+            _ => None,
+        };
+
+        match compressed {
+            Some(compressed) if compressed.len() < data.len() + HEADER_SIZE => compressed.into(),
+            _ => {
+                let mut buf = BytesMut::with_capacity(data.len() + HEADER_SIZE);
+                buf.put_u8(UNCOMPRESSED);
+                buf.put_slice(data);
+                buf.freeze()
+            }
+        }
+    }
+}
+
+/// Decompresses a received data packet prefixed by the header.
+///
+/// Fails if the decompressed size would exceed `max_size`.
+pub(crate) fn decompress(data: &Bytes, max_size: usize) -> io::Result<Bytes> {
+    let Some(&header) = data.first() else {
+        return Err(protocol_err!("compression header missing"));
+    };
+    let payload = &data[HEADER_SIZE..];
+
+    match header {
+        UNCOMPRESSED => Ok(data.slice(HEADER_SIZE..)),
+        LZ4 => {
+            if payload.len() < 4 {
+                return Err(protocol_err!("lz4 size missing"));
+            }
+            let (size, block) = payload.split_at(4);
+            let size = u32::from_be_bytes(size.try_into().unwrap()) as usize;
+            if size > max_size {
+                return Err(protocol_err!("decompressed data too big"));
+            }
+            let decompressed = lz4_flex::block::decompress(block, size)
+                .map_err(|_| protocol_err!("lz4 decompression failed"))?;
+            Ok(decompressed.into())
+        }
+        #[cfg(feature = "zstd")]
+        ZSTD => {
+            let decompressed = zstd::bulk::decompress(payload, max_size)
+                .map_err(|_| protocol_err!("zstd decompression failed"))?;
+            Ok(decompressed.into())
+        }
+        _ => Err(protocol_err!("unsupported compression method")),
+    }
+}
@@ -16,11 +16,16 @@ use std::{
     fmt,
     future::IntoFuture,
     io,
-    sync::{Arc, Mutex},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
 use tokio::{
     io::{AsyncRead, AsyncWrite},
-    sync::{mpsc, oneshot},
+    sync::{mpsc, mpsc::error::TrySendError, oneshot, watch},
     time::{error::Elapsed, timeout, Instant},
 };
 use x25519_dalek::{EphemeralSecret, PublicKey};
@@ -28,14 +33,23 @@ use x25519_dalek::{EphemeralSecret, PublicKey};
 use crate::{
     agg::{link_int::LinkInt, task::Task, AggParts},
     alc::Channel,
-    cfg::{Cfg, ExchangedCfg},
-    control::{Control, Direction, Link},
-    id::{ConnId, OwnedConnId, ServerId},
+    auth::Transcript,
+    cfg::{Cfg, ExchangedCfg, RateLimit},
+    control::{Control, Direction, Link, Metadata},
+    crypto::DataKeys,
+    id::{ConnId, OwnedConnId, ResumeTicket, ServerId},
     io::{IoRx, IoTx},
     msg::{LinkMsg, RefusedReason},
     protocol_err,
+    shaper::TokenBucket,
 };
 
+/// Maximum number of remote addresses tracked for the incoming link rate limit.
+const MAX_RATE_LIMITERS: usize = 65_536;
+
+/// Minimum interval between removals of refilled rate limiters of remote addresses.
+const RATE_LIMITERS_PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
 /// Listen error.
 #[derive(Debug)]
 pub enum ListenError {
@@ -61,6 +75,7 @@ impl From<ListenError> for io::Error {
 
 /// Incoming link error.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum IncomingError {
     /// Sending or receiving over the link failed.
     Io(io::Error),
@@ -70,8 +85,22 @@ pub enum IncomingError {
     NotListening,
     /// The incoming link belonged to an already closed connection.
     Closed,
+    /// The incoming link presented an invalid resumption ticket.
+    InvalidResumeTicket,
+    /// The incoming link failed to authenticate.
+    AuthenticationFailed,
+    /// The incoming link did not request end-to-end encryption of data.
+    EncryptionRequired,
     /// The link aggregator server was dropped.
     ServerDropped,
+    /// The server has reached its [maximum number of connections](Cfg::max_connections).
+    TooManyConnections,
+    /// The connection has reached its [maximum number of links](Cfg::max_links_per_connection).
+    TooManyLinks,
+    /// The [maximum number of pending connections](Cfg::max_pending_incoming) has been reached.
+    TooManyPending,
+    /// The remote address exceeded the [incoming link rate limit](Cfg::incoming_rate_limit).
+    RateLimited,
 }
 
 impl fmt::Display for IncomingError {
@@ -81,7 +110,14 @@ impl fmt::Display for IncomingError {
             Self::Refused => write!(f, "connection refused"),
             Self::NotListening => write!(f, "not listening"),
             Self::Closed => write!(f, "connection was closed"),
+            Self::InvalidResumeTicket => write!(f, "invalid resumption ticket"),
+            Self::AuthenticationFailed => write!(f, "authentication failed"),
+            Self::EncryptionRequired => write!(f, "encryption required"),
             Self::ServerDropped => write!(f, "server dropped"),
+            Self::TooManyConnections => write!(f, "too many connections"),
+            Self::TooManyLinks => write!(f, "too many links"),
+            Self::TooManyPending => write!(f, "too many pending connections"),
+            Self::RateLimited => write!(f, "rate limited"),
         }
     }
 }
@@ -107,22 +143,33 @@ impl From<IncomingError> for io::Error {
             IncomingError::Refused => io::Error::new(io::ErrorKind::ConnectionRefused, err),
             IncomingError::NotListening => io::Error::new(io::ErrorKind::ConnectionRefused, err),
             IncomingError::Closed => io::Error::new(io::ErrorKind::ConnectionAborted, err),
+            IncomingError::InvalidResumeTicket => io::Error::new(io::ErrorKind::PermissionDenied, err),
+            IncomingError::AuthenticationFailed => io::Error::new(io::ErrorKind::PermissionDenied, err),
+            IncomingError::EncryptionRequired => io::Error::new(io::ErrorKind::PermissionDenied, err),
             IncomingError::ServerDropped => io::Error::new(io::ErrorKind::ConnectionRefused, err),
+            IncomingError::TooManyConnections => io::Error::new(io::ErrorKind::ConnectionRefused, err),
+            IncomingError::TooManyLinks => io::Error::new(io::ErrorKind::ConnectionRefused, err),
+            IncomingError::TooManyPending => io::Error::new(io::ErrorKind::ConnectionRefused, err),
+            IncomingError::RateLimited => io::Error::new(io::ErrorKind::ConnectionRefused, err),
         }
     }
 }
 
 /// Outgoing connection error.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum ConnectError {
     /// No working link was established during the configured timeout.
     Timeout,
+    /// The server refused the connection with an application-defined reason.
+    Refused(Refusal),
 }
 
 impl fmt::Display for ConnectError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             ConnectError::Timeout => write!(f, "connect timeout"),
+            ConnectError::Refused(refusal) => write!(f, "connection refused: {refusal}"),
         }
     }
 }
@@ -131,7 +178,51 @@ impl std::error::Error for ConnectError {}
 
 impl From<ConnectError> for io::Error {
     fn from(err: ConnectError) -> Self {
-        io::Error::new(io::ErrorKind::TimedOut, err)
+        match err {
+            ConnectError::Timeout => io::Error::new(io::ErrorKind::TimedOut, err),
+            ConnectError::Refused(_) => io::Error::new(io::ErrorKind::ConnectionRefused, err),
+        }
+    }
+}
+
+/// Application-defined reason for refusing an incoming connection.
+///
+/// It is sent to the remote endpoint using [`Incoming::refuse_with`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Refusal {
+    /// Application-defined code.
+    pub code: u16,
+    /// Human-readable message.
+    pub message: String,
+}
+
+impl Refusal {
+    /// Maximum length of the message in bytes.
+    pub const MAX_MESSAGE_LEN: usize = 255;
+
+    /// Creates a new refusal reason.
+    ///
+    /// The message is truncated to [`MAX_MESSAGE_LEN`](Self::MAX_MESSAGE_LEN) bytes.
+    pub fn new(code: u16, message: impl Into<String>) -> Self {
+        let mut message = message.into();
+        let len = Self::truncate(&message).len();
+        message.truncate(len);
+        Self { code, message }
+    }
+
+    /// Truncates the message to [`MAX_MESSAGE_LEN`](Self::MAX_MESSAGE_LEN) bytes at a character boundary.
+    pub(crate) fn truncate(message: &str) -> &str {
+        let mut len = message.len().min(Self::MAX_MESSAGE_LEN);
+        while !message.is_char_boundary(len) {
+            len -= 1;
+        }
+        &message[..len]
+    }
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (code {})", &self.message, self.code)
     }
 }
 
@@ -147,6 +238,7 @@ pub struct Incoming<TX, RX, TAG> {
     link_tx: mpsc::Sender<LinkInt<TX, RX, TAG>>,
     link_rx: mpsc::Receiver<LinkInt<TX, RX, TAG>>,
     links: Vec<LinkInt<TX, RX, TAG>>,
+    metadata: Metadata,
 }
 
 impl<TX, RX, TAG> fmt::Debug for Incoming<TX, RX, TAG>
@@ -161,6 +253,7 @@ where
             .field("server_id", &self.server_id)
             .field("remote_server_id", &self.remote_server_id)
             .field("link_tags", &link_tags)
+            .field("metadata", &self.metadata)
             .finish()
     }
 }
@@ -183,6 +276,11 @@ impl<TX, RX, TAG> Incoming<TX, RX, TAG> {
         self.remote_server_id
     }
 
+    /// The metadata sent by the remote endpoint for the connection.
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
     /// Updates the incoming links for the connection.
     fn update_links(&mut self) {
         while let Ok(link_int) = self.link_rx.try_recv() {
@@ -225,9 +323,9 @@ where
     pub fn accept(mut self) -> (Task<TX, RX, TAG>, Channel, Control<TX, RX, TAG>) {
         self.update_links();
 
-        let Self { cfg, conn_id, server_id, remote_server_id, link_tx, link_rx, links } = self;
+        let Self { cfg, conn_id, server_id, remote_server_id, link_tx, link_rx, links, metadata } = self;
 
-        let AggParts { task, channel, control, connected_rx: _ } = AggParts::new(
+        let AggParts { task, channel, control, .. } = AggParts::new(
             cfg,
             conn_id,
             Direction::Incoming,
@@ -235,38 +333,150 @@ where
             remote_server_id,
             links,
             Some((link_tx, link_rx)),
+            metadata,
         );
 
         (task, channel, control)
     }
 
     /// Refuses the incoming connection.
-    pub async fn refuse(mut self) {
+    pub async fn refuse(self) {
+        self.refuse_with_reason(RefusedReason::ConnectionRefused).await
+    }
+
+    /// Refuses the incoming connection with an application-defined reason.
+    ///
+    /// The remote endpoint receives the refusal as [`AddLinkError::Refused`](crate::control::AddLinkError::Refused)
+    /// and [`ConnectError::Refused`].
+    /// Remote endpoints using an older protocol version are informed that the connection was refused.
+    pub async fn refuse_with(self, refusal: Refusal) {
+        self.refuse_with_reason(RefusedReason::Custom(refusal)).await
+    }
+
+    /// Refuses the incoming connection with the specified reason.
+    async fn refuse_with_reason(mut self, reason: RefusedReason) {
         self.link_rx.close();
         self.update_links();
 
-        let send_refused = future::join_all(self.links.iter_mut().map(|link| async move {
-            let _ = link.send_msg_and_flush(LinkMsg::Refused { reason: RefusedReason::ConnectionRefused }).await;
+        let send_refused = future::join_all(self.links.iter_mut().map(|link| {
+            let reason = reason.clone();
+            async move {
+                let _ = link.send_msg_and_flush(LinkMsg::Refused { reason }).await;
+            }
         }));
         let _ = timeout(self.cfg.link_non_working_timeout, send_refused).await;
     }
 }
 
+/// Admission statistics of a link aggregator server.
+///
+/// Obtained using [`Server::stats`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ServerStats {
+    /// Number of current connections, including those waiting to be accepted.
+    pub connections: usize,
+    /// Number of links refused because the [maximum number of connections](Cfg::max_connections)
+    /// was reached.
+    pub refused_too_many_connections: u64,
+    /// Number of links refused because their connection reached the
+    /// [maximum number of links](Cfg::max_links_per_connection).
+    pub refused_too_many_links: u64,
+    /// Number of links refused because the
+    /// [maximum number of pending connections](Cfg::max_pending_incoming) was reached.
+    pub refused_too_many_pending: u64,
+    /// Number of links refused because their remote address exceeded the
+    /// [incoming link rate limit](Cfg::incoming_rate_limit).
+    pub refused_rate_limited: u64,
+    /// Number of incoming links whose handshake did not complete within the
+    /// [handshake timeout](Cfg::handshake_timeout).
+    pub handshake_timeouts: u64,
+}
+
+/// Connection managed by server.
+struct ServerConn<TX, RX, TAG> {
+    link_tx: mpsc::Sender<LinkInt<TX, RX, TAG>>,
+    resume_ticket: Option<ResumeTicket>,
+    /// Whether the resumption ticket has been sent to the client.
+    resume_ticket_sent: Arc<AtomicBool>,
+    encryption: Option<(PublicKey, DataKeys)>,
+    links: Vec<Link<TAG>>,
+    /// Number of links admitted to the connection that are not yet tracked in `links`.
+    reserved_links: usize,
+}
+
+impl<TX, RX, TAG> ServerConn<TX, RX, TAG> {
+    /// Number of links of the connection that have not been disconnected,
+    /// including admitted links that are still being added.
+    fn link_count(&mut self) -> usize {
+        self.links.retain(|link| !link.is_disconnected());
+        self.links.len() + self.reserved_links
+    }
+}
+
+/// Slot reserved for an admitted link in a connection managed by the server.
+///
+/// The reservation is released when the slot is dropped without tracking the link.
+struct LinkSlot<TX, RX, TAG> {
+    inner: Arc<Mutex<ServerInner<TX, RX, TAG>>>,
+    conn_id: ConnId,
+    tracked: bool,
+}
+
+impl<TX, RX, TAG> LinkSlot<TX, RX, TAG> {
+    /// Tracks the link for enforcing the maximum number of links per connection.
+    fn track(mut self, link: &Link<TAG>) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(conn) = inner.conns.get_mut(&self.conn_id) {
+            conn.reserved_links = conn.reserved_links.saturating_sub(1);
+            conn.links.push(link.clone());
+        }
+        self.tracked = true;
+    }
+}
+
+impl<TX, RX, TAG> Drop for LinkSlot<TX, RX, TAG> {
+    fn drop(&mut self) {
+        if self.tracked {
+            return;
+        }
+
+        if let Ok(mut inner) = self.inner.lock() {
+            if let Some(conn) = inner.conns.get_mut(&self.conn_id) {
+                conn.reserved_links = conn.reserved_links.saturating_sub(1);
+            }
+        }
+    }
+}
+
 /// Server implementation.
 struct ServerInner<TX, RX, TAG> {
     cfg: Arc<Cfg>,
     server_id: ServerId,
-    conns: HashMap<ConnId, mpsc::Sender<LinkInt<TX, RX, TAG>>>,
+    conns: HashMap<ConnId, ServerConn<TX, RX, TAG>>,
     closed_conns_tx: mpsc::UnboundedSender<ConnId>,
     closed_conns_rx: mpsc::UnboundedReceiver<ConnId>,
     listen_tx: mpsc::Sender<Incoming<TX, RX, TAG>>,
+    rate_limiters: HashMap<IpAddr, TokenBucket>,
+    rate_limiters_pruned: Instant,
+    stats: ServerStats,
 }
 
 impl<TX, RX, TAG> ServerInner<TX, RX, TAG> {
     fn new(cfg: Arc<Cfg>, server_id: ServerId) -> Self {
         let (closed_conns_tx, closed_conns_rx) = mpsc::unbounded_channel();
-        let listen_tx = mpsc::channel(cfg.connect_queue.get()).0;
-        Self { cfg, server_id, conns: HashMap::new(), closed_conns_tx, closed_conns_rx, listen_tx }
+        let listen_tx = mpsc::channel(cfg.max_pending_incoming.unwrap_or(cfg.connect_queue).get()).0;
+        Self {
+            cfg,
+            server_id,
+            conns: HashMap::new(),
+            closed_conns_tx,
+            closed_conns_rx,
+            listen_tx,
+            rate_limiters: HashMap::new(),
+            rate_limiters_pruned: Instant::now(),
+            stats: ServerStats::default(),
+        }
     }
 
     /// Clean up closed connections.
@@ -275,6 +485,40 @@ impl<TX, RX, TAG> ServerInner<TX, RX, TAG> {
             self.conns.remove(&id);
         }
     }
+
+    /// Checks the incoming link rate limit of the specified remote address
+    /// and takes a token if it is not exceeded.
+    ///
+    /// Links from addresses that are not yet tracked are refused while
+    /// the maximum number of addresses is tracked.
+    fn admit_remote(&mut self, remote: IpAddr) -> bool {
+        let Some(limit) = self.cfg.incoming_rate_limit else { return true };
+        let limit = RateLimit { rate: limit.rate, burst: limit.burst.get() };
+
+        // Periodically forget addresses whose buckets have been refilled completely.
+        if self.rate_limiters_pruned.elapsed() >= RATE_LIMITERS_PRUNE_INTERVAL {
+            self.rate_limiters.retain(|_, bucket| !bucket.is_full());
+            self.rate_limiters_pruned = Instant::now();
+        }
+
+        let n_rate_limiters = self.rate_limiters.len();
+        match self.rate_limiters.entry(remote) {
+            Entry::Occupied(mut entry) => entry.get_mut().try_consume(1),
+            Entry::Vacant(_) if n_rate_limiters >= MAX_RATE_LIMITERS => false,
+            Entry::Vacant(entry) => entry.insert(TokenBucket::new(Some(limit))).try_consume(1),
+        }
+    }
+
+    /// Counts a refused link.
+    fn count_refusal(&mut self, err: &IncomingError) {
+        match err {
+            IncomingError::TooManyConnections => self.stats.refused_too_many_connections += 1,
+            IncomingError::TooManyLinks => self.stats.refused_too_many_links += 1,
+            IncomingError::TooManyPending => self.stats.refused_too_many_pending += 1,
+            IncomingError::RateLimited => self.stats.refused_rate_limited += 1,
+            _ => (),
+        }
+    }
 }
 
 /// Handle to a link aggregator server.
@@ -308,7 +552,15 @@ where
 {
     /// Creates a new link aggregator server.
     pub fn new(cfg: Cfg) -> Self {
-        let server_id = ServerId::generate();
+        Self::with_id(cfg, ServerId::generate())
+    }
+
+    /// Creates a new link aggregator server using the specified server id.
+    ///
+    /// Keeping the server id across restarts of the server prevents clients
+    /// from failing with a server id mismatch when they add links after the restart.
+    /// Connections are not preserved across restarts.
+    pub fn with_id(cfg: Cfg, server_id: ServerId) -> Self {
         Self { server_id, inner: Arc::new(Mutex::new(ServerInner::new(Arc::new(cfg), server_id))) }
     }
 
@@ -317,6 +569,13 @@ where
         self.server_id
     }
 
+    /// Admission statistics of the server.
+    pub fn stats(&self) -> ServerStats {
+        let mut inner = self.inner.lock().unwrap();
+        inner.cleanup_links();
+        ServerStats { connections: inner.conns.len(), ..inner.stats.clone() }
+    }
+
     /// Starts building a new outgoing connection.
     ///
     /// Incoming links can be added to this connection.
@@ -331,7 +590,7 @@ where
         let conn_id = ConnId::generate();
         let (link_tx, link_rx) = mpsc::channel(inner.cfg.connect_queue.get());
 
-        let AggParts { task, channel, control, connected_rx } = AggParts::new(
+        let AggParts { task, channel, control, connected_rx, refused_rx } = AggParts::new(
             inner.cfg.clone(),
             OwnedConnId::new(conn_id, inner.closed_conns_tx.clone()),
             Direction::Outgoing,
@@ -339,11 +598,22 @@ where
             None,
             Vec::new(),
             Some((link_tx.clone(), link_rx)),
+            Metadata::new(),
         );
 
-        inner.conns.insert(conn_id, link_tx);
+        inner.conns.insert(
+            conn_id,
+            ServerConn {
+                link_tx,
+                resume_ticket: None,
+                resume_ticket_sent: Default::default(),
+                encryption: None,
+                links: Vec::new(),
+                reserved_links: 0,
+            },
+        );
 
-        (task, Outgoing { channel, connected_rx }, control)
+        (task, Outgoing { channel, connected_rx, refused_rx }, control)
     }
 
     /// Starts accepting *new* incoming connections.
@@ -359,7 +629,8 @@ where
             return Err(ListenError::AlreadyListening);
         }
 
-        let (listen_tx, listen_rx) = mpsc::channel(inner.cfg.connect_queue.get());
+        let (listen_tx, listen_rx) =
+            mpsc::channel(inner.cfg.max_pending_incoming.unwrap_or(inner.cfg.connect_queue).get());
         inner.listen_tx = listen_tx;
         Ok(Listener { server_id: inner.server_id, listen_rx })
     }
@@ -385,68 +656,206 @@ where
     /// # Panics
     /// Panics when the size of `user_data` exceeds [`u16::MAX`].
     pub async fn add_incoming(
-        &self, mut tx: TX, mut rx: RX, tag: TAG, user_data: &[u8],
+        &self, tx: TX, rx: RX, tag: TAG, user_data: &[u8],
+    ) -> Result<Link<TAG>, IncomingError> {
+        self.add_incoming_int(tx, rx, tag, user_data, None).await
+    }
+
+    /// Adds an incoming, packet-based link from the specified remote address.
+    ///
+    /// This behaves like [`add_incoming`](Self::add_incoming), but additionally enforces
+    /// the [incoming link rate limit](Cfg::incoming_rate_limit) for the remote address.
+    ///
+    /// # Panics
+    /// Panics when the size of `user_data` exceeds [`u16::MAX`].
+    pub async fn add_incoming_from(
+        &self, tx: TX, rx: RX, tag: TAG, user_data: &[u8], remote: IpAddr,
+    ) -> Result<Link<TAG>, IncomingError> {
+        self.add_incoming_int(tx, rx, tag, user_data, Some(remote)).await
+    }
+
+    async fn add_incoming_int(
+        &self, mut tx: TX, mut rx: RX, tag: TAG, user_data: &[u8], remote: Option<IpAddr>,
     ) -> Result<Link<TAG>, IncomingError> {
         assert!(user_data.len() <= u16::MAX as usize, "user_data is too big");
 
         let server_id;
         let cfg;
         let closed_conns_tx;
+        let rate_limited;
         {
             let mut inner = self.inner.lock().unwrap();
             inner.cleanup_links();
             server_id = inner.server_id;
             cfg = inner.cfg.clone();
             closed_conns_tx = inner.closed_conns_tx.clone();
+            rate_limited = match remote {
+                Some(remote) => !inner.admit_remote(remote),
+                None => false,
+            };
+        }
+
+        // Refuse link from remote address exceeding rate limit before starting the handshake.
+        // The protocol version has not been negotiated yet, but clients that do not know
+        // the reason fail the handshake either way.
+        if rate_limited {
+            tracing::debug!("refusing link from {remote:?} exceeding rate limit");
+            let err = IncomingError::RateLimited;
+            self.inner.lock().unwrap().count_refusal(&err);
+            let refused = LinkMsg::Refused { reason: RefusedReason::RateLimited };
+            let _ = timeout(cfg.handshake_timeout, refused.send(&mut tx)).await;
+            return Err(err);
         }
 
         // Perform protocol handshake.
-        let (remote_server_id, conn_id, existing, remote_cfg, roundtrip, remote_user_data) =
-            timeout(cfg.link_ping_timeout, async {
-                let server_secret = EphemeralSecret::random_from_rng(rand_core::OsRng);
-                let server_public_key = PublicKey::from(&server_secret);
-
-                let start = Instant::now();
-                LinkMsg::Welcome {
-                    extensions: 0,
-                    public_key: server_public_key,
-                    server_id,
-                    user_data: user_data.to_vec(),
-                    cfg: (&*cfg).into(),
-                }
-                .send(&mut tx)
-                .await?;
-
-                let LinkMsg::Connect {
-                    extensions: _,
-                    public_key: client_public_key,
-                    server_id,
-                    connection_id: encrypted_conn_id,
-                    existing_connection,
-                    user_data: remote_user_data,
-                    cfg,
-                } = LinkMsg::recv(&mut rx).await?
-                else {
-                    return Err::<_, IncomingError>(protocol_err!("expected Connect message").into());
-                };
+        let handshake = timeout(cfg.handshake_timeout, async {
+            let server_secret = EphemeralSecret::random_from_rng(rand_core::OsRng);
+            let server_public_key = PublicKey::from(&server_secret);
+
+            let start = Instant::now();
+            LinkMsg::Welcome {
+                version: LinkMsg::MIN_PROTOCOL_VERSION,
+                extensions: LinkMsg::EXTENSIONS,
+                public_key: server_public_key,
+                server_id,
+                user_data: user_data.to_vec(),
+                cfg: (&*cfg).into(),
+            }
+            .send(&mut tx)
+            .await?;
+
+            let LinkMsg::Connect {
+                version,
+                extensions: remote_extensions,
+                public_key: client_public_key,
+                server_id,
+                connection_id: encrypted_conn_id,
+                existing_connection,
+                user_data: remote_user_data,
+                cfg: remote_cfg,
+                resume_ticket,
+                auth,
+                encryption_key,
+                metadata,
+            } = LinkMsg::recv(&mut rx).await?
+            else {
+                return Err::<_, IncomingError>(protocol_err!("expected Connect message").into());
+            };
 
-                let shared_secret = server_secret.diffie_hellman(&client_public_key);
-                let conn_id = encrypted_conn_id.decrypt(&shared_secret);
+            // Choose the highest protocol version supported by both endpoints.
+            let remote_max_version = remote_cfg.max_version.unwrap_or(version);
+            let Some(version) = LinkMsg::negotiate_version(version, remote_max_version) else {
+                return Err(protocol_err!(
+                    "client supports only protocol versions {version} to {remote_max_version}"
+                )
+                .into());
+            };
 
-                Ok((server_id, conn_id, existing_connection, cfg, start.elapsed(), remote_user_data))
-            })
-            .await??;
+            let shared_secret = server_secret.diffie_hellman(&client_public_key);
+            let conn_id = encrypted_conn_id.decrypt(&shared_secret);
+            let resume_ticket = resume_ticket.and_then(|resume_ticket| resume_ticket.decrypt(&shared_secret));
+
+            // Verify that client knows the authentication key.
+            let (auth_mac, auth_key) = match &cfg.link_auth {
+                Some(link_auth) => {
+                    let transcript = Transcript {
+                        server_id: self.server_id,
+                        server_public_key: &server_public_key,
+                        client_public_key: &client_public_key,
+                        shared_secret: &shared_secret,
+                    };
+                    match link_auth.verify_client(&transcript, auth.as_deref()) {
+                        Some(auth_mac) => (
+                            Some(auth_mac),
+                            auth.and_then(|auth| link_auth.server_key(&auth.token_id).map(|key| key.to_vec())),
+                        ),
+                        None => {
+                            tracing::debug!("refusing link that failed to authenticate");
+                            LinkMsg::Refused { reason: RefusedReason::AuthenticationFailed.for_version(version) }
+                                .send(&mut tx)
+                                .await?;
+                            return Err(IncomingError::AuthenticationFailed);
+                        }
+                    }
+                }
+                None => (None, None),
+            };
+
+            Ok((
+                version,
+                remote_extensions,
+                shared_secret,
+                server_id,
+                conn_id,
+                existing_connection,
+                remote_cfg,
+                start.elapsed(),
+                remote_user_data,
+                resume_ticket,
+                auth_mac,
+                auth_key,
+                encryption_key,
+                metadata,
+            ))
+        })
+        .await;
+
+        let (
+            version,
+            remote_extensions,
+            shared_secret,
+            remote_server_id,
+            conn_id,
+            existing,
+            remote_cfg,
+            roundtrip,
+            remote_user_data,
+            remote_resume_ticket,
+            auth_mac,
+            auth_key,
+            remote_encryption_key,
+            metadata,
+        ) = match handshake {
+            Ok(Ok(handshake)) => handshake,
+            Ok(Err(err)) => {
+                self.inner.lock().unwrap().count_refusal(&err);
+                return Err(err);
+            }
+            Err(elapsed) => {
+                tracing::debug!("handshake of incoming link timed out");
+                self.inner.lock().unwrap().stats.handshake_timeouts += 1;
+                return Err(elapsed.into());
+            }
+        };
 
         tracing::debug!(?server_id, ?conn_id, ?existing, "handling incoming link");
 
+        if cfg.data_encryption && remote_encryption_key.is_none() {
+            tracing::debug!("refusing link that does not request encryption");
+            timeout(
+                cfg.link_ping_timeout,
+                LinkMsg::Refused { reason: RefusedReason::EncryptionRequired.for_version(version) }.send(&mut tx),
+            )
+            .await??;
+            return Err(IncomingError::EncryptionRequired);
+        }
+
         enum Connection<TX, RX, TAG> {
             Existing {
                 link_tx: mpsc::Sender<LinkInt<TX, RX, TAG>>,
+                resume_ticket: Option<ResumeTicket>,
+                resume_ticket_sent: Arc<AtomicBool>,
+                encryption: Option<(PublicKey, DataKeys)>,
+                slot: LinkSlot<TX, RX, TAG>,
             },
             New {
                 link_tx: mpsc::Sender<LinkInt<TX, RX, TAG>>,
                 link_rx: mpsc::Receiver<LinkInt<TX, RX, TAG>>,
                 listen_tx_permit: mpsc::OwnedPermit<Incoming<TX, RX, TAG>>,
+                resume_ticket: Option<ResumeTicket>,
+                resume_ticket_sent: Arc<AtomicBool>,
+                encryption: Option<(PublicKey, DataKeys)>,
+                slot: LinkSlot<TX, RX, TAG>,
             },
             Refuse {
                 reason: RefusedReason,
@@ -457,32 +866,109 @@ where
         let mut need_listen_tx_permit = false;
         let connection = loop {
             // Obtain listen queue permit if required.
+            // Without a limit on pending connections wait for the listener to make room.
             let listen_tx_permit = if need_listen_tx_permit {
                 let listen_tx = self.inner.lock().unwrap().listen_tx.clone();
-                Some(listen_tx.reserve_owned().await)
+                Some(match cfg.max_pending_incoming {
+                    Some(_) => listen_tx.try_reserve_owned().map_err(|err| match err {
+                        TrySendError::Full(_) => IncomingError::TooManyPending,
+                        TrySendError::Closed(_) => IncomingError::NotListening,
+                    }),
+                    None => listen_tx.reserve_owned().await.map_err(|_| IncomingError::NotListening),
+                })
             } else {
                 None
             };
 
             // Check if link belongs to existing connection.
             let mut inner = self.inner.lock().unwrap();
+            let conns = inner.conns.len();
             match inner.conns.entry(conn_id) {
-                // Link joins existing connection.
-                Entry::Occupied(ocu) => break Connection::Existing { link_tx: ocu.get().clone() },
+                // Link joins existing connection, which requires a valid resumption ticket if
+                // one has been issued, regardless of whether the remote endpoint claims that
+                // the connection exists.
+                // Links started by the client before it received the ticket may omit it
+                // until the ticket has been sent.
+                Entry::Occupied(mut ocu) => {
+                    let conn = ocu.get_mut();
+                    let links = conn.link_count();
+                    match conn.resume_ticket {
+                        Some(resume_ticket)
+                            if remote_resume_ticket != Some(resume_ticket)
+                                && (remote_resume_ticket.is_some()
+                                    || conn.resume_ticket_sent.load(Ordering::Acquire)) =>
+                        {
+                            break Connection::Refuse {
+                                reason: RefusedReason::InvalidResumeTicket,
+                                err: IncomingError::InvalidResumeTicket,
+                            }
+                        }
+                        _ if matches!(cfg.max_links_per_connection, Some(max) if links >= max.get()) => {
+                            break Connection::Refuse {
+                                reason: RefusedReason::TooManyLinks,
+                                err: IncomingError::TooManyLinks,
+                            }
+                        }
+                        // The slot is reserved under the lock, so that concurrently added
+                        // links cannot exceed the maximum number of links.
+                        resume_ticket => {
+                            conn.reserved_links += 1;
+                            break Connection::Existing {
+                                link_tx: conn.link_tx.clone(),
+                                resume_ticket,
+                                resume_ticket_sent: conn.resume_ticket_sent.clone(),
+                                encryption: conn.encryption.clone(),
+                                slot: LinkSlot { inner: self.inner.clone(), conn_id, tracked: false },
+                            };
+                        }
+                    }
+                }
+
+                // Server has no room for another connection.
+                Entry::Vacant(_)
+                    if !existing && matches!(cfg.max_connections, Some(max) if conns >= max.get()) =>
+                {
+                    break Connection::Refuse {
+                        reason: RefusedReason::TooManyConnections,
+                        err: IncomingError::TooManyConnections,
+                    }
+                }
 
                 // Link belongs to new, incoming connection.
                 Entry::Vacant(vac) if !existing => match listen_tx_permit {
                     Some(Ok(listen_tx_permit)) => {
                         let (link_tx, link_rx) = mpsc::channel(cfg.connect_queue.get());
-                        vac.insert(link_tx.clone());
-                        break Connection::New { link_tx, link_rx, listen_tx_permit };
+                        // The resumption ticket is issued by the server if the connection can be
+                        // resumed and must be presented by all links joining the connection.
+                        let resume_ticket = (cfg.resume_window.is_some()
+                            && remote_extensions & LinkMsg::EXT_RESUME != 0)
+                            .then(ResumeTicket::generate);
+                        let resume_ticket_sent = Arc::new(AtomicBool::new(false));
+                        let encryption =
+                            remote_encryption_key.map(|key| DataKeys::server(conn_id, &key, auth_key.as_deref()));
+                        vac.insert(ServerConn {
+                            link_tx: link_tx.clone(),
+                            resume_ticket,
+                            resume_ticket_sent: resume_ticket_sent.clone(),
+                            encryption: encryption.clone(),
+                            links: Vec::new(),
+                            reserved_links: 1,
+                        });
+                        let slot = LinkSlot { inner: self.inner.clone(), conn_id, tracked: false };
+                        break Connection::New {
+                            link_tx,
+                            link_rx,
+                            listen_tx_permit,
+                            resume_ticket,
+                            resume_ticket_sent,
+                            encryption,
+                            slot,
+                        };
                     }
-                    Some(Err(_)) => {
-                        break Connection::Refuse {
-                            reason: RefusedReason::NotListening,
-                            err: IncomingError::NotListening,
-                        }
+                    Some(Err(err @ IncomingError::TooManyPending)) => {
+                        break Connection::Refuse { reason: RefusedReason::TooManyPending, err }
                     }
+                    Some(Err(err)) => break Connection::Refuse { reason: RefusedReason::NotListening, err },
                     None => need_listen_tx_permit = true,
                 },
 
@@ -495,51 +981,81 @@ where
 
         match connection {
             // Link joins existing connection.
-            Connection::Existing { link_tx } => match link_tx.reserve_owned().await {
-                Ok(link_tx_permit) => {
-                    let link_int = LinkInt::new(
-                        tag,
-                        conn_id,
-                        tx,
-                        rx,
-                        cfg,
-                        remote_cfg,
-                        Direction::Incoming,
-                        roundtrip,
-                        remote_user_data,
-                    );
-                    let link = Link::from(&link_int);
-                    link_tx_permit.send(link_int);
-
-                    tracing::debug!("link joins existing connection {conn_id:?}");
-                    Ok(link)
-                }
-                Err(_) => {
-                    tracing::debug!("refusing link that belongs to closed connection");
-                    timeout(
-                        cfg.link_ping_timeout,
-                        LinkMsg::Refused { reason: RefusedReason::Closed }.send(&mut tx),
-                    )
-                    .await??;
-                    Err(IncomingError::Closed)
+            Connection::Existing { link_tx, resume_ticket, resume_ticket_sent, encryption, slot } => {
+                match link_tx.reserve_owned().await {
+                    Ok(link_tx_permit) => {
+                        let mut link_int = LinkInt::new(
+                            tag,
+                            conn_id,
+                            tx,
+                            rx,
+                            cfg,
+                            version,
+                            remote_cfg,
+                            Direction::Incoming,
+                            roundtrip,
+                            remote_user_data,
+                        );
+                        link_int.resume_ticket =
+                            resume_ticket.and_then(|resume_ticket| resume_ticket.encrypt(&shared_secret));
+                        link_int.resume_ticket_sent = resume_ticket.map(|_| resume_ticket_sent);
+                        link_int.auth_mac = auth_mac;
+                        if let Some((encryption_key, data_keys)) = encryption {
+                            link_int.encryption_key = Some(encryption_key);
+                            link_int.data_keys = Some(data_keys);
+                        }
+                        let link = Link::from(&link_int);
+                        link_tx_permit.send(link_int);
+                        slot.track(&link);
+
+                        tracing::debug!("link joins existing connection {conn_id:?}");
+                        Ok(link)
+                    }
+                    Err(_) => {
+                        tracing::debug!("refusing link that belongs to closed connection");
+                        timeout(
+                            cfg.link_ping_timeout,
+                            LinkMsg::Refused { reason: RefusedReason::Closed.for_version(version) }.send(&mut tx),
+                        )
+                        .await??;
+                        Err(IncomingError::Closed)
+                    }
                 }
-            },
+            }
 
             // Link belongs to new, incoming connection.
-            Connection::New { link_tx, link_rx, listen_tx_permit } => {
-                let link_int = LinkInt::new(
+            Connection::New {
+                link_tx,
+                link_rx,
+                listen_tx_permit,
+                resume_ticket,
+                resume_ticket_sent,
+                encryption,
+                slot,
+            } => {
+                let mut link_int = LinkInt::new(
                     tag,
                     conn_id,
                     tx,
                     rx,
                     cfg.clone(),
+                    version,
                     remote_cfg,
                     Direction::Incoming,
                     roundtrip,
                     remote_user_data,
                 );
+                link_int.resume_ticket =
+                    resume_ticket.and_then(|resume_ticket| resume_ticket.encrypt(&shared_secret));
+                link_int.resume_ticket_sent = resume_ticket.map(|_| resume_ticket_sent);
+                link_int.auth_mac = auth_mac;
+                if let Some((encryption_key, data_keys)) = encryption {
+                    link_int.encryption_key = Some(encryption_key);
+                    link_int.data_keys = Some(data_keys);
+                }
                 let link = Link::from(&link_int);
                 link_tx.try_send(link_int).unwrap();
+                slot.track(&link);
 
                 listen_tx_permit.send(Incoming {
                     cfg,
@@ -549,6 +1065,7 @@ where
                     link_tx,
                     link_rx,
                     links: Vec::new(),
+                    metadata: *metadata,
                 });
 
                 tracing::debug!("link starts new connection {conn_id:?}");
@@ -558,7 +1075,12 @@ where
             // Link cannot be accepted.
             Connection::Refuse { reason, err } => {
                 tracing::debug!("refusing link with reason {reason:?}: {err}");
-                timeout(cfg.link_ping_timeout, LinkMsg::Refused { reason }.send(&mut tx)).await??;
+                self.inner.lock().unwrap().count_refusal(&err);
+                timeout(
+                    cfg.link_ping_timeout,
+                    LinkMsg::Refused { reason: reason.for_version(version) }.send(&mut tx),
+                )
+                .await??;
                 Err(err)
             }
         }
@@ -599,6 +1121,19 @@ where
     ) -> Result<Link<TAG>, IncomingError> {
         self.add_incoming(IoTx::new(write), IoRx::new(read), tag, user_data).await
     }
+
+    /// Adds an incoming, stream-based link from the specified remote address.
+    ///
+    /// This behaves like [`add_incoming_io`](Self::add_incoming_io), but additionally enforces
+    /// the [incoming link rate limit](Cfg::incoming_rate_limit) for the remote address.
+    ///
+    /// # Panics
+    /// Panics when the size of `user_data` exceeds [`u16::MAX`].
+    pub async fn add_incoming_io_from(
+        &self, read: R, write: W, tag: TAG, user_data: &[u8], remote: IpAddr,
+    ) -> Result<Link<TAG>, IncomingError> {
+        self.add_incoming_from(IoTx::new(write), IoRx::new(read), tag, user_data, remote).await
+    }
 }
 
 /// Listens for new connections consisting of aggregated links.
@@ -652,6 +1187,7 @@ where
 pub struct Outgoing {
     channel: Channel,
     connected_rx: oneshot::Receiver<Arc<ExchangedCfg>>,
+    refused_rx: watch::Receiver<Option<Refusal>>,
 }
 
 impl fmt::Debug for Outgoing {
@@ -670,10 +1206,27 @@ impl Outgoing {
     ///
     /// If the connection cannot be established over any link
     /// after the connection timeout has passed, an error is returned.
+    /// If the server refuses the connection with an application-defined reason,
+    /// that reason is returned.
     pub async fn connect(self) -> Result<Channel, ConnectError> {
-        let Self { mut channel, connected_rx } = self;
+        let Self { mut channel, connected_rx, mut refused_rx } = self;
+
+        let refused = async {
+            loop {
+                if let Some(refusal) = refused_rx.borrow_and_update().clone() {
+                    break refusal;
+                }
+                if refused_rx.changed().await.is_err() {
+                    future::pending::<()>().await;
+                }
+            }
+        };
 
-        let remote_cfg = connected_rx.await.map_err(|_| ConnectError::Timeout)?;
+        let remote_cfg = tokio::select! {
+            biased;
+            res = connected_rx => res.map_err(|_| ConnectError::Timeout)?,
+            refusal = refused => return Err(ConnectError::Refused(refusal)),
+        };
         channel.set_remote_cfg(remote_cfg);
 
         Ok(channel)
@@ -703,7 +1256,7 @@ where
     TX: Sink<Bytes, Error = io::Error> + Unpin + Send + 'static,
     TAG: Send + Sync + 'static,
 {
-    let AggParts { task, channel, control, connected_rx } = AggParts::new(
+    let AggParts { task, channel, control, connected_rx, refused_rx } = AggParts::new(
         Arc::new(cfg),
         OwnedConnId::untracked(ConnId::generate()),
         Direction::Outgoing,
@@ -711,7 +1264,8 @@ where
         None,
         Vec::new(),
         None,
+        Metadata::new(),
     );
 
-    (task, Outgoing { channel, connected_rx }, control)
+    (task, Outgoing { channel, connected_rx, refused_rx }, control)
 }
@@ -1,36 +1,44 @@
 //! Connection and link control.
 
 use bytes::Bytes;
-use futures::{Sink, Stream};
+use futures::{stream, Sink, Stream};
 use std::{
+    collections::BTreeMap,
     error::Error,
     fmt,
     hash::Hash,
     io,
     sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Arc, Mutex as StdMutex,
     },
     time::Duration,
 };
 use tokio::{
     io::{AsyncRead, AsyncWrite},
-    sync::{mpsc, watch, Mutex},
+    sync::{broadcast, mpsc, watch, Mutex},
     time::{error::Elapsed, timeout, Instant},
 };
 use x25519_dalek::{EphemeralSecret, PublicKey};
 
 use crate::{
     agg::link_int::LinkInt,
-    cfg::Cfg,
-    id::{ConnId, EncryptedConnId, LinkId, ServerId},
+    auth::Transcript,
+    cfg::{Cfg, Quota, RateLimit},
+    connect::Refusal,
+    crypto::ClientKeyExchange,
+    ext::Extensions,
+    id::{ConnId, EncryptedConnId, LinkId, ResumeTicket, ServerId},
     io::{IoRx, IoTx},
     msg::{LinkMsg, RefusedReason},
-    protocol_err, TaskError,
+    protocol_err,
+    shaper::TokenBucket,
+    TaskError,
 };
 
 /// Error adding a link to a connection.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum AddLinkError {
     /// IO error.
     Io(io::Error),
@@ -52,6 +60,32 @@ pub enum AddLinkError {
     ConnectionRefused,
     /// The link was actively refused by the link filter.
     LinkRefused,
+    /// The server did not accept the resumption ticket of the connection.
+    InvalidResumeTicket,
+    /// Authentication failed, either because the server refused the
+    /// authentication of the client or because the server failed to authenticate.
+    AuthenticationFailed,
+    /// End-to-end encryption of data is required by one endpoint, but not
+    /// requested or supported by the other endpoint.
+    EncryptionRequired,
+    /// The server supports no protocol version supported by this endpoint.
+    UnsupportedProtocolVersion {
+        /// Lowest protocol version supported by the server.
+        min: u8,
+        /// Highest protocol version supported by the server.
+        max: u8,
+    },
+    /// The connection was refused with an application-defined reason.
+    Refused(Refusal),
+    /// The server has reached its maximum number of connections.
+    TooManyConnections,
+    /// The connection has reached the maximum number of links allowed by the server.
+    TooManyLinks,
+    /// Too many incoming connections are waiting to be accepted by the server.
+    TooManyPending,
+    /// The server refused the link because too many links have been
+    /// established from this address recently.
+    RateLimited,
 }
 
 impl From<io::Error> for AddLinkError {
@@ -86,6 +120,17 @@ impl fmt::Display for AddLinkError {
             AddLinkError::ConnectionClosed => write!(f, "connection closed"),
             AddLinkError::ConnectionRefused => write!(f, "connection refused"),
             AddLinkError::LinkRefused => write!(f, "link refused"),
+            AddLinkError::InvalidResumeTicket => write!(f, "invalid resumption ticket"),
+            AddLinkError::AuthenticationFailed => write!(f, "authentication failed"),
+            AddLinkError::EncryptionRequired => write!(f, "encryption required"),
+            AddLinkError::UnsupportedProtocolVersion { min, max } => {
+                write!(f, "server supports only protocol versions {min} to {max}")
+            }
+            AddLinkError::Refused(refusal) => write!(f, "connection refused: {refusal}"),
+            AddLinkError::TooManyConnections => write!(f, "too many connections"),
+            AddLinkError::TooManyLinks => write!(f, "too many links"),
+            AddLinkError::TooManyPending => write!(f, "too many pending connections"),
+            AddLinkError::RateLimited => write!(f, "rate limited"),
         }
     }
 }
@@ -99,6 +144,14 @@ impl From<RefusedReason> for AddLinkError {
             RefusedReason::NotListening => Self::NotListening,
             RefusedReason::ConnectionRefused => Self::ConnectionRefused,
             RefusedReason::LinkRefused => Self::LinkRefused,
+            RefusedReason::InvalidResumeTicket => Self::InvalidResumeTicket,
+            RefusedReason::AuthenticationFailed => Self::AuthenticationFailed,
+            RefusedReason::EncryptionRequired => Self::EncryptionRequired,
+            RefusedReason::Custom(refusal) => Self::Refused(refusal),
+            RefusedReason::TooManyConnections => Self::TooManyConnections,
+            RefusedReason::TooManyLinks => Self::TooManyLinks,
+            RefusedReason::TooManyPending => Self::TooManyPending,
+            RefusedReason::RateLimited => Self::RateLimited,
         }
     }
 }
@@ -140,6 +193,49 @@ impl Direction {
     }
 }
 
+/// Connection-level metadata.
+///
+/// It consists of key/value pairs, for example the application name, client version or tenant id,
+/// and is sent by the client when establishing the connection.
+pub type Metadata = BTreeMap<String, String>;
+
+/// Error setting connection metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataError {
+    /// The metadata has more than [`u16::MAX`] entries.
+    TooManyEntries,
+    /// A key or value is longer than [`u16::MAX`] bytes.
+    TooLong,
+}
+
+impl fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::TooManyEntries => write!(f, "metadata has too many entries"),
+            Self::TooLong => write!(f, "metadata key or value is too long"),
+        }
+    }
+}
+
+impl std::error::Error for MetadataError {}
+
+impl From<MetadataError> for io::Error {
+    fn from(err: MetadataError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, err)
+    }
+}
+
+/// Verifies that the metadata can be transmitted.
+pub(crate) fn check_metadata(metadata: &Metadata) -> Result<(), MetadataError> {
+    if metadata.len() > u16::MAX as usize {
+        return Err(MetadataError::TooManyEntries);
+    }
+    if metadata.iter().any(|(key, value)| key.len() > u16::MAX as usize || value.len() > u16::MAX as usize) {
+        return Err(MetadataError::TooLong);
+    }
+    Ok(())
+}
+
 /// A handle for controlling and monitoring a connection consisting of aggregated links.
 ///
 /// Clones of this handle refer to the same underlying connection.
@@ -156,6 +252,16 @@ pub struct Control<TX, RX, TAG> {
     pub(crate) stats_rx: watch::Receiver<Stats>,
     pub(crate) server_changed_tx: mpsc::Sender<()>,
     pub(crate) result_rx: watch::Receiver<Result<(), TaskError>>,
+    pub(crate) tag_quotas: Arc<StdMutex<Vec<TagQuota<TAG>>>>,
+    pub(crate) quota_changed_rx: watch::Receiver<()>,
+    pub(crate) quota_set_tx: mpsc::Sender<()>,
+    pub(crate) send_shaper: Arc<StdMutex<TokenBucket>>,
+    pub(crate) resume_ticket: Arc<StdMutex<Option<ResumeTicket>>>,
+    pub(crate) key_exchange: Option<Arc<ClientKeyExchange>>,
+    pub(crate) extensions: Arc<StdMutex<Option<Extensions>>>,
+    pub(crate) refused_tx: Arc<watch::Sender<Option<Refusal>>>,
+    pub(crate) metadata: Arc<StdMutex<Metadata>>,
+    pub(crate) event_txs: EventTxs<TAG>,
 }
 
 impl<TX, RX, TAG> Clone for Control<TX, RX, TAG> {
@@ -172,6 +278,16 @@ impl<TX, RX, TAG> Clone for Control<TX, RX, TAG> {
             stats_rx: self.stats_rx.clone(),
             server_changed_tx: self.server_changed_tx.clone(),
             result_rx: self.result_rx.clone(),
+            tag_quotas: self.tag_quotas.clone(),
+            quota_changed_rx: self.quota_changed_rx.clone(),
+            quota_set_tx: self.quota_set_tx.clone(),
+            send_shaper: self.send_shaper.clone(),
+            resume_ticket: self.resume_ticket.clone(),
+            key_exchange: self.key_exchange.clone(),
+            extensions: self.extensions.clone(),
+            refused_tx: self.refused_tx.clone(),
+            metadata: self.metadata.clone(),
+            event_txs: self.event_txs.clone(),
         }
     }
 }
@@ -238,6 +354,27 @@ impl<TX, RX, TAG> Control<TX, RX, TAG> {
         &self.cfg
     }
 
+    /// The metadata of the connection.
+    ///
+    /// For outgoing connections this is the metadata set using [`set_metadata`](Self::set_metadata).
+    /// For incoming connections this is the metadata sent by the remote endpoint.
+    pub fn metadata(&self) -> Metadata {
+        self.metadata.lock().unwrap().clone()
+    }
+
+    /// Sets the metadata of the connection.
+    ///
+    /// The metadata is sent to the server with the link that establishes the connection,
+    /// thus it must be set before the first link is added.
+    /// It may have at most [`u16::MAX`] entries and keys and values must not exceed 64 kB each.
+    ///
+    /// It is only transmitted if the server supports the [metadata extension](crate::ext::Extension::Metadata).
+    pub fn set_metadata(&self, metadata: Metadata) -> Result<(), MetadataError> {
+        check_metadata(&metadata)?;
+        *self.metadata.lock().unwrap() = metadata;
+        Ok(())
+    }
+
     /// Returns whether the connection has been terminated.
     pub fn is_terminated(&self) -> bool {
         self.link_tx.is_closed()
@@ -266,6 +403,28 @@ impl<TX, RX, TAG> Control<TX, RX, TAG> {
         let _ = self.links_rx.changed().await;
     }
 
+    /// Returns a stream of connection and link events.
+    ///
+    /// Only events occuring after this function has been called are provided.
+    /// The stream ends after the [terminated event](EventKind::Terminated) has been provided.
+    ///
+    /// At most [`event_queue`](Cfg::event_queue) events are buffered for the stream.
+    /// If it is not polled fast enough, the oldest events are dropped and
+    /// a [lagged event](EventKind::Lagged) is provided in their place.
+    pub fn events(&self) -> impl Stream<Item = Event<TAG>> {
+        Box::pin(stream::unfold(self.event_txs.subscribe(), |rx| async move {
+            let mut rx = rx?;
+            let event = match rx.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    Event { time: Instant::now(), kind: EventKind::Lagged(missed) }
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+            Some((event, Some(rx)))
+        }))
+    }
+
     /// The current connection statistics.
     pub fn stats(&self) -> Stats {
         self.stats_rx.borrow().clone()
@@ -282,6 +441,96 @@ impl<TX, RX, TAG> Control<TX, RX, TAG> {
     pub async fn stats_changed(&mut self) {
         let _ = self.stats_rx.changed().await;
     }
+
+    /// Sets the byte quota shared by all links with the specified tag.
+    ///
+    /// Bytes transferred over links that have since been disconnected also count towards
+    /// the quota, thus it also covers reconnected links.
+    /// Replaces the quota of the tag, if present, but keeps the bytes used so far.
+    pub fn set_tag_quota(&self, tag: TAG, quota: Quota)
+    where
+        TAG: PartialEq + Send + Sync + 'static,
+    {
+        let mut tag_quotas = self.tag_quotas.lock().unwrap();
+        match tag_quotas.iter_mut().find(|tq| *tq.tag == tag) {
+            Some(tq) => tq.status.quota = quota,
+            None => tag_quotas.push(TagQuota::new(tag, quota)),
+        }
+        let _ = self.quota_set_tx.try_send(());
+    }
+
+    /// Removes the byte quota of the specified tag.
+    pub fn remove_tag_quota(&self, tag: &TAG)
+    where
+        TAG: PartialEq,
+    {
+        self.tag_quotas.lock().unwrap().retain(|tq| *tq.tag != *tag);
+        let _ = self.quota_set_tx.try_send(());
+    }
+
+    /// Status of the byte quota of the specified tag.
+    ///
+    /// `None` if no quota is set for the tag.
+    pub fn tag_quota_status(&self, tag: &TAG) -> Option<QuotaStatus>
+    where
+        TAG: PartialEq,
+    {
+        self.tag_quotas.lock().unwrap().iter().find(|tq| *tq.tag == *tag).map(|tq| tq.status.clone())
+    }
+
+    /// Status of the byte quotas of all tags.
+    pub fn tag_quotas(&self) -> Vec<(TAG, QuotaStatus)>
+    where
+        TAG: Clone,
+    {
+        self.tag_quotas.lock().unwrap().iter().map(|tq| ((*tq.tag).clone(), tq.status.clone())).collect()
+    }
+
+    /// Links that have reached the soft or hard threshold of their quota or
+    /// the quota of their tag.
+    pub fn quota_exceeded_links(&self) -> Vec<Link<TAG>> {
+        self.links_rx.borrow().iter().filter(|link| link.quota_state() != QuotaState::Within).cloned().collect()
+    }
+
+    /// Marks the current quota states as seen.
+    ///
+    /// This will cause [`quota_changed`](Self::quota_changed) to wait until a change occurs.
+    pub fn quota_update(&mut self) {
+        self.quota_changed_rx.borrow_and_update();
+    }
+
+    /// Waits until the quota state of a link or tag has changed.
+    pub async fn quota_changed(&mut self) {
+        let _ = self.quota_changed_rx.changed().await;
+    }
+
+    /// Whether the server has issued a resumption ticket for the connection.
+    ///
+    /// If so, links can be added to resume the connection after all links have been lost,
+    /// as long as the [resume window](crate::cfg::Cfg::resume_window) has not passed.
+    pub fn is_resumable(&self) -> bool {
+        self.resume_ticket.lock().unwrap().is_some()
+    }
+
+    /// The protocol extensions agreed with the remote endpoint.
+    ///
+    /// `None` if no link has been connected yet.
+    pub fn extensions(&self) -> Option<Extensions> {
+        *self.extensions.lock().unwrap()
+    }
+
+    /// The rate limit for sending data over the connection.
+    pub fn send_rate_limit(&self) -> Option<RateLimit> {
+        self.send_shaper.lock().unwrap().limit()
+    }
+
+    /// Sets the rate limit for sending data over the connection.
+    ///
+    /// The default rate limit is specified in the [configuration](crate::cfg::Cfg::send_rate_limit).
+    /// `None` removes the rate limit.
+    pub fn set_send_rate_limit(&self, limit: Option<RateLimit>) {
+        self.send_shaper.lock().unwrap().set_limit(limit);
+    }
 }
 
 impl<TX, RX, TAG> Control<TX, RX, TAG>
@@ -311,78 +560,164 @@ where
         assert!(user_data.len() <= u16::MAX as usize, "user_data is too big");
 
         // Perform protocol handshake.
-        let (remote_cfg, roundtrip, remote_user_data) = timeout(self.cfg.link_ping_timeout, async {
-            let client_secret = EphemeralSecret::random_from_rng(rand_core::OsRng);
-            let client_public_key = PublicKey::from(&client_secret);
-
-            let LinkMsg::Welcome {
-                extensions: _,
-                public_key: server_public_key,
-                server_id,
-                cfg,
-                user_data: remote_user_data,
-            } = LinkMsg::recv(&mut rx).await?
-            else {
-                return Err::<_, AddLinkError>(protocol_err!("expected Welcome message").into());
-            };
+        let (version, remote_cfg, roundtrip, remote_user_data, data_keys) =
+            timeout(self.cfg.link_ping_timeout, async {
+                let client_secret = EphemeralSecret::random_from_rng(rand_core::OsRng);
+                let client_public_key = PublicKey::from(&client_secret);
+
+                // The server may refuse the link before the handshake.
+                let msg = LinkMsg::recv(&mut rx).await?;
+                if let LinkMsg::Refused { reason } = msg {
+                    return Err(reason.into());
+                }
+                let LinkMsg::Welcome {
+                    version: remote_min_version,
+                    extensions,
+                    public_key: server_public_key,
+                    server_id,
+                    cfg,
+                    user_data: remote_user_data,
+                } = msg
+                else {
+                    return Err::<_, AddLinkError>(protocol_err!("expected Welcome message").into());
+                };
+
+                // The server chooses the protocol version, which requires overlapping version ranges.
+                let remote_max_version = cfg.max_version.unwrap_or(remote_min_version);
+                if LinkMsg::negotiate_version(remote_min_version, remote_max_version).is_none() {
+                    return Err(AddLinkError::UnsupportedProtocolVersion {
+                        min: remote_min_version,
+                        max: remote_max_version,
+                    });
+                }
 
-            let shared_secret = client_secret.diffie_hellman(&server_public_key);
+                if self.key_exchange.is_some() && extensions & LinkMsg::EXT_ENCRYPT == 0 {
+                    return Err(AddLinkError::EncryptionRequired);
+                }
 
-            {
-                let mut remote_server_id = self.remote_server_id.lock().await;
-                match &*remote_server_id {
-                    Some(remote_server_id) if *remote_server_id != server_id => {
-                        if self.cfg.disconnect_on_server_id_mismatch {
-                            let _ = self.server_changed_tx.try_send(());
+                let shared_secret = client_secret.diffie_hellman(&server_public_key);
+
+                {
+                    let mut remote_server_id = self.remote_server_id.lock().await;
+                    match &*remote_server_id {
+                        Some(remote_server_id) if *remote_server_id != server_id => {
+                            if self.cfg.disconnect_on_server_id_mismatch {
+                                let _ = self.server_changed_tx.try_send(());
+                            }
+                            return Err(AddLinkError::ServerIdMismatch {
+                                expected: *remote_server_id,
+                                present: server_id,
+                            });
+                        }
+                        Some(_) => (),
+                        None => {
+                            *remote_server_id = Some(server_id);
                         }
-                        return Err(AddLinkError::ServerIdMismatch {
-                            expected: *remote_server_id,
-                            present: server_id,
-                        });
-                    }
-                    Some(_) => (),
-                    None => {
-                        *remote_server_id = Some(server_id);
                     }
                 }
-            }
 
-            let start = Instant::now();
-            LinkMsg::Connect {
-                extensions: 0,
-                public_key: client_public_key,
-                server_id: self.server_id,
-                connection_id: EncryptedConnId::new(self.conn_id, &shared_secret),
-                existing_connection: self.connected.load(Ordering::Acquire),
-                user_data: user_data.to_vec(),
-                cfg: (&*self.cfg).into(),
-            }
-            .send(&mut tx)
-            .await?;
+                let transcript = Transcript {
+                    server_id,
+                    server_public_key: &server_public_key,
+                    client_public_key: &client_public_key,
+                    shared_secret: &shared_secret,
+                };
+                let auth = self.cfg.link_auth.as_ref().and_then(|link_auth| link_auth.client_proof(&transcript));
+
+                let existing_connection = self.connected.load(Ordering::Acquire);
+                let resume_ticket = *self.resume_ticket.lock().unwrap();
+                let resume_ticket = resume_ticket.and_then(|resume_ticket| resume_ticket.encrypt(&shared_secret));
+                let start = Instant::now();
+                LinkMsg::Connect {
+                    version: LinkMsg::MIN_PROTOCOL_VERSION,
+                    extensions: LinkMsg::EXTENSIONS,
+                    public_key: client_public_key,
+                    server_id: self.server_id,
+                    connection_id: EncryptedConnId::new(self.conn_id, &shared_secret),
+                    existing_connection,
+                    user_data: user_data.to_vec(),
+                    cfg: (&*self.cfg).into(),
+                    resume_ticket,
+                    auth: auth.map(Box::new),
+                    encryption_key: self.key_exchange.as_ref().map(|key_exchange| key_exchange.public_key()),
+                    metadata: if existing_connection || extensions & LinkMsg::EXT_METADATA == 0 {
+                        Box::default()
+                    } else {
+                        Box::new(self.metadata())
+                    },
+                }
+                .send(&mut tx)
+                .await?;
+
+                match LinkMsg::recv(&mut rx).await? {
+                    LinkMsg::Accepted { version, resume_ticket, auth_mac, encryption_key } => {
+                        // Servers not negotiating the protocol version use the lowest version of the client.
+                        let version = version.unwrap_or(LinkMsg::MIN_PROTOCOL_VERSION);
+                        if !LinkMsg::supports_version(version, remote_min_version, remote_max_version) {
+                            return Err(
+                                protocol_err!("server chose unsupported protocol version {version}").into()
+                            );
+                        }
 
-            match LinkMsg::recv(&mut rx).await? {
-                LinkMsg::Accepted => {
-                    self.connected.store(true, Ordering::Release);
-                    Ok((cfg, start.elapsed(), remote_user_data))
+                        // Verify that server knows the authentication key.
+                        if let Some(link_auth) = &self.cfg.link_auth {
+                            if !link_auth.verify_server(&transcript, auth_mac.as_ref()) {
+                                return Err(AddLinkError::AuthenticationFailed);
+                            }
+                        }
+
+                        // Agree on keys for end-to-end encryption.
+                        let data_keys = match &self.key_exchange {
+                            Some(key_exchange) => {
+                                let Some(encryption_key) = encryption_key else {
+                                    return Err(AddLinkError::EncryptionRequired);
+                                };
+                                let psk = self
+                                    .cfg
+                                    .link_auth
+                                    .as_ref()
+                                    .and_then(|auth| auth.client_key())
+                                    .map(|(key, _)| key);
+                                Some(key_exchange.complete(self.conn_id, &encryption_key, psk)?)
+                            }
+                            None => None,
+                        };
+
+                        if let Some(resume_ticket) =
+                            resume_ticket.and_then(|resume_ticket| resume_ticket.decrypt(&shared_secret))
+                        {
+                            *self.resume_ticket.lock().unwrap() = Some(resume_ticket);
+                        }
+                        self.connected.store(true, Ordering::Release);
+                        Ok((version, cfg, start.elapsed(), remote_user_data, data_keys))
+                    }
+                    LinkMsg::Refused { reason } => {
+                        if let RefusedReason::Custom(refusal) = &reason {
+                            self.refused_tx.send_replace(Some(refusal.clone()));
+                        }
+                        Err(reason.into())
+                    }
+                    _ => Err(protocol_err!("expected Accepted or Refused message").into()),
                 }
-                LinkMsg::Refused { reason } => Err(reason.into()),
-                _ => Err(protocol_err!("expected Accepted or Refused message").into()),
-            }
-        })
-        .await??;
+            })
+            .await??;
+
+        self.extensions.lock().unwrap().get_or_insert_with(|| Extensions::agreed(remote_cfg.extensions));
 
         // Create link.
-        let link_int = LinkInt::new(
+        let mut link_int = LinkInt::new(
             tag,
             self.conn_id,
             tx,
             rx,
             self.cfg.clone(),
+            version,
             remote_cfg,
             Direction::Outgoing,
             roundtrip,
             remote_user_data,
         );
+        link_int.data_keys = data_keys;
         let link = Link::from(&link_int);
         self.link_tx.send(link_int).await.map_err(|_| AddLinkError::ConnectionClosed)?;
 
@@ -444,6 +779,55 @@ pub struct Stats {
     pub recved_unconsumed: usize,
     /// Number of packets received and not yet consumed.
     pub recved_unconsumed_count: usize,
+    /// Number of redundant copies of data packets sent.
+    pub sent_redundant: u64,
+    /// Size of redundant copies of data packets sent.
+    pub sent_redundant_bytes: u64,
+    /// Number of data packets sent over fewer links than requested by their redundancy,
+    /// because not enough links were usable.
+    pub sent_redundancy_shortfall: u64,
+    /// Number of duplicate data packets received, which have been discarded.
+    pub recved_duplicates: u64,
+    /// Size of duplicate data packets received, i.e. wasted bytes.
+    pub recved_duplicate_bytes: u64,
+    /// Number of datagrams sent.
+    pub sent_datagrams: u64,
+    /// Number of datagrams dropped because no link was ready for sending.
+    pub sent_datagrams_dropped: u64,
+    /// Number of datagrams received.
+    pub recved_datagrams: u64,
+    /// Number of received datagrams dropped because they were not received fast enough.
+    pub recved_datagrams_dropped: u64,
+    /// Size of data sent with compression enabled, before compression.
+    pub sent_uncompressed_bytes: u64,
+    /// Size of data sent with compression enabled, after compression.
+    pub sent_compressed_bytes: u64,
+    /// Size of compressed data received, before decompression.
+    pub recved_compressed_bytes: u64,
+    /// Size of compressed data received, after decompression.
+    pub recved_uncompressed_bytes: u64,
+}
+
+impl Stats {
+    /// Compression ratio of sent data, i.e. compressed size divided by uncompressed size.
+    ///
+    /// `None` if no data has been sent with compression enabled.
+    pub fn compression_ratio(&self) -> Option<f64> {
+        if self.sent_uncompressed_bytes == 0 {
+            return None;
+        }
+        Some(self.sent_compressed_bytes as f64 / self.sent_uncompressed_bytes as f64)
+    }
+
+    /// Compression ratio of received data, i.e. compressed size divided by uncompressed size.
+    ///
+    /// `None` if no compressed data has been received.
+    pub fn recved_compression_ratio(&self) -> Option<f64> {
+        if self.recved_uncompressed_bytes == 0 {
+            return None;
+        }
+        Some(self.recved_compressed_bytes as f64 / self.recved_uncompressed_bytes as f64)
+    }
 }
 
 /// A handle for controlling and monitoring a link.
@@ -454,16 +838,24 @@ pub struct Link<TAG> {
     pub(crate) conn_id: ConnId,
     pub(crate) link_id: LinkId,
     pub(crate) direction: Direction,
+    pub(crate) version: u8,
     pub(crate) tag: Arc<TAG>,
     pub(crate) cfg: Arc<Cfg>,
     pub(crate) disconnected_rx: watch::Receiver<DisconnectReason>,
     pub(crate) disconnect_tx: mpsc::Sender<()>,
+    pub(crate) drain_tx: mpsc::Sender<()>,
     pub(crate) stats_rx: watch::Receiver<LinkStats>,
     pub(crate) remote_user_data: Arc<Vec<u8>>,
     pub(crate) blocked: Arc<AtomicBool>,
     pub(crate) blocked_changed_tx: mpsc::Sender<()>,
     pub(crate) blocked_changed_rx: watch::Receiver<()>,
     pub(crate) remotely_blocked: Arc<AtomicBool>,
+    pub(crate) priority: Arc<AtomicU8>,
+    pub(crate) priority_changed_tx: mpsc::Sender<()>,
+    pub(crate) quota: Arc<StdMutex<Quota>>,
+    pub(crate) quota_set_tx: mpsc::Sender<()>,
+    pub(crate) quota_rx: watch::Receiver<(QuotaStatus, QuotaState)>,
+    pub(crate) shaper: Arc<StdMutex<TokenBucket>>,
     pub(crate) not_working_rx: watch::Receiver<Option<(Instant, NotWorkingReason)>>,
 }
 
@@ -473,16 +865,24 @@ impl<TAG> Clone for Link<TAG> {
             conn_id: self.conn_id,
             link_id: self.link_id,
             direction: self.direction,
+            version: self.version,
             tag: self.tag.clone(),
             cfg: self.cfg.clone(),
             disconnected_rx: self.disconnected_rx.clone(),
             disconnect_tx: self.disconnect_tx.clone(),
+            drain_tx: self.drain_tx.clone(),
             stats_rx: self.stats_rx.clone(),
             remote_user_data: self.remote_user_data.clone(),
             blocked: self.blocked.clone(),
             blocked_changed_tx: self.blocked_changed_tx.clone(),
             blocked_changed_rx: self.blocked_changed_rx.clone(),
             remotely_blocked: self.remotely_blocked.clone(),
+            priority: self.priority.clone(),
+            priority_changed_tx: self.priority_changed_tx.clone(),
+            quota: self.quota.clone(),
+            quota_set_tx: self.quota_set_tx.clone(),
+            quota_rx: self.quota_rx.clone(),
+            shaper: self.shaper.clone(),
             not_working_rx: self.not_working_rx.clone(),
         }
     }
@@ -544,6 +944,11 @@ impl<TAG> Link<TAG> {
         self.direction
     }
 
+    /// Protocol version agreed with the remote endpoint.
+    pub fn protocol_version(&self) -> u8 {
+        self.version
+    }
+
     /// The configuration of the connection.
     pub fn cfg(&self) -> &Cfg {
         &self.cfg
@@ -598,9 +1003,32 @@ impl<TAG> Link<TAG> {
         let _ = self.disconnect_tx.try_send(());
     }
 
+    /// Gracefully drains and then disconnects this link.
+    ///
+    /// No new data is scheduled over the link.
+    /// Once all data sent over it has been acknowledged by the remote endpoint or
+    /// the [drain timeout](Cfg::link_drain_timeout) has passed, the link is disconnected.
+    /// This avoids resending outstanding data over other links.
+    ///
+    /// Returns when the link has been disconnected.
+    pub async fn drain(&self) {
+        self.start_drain();
+        self.disconnected().await;
+    }
+
+    /// Starts draining of this link.
+    ///
+    /// Returns immediately.
+    pub fn start_drain(&self) {
+        let _ = self.drain_tx.try_send(());
+    }
+
     /// Returns whether the link is blocked locally.
+    ///
+    /// This is the case if it has been blocked using [`set_blocked`](Self::set_blocked)
+    /// or the hard threshold of its quota or the quota of its tag has been reached.
     pub fn is_blocked(&self) -> bool {
-        self.blocked.load(Ordering::SeqCst)
+        self.blocked.load(Ordering::SeqCst) || self.quota_state() == QuotaState::HardExceeded
     }
 
     /// Blocks or unblocks the link.
@@ -628,6 +1056,66 @@ impl<TAG> Link<TAG> {
         self.blocked_changed_rx.borrow_and_update();
     }
 
+    /// The priority of the link.
+    ///
+    /// Lower values denote higher priority.
+    /// The default priority of a link is zero, i.e. the highest priority.
+    pub fn priority(&self) -> u8 {
+        self.priority.load(Ordering::SeqCst)
+    }
+
+    /// Sets the priority of the link.
+    ///
+    /// Lower values denote higher priority.
+    /// In [active-backup bonding mode](crate::cfg::BondingMode::ActiveBackup) only the working
+    /// links with the highest priority carry data.
+    /// The priority is also provided to the [link scheduler](crate::sched::LinkState::priority).
+    pub fn set_priority(&self, priority: u8) {
+        self.priority.store(priority, Ordering::SeqCst);
+        let _ = self.priority_changed_tx.try_send(());
+    }
+
+    /// The byte quota of the link.
+    pub fn quota(&self) -> Quota {
+        *self.quota.lock().unwrap()
+    }
+
+    /// Sets the byte quota of the link.
+    ///
+    /// The default quota is specified in the [configuration](crate::cfg::Cfg::link_quota).
+    /// When the hard threshold is reached, the link is blocked and this is signalled to
+    /// the remote endpoint.
+    pub fn set_quota(&self, quota: Quota) {
+        *self.quota.lock().unwrap() = quota;
+        let _ = self.quota_set_tx.try_send(());
+    }
+
+    /// Status of the byte quota of the link.
+    ///
+    /// This does not consider the quota of the link tag.
+    pub fn quota_status(&self) -> QuotaStatus {
+        self.quota_rx.borrow().0.clone()
+    }
+
+    /// Quota state of the link, considering its own quota and the quota of its tag.
+    pub fn quota_state(&self) -> QuotaState {
+        self.quota_rx.borrow().1
+    }
+
+    /// The rate limit for sending over the link.
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        self.shaper.lock().unwrap().limit()
+    }
+
+    /// Sets the rate limit for sending over the link.
+    ///
+    /// The default rate limit is specified in the [configuration](crate::cfg::Cfg::link_rate_limit).
+    /// A rate limit installed on an unlimited link permits an initial burst.
+    /// `None` removes the rate limit.
+    pub fn set_rate_limit(&self, limit: Option<RateLimit>) {
+        self.shaper.lock().unwrap().set_limit(limit);
+    }
+
     /// Returns whether the link is working.
     pub fn is_working(&self) -> bool {
         self.not_working_reason().is_none()
@@ -677,6 +1165,78 @@ impl<TAG> Link<TAG> {
     }
 }
 
+/// State of a byte quota.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum QuotaState {
+    /// Used bytes are below the thresholds.
+    #[default]
+    Within,
+    /// The soft threshold has been reached.
+    ///
+    /// The link is only used for sending data if no other link is usable.
+    SoftExceeded,
+    /// The hard threshold has been reached.
+    ///
+    /// The link is blocked.
+    HardExceeded,
+}
+
+impl fmt::Display for QuotaState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Within => write!(f, "within quota"),
+            Self::SoftExceeded => write!(f, "soft quota exceeded"),
+            Self::HardExceeded => write!(f, "hard quota exceeded"),
+        }
+    }
+}
+
+/// Status of a byte quota.
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub struct QuotaStatus {
+    /// The quota.
+    pub quota: Quota,
+    /// Bytes sent and received.
+    pub used: u64,
+    /// State of the quota.
+    pub state: QuotaState,
+}
+
+impl QuotaStatus {
+    pub(crate) fn new(quota: Quota, used: u64) -> Self {
+        Self { quota, used, state: quota.state(used) }
+    }
+}
+
+/// Byte quota shared by all links with the same tag.
+pub(crate) struct TagQuota<TAG> {
+    /// Link tag.
+    pub tag: Arc<TAG>,
+    /// Function checking whether a link tag matches.
+    pub matches: Box<dyn Fn(&TAG) -> bool + Send + Sync>,
+    /// Current status.
+    pub status: QuotaStatus,
+    /// Bytes used by links that have been disconnected.
+    pub disconnected_used: u64,
+}
+
+impl<TAG> TagQuota<TAG>
+where
+    TAG: PartialEq + Send + Sync + 'static,
+{
+    fn new(tag: TAG, quota: Quota) -> Self {
+        let tag = Arc::new(tag);
+        let matching_tag = tag.clone();
+        Self {
+            tag,
+            matches: Box::new(move |other| *other == *matching_tag),
+            status: QuotaStatus::new(quota, 0),
+            disconnected_used: 0,
+        }
+    }
+}
+
 /// Link statistics over a time interval.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[non_exhaustive]
@@ -723,8 +1283,14 @@ pub struct LinkStats {
     pub sent_unacked: u64,
     /// Current limit of [`sent_unacked`](Self::sent_unacked).
     pub unacked_limit: u64,
-    /// Round trip duration, i.e. ping.
+    /// Smoothed round trip duration, i.e. ping.
     pub roundtrip: Duration,
+    /// Lowest observed round trip duration.
+    pub min_roundtrip: Duration,
+    /// Variation of the round trip duration.
+    pub roundtrip_var: Duration,
+    /// Jitter, i.e. smoothed difference between consecutive round trip measurements.
+    pub jitter: Duration,
     /// Number of times link exceeded timeout.
     pub hangs: usize,
     /// Statistics over time intervals specified in the [configuration](crate::cfg::Cfg::stats_intervals).
@@ -830,3 +1396,151 @@ impl DisconnectReason {
         matches!(self, Self::SendTimeout | Self::PingTimeout | Self::UnconfirmedTimeout | Self::IoError(_))
     }
 }
+
+/// Sender of connection events to the subscribers.
+///
+/// Contains `None` once the connection task has terminated.
+pub(crate) struct EventTxs<TAG>(Arc<StdMutex<Option<broadcast::Sender<Event<TAG>>>>>);
+
+impl<TAG> Clone for EventTxs<TAG> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<TAG> EventTxs<TAG> {
+    /// Creates a new event sender without subscribers, buffering up to `capacity` events
+    /// for each subscriber.
+    pub(crate) fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+        Self(Arc::new(StdMutex::new(Some(tx))))
+    }
+
+    /// Subscribes to events.
+    ///
+    /// Returns `None` if the connection task has terminated.
+    pub(crate) fn subscribe(&self) -> Option<broadcast::Receiver<Event<TAG>>> {
+        self.0.lock().unwrap().as_ref().map(|tx| tx.subscribe())
+    }
+
+    /// Sends an event to all subscribers.
+    ///
+    /// The event is only constructed if there are subscribers.
+    pub(crate) fn emit(&self, kind: impl FnOnce() -> EventKind<TAG>) {
+        let txs = self.0.lock().unwrap();
+        let Some(tx) = &*txs else { return };
+        if tx.receiver_count() == 0 {
+            return;
+        }
+
+        let _ = tx.send(Event { time: Instant::now(), kind: kind() });
+    }
+
+    /// Closes all subscriptions.
+    pub(crate) fn close(&self) {
+        self.0.lock().unwrap().take();
+    }
+}
+
+/// An event of a connection.
+///
+/// Obtained from [`Control::events`].
+#[derive(Debug)]
+pub struct Event<TAG> {
+    /// Time of occurrence.
+    pub time: Instant,
+    /// Kind of event.
+    pub kind: EventKind<TAG>,
+}
+
+impl<TAG> Clone for Event<TAG> {
+    fn clone(&self) -> Self {
+        Self { time: self.time, kind: self.kind.clone() }
+    }
+}
+
+/// Kind of a connection [event](Event).
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum EventKind<TAG> {
+    /// A link was added to the connection.
+    ///
+    /// The link is not working until it has been confirmed.
+    LinkAdded(Link<TAG>),
+    /// A link has been confirmed and is working.
+    LinkConfirmed(Link<TAG>),
+    /// A link has become unconfirmed and is not working.
+    LinkUnconfirmed {
+        /// Link.
+        link: Link<TAG>,
+        /// Reason why the link is not working.
+        reason: NotWorkingReason,
+    },
+    /// A link has been blocked.
+    LinkBlocked {
+        /// Link.
+        link: Link<TAG>,
+        /// Whether the link was blocked by the remote endpoint.
+        remote: bool,
+    },
+    /// A link has been unblocked.
+    LinkUnblocked {
+        /// Link.
+        link: Link<TAG>,
+        /// Whether the link was unblocked by the remote endpoint.
+        remote: bool,
+    },
+    /// A link was disconnected.
+    LinkDisconnected {
+        /// Link.
+        link: Link<TAG>,
+        /// Reason for disconnection.
+        reason: DisconnectReason,
+    },
+    /// The connection has been established.
+    Established,
+    /// The connection task terminated.
+    Terminated(Result<(), TaskError>),
+    /// The specified number of events were dropped, because the
+    /// [event stream](Control::events) was not polled fast enough.
+    Lagged(u64),
+}
+
+impl<TAG> Clone for EventKind<TAG> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::LinkAdded(link) => Self::LinkAdded(link.clone()),
+            Self::LinkConfirmed(link) => Self::LinkConfirmed(link.clone()),
+            Self::LinkUnconfirmed { link, reason } => {
+                Self::LinkUnconfirmed { link: link.clone(), reason: reason.clone() }
+            }
+            Self::LinkBlocked { link, remote } => Self::LinkBlocked { link: link.clone(), remote: *remote },
+            Self::LinkUnblocked { link, remote } => Self::LinkUnblocked { link: link.clone(), remote: *remote },
+            Self::LinkDisconnected { link, reason } => {
+                Self::LinkDisconnected { link: link.clone(), reason: reason.clone() }
+            }
+            Self::Established => Self::Established,
+            Self::Terminated(result) => Self::Terminated(result.clone()),
+            Self::Lagged(missed) => Self::Lagged(*missed),
+        }
+    }
+}
+
+impl<TAG> fmt::Display for EventKind<TAG> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::LinkAdded(link) => write!(f, "link {} added", link.id()),
+            Self::LinkConfirmed(link) => write!(f, "link {} confirmed", link.id()),
+            Self::LinkUnconfirmed { link, reason } => write!(f, "link {} unconfirmed: {reason}", link.id()),
+            Self::LinkBlocked { link, remote: false } => write!(f, "link {} blocked locally", link.id()),
+            Self::LinkBlocked { link, remote: true } => write!(f, "link {} blocked remotely", link.id()),
+            Self::LinkUnblocked { link, remote: false } => write!(f, "link {} unblocked locally", link.id()),
+            Self::LinkUnblocked { link, remote: true } => write!(f, "link {} unblocked remotely", link.id()),
+            Self::LinkDisconnected { link, reason } => write!(f, "link {} disconnected: {reason}", link.id()),
+            Self::Established => write!(f, "connection established"),
+            Self::Terminated(Ok(())) => write!(f, "connection terminated"),
+            Self::Terminated(Err(err)) => write!(f, "connection failed: {err}"),
+            Self::Lagged(missed) => write!(f, "{missed} events dropped"),
+        }
+    }
+}
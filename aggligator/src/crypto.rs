@@ -0,0 +1,230 @@
+//! End-to-end encryption of data.
+//!
+//! The client and the server each generate an ephemeral Diffie-Hellman key per connection
+//! and exchange the public keys during the handshake of the links.
+//! The keys for both directions are derived from the shared secret, the connection id
+//! and, if configured, the pre-shared authentication key.
+//!
+//! Data packets are sealed using ChaCha20-Poly1305 when they are submitted for sending.
+//! Since data is delivered in order, the nonce is given by the number of
+//! data packets sent before in the same direction and thus not transmitted.
+//! Datagrams may be lost or reordered and are thus prefixed by their nonce.
+
+use bytes::Bytes;
+use chacha20poly1305::{aead::Aead, ChaCha20Poly1305, Key, KeyInit, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::{fmt, io, sync::Mutex as StdMutex};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+use crate::{control::Direction, id::ConnId, protocol_err};
+
+/// Size of authentication tag appended to each sealed data packet.
+pub(crate) const TAG_SIZE: usize = 16;
+
+/// Size of nonce prefixed to each sealed datagram.
+const DATAGRAM_NONCE_SIZE: usize = 8;
+
+/// Overhead of a sealed datagram.
+pub(crate) const DATAGRAM_OVERHEAD: usize = DATAGRAM_NONCE_SIZE + TAG_SIZE;
+
+/// Nonce domain of data packets.
+const DOMAIN_DATA: u32 = 0;
+
+/// Nonce domain of datagrams.
+const DOMAIN_DATAGRAM: u32 = 1;
+
+/// Keys for encrypting data of a connection.
+#[derive(Clone)]
+pub(crate) struct DataKeys {
+    tx: [u8; 32],
+    rx: [u8; 32],
+}
+
+impl fmt::Debug for DataKeys {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DataKeys")
+    }
+}
+
+impl DataKeys {
+    /// Derives the keys of the specified endpoint from the Diffie-Hellman key exchange.
+    fn derive(
+        direction: Direction, conn_id: ConnId, client_public_key: &PublicKey, server_public_key: &PublicKey,
+        shared_secret: &[u8], psk: Option<&[u8]>,
+    ) -> Self {
+        let mut ikm = shared_secret.to_vec();
+        ikm.extend_from_slice(psk.unwrap_or_default());
+
+        let mut info = b"aggligator data".to_vec();
+        info.extend_from_slice(client_public_key.as_bytes());
+        info.extend_from_slice(server_public_key.as_bytes());
+
+        let hkdf = Hkdf::<Sha256>::new(Some(&conn_id.0.to_be_bytes()), &ikm);
+        let mut okm = [0; 64];
+        hkdf.expand(&info, &mut okm).expect("HKDF output size is valid");
+
+        let mut client_to_server = [0; 32];
+        let mut server_to_client = [0; 32];
+        client_to_server.copy_from_slice(&okm[..32]);
+        server_to_client.copy_from_slice(&okm[32..]);
+
+        match direction {
+            Direction::Outgoing => Self { tx: client_to_server, rx: server_to_client },
+            Direction::Incoming => Self { tx: server_to_client, rx: client_to_server },
+        }
+    }
+
+    /// Performs the key exchange on the server using the public key of the client.
+    ///
+    /// Returns the public key of the server and the derived keys.
+    pub fn server(conn_id: ConnId, client_public_key: &PublicKey, psk: Option<&[u8]>) -> (PublicKey, Self) {
+        let secret = EphemeralSecret::random_from_rng(rand_core::OsRng);
+        let public_key = PublicKey::from(&secret);
+        let shared_secret = secret.diffie_hellman(client_public_key);
+        let keys = Self::derive(
+            Direction::Incoming,
+            conn_id,
+            client_public_key,
+            &public_key,
+            shared_secret.as_bytes(),
+            psk,
+        );
+        (public_key, keys)
+    }
+
+    /// Creates the ciphers for sending and receiving.
+    pub fn ciphers(&self) -> (DataCipher, DataCipher) {
+        (DataCipher::new(&self.tx), DataCipher::new(&self.rx))
+    }
+}
+
+/// Key exchange of the client.
+pub(crate) struct ClientKeyExchange {
+    public_key: PublicKey,
+    state: StdMutex<ClientKeyExchangeState>,
+}
+
+enum ClientKeyExchangeState {
+    Pending(Option<EphemeralSecret>),
+    Done { server_public_key: PublicKey, keys: DataKeys },
+}
+
+impl fmt::Debug for ClientKeyExchange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClientKeyExchange").field("public_key", &self.public_key).finish_non_exhaustive()
+    }
+}
+
+impl ClientKeyExchange {
+    /// Starts the key exchange by generating an ephemeral key.
+    pub fn new() -> Self {
+        let secret = EphemeralSecret::random_from_rng(rand_core::OsRng);
+        let public_key = PublicKey::from(&secret);
+        Self { public_key, state: StdMutex::new(ClientKeyExchangeState::Pending(Some(secret))) }
+    }
+
+    /// Public key of the client.
+    pub fn public_key(&self) -> PublicKey {
+        self.public_key
+    }
+
+    /// Completes the key exchange using the public key of the server.
+    ///
+    /// All links of a connection must present the same public key of the server.
+    pub fn complete(
+        &self, conn_id: ConnId, server_public_key: &PublicKey, psk: Option<&[u8]>,
+    ) -> Result<DataKeys, io::Error> {
+        let mut state = self.state.lock().unwrap();
+        match &mut *state {
+            ClientKeyExchangeState::Pending(secret) => {
+                let shared_secret = secret.take().unwrap().diffie_hellman(server_public_key);
+                let keys = DataKeys::derive(
+                    Direction::Outgoing,
+                    conn_id,
+                    &self.public_key,
+                    server_public_key,
+                    shared_secret.as_bytes(),
+                    psk,
+                );
+                *state =
+                    ClientKeyExchangeState::Done { server_public_key: *server_public_key, keys: keys.clone() };
+                Ok(keys)
+            }
+            ClientKeyExchangeState::Done { server_public_key: expected, keys } => {
+                if expected != server_public_key {
+                    return Err(protocol_err!("server encryption key changed"));
+                }
+                Ok(keys.clone())
+            }
+        }
+    }
+}
+
+/// Cipher for sealing or opening data packets and datagrams of one direction.
+pub(crate) struct DataCipher {
+    cipher: ChaCha20Poly1305,
+    counter: u64,
+    datagram_counter: u64,
+}
+
+impl fmt::Debug for DataCipher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DataCipher")
+            .field("counter", &self.counter)
+            .field("datagram_counter", &self.datagram_counter)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DataCipher {
+    fn new(key: &[u8; 32]) -> Self {
+        Self { cipher: ChaCha20Poly1305::new(Key::from_slice(key)), counter: 0, datagram_counter: 0 }
+    }
+
+    fn nonce(domain: u32, counter: u64) -> Nonce {
+        let mut nonce = Nonce::default();
+        nonce[..4].copy_from_slice(&domain.to_be_bytes());
+        nonce[4..].copy_from_slice(&counter.to_be_bytes());
+        nonce
+    }
+
+    /// Encrypts and authenticates the next data packet.
+    pub fn seal(&mut self, data: &[u8]) -> Bytes {
+        let nonce = Self::nonce(DOMAIN_DATA, self.counter);
+        self.counter += 1;
+        self.cipher.encrypt(&nonce, data).expect("encryption failed").into()
+    }
+
+    /// Decrypts and verifies the next data packet.
+    pub fn open(&mut self, data: &[u8]) -> Result<Bytes, io::Error> {
+        let nonce = Self::nonce(DOMAIN_DATA, self.counter);
+        let data = self.cipher.decrypt(&nonce, data).map_err(|_| protocol_err!("data decryption failed"))?;
+        self.counter += 1;
+        Ok(data.into())
+    }
+
+    /// Encrypts and authenticates a datagram.
+    pub fn seal_datagram(&mut self, data: &[u8]) -> Bytes {
+        let counter = self.datagram_counter;
+        self.datagram_counter += 1;
+        let nonce = Self::nonce(DOMAIN_DATAGRAM, counter);
+        let mut sealed = Vec::with_capacity(data.len() + DATAGRAM_OVERHEAD);
+        sealed.extend_from_slice(&counter.to_be_bytes());
+        sealed.extend_from_slice(&self.cipher.encrypt(&nonce, data).expect("encryption failed"));
+        sealed.into()
+    }
+
+    /// Decrypts and verifies a datagram.
+    ///
+    /// Datagrams are not protected against replay.
+    pub fn open_datagram(&self, data: &[u8]) -> Result<Bytes, io::Error> {
+        if data.len() < DATAGRAM_OVERHEAD {
+            return Err(protocol_err!("sealed datagram too short"));
+        }
+        let (counter, data) = data.split_at(DATAGRAM_NONCE_SIZE);
+        let nonce = Self::nonce(DOMAIN_DATAGRAM, u64::from_be_bytes(counter.try_into().unwrap()));
+        let data = self.cipher.decrypt(&nonce, data).map_err(|_| protocol_err!("datagram decryption failed"))?;
+        Ok(data.into())
+    }
+}
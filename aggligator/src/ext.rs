@@ -0,0 +1,154 @@
+//! Protocol extensions.
+//!
+//! During the handshake of each link both endpoints advertise the protocol extensions
+//! they support as a set of feature bits.
+//! An extension is used on a connection only if it has been agreed on,
+//! i.e. it is supported by both endpoints.
+//! This allows endpoints of different versions to interoperate without changing the
+//! protocol version.
+//!
+//! The agreed extensions of a connection are available from
+//! [`Control::extensions`](crate::control::Control::extensions).
+
+use std::fmt;
+
+/// A protocol extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Extension {
+    /// Unreliable datagrams.
+    Datagram,
+    /// Connection resumption.
+    Resume,
+    /// Link authentication.
+    Auth,
+    /// End-to-end encryption of data.
+    Encrypt,
+    /// Compression of data using lz4.
+    Compress,
+    /// Compression of data using zstd.
+    Zstd,
+    /// Extensible configuration section exchanged during the handshake.
+    CfgSection,
+    /// Connection-level metadata sent with the first link of a connection.
+    Metadata,
+}
+
+impl Extension {
+    /// All known protocol extensions.
+    pub const ALL: &'static [Self] = &[
+        Self::Datagram,
+        Self::Resume,
+        Self::Auth,
+        Self::Encrypt,
+        Self::Compress,
+        Self::Zstd,
+        Self::CfgSection,
+        Self::Metadata,
+    ];
+
+    /// Feature bit of the extension.
+    ///
+    /// Bits must never be reassigned, since they are exchanged with the remote endpoint.
+    pub(crate) const fn bit(self) -> u32 {
+        match self {
+            Self::Datagram => 1 << 0,
+            Self::Resume => 1 << 1,
+            Self::Auth => 1 << 2,
+            Self::Encrypt => 1 << 3,
+            Self::Compress => 1 << 4,
+            Self::Zstd => 1 << 5,
+            Self::CfgSection => 1 << 6,
+            Self::Metadata => 1 << 7,
+        }
+    }
+
+    /// Whether the extension is supported by this build.
+    pub const fn is_supported(self) -> bool {
+        !matches!(self, Self::Zstd) || cfg!(feature = "zstd")
+    }
+
+    /// Name of the extension.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Datagram => "datagram",
+            Self::Resume => "resume",
+            Self::Auth => "auth",
+            Self::Encrypt => "encrypt",
+            Self::Compress => "compress",
+            Self::Zstd => "zstd",
+            Self::CfgSection => "cfg-section",
+            Self::Metadata => "metadata",
+        }
+    }
+}
+
+impl fmt::Display for Extension {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A set of protocol extensions.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Extensions(u32);
+
+impl Extensions {
+    /// The extensions supported by this build.
+    pub const SUPPORTED: Self = Self::supported();
+
+    const fn supported() -> Self {
+        let mut bits = 0;
+        let mut i = 0;
+        while i < Extension::ALL.len() {
+            if Extension::ALL[i].is_supported() {
+                bits |= Extension::ALL[i].bit();
+            }
+            i += 1;
+        }
+        Self(bits)
+    }
+
+    /// Feature bits of the set.
+    pub(crate) const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// The extensions agreed with a remote endpoint supporting the specified extensions.
+    pub(crate) const fn agreed(remote: u32) -> Self {
+        Self(Self::SUPPORTED.0 & remote)
+    }
+
+    /// Whether the set contains the extension.
+    pub const fn contains(self, extension: Extension) -> bool {
+        self.0 & extension.bit() != 0
+    }
+
+    /// Whether the set is empty.
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Iterates over the known extensions contained in the set.
+    pub fn iter(self) -> impl Iterator<Item = Extension> {
+        Extension::ALL.iter().copied().filter(move |&ext| self.contains(ext))
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl fmt::Display for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (n, ext) in self.iter().enumerate() {
+            if n > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{ext}")?;
+        }
+        Ok(())
+    }
+}
@@ -132,6 +132,55 @@ impl LinkId {
     }
 }
 
+/// Ticket for resuming a connection after all of its links have been lost.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct ResumeTicket(pub NonZeroU128);
+
+impl fmt::Debug for ResumeTicket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ResumeTicket")
+    }
+}
+
+impl ResumeTicket {
+    /// Generates a new resumption ticket.
+    pub(crate) fn generate() -> Self {
+        loop {
+            match OsRng.gen() {
+                0 => (),
+                ticket => return Self(NonZeroU128::new(ticket).unwrap()),
+            }
+        }
+    }
+
+    /// Encrypts the resumption ticket with the last 16 bytes of the shared secret.
+    ///
+    /// Returns `None` in the negligible case that the encrypted ticket would be zero,
+    /// since this encodes the absence of a ticket.
+    pub fn encrypt(self, secret: &SharedSecret) -> Option<EncryptedResumeTicket> {
+        let key = BE::read_u128(&secret.as_bytes()[16..]);
+        NonZeroU128::new(key ^ self.0.get()).map(EncryptedResumeTicket)
+    }
+}
+
+/// Encrypted resumption ticket.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct EncryptedResumeTicket(pub NonZeroU128);
+
+impl fmt::Debug for EncryptedResumeTicket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "*ResumeTicket*")
+    }
+}
+
+impl EncryptedResumeTicket {
+    /// Decrypts the resumption ticket with the last 16 bytes of the shared secret.
+    pub fn decrypt(self, secret: &SharedSecret) -> Option<ResumeTicket> {
+        let key = BE::read_u128(&secret.as_bytes()[16..]);
+        NonZeroU128::new(key ^ self.0.get()).map(ResumeTicket)
+    }
+}
+
 /// Server identifier.
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ServerId(pub NonZeroU128);
@@ -56,7 +56,8 @@ struct Header {
 }
 
 impl IntegrityCodec {
-    const HEADER_LEN: usize = size_of::<u32>() + size_of::<u16>() + size_of::<u32>();
+    /// Length of the frame header.
+    pub(crate) const HEADER_LEN: usize = size_of::<u32>() + size_of::<u16>() + size_of::<u32>();
 
     /// Creates a new `IntegrityCodec` with the default configuration values.
     pub fn new() -> Self {
@@ -114,6 +115,27 @@ impl IntegrityCodec {
 
         Ok(Some(data))
     }
+
+    /// Encodes the frame header for the specified data.
+    ///
+    /// The data itself must be sent directly after the header.
+    pub(crate) fn encode_header(&mut self, data: &[u8]) -> io::Result<[u8; Self::HEADER_LEN]> {
+        if data.len() > self.max_frame_len as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, IntegrityError::PacketTooBig));
+        }
+
+        let mut header = [0; Self::HEADER_LEN];
+        let mut buf = &mut header[..];
+
+        buf.put_u32(data.len() as u32);
+
+        buf.put_u16(self.encode_seq);
+        self.encode_seq = self.encode_seq.wrapping_add(1);
+
+        buf.put_u32(hash(data));
+
+        Ok(header)
+    }
 }
 
 impl Decoder for IntegrityCodec {
@@ -148,19 +170,10 @@ impl Encoder<Bytes> for IntegrityCodec {
     type Error = io::Error;
 
     fn encode(&mut self, data: Bytes, dst: &mut BytesMut) -> io::Result<()> {
-        if data.len() > self.max_frame_len as usize {
-            return Err(io::Error::new(io::ErrorKind::InvalidInput, IntegrityError::PacketTooBig));
-        }
+        let header = self.encode_header(&data)?;
 
         dst.reserve(Self::HEADER_LEN + data.len());
-
-        dst.put_u32(data.len() as u32);
-
-        dst.put_u16(self.encode_seq);
-        self.encode_seq = self.encode_seq.wrapping_add(1);
-
-        dst.put_u32(hash(&data));
-
+        dst.extend_from_slice(&header);
         dst.extend_from_slice(&data[..]);
 
         Ok(())
@@ -10,21 +10,51 @@
 
 mod codec;
 
-use bytes::Bytes;
-use futures::{Sink, SinkExt, Stream, StreamExt};
+use bytes::{Buf, Bytes, BytesMut};
+use futures::{ready, Sink, Stream, StreamExt};
 use std::{
+    collections::VecDeque,
     io,
+    io::IoSlice,
     pin::Pin,
     task::{Context, Poll},
 };
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio_util::codec::{FramedRead, FramedWrite};
+use tokio_util::codec::FramedRead;
 
 pub use codec::*;
 
+/// Number of queued bytes above which no more packets are accepted until some are written.
+const BACKPRESSURE_BOUNDARY: usize = 8 * 1_024;
+
+/// Capacity of each allocation of the buffer for frame headers and small packets.
+const BUF_CAPACITY: usize = 16 * 1_024;
+
+/// Packets up to this size are copied into the buffer instead of being queued separately.
+const COPY_THRESHOLD: usize = 256;
+
+/// Maximum number of chunks passed to a single vectored write.
+const MAX_SLICES: usize = 64;
+
 /// Transmit wrapper for using an IO-stream-based link.
+///
+/// Each packet is prefixed by a header generated by the [integrity codec](IntegrityCodec).
+/// Frame headers and small packets are collected in a reusable buffer, while
+/// larger packets are written directly from their [`Bytes`] without copying,
+/// using vectored writes if supported by the writer.
 #[derive(Debug)]
-pub struct IoTx<W>(pub FramedWrite<W, IntegrityCodec>);
+pub struct IoTx<W> {
+    /// IO writer.
+    write: W,
+    /// Integrity codec for encoding frame headers.
+    codec: IntegrityCodec,
+    /// Buffer for frame headers and small packets.
+    buf: BytesMut,
+    /// Chunks queued for writing.
+    queue: VecDeque<Bytes>,
+    /// Total length of queued chunks and buffered data.
+    queued: usize,
+}
 
 impl<W> IoTx<W>
 where
@@ -32,12 +62,80 @@ where
 {
     /// Wraps an IO writer using the default configuration of the integrity codec.
     pub fn new(write: W) -> Self {
-        Self(FramedWrite::new(write, IntegrityCodec::new()))
+        Self::with_codec(write, IntegrityCodec::new())
     }
 
     /// Wraps an IO writer using a customized integrity codec.
     pub fn with_codec(write: W, codec: IntegrityCodec) -> Self {
-        Self(FramedWrite::new(write, codec))
+        Self { write, codec, buf: BytesMut::new(), queue: VecDeque::new(), queued: 0 }
+    }
+
+    /// Returns a reference to the underlying IO writer.
+    pub fn get_ref(&self) -> &W {
+        &self.write
+    }
+
+    /// Returns a mutable reference to the underlying IO writer.
+    ///
+    /// Writing to the IO writer directly will corrupt the stream of frames.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.write
+    }
+
+    /// Returns the underlying IO writer.
+    ///
+    /// Packets that have not been flushed are lost.
+    pub fn into_inner(self) -> W {
+        self.write
+    }
+
+    /// Moves the contents of the buffer to the write queue.
+    fn queue_buf(&mut self) {
+        if !self.buf.is_empty() {
+            self.queue.push_back(self.buf.split().freeze());
+        }
+    }
+
+    /// Writes queued data until the queued length is at most `limit` bytes.
+    fn poll_write_queued(&mut self, cx: &mut Context, limit: usize) -> Poll<io::Result<()>>
+    where
+        W: Unpin,
+    {
+        self.queue_buf();
+
+        while self.queued > limit {
+            let n = if self.write.is_write_vectored() {
+                let mut slices = [IoSlice::new(&[]); MAX_SLICES];
+                let mut cnt = 0;
+                for (slice, chunk) in slices.iter_mut().zip(&self.queue) {
+                    *slice = IoSlice::new(chunk);
+                    cnt += 1;
+                }
+                ready!(Pin::new(&mut self.write).poll_write_vectored(cx, &slices[..cnt]))?
+            } else {
+                let chunk = self.queue.front().expect("write queue empty");
+                ready!(Pin::new(&mut self.write).poll_write(cx, chunk))?
+            };
+
+            if n == 0 {
+                return Poll::Ready(Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write frame")));
+            }
+            self.queued -= n;
+
+            let mut remaining = n;
+            while remaining > 0 {
+                let chunk = self.queue.front_mut().expect("write queue empty");
+                if chunk.len() <= remaining {
+                    remaining -= chunk.len();
+                    self.queue.pop_front();
+                } else {
+                    chunk.advance(remaining);
+                    remaining = 0;
+                }
+            }
+        }
+
+        Poll::Ready(Ok(()))
     }
 }
 
@@ -47,23 +145,48 @@ where
 {
     type Error = io::Error;
 
-    #[inline]
     fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
-        Pin::into_inner(self).0.poll_ready_unpin(cx)
+        let this = Pin::into_inner(self);
+        if this.queued >= BACKPRESSURE_BOUNDARY {
+            ready!(this.poll_write_queued(cx, BACKPRESSURE_BOUNDARY - 1))?;
+        }
+        Poll::Ready(Ok(()))
     }
 
-    #[inline]
     fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
-        Pin::into_inner(self).0.start_send_unpin(item)
+        let this = Pin::into_inner(self);
+        let header = this.codec.encode_header(&item)?;
+        let copy = item.len() <= COPY_THRESHOLD || !this.write.is_write_vectored();
+
+        let needed = IntegrityCodec::HEADER_LEN + if copy { item.len() } else { 0 };
+        if this.buf.capacity() - this.buf.len() < needed {
+            // Reuses the allocation if all previously split off chunks have been written.
+            this.buf.reserve(needed.max(BUF_CAPACITY));
+        }
+
+        this.buf.extend_from_slice(&header);
+        this.queued += header.len() + item.len();
+
+        if copy {
+            this.buf.extend_from_slice(&item);
+        } else {
+            this.queue_buf();
+            this.queue.push_back(item);
+        }
+
+        Ok(())
     }
 
-    #[inline]
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
-        Pin::into_inner(self).0.poll_flush_unpin(cx)
+        let this = Pin::into_inner(self);
+        ready!(this.poll_write_queued(cx, 0))?;
+        Pin::new(&mut this.write).poll_flush(cx)
     }
 
     fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
-        Pin::into_inner(self).0.poll_close_unpin(cx)
+        let this = Pin::into_inner(self);
+        ready!(this.poll_write_queued(cx, 0))?;
+        Pin::new(&mut this.write).poll_shutdown(cx)
     }
 }
 
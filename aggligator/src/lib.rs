@@ -67,11 +67,16 @@
 //!
 //! # Connection security
 //!
-//! Aggligator does *not* perform cryptographic authentication of the remote endpoint or encryption of data.
+//! By default Aggligator does *not* perform cryptographic authentication of the remote endpoint
+//! or encryption of data.
 //! If you are sending sensitive data over an untrusted connection you should encrypt it
-//! and authenticate the remote endpoint, for example using [TLS].
+//! and authenticate the remote endpoint, for example using [TLS] on each link.
 //! The implementation provided in the [tokio-rustls] crate works nicely with Aggligator.
 //!
+//! Alternatively, links can be authenticated using a pre-shared key or tokens by setting
+//! [`Cfg::link_auth`] and data can be encrypted end-to-end by setting [`Cfg::data_encryption`].
+//! The latter agrees on keys once per connection and thus allows links to remain unencrypted.
+//!
 //! However, the unique identifier of each connection is encrypted using a shared
 //! secret that is exchanged via [Diffie-Hellman key exchange].
 //! Thus, an eavesdropper cannot inject fake links to an existing connection by using
@@ -96,19 +101,30 @@
 //! [aggligator-util]: https://docs.rs/aggligator-util/latest/aggligator_util/
 //!
 
-#[cfg(any(target_pointer_width = "8", target_pointer_width = "16"))]
+#[cfg(target_pointer_width = "16")]
 compile_error!("target pointer width must be at least 32 bits");
 
 mod agg;
 pub mod alc;
+mod auth;
+pub mod cc;
 pub mod cfg;
+mod compress;
 pub mod connect;
 pub mod control;
+mod crypto;
+pub mod ext;
 pub mod id;
 pub mod io;
 mod msg;
 mod peekable_mpsc;
+pub mod sched;
 mod seq;
+mod shaper;
+
+#[cfg(feature = "testing")]
+#[cfg_attr(docsrs, doc(cfg(feature = "testing")))]
+pub mod testing;
 
 #[cfg(feature = "dump")]
 #[cfg_attr(docsrs, doc(cfg(feature = "dump")))]
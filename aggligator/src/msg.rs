@@ -1,20 +1,24 @@
 //! Protocol messages.
 
 use byteorder::{ReadBytesExt, WriteBytesExt, BE};
-use bytes::Bytes;
+use bytes::{BufMut, Bytes, BytesMut};
 use futures::{Sink, SinkExt, Stream, StreamExt};
 use std::{fmt, io, num::NonZeroU128};
 use x25519_dalek::PublicKey;
 
 use crate::{
+    auth::{AuthMac, AuthProof},
     cfg::ExchangedCfg,
-    id::{EncryptedConnId, ServerId},
+    connect::Refusal,
+    control::Metadata,
+    ext::{Extension, Extensions},
+    id::{EncryptedConnId, EncryptedResumeTicket, ServerId},
     protocol_err,
     seq::Seq,
 };
 
 /// Reason for refusal of an incoming link.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) enum RefusedReason {
     /// The connection was closed.
     Closed,
@@ -24,6 +28,22 @@ pub(crate) enum RefusedReason {
     ConnectionRefused,
     /// The incoming link was refused by the link filter.
     LinkRefused,
+    /// The resumption ticket presented by the link is invalid.
+    InvalidResumeTicket,
+    /// The link failed to authenticate.
+    AuthenticationFailed,
+    /// The server requires end-to-end encryption of data.
+    EncryptionRequired,
+    /// The incoming connection was refused with an application-defined reason.
+    Custom(Refusal),
+    /// The server has reached its maximum number of connections.
+    TooManyConnections,
+    /// The connection has reached its maximum number of links.
+    TooManyLinks,
+    /// Too many incoming connections are waiting to be accepted by the server.
+    TooManyPending,
+    /// Too many links have been established from the remote address recently.
+    RateLimited,
 }
 
 impl RefusedReason {
@@ -31,27 +51,81 @@ impl RefusedReason {
     const ID_NOT_LISTENING: u8 = 2;
     const ID_CONNECTION_REFUSED: u8 = 3;
     const ID_LINK_REFUSED: u8 = 4;
-}
+    const ID_INVALID_RESUME_TICKET: u8 = 5;
+    const ID_AUTHENTICATION_FAILED: u8 = 6;
+    const ID_ENCRYPTION_REQUIRED: u8 = 7;
+    const ID_CUSTOM: u8 = 8;
+    const ID_TOO_MANY_CONNECTIONS: u8 = 9;
+    const ID_TOO_MANY_LINKS: u8 = 10;
+    const ID_TOO_MANY_PENDING: u8 = 11;
+    const ID_RATE_LIMITED: u8 = 12;
 
-impl From<RefusedReason> for u8 {
-    fn from(rr: RefusedReason) -> Self {
-        match rr {
-            RefusedReason::Closed => RefusedReason::ID_CLOSED,
-            RefusedReason::NotListening => RefusedReason::ID_NOT_LISTENING,
-            RefusedReason::ConnectionRefused => RefusedReason::ID_CONNECTION_REFUSED,
-            RefusedReason::LinkRefused => RefusedReason::ID_LINK_REFUSED,
+    /// The reason as understood by a remote endpoint using the specified protocol version.
+    ///
+    /// Reasons introduced after protocol version 4 are reported as refusal of the connection.
+    pub fn for_version(self, version: u8) -> Self {
+        match self {
+            Self::InvalidResumeTicket | Self::AuthenticationFailed | Self::EncryptionRequired if version < 5 => {
+                Self::ConnectionRefused
+            }
+            Self::Custom(_) if version < 6 => Self::ConnectionRefused,
+            Self::TooManyConnections | Self::TooManyLinks | Self::TooManyPending | Self::RateLimited
+                if version < 7 =>
+            {
+                Self::ConnectionRefused
+            }
+            other => other,
         }
     }
-}
 
-impl TryFrom<u8> for RefusedReason {
-    type Error = io::Error;
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
-        match value {
+    fn write(&self, mut writer: impl io::Write) -> Result<(), io::Error> {
+        let id = match self {
+            Self::Closed => Self::ID_CLOSED,
+            Self::NotListening => Self::ID_NOT_LISTENING,
+            Self::ConnectionRefused => Self::ID_CONNECTION_REFUSED,
+            Self::LinkRefused => Self::ID_LINK_REFUSED,
+            Self::InvalidResumeTicket => Self::ID_INVALID_RESUME_TICKET,
+            Self::AuthenticationFailed => Self::ID_AUTHENTICATION_FAILED,
+            Self::EncryptionRequired => Self::ID_ENCRYPTION_REQUIRED,
+            Self::Custom(_) => Self::ID_CUSTOM,
+            Self::TooManyConnections => Self::ID_TOO_MANY_CONNECTIONS,
+            Self::TooManyLinks => Self::ID_TOO_MANY_LINKS,
+            Self::TooManyPending => Self::ID_TOO_MANY_PENDING,
+            Self::RateLimited => Self::ID_RATE_LIMITED,
+        };
+        writer.write_u8(id)?;
+
+        if let Self::Custom(Refusal { code, message }) = self {
+            // The message may exceed the maximum length if the refusal was constructed directly.
+            let message = Refusal::truncate(message);
+            writer.write_u16::<BE>(*code)?;
+            writer.write_u8(message.len() as u8)?;
+            writer.write_all(message.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    fn read(mut reader: impl io::Read) -> Result<Self, io::Error> {
+        match reader.read_u8()? {
             Self::ID_CLOSED => Ok(Self::Closed),
             Self::ID_NOT_LISTENING => Ok(Self::NotListening),
             Self::ID_CONNECTION_REFUSED => Ok(Self::ConnectionRefused),
             Self::ID_LINK_REFUSED => Ok(Self::LinkRefused),
+            Self::ID_INVALID_RESUME_TICKET => Ok(Self::InvalidResumeTicket),
+            Self::ID_AUTHENTICATION_FAILED => Ok(Self::AuthenticationFailed),
+            Self::ID_ENCRYPTION_REQUIRED => Ok(Self::EncryptionRequired),
+            Self::ID_CUSTOM => {
+                let code = reader.read_u16::<BE>()?;
+                let mut message = vec![0; reader.read_u8()?.into()];
+                reader.read_exact(&mut message)?;
+                let message = String::from_utf8(message).map_err(|_| protocol_err!("invalid refusal message"))?;
+                Ok(Self::Custom(Refusal { code, message }))
+            }
+            Self::ID_TOO_MANY_CONNECTIONS => Ok(Self::TooManyConnections),
+            Self::ID_TOO_MANY_LINKS => Ok(Self::TooManyLinks),
+            Self::ID_TOO_MANY_PENDING => Ok(Self::TooManyPending),
+            Self::ID_RATE_LIMITED => Ok(Self::RateLimited),
             other => Err(protocol_err!("unknown refused reason {other}")),
         }
     }
@@ -63,7 +137,10 @@ pub(crate) enum LinkMsg {
     /// Welcome message sent from server to client.
     Welcome {
         // Magic identifier "LIAG\0".
-        // Protocol version of server.
+        /// Lowest protocol version supported by server.
+        ///
+        /// The highest supported version is part of the configuration.
+        version: u8,
         /// Flags of supported protocol extensions.
         extensions: u32,
         /// Diffie-Hellman public key for this link of server.
@@ -78,7 +155,11 @@ pub(crate) enum LinkMsg {
     /// Connect message from client to server.
     Connect {
         // Magic identifier "LIAG\0".
-        // Protocol version of client.
+        /// Lowest protocol version supported by client.
+        ///
+        /// The highest supported version is part of the configuration.
+        /// The server chooses the protocol version and returns it in the `Accepted` message.
+        version: u8,
         /// Flags of supported protocol extensions.
         extensions: u32,
         /// Diffie-Hellman public key for this link of client.
@@ -94,9 +175,33 @@ pub(crate) enum LinkMsg {
         user_data: Vec<u8>,
         /// Configuration of client.
         cfg: ExchangedCfg,
+        /// Resumption ticket issued by the server, required for joining an existing connection.
+        ///
+        /// It is encrypted with the shared secret of this link.
+        resume_ticket: Option<EncryptedResumeTicket>,
+        /// Proof that the client knows the authentication key.
+        auth: Option<Box<AuthProof>>,
+        /// Diffie-Hellman public key of client for end-to-end encryption of the connection.
+        encryption_key: Option<PublicKey>,
+        /// Connection metadata, only sent when establishing a new connection.
+        metadata: Box<Metadata>,
     },
     /// Connection accepted by server.
-    Accepted,
+    Accepted {
+        /// Protocol version chosen by server.
+        ///
+        /// Only sent if it is at least [`LinkMsg::NEGOTIATION_VERSION`], otherwise the
+        /// lowest protocol version supported by the client is used.
+        version: Option<u8>,
+        /// Resumption ticket issued for the connection, if it can be resumed.
+        ///
+        /// It is encrypted with the shared secret of this link.
+        resume_ticket: Option<EncryptedResumeTicket>,
+        /// Proof that the server knows the authentication key.
+        auth_mac: Option<AuthMac>,
+        /// Diffie-Hellman public key of server for end-to-end encryption of the connection.
+        encryption_key: Option<PublicKey>,
+    },
     /// Connection refused by server.
     Refused {
         /// Reason for refusal.
@@ -113,6 +218,11 @@ pub(crate) enum LinkMsg {
         /// Sequence number.
         seq: Seq,
     },
+    /// Unreliable datagram.
+    ///
+    /// This is followed by one data packet.
+    /// It is not acknowledged.
+    Datagram,
     /// Acknowledges data received over this link.
     Ack {
         /// Sequence that has been received on this link.
@@ -157,12 +267,55 @@ pub(crate) enum LinkMsg {
 }
 
 impl LinkMsg {
-    /// Protocol version.
-    pub const PROTOCOL_VERSION: u8 = 4;
+    /// Highest supported protocol version.
+    pub const PROTOCOL_VERSION: u8 = 7;
+
+    /// Lowest supported protocol version.
+    pub const MIN_PROTOCOL_VERSION: u8 = 4;
+
+    /// Lowest protocol version in which the server chooses the protocol version,
+    /// protocol extensions are used and the configuration section is exchanged.
+    pub const NEGOTIATION_VERSION: u8 = 5;
 
     /// Magic identifier.
     const MAGIC: &'static [u8; 5] = b"LIAG\0";
 
+    /// Protocol extension: unreliable datagrams.
+    pub const EXT_DATAGRAM: u32 = Extension::Datagram.bit();
+
+    /// Protocol extension: connection resumption.
+    pub const EXT_RESUME: u32 = Extension::Resume.bit();
+
+    /// Protocol extension: link authentication.
+    pub const EXT_AUTH: u32 = Extension::Auth.bit();
+
+    /// Protocol extension: end-to-end encryption of data.
+    pub const EXT_ENCRYPT: u32 = Extension::Encrypt.bit();
+
+    /// Protocol extension: compression of data using lz4.
+    pub const EXT_COMPRESS: u32 = Extension::Compress.bit();
+
+    /// Protocol extension: compression of data using zstd.
+    pub const EXT_ZSTD: u32 = Extension::Zstd.bit();
+
+    /// Protocol extension: extensible configuration section.
+    pub const EXT_CFG_SECTION: u32 = Extension::CfgSection.bit();
+
+    /// Protocol extension: connection metadata.
+    pub const EXT_METADATA: u32 = Extension::Metadata.bit();
+
+    /// Supported protocol extensions.
+    pub const EXTENSIONS: u32 = Extensions::SUPPORTED.bits();
+
+    /// Flag of Accepted message: authentication code is present.
+    const ACCEPTED_AUTH_MAC: u8 = 1 << 0;
+
+    /// Flag of Accepted message: encryption key is present.
+    const ACCEPTED_ENCRYPTION_KEY: u8 = 1 << 1;
+
+    /// Flag of Accepted message: protocol version is present.
+    const ACCEPTED_VERSION: u8 = 1 << 2;
+
     const MSG_WELCOME: u8 = 1;
     const MSG_CONNECT: u8 = 2;
     const MSG_ACCEPTED: u8 = 3;
@@ -178,13 +331,14 @@ impl LinkMsg {
     const MSG_TEST_DATA: u8 = 13;
     const MSG_SET_BLOCK: u8 = 14;
     const MSG_GOODBYE: u8 = 15;
+    const MSG_DATAGRAM: u8 = 16;
 
     fn write(&self, mut writer: impl io::Write) -> Result<(), io::Error> {
         match self {
-            LinkMsg::Welcome { server_id, extensions, public_key, user_data, cfg } => {
+            LinkMsg::Welcome { version, server_id, extensions, public_key, user_data, cfg } => {
                 writer.write_u8(Self::MSG_WELCOME)?;
                 writer.write_all(Self::MAGIC)?;
-                writer.write_u8(Self::PROTOCOL_VERSION)?;
+                writer.write_u8(*version)?;
                 writer.write_u32::<BE>(*extensions)?;
                 writer.write_all(public_key.as_bytes())?;
                 writer.write_u128::<BE>(server_id.0.get())?;
@@ -195,9 +349,10 @@ impl LinkMsg {
                         .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "user data is too long"))?,
                 )?;
                 writer.write_all(user_data)?;
-                cfg.write(&mut writer)?;
+                cfg.write(&mut writer, *extensions)?;
             }
             LinkMsg::Connect {
+                version,
                 extensions,
                 public_key,
                 server_id,
@@ -205,10 +360,14 @@ impl LinkMsg {
                 existing_connection,
                 user_data,
                 cfg,
+                resume_ticket,
+                auth,
+                encryption_key,
+                metadata,
             } => {
                 writer.write_u8(Self::MSG_CONNECT)?;
                 writer.write_all(Self::MAGIC)?;
-                writer.write_u8(Self::PROTOCOL_VERSION)?;
+                writer.write_u8(*version)?;
                 writer.write_u32::<BE>(*extensions)?;
                 writer.write_all(public_key.as_bytes())?;
                 writer.write_u128::<BE>(server_id.map(|si| si.0.get()).unwrap_or(0))?;
@@ -221,14 +380,73 @@ impl LinkMsg {
                         .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "user data is too long"))?,
                 )?;
                 writer.write_all(user_data)?;
-                cfg.write(&mut writer)?;
+                cfg.write(&mut writer, *extensions)?;
+                if extensions & Self::EXT_RESUME != 0 {
+                    writer.write_u128::<BE>(resume_ticket.map(|rt| rt.0.get()).unwrap_or(0))?;
+                }
+                if extensions & Self::EXT_AUTH != 0 {
+                    match auth.as_deref() {
+                        Some(AuthProof { token_id, mac }) => {
+                            writer.write_u8(1)?;
+                            writer.write_u16::<BE>(token_id.len().try_into().map_err(|_| {
+                                io::Error::new(io::ErrorKind::InvalidData, "token id is too long")
+                            })?)?;
+                            writer.write_all(token_id)?;
+                            writer.write_all(mac)?;
+                        }
+                        None => writer.write_u8(0)?,
+                    }
+                }
+                if extensions & Self::EXT_ENCRYPT != 0 {
+                    match encryption_key {
+                        Some(encryption_key) => {
+                            writer.write_u8(1)?;
+                            writer.write_all(encryption_key.as_bytes())?;
+                        }
+                        None => writer.write_u8(0)?,
+                    }
+                }
+                if extensions & Self::EXT_METADATA != 0 {
+                    let too_long = || io::Error::new(io::ErrorKind::InvalidData, "metadata is too long");
+                    writer.write_u16::<BE>(metadata.len().try_into().map_err(|_| too_long())?)?;
+                    for (key, value) in metadata.iter() {
+                        writer.write_u16::<BE>(key.len().try_into().map_err(|_| too_long())?)?;
+                        writer.write_all(key.as_bytes())?;
+                        writer.write_u16::<BE>(value.len().try_into().map_err(|_| too_long())?)?;
+                        writer.write_all(value.as_bytes())?;
+                    }
+                }
             }
-            LinkMsg::Accepted => {
+            LinkMsg::Accepted { version, resume_ticket, auth_mac, encryption_key } => {
                 writer.write_u8(Self::MSG_ACCEPTED)?;
+                if version.is_some() || resume_ticket.is_some() || auth_mac.is_some() || encryption_key.is_some()
+                {
+                    writer.write_u128::<BE>(resume_ticket.map(|rt| rt.0.get()).unwrap_or(0))?;
+                    let mut flags = 0;
+                    if auth_mac.is_some() {
+                        flags |= Self::ACCEPTED_AUTH_MAC;
+                    }
+                    if encryption_key.is_some() {
+                        flags |= Self::ACCEPTED_ENCRYPTION_KEY;
+                    }
+                    if version.is_some() {
+                        flags |= Self::ACCEPTED_VERSION;
+                    }
+                    writer.write_u8(flags)?;
+                }
+                if let Some(auth_mac) = auth_mac {
+                    writer.write_all(auth_mac)?;
+                }
+                if let Some(encryption_key) = encryption_key {
+                    writer.write_all(encryption_key.as_bytes())?;
+                }
+                if let Some(version) = version {
+                    writer.write_u8(*version)?;
+                }
             }
             LinkMsg::Refused { reason } => {
                 writer.write_u8(Self::MSG_REFUSED)?;
-                writer.write_u8((*reason).into())?;
+                reason.write(&mut writer)?;
             }
             LinkMsg::Ping => {
                 writer.write_u8(Self::MSG_PING)?;
@@ -240,6 +458,9 @@ impl LinkMsg {
                 writer.write_u8(Self::MSG_DATA)?;
                 writer.write_u32::<BE>((*seq).into())?;
             }
+            LinkMsg::Datagram => {
+                writer.write_u8(Self::MSG_DATAGRAM)?;
+            }
             LinkMsg::Ack { received } => {
                 writer.write_u8(Self::MSG_ACK)?;
                 writer.write_u32::<BE>((*received).into())?;
@@ -286,15 +507,12 @@ impl LinkMsg {
                 if magic != Self::MAGIC {
                     return Err(protocol_err!("invalid magic"));
                 }
+                // Support of the protocol version is checked during negotiation.
                 let version = reader.read_u8()?;
-                if version != Self::PROTOCOL_VERSION {
-                    return Err(protocol_err!(
-                        "expected protocol version {} but got {version}",
-                        Self::PROTOCOL_VERSION
-                    ));
-                }
+                let extensions = reader.read_u32::<BE>()?;
                 Self::Welcome {
-                    extensions: reader.read_u32::<BE>()?,
+                    version,
+                    extensions,
                     public_key: {
                         let mut buf = [0; 32];
                         reader.read_exact(&mut buf)?;
@@ -310,7 +528,7 @@ impl LinkMsg {
                         reader.read_exact(&mut buf)?;
                         buf
                     },
-                    cfg: ExchangedCfg::read(&mut reader)?,
+                    cfg: ExchangedCfg::read(&mut reader, extensions)?,
                 }
             }
             Self::MSG_CONNECT => {
@@ -319,15 +537,12 @@ impl LinkMsg {
                 if magic != Self::MAGIC {
                     return Err(protocol_err!("invalid magic"));
                 }
+                // Support of the protocol version is checked during negotiation.
                 let version = reader.read_u8()?;
-                if version != Self::PROTOCOL_VERSION {
-                    return Err(protocol_err!(
-                        "expected protocol version {} but got {version}",
-                        Self::PROTOCOL_VERSION
-                    ));
-                }
+                let extensions = reader.read_u32::<BE>()?;
                 Self::Connect {
-                    extensions: reader.read_u32::<BE>()?,
+                    version,
+                    extensions,
                     public_key: {
                         let mut buf = [0; 32];
                         reader.read_exact(&mut buf)?;
@@ -342,14 +557,81 @@ impl LinkMsg {
                         reader.read_exact(&mut buf)?;
                         buf
                     },
-                    cfg: ExchangedCfg::read(&mut reader)?,
+                    cfg: ExchangedCfg::read(&mut reader, extensions)?,
+                    resume_ticket: if extensions & Self::EXT_RESUME != 0 {
+                        NonZeroU128::new(reader.read_u128::<BE>()?).map(EncryptedResumeTicket)
+                    } else {
+                        None
+                    },
+                    auth: if extensions & Self::EXT_AUTH != 0 && reader.read_u8()? != 0 {
+                        Some(Box::new(AuthProof {
+                            token_id: {
+                                let len = reader.read_u16::<BE>()?;
+                                let mut buf = vec![0; len.into()];
+                                reader.read_exact(&mut buf)?;
+                                buf
+                            },
+                            mac: {
+                                let mut buf = AuthMac::default();
+                                reader.read_exact(&mut buf)?;
+                                buf
+                            },
+                        }))
+                    } else {
+                        None
+                    },
+                    encryption_key: if extensions & Self::EXT_ENCRYPT != 0 && reader.read_u8()? != 0 {
+                        let mut buf = [0; 32];
+                        reader.read_exact(&mut buf)?;
+                        Some(buf.into())
+                    } else {
+                        None
+                    },
+                    metadata: if extensions & Self::EXT_METADATA != 0 {
+                        let mut metadata = Metadata::new();
+                        for _ in 0..reader.read_u16::<BE>()? {
+                            let key = Self::read_string(&mut reader)?;
+                            let value = Self::read_string(&mut reader)?;
+                            metadata.insert(key, value);
+                        }
+                        Box::new(metadata)
+                    } else {
+                        Box::default()
+                    },
                 }
             }
-            Self::MSG_ACCEPTED => Self::Accepted,
-            Self::MSG_REFUSED => Self::Refused { reason: RefusedReason::try_from(reader.read_u8()?)? },
+            Self::MSG_ACCEPTED => {
+                // All fields are optional for compatibility with older versions.
+                let (resume_ticket, flags) = match reader.read_u128::<BE>() {
+                    Ok(ticket) => (
+                        NonZeroU128::new(ticket).map(EncryptedResumeTicket),
+                        reader.read_u8().unwrap_or_default(),
+                    ),
+                    Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => (None, 0),
+                    Err(err) => return Err(err),
+                };
+                let auth_mac = if flags & Self::ACCEPTED_AUTH_MAC != 0 {
+                    let mut buf = AuthMac::default();
+                    reader.read_exact(&mut buf)?;
+                    Some(buf)
+                } else {
+                    None
+                };
+                let encryption_key = if flags & Self::ACCEPTED_ENCRYPTION_KEY != 0 {
+                    let mut buf = [0; 32];
+                    reader.read_exact(&mut buf)?;
+                    Some(buf.into())
+                } else {
+                    None
+                };
+                let version = if flags & Self::ACCEPTED_VERSION != 0 { Some(reader.read_u8()?) } else { None };
+                Self::Accepted { version, resume_ticket, auth_mac, encryption_key }
+            }
+            Self::MSG_REFUSED => Self::Refused { reason: RefusedReason::read(&mut reader)? },
             Self::MSG_PING => Self::Ping,
             Self::MSG_PONG => Self::Pong,
             Self::MSG_DATA => Self::Data { seq: reader.read_u32::<BE>()?.into() },
+            Self::MSG_DATAGRAM => Self::Datagram,
             Self::MSG_ACK => Self::Ack { received: reader.read_u32::<BE>()?.into() },
             Self::MSG_CONSUMED => {
                 Self::Consumed { seq: reader.read_u32::<BE>()?.into(), consumed: reader.read_u32::<BE>()? }
@@ -357,7 +639,7 @@ impl LinkMsg {
             Self::MSG_SEND_FINISH => Self::SendFinish { seq: reader.read_u32::<BE>()?.into() },
             Self::MSG_RECEIVE_CLOSE => Self::ReceiveClose { seq: reader.read_u32::<BE>()?.into() },
             Self::MSG_RECEIVE_FINISH => Self::ReceiveFinish { seq: reader.read_u32::<BE>()?.into() },
-            Self::MSG_TEST_DATA => Self::TestData { size: reader.bytes().count() },
+            Self::MSG_TEST_DATA => Self::TestData { size: io::copy(&mut reader, &mut io::sink())? as usize },
             Self::MSG_SET_BLOCK => Self::SetBlock { blocked: reader.read_u8()? != 0 },
             Self::MSG_GOODBYE => Self::Goodbye,
             other => return Err(protocol_err!("invalid message id {other}")),
@@ -365,20 +647,61 @@ impl LinkMsg {
         Ok(msg)
     }
 
-    pub(crate) fn encode(&self) -> Bytes {
-        let mut buf = Vec::with_capacity(match self {
+    /// Reads a UTF-8 string prefixed by its length.
+    fn read_string(mut reader: impl io::Read) -> Result<String, io::Error> {
+        let mut buf = vec![0; reader.read_u16::<BE>()?.into()];
+        reader.read_exact(&mut buf)?;
+        String::from_utf8(buf).map_err(|_| protocol_err!("invalid UTF-8 string"))
+    }
+
+    /// Highest protocol version supported by this and the remote endpoint.
+    ///
+    /// Returns `None` if the supported version ranges do not overlap.
+    pub fn negotiate_version(remote_min: u8, remote_max: u8) -> Option<u8> {
+        let version = Self::PROTOCOL_VERSION.min(remote_max);
+        (version >= Self::MIN_PROTOCOL_VERSION.max(remote_min)).then_some(version)
+    }
+
+    /// Whether the protocol version is supported by this and the remote endpoint.
+    pub fn supports_version(version: u8, remote_min: u8, remote_max: u8) -> bool {
+        (Self::MIN_PROTOCOL_VERSION.max(remote_min)..=Self::PROTOCOL_VERSION.min(remote_max)).contains(&version)
+    }
+
+    /// Lowest protocol version supporting the message.
+    pub fn min_version(&self) -> u8 {
+        match self {
+            Self::Datagram => Self::NEGOTIATION_VERSION,
+            _ => Self::MIN_PROTOCOL_VERSION,
+        }
+    }
+
+    /// The message as understood by a remote endpoint using the specified protocol version.
+    pub fn for_version(self, version: u8) -> Self {
+        match self {
+            Self::Refused { reason } => Self::Refused { reason: reason.for_version(version) },
+            other => other,
+        }
+    }
+
+    /// Buffer size sufficient for encoding the message in most cases.
+    fn size_hint(&self) -> usize {
+        match self {
             Self::TestData { size } => size + 16,
             _ => 16,
-        });
-        self.write(&mut buf).unwrap();
-        buf.into()
+        }
+    }
+
+    pub(crate) fn encode(&self) -> Result<Bytes, io::Error> {
+        let mut buf = Vec::with_capacity(self.size_hint());
+        self.write(&mut buf)?;
+        Ok(buf.into())
     }
 
     pub async fn send<S>(&self, mut tx: S) -> Result<(), io::Error>
     where
         S: Sink<Bytes, Error = io::Error> + Unpin,
     {
-        tx.send(self.encode()).await?;
+        tx.send(self.encode()?).await?;
         Ok(())
     }
 
@@ -395,6 +718,32 @@ impl LinkMsg {
     }
 }
 
+/// Pooled buffer for encoding link messages.
+///
+/// Messages are encoded into a shared allocation and split off from it.
+/// The allocation is reused once all messages encoded into it have been dropped.
+#[derive(Debug, Default)]
+pub(crate) struct MsgBuf(BytesMut);
+
+impl MsgBuf {
+    /// Capacity of each allocation.
+    const CAPACITY: usize = 4 * 1_024;
+
+    /// Encodes the message.
+    pub(crate) fn encode(&mut self, msg: &LinkMsg) -> Result<Bytes, io::Error> {
+        let hint = msg.size_hint();
+        if self.0.capacity() < hint {
+            self.0.reserve(hint.max(Self::CAPACITY));
+        }
+
+        if let Err(err) = msg.write((&mut self.0).writer()) {
+            self.0.clear();
+            return Err(err);
+        }
+        Ok(self.0.split().freeze())
+    }
+}
+
 /// A reliable message.
 ///
 /// Its reception must be acknowledged by the receiver and it will be resent if lost.
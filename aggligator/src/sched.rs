@@ -0,0 +1,215 @@
+//! Link scheduling.
+//!
+//! A [link scheduler](LinkScheduler) selects the link that carries the next data packet
+//! of a connection.
+//! It is set on the connection [task](crate::Task) using [`Task::set_link_scheduler`](crate::Task::set_link_scheduler).
+//!
+//! The following schedulers are provided:
+//!
+//!   * [FirstReady] sends over the link that became ready most recently (default),
+//!   * [WeightedRoundRobin] distributes data over the links according to configurable weights,
+//!   * [LowestRoundtrip] fills the link with the lowest roundtrip time first,
+//!   * [EarliestDelivery] sends over the link that is expected to deliver the data first.
+//!
+
+use std::{collections::HashMap, fmt, time::Duration};
+use tokio::time::Instant;
+
+use crate::{control::QuotaState, id::LinkId};
+
+/// State of a link provided to a [link scheduler](LinkScheduler).
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct LinkState<'a, TAG> {
+    /// Link id.
+    pub id: LinkId,
+    /// User-supplied link tag.
+    pub tag: &'a TAG,
+    /// Whether the link is idle and can send data immediately.
+    pub ready: bool,
+    /// Since when the link is idle.
+    pub idle_since: Option<Instant>,
+    /// Whether the link is unconfirmed, i.e. it is not known to be working.
+    pub unconfirmed: bool,
+    /// Whether the link is blocked locally or by the remote endpoint.
+    pub blocked: bool,
+    /// [Priority](crate::control::Link::set_priority) of the link; lower values denote higher priority.
+    pub priority: u8,
+    /// State of the byte quota of the link and its tag.
+    ///
+    /// Links that exceeded their soft quota are only provided to the scheduler,
+    /// if no other link is usable.
+    pub quota_state: QuotaState,
+    /// Round trip duration.
+    pub roundtrip: Duration,
+    /// Data sent but not yet acknowledged by the remote endpoint in bytes.
+    pub unacked: usize,
+    /// Current limit of [unacked](Self::unacked) data.
+    pub unacked_limit: usize,
+    /// Send throughput in bytes per second, measured over the shortest
+    /// [statistics interval](crate::cfg::Cfg::stats_intervals).
+    pub throughput: f64,
+}
+
+impl<'a, TAG> LinkState<'a, TAG> {
+    /// Whether the link can be used for sending data, i.e. it is confirmed and not blocked.
+    pub fn is_usable(&self) -> bool {
+        !self.unconfirmed && !self.blocked
+    }
+
+    /// Whether unacknowledged data is below its limit.
+    pub fn has_space(&self) -> bool {
+        self.unacked < self.unacked_limit
+    }
+
+    /// Estimated bandwidth of the link in bytes per second.
+    ///
+    /// This is the higher value of the measured throughput and the unacknowledged
+    /// data limit divided by the round trip duration.
+    pub fn bandwidth(&self) -> f64 {
+        let bdp_rate = self.unacked_limit as f64 / self.roundtrip.as_secs_f64().max(0.001);
+        self.throughput.max(bdp_rate)
+    }
+}
+
+/// Link scheduler selecting the link that carries the next data packet.
+///
+/// The scheduler is consulted whenever a data packet is ready for sending and the state
+/// of the connection has changed.
+pub trait LinkScheduler<TAG>: Send {
+    /// Selects the link for sending the next data packet of the specified size.
+    ///
+    /// Links that are being drained or disconnected are not provided.
+    /// Returns the index of the selected link within `links`.
+    ///
+    /// If the selected link is not [ready](LinkState::ready) or `None` is returned,
+    /// sending is deferred until the scheduler is consulted again.
+    fn select(&mut self, links: &[LinkState<TAG>], size: usize) -> Option<usize>;
+
+    /// Notifies the scheduler that a data packet of the specified size has been sent
+    /// over the specified link.
+    fn sent(&mut self, _link: &LinkState<TAG>, _size: usize) {}
+}
+
+/// Sends over the link that became ready most recently.
+///
+/// This is the default link scheduler.
+/// It keeps using links that quickly process data, while slower links only
+/// receive data when the faster ones are busy.
+#[derive(Debug, Clone, Default)]
+pub struct FirstReady;
+
+impl<TAG> LinkScheduler<TAG> for FirstReady {
+    fn select(&mut self, links: &[LinkState<TAG>], _size: usize) -> Option<usize> {
+        links
+            .iter()
+            .enumerate()
+            .filter(|(_, link)| link.ready)
+            .max_by_key(|(_, link)| link.idle_since)
+            .map(|(idx, _)| idx)
+    }
+}
+
+/// Link weight function type.
+type WeightFn<TAG> = Box<dyn Fn(&LinkState<TAG>) -> u32 + Send>;
+
+/// Distributes data over the usable links in proportion to their weights.
+///
+/// A link with weight zero is not used.
+/// If the link whose turn it is cannot send immediately, sending is deferred until it can.
+pub struct WeightedRoundRobin<TAG> {
+    weight: WeightFn<TAG>,
+    passes: HashMap<LinkId, f64>,
+}
+
+impl<TAG> fmt::Debug for WeightedRoundRobin<TAG> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WeightedRoundRobin").field("passes", &self.passes).finish_non_exhaustive()
+    }
+}
+
+impl<TAG> Default for WeightedRoundRobin<TAG> {
+    /// Distributes data equally over all usable links.
+    fn default() -> Self {
+        Self::new(|_| 1)
+    }
+}
+
+impl<TAG> WeightedRoundRobin<TAG> {
+    /// Creates a new weighted round-robin scheduler using the specified function
+    /// to determine the weight of a link.
+    pub fn new(weight: impl Fn(&LinkState<TAG>) -> u32 + Send + 'static) -> Self {
+        Self { weight: Box::new(weight), passes: HashMap::new() }
+    }
+}
+
+impl<TAG> LinkScheduler<TAG> for WeightedRoundRobin<TAG> {
+    fn select(&mut self, links: &[LinkState<TAG>], _size: usize) -> Option<usize> {
+        self.passes.retain(|id, _| links.iter().any(|link| link.id == *id));
+
+        // Links that were not used yet start at the current minimum pass.
+        let min_pass = self.passes.values().copied().reduce(f64::min).unwrap_or_default();
+        for link in links {
+            self.passes.entry(link.id).or_insert(min_pass);
+        }
+
+        links
+            .iter()
+            .enumerate()
+            .filter(|(_, link)| link.is_usable() && (self.weight)(link) > 0)
+            .min_by(|(_, a), (_, b)| self.passes[&a.id].total_cmp(&self.passes[&b.id]))
+            .map(|(idx, _)| idx)
+    }
+
+    fn sent(&mut self, link: &LinkState<TAG>, size: usize) {
+        let weight = (self.weight)(link).max(1);
+        *self.passes.entry(link.id).or_default() += size as f64 / weight as f64;
+    }
+}
+
+/// Fills the link with the lowest round trip time first.
+///
+/// Data is sent over the link with the lowest round trip time whose unacknowledged data
+/// is below its limit.
+/// Thus slower links are only used when the faster links are saturated.
+#[derive(Debug, Clone, Default)]
+pub struct LowestRoundtrip;
+
+impl<TAG> LinkScheduler<TAG> for LowestRoundtrip {
+    fn select(&mut self, links: &[LinkState<TAG>], _size: usize) -> Option<usize> {
+        links
+            .iter()
+            .enumerate()
+            .filter(|(_, link)| link.is_usable() && link.has_space())
+            .min_by_key(|(_, link)| link.roundtrip)
+            .map(|(idx, _)| idx)
+    }
+}
+
+/// Sends over the link that is expected to deliver the data first.
+///
+/// The delivery time of a link is estimated from half its round trip time and the
+/// time required to transmit its unacknowledged data and the data packet at the
+/// link's [estimated bandwidth](LinkState::bandwidth).
+#[derive(Debug, Clone, Default)]
+pub struct EarliestDelivery;
+
+impl EarliestDelivery {
+    /// Expected delivery time in seconds of data of the specified size over a link.
+    fn expected_delivery<TAG>(link: &LinkState<TAG>, size: usize) -> f64 {
+        let bandwidth = link.bandwidth().max(1.0);
+        link.roundtrip.as_secs_f64() / 2.0 + (link.unacked + size) as f64 / bandwidth
+    }
+}
+
+impl<TAG> LinkScheduler<TAG> for EarliestDelivery {
+    fn select(&mut self, links: &[LinkState<TAG>], size: usize) -> Option<usize> {
+        links
+            .iter()
+            .enumerate()
+            .filter(|(_, link)| link.is_usable())
+            .map(|(idx, link)| (idx, Self::expected_delivery(link, size)))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(idx, _)| idx)
+    }
+}
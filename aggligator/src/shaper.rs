@@ -0,0 +1,107 @@
+//! Token bucket traffic shaping.
+
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::cfg::RateLimit;
+
+/// Token bucket limiting the data rate.
+///
+/// Data may be sent while no tokens are owed, even if its size exceeds the available tokens.
+/// The resulting deficit must be paid back before more data can be sent.
+#[derive(Debug, Clone)]
+pub(crate) struct TokenBucket {
+    limit: Option<RateLimit>,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a new, full token bucket.
+    pub fn new(limit: Option<RateLimit>) -> Self {
+        Self { limit, tokens: limit.map(|limit| limit.burst as f64).unwrap_or_default(), updated: Instant::now() }
+    }
+
+    /// The rate limit; `None` if unlimited.
+    pub fn limit(&self) -> Option<RateLimit> {
+        self.limit
+    }
+
+    /// Sets the rate limit.
+    ///
+    /// The bucket is full when a limit is installed on a previously unlimited bucket.
+    pub fn set_limit(&mut self, limit: Option<RateLimit>) {
+        self.refill();
+        self.tokens = match (self.limit, limit) {
+            (None, Some(limit)) => limit.burst as f64,
+            (Some(_), Some(limit)) => self.tokens.min(limit.burst as f64),
+            (_, None) => 0.0,
+        };
+        self.limit = limit;
+    }
+
+    /// Tokens available at the specified time.
+    fn tokens_at(&self, now: Instant) -> f64 {
+        match &self.limit {
+            Some(limit) => {
+                let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+                (self.tokens + elapsed * limit.rate.get() as f64).min(limit.burst as f64)
+            }
+            None => self.tokens,
+        }
+    }
+
+    /// Adds tokens that accumulated since last update.
+    fn refill(&mut self) {
+        let now = Instant::now();
+        self.tokens = self.tokens_at(now);
+        self.updated = now;
+    }
+
+    /// Takes tokens for sending data of the specified size.
+    pub fn consume(&mut self, size: usize) {
+        if self.limit.is_some() {
+            self.refill();
+            self.tokens -= size as f64;
+        }
+    }
+
+    /// Takes tokens for the specified size if they are available.
+    ///
+    /// Returns whether the tokens were taken.
+    /// Unlike [`consume`](Self::consume), this never incurs a deficit.
+    pub fn try_consume(&mut self, size: usize) -> bool {
+        if self.limit.is_none() {
+            return true;
+        }
+
+        self.refill();
+        if self.tokens >= size as f64 {
+            self.tokens -= size as f64;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the bucket is full, i.e. no tokens have been taken recently.
+    pub fn is_full(&mut self) -> bool {
+        self.refill();
+        self.limit.map(|limit| self.tokens >= limit.burst as f64).unwrap_or(true)
+    }
+
+    /// Time when data can be sent again.
+    ///
+    /// `None` if data can be sent immediately.
+    pub fn ready_at(&self) -> Option<Instant> {
+        let limit = self.limit?;
+        let now = Instant::now();
+        let tokens = self.tokens_at(now);
+        if tokens >= 0.0 {
+            None
+        } else {
+            let wait = -tokens / limit.rate.get() as f64;
+            Some(now + Duration::from_secs_f64(wait))
+        }
+    }
+}
@@ -1,8 +1,24 @@
-//! Test channel.
-#![allow(dead_code)]
+//! Simulated network links for testing.
+//!
+//! This provides packet-based virtual links with configurable speed, latency, jitter,
+//! buffering, bandwidth traces and scheduled outages.
+//! They can be added to a connection like any other link and allow to test link
+//! failover and scheduling behavior without a real network.
+//!
+//! All timing is based on [tokio's clock](tokio::time), thus the simulation also works
+//! when time is paused using `tokio::time::pause` or `#[tokio::test(start_paused = true)]`.
+//! Randomness, i.e. jitter, is derived from a [seed](Cfg::seed).
+//! Using a single-threaded runtime with paused time, a simulation is thus fully
+//! reproducible and runs as fast as the CPU allows.
+//!
+//! Use [`channel`] to create a unidirectional channel and [`link`] to create
+//! a bidirectional link with possibly asymmetric configuration.
+//!
 
 use bytes::Bytes;
 use futures::{future, ready, Sink, SinkExt, Stream, StreamExt};
+use rand::prelude::*;
+use rand_xoshiro::Xoshiro256PlusPlus;
 use std::{
     io::{Error, ErrorKind},
     pin::Pin,
@@ -20,12 +36,13 @@ use tokio::{
 use tokio_stream::wrappers::ReceiverStream;
 use tokio_util::sync::{PollSemaphore, PollSender};
 
-/// Test channel configuration.
+/// Simulated channel configuration.
 #[derive(Clone, Debug)]
 pub struct Cfg {
     /// Speed in bytes per second.
     ///
     /// Zero for no throtteling.
+    /// Ignored if a [bandwidth trace](Self::trace) is specified.
     pub speed: usize,
     /// Maximum buffer size in items.
     pub buffer_items: usize,
@@ -33,11 +50,102 @@ pub struct Cfg {
     pub buffer_size: usize,
     /// Latency.
     pub latency: Option<Duration>,
+    /// Maximum jitter.
+    ///
+    /// Each packet is delayed by an additional random duration between zero and this value.
+    /// Packets are never reordered.
+    pub jitter: Duration,
+    /// Bandwidth trace specifying the speed over time.
+    pub trace: Option<BandwidthTrace>,
+    /// Scheduled outages.
+    pub outages: Vec<Outage>,
+    /// Seed for randomness.
+    pub seed: u64,
 }
 
 impl Default for Cfg {
     fn default() -> Self {
-        Self { speed: 0, buffer_items: 128, buffer_size: 16384, latency: None }
+        Self {
+            speed: 0,
+            buffer_items: 128,
+            buffer_size: 16384,
+            latency: None,
+            jitter: Duration::ZERO,
+            trace: None,
+            outages: Vec::new(),
+            seed: 0,
+        }
+    }
+}
+
+/// Speed of a channel changing over time.
+///
+/// Time is measured from the creation of the channel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BandwidthTrace {
+    steps: Vec<(Duration, usize)>,
+    repeat: bool,
+}
+
+impl BandwidthTrace {
+    /// Creates a bandwidth trace from steps consisting of a duration and the speed
+    /// in bytes per second during that duration.
+    ///
+    /// A speed of zero means no throtteling.
+    /// After the last step its speed is kept.
+    pub fn new(steps: impl IntoIterator<Item = (Duration, usize)>) -> Self {
+        Self { steps: steps.into_iter().collect(), repeat: false }
+    }
+
+    /// Repeats the steps of the trace after the last step has ended.
+    #[must_use]
+    pub fn repeating(mut self) -> Self {
+        self.repeat = true;
+        self
+    }
+
+    /// Speed in bytes per second at the specified time since the start of the trace.
+    pub fn speed_at(&self, mut elapsed: Duration) -> usize {
+        let total: Duration = self.steps.iter().map(|(dur, _)| *dur).sum();
+        if self.repeat && !total.is_zero() {
+            elapsed = Duration::from_nanos((elapsed.as_nanos() % total.as_nanos()) as u64);
+        }
+
+        for (dur, speed) in &self.steps {
+            if elapsed < *dur {
+                return *speed;
+            }
+            elapsed -= *dur;
+        }
+
+        self.steps.last().map(|(_, speed)| *speed).unwrap_or_default()
+    }
+}
+
+/// Scheduled outage of a channel.
+///
+/// Time is measured from the creation of the channel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outage {
+    /// No data is delivered during the specified duration.
+    Pause {
+        /// Start of the outage.
+        at: Duration,
+        /// Duration of the outage.
+        duration: Duration,
+    },
+    /// The channel is disconnected.
+    Disconnect {
+        /// Time of disconnection.
+        at: Duration,
+    },
+}
+
+impl Outage {
+    fn at(&self) -> Duration {
+        match self {
+            Self::Pause { at, .. } | Self::Disconnect { at } => *at,
+        }
     }
 }
 
@@ -50,6 +158,7 @@ enum ControlReq {
     PauseFor(Duration),
     PauseThenDisconnect(Duration),
     SetLatency(Option<Duration>),
+    SetJitter(Duration),
     SetSpeed(usize),
     Disconnect,
 }
@@ -59,7 +168,9 @@ struct ControlMsg {
     processed_tx: oneshot::Sender<()>,
 }
 
-/// Creates a new test channel using the provided configuration.
+/// Creates a new simulated unidirectional channel using the provided configuration.
+///
+/// This spawns a task and thus must be called from within a Tokio runtime.
 pub fn channel(mut cfg: Cfg) -> (Sender, Receiver, Control) {
     let sender_items = (cfg.buffer_items / 2).max(1);
     let receiver_items = (cfg.buffer_items - sender_items).max(1);
@@ -72,7 +183,7 @@ pub fn channel(mut cfg: Cfg) -> (Sender, Receiver, Control) {
     let disconnected = Arc::new(AtomicBool::new(false));
 
     let sender = Sender {
-        cfg: cfg.clone(),
+        buffer_limit: cfg.buffer_size,
         tx: PollSender::new(sender_tx),
         buffer_size: buffer_size.clone(),
         buffer_consumed: PollSemaphore::new(buffer_consumed.clone()),
@@ -90,24 +201,41 @@ pub fn channel(mut cfg: Cfg) -> (Sender, Receiver, Control) {
     let (control_tx, control_rx) = mpsc::channel(1);
     let control = Control { tx: control_tx };
 
+    let start = Instant::now();
+    let mut rng = Xoshiro256PlusPlus::seed_from_u64(cfg.seed);
+    let mut outages = cfg.outages.clone();
+    outages.sort_by_key(|outage| outage.at());
+    let mut outages = outages.into_iter().peekable();
+
     tokio::spawn(async move {
         let mut control_rx_opt = Some(control_rx);
         let mut sleep_need = Duration::ZERO;
+        let mut last_until = start;
         loop {
             tokio::select! {
                 packet_opt = sender_rx.recv() => {
                     let Some(packet) = packet_opt else { break };
 
-                    if let Some(latency) = cfg.latency {
-                        let until = packet.sent + latency;
+                    let jitter = if cfg.jitter.is_zero() {
+                        Duration::ZERO
+                    } else {
+                        cfg.jitter.mul_f64(rng.gen())
+                    };
+                    let delay = cfg.latency.unwrap_or_default() + jitter;
+                    if !delay.is_zero() {
+                        let until = (packet.sent + delay).max(last_until);
+                        last_until = until;
                         if until > Instant::now() {
-                            // println!("latency wait");
                             sleep_until(until).await;
                         }
                     }
 
-                    if cfg.speed > 0 {
-                        sleep_need += Duration::from_secs_f64(packet.data.len() as f64 / cfg.speed as f64);
+                    let speed = match &cfg.trace {
+                        Some(trace) => trace.speed_at(start.elapsed()),
+                        None => cfg.speed,
+                    };
+                    if speed > 0 {
+                        sleep_need += Duration::from_secs_f64(packet.data.len() as f64 / speed as f64);
                         if sleep_need >= Duration::from_millis(100) {
                             sleep(sleep_need).await;
                             sleep_need = Duration::ZERO;
@@ -118,6 +246,25 @@ pub fn channel(mut cfg: Cfg) -> (Sender, Receiver, Control) {
                         break;
                     }
                 }
+                () = async {
+                    match outages.peek() {
+                        Some(outage) => sleep_until(start + outage.at()).await,
+                        None => future::pending().await,
+                    }
+                } => {
+                    match outages.next() {
+                        Some(Outage::Pause { duration, .. }) => {
+                            tracing::debug!("simulated channel paused for {duration:?}");
+                            sleep(duration).await;
+                        }
+                        Some(Outage::Disconnect { .. }) => {
+                            tracing::debug!("simulated channel disconnected");
+                            disconnected.store(true, Ordering::SeqCst);
+                            break;
+                        }
+                        None => (),
+                    }
+                }
                 msg_opt = async {
                     match control_rx_opt.as_mut() {
                         Some(control_rx) => control_rx.recv().await,
@@ -134,7 +281,11 @@ pub fn channel(mut cfg: Cfg) -> (Sender, Receiver, Control) {
                                     break;
                                 }
                                 ControlReq::SetLatency (latency) => cfg.latency = latency,
-                                ControlReq::SetSpeed (speed) => cfg.speed = speed,
+                                ControlReq::SetJitter (jitter) => cfg.jitter = jitter,
+                                ControlReq::SetSpeed (speed) => {
+                                    cfg.speed = speed;
+                                    cfg.trace = None;
+                                }
                                 ControlReq::Disconnect => {
                                     disconnected.store(true, Ordering::SeqCst);
                                     break;
@@ -152,7 +303,7 @@ pub fn channel(mut cfg: Cfg) -> (Sender, Receiver, Control) {
     (sender, receiver, control)
 }
 
-/// Controls the test channel.
+/// Controls a simulated channel.
 #[derive(Clone)]
 pub struct Control {
     tx: mpsc::Sender<ControlMsg>,
@@ -172,7 +323,7 @@ impl Control {
     }
 
     /// Pauses the channel and then disconnects it.
-    pub async fn pause_then_disconnected(self, duration: Duration) -> Result<(), Error> {
+    pub async fn pause_then_disconnect(&self, duration: Duration) -> Result<(), Error> {
         self.send_req(ControlReq::PauseThenDisconnect(duration)).await
     }
 
@@ -181,20 +332,27 @@ impl Control {
         self.send_req(ControlReq::SetLatency(latency)).await
     }
 
+    /// Sets the maximum jitter.
+    pub async fn set_jitter(&self, jitter: Duration) -> Result<(), Error> {
+        self.send_req(ControlReq::SetJitter(jitter)).await
+    }
+
     /// Sets the speed.
+    ///
+    /// This replaces the bandwidth trace, if any.
     pub async fn set_speed(&self, speed: usize) -> Result<(), Error> {
         self.send_req(ControlReq::SetSpeed(speed)).await
     }
 
     /// Disconnects the channel.
-    pub async fn disconnect(self) -> Result<(), Error> {
+    pub async fn disconnect(&self) -> Result<(), Error> {
         self.send_req(ControlReq::Disconnect).await
     }
 }
 
-/// Sending half of test channel.
+/// Sending half of a simulated channel.
 pub struct Sender {
-    cfg: Cfg,
+    buffer_limit: usize,
     tx: PollSender<Packet>,
     buffer_size: Arc<AtomicUsize>,
     buffer_consumed: PollSemaphore,
@@ -208,7 +366,7 @@ impl Sink<Bytes> for Sender {
     fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
         let this = Pin::into_inner(self);
 
-        while this.buffer_size.load(Ordering::SeqCst) >= this.cfg.buffer_size {
+        while this.buffer_size.load(Ordering::SeqCst) >= this.buffer_limit {
             match ready!(this.buffer_consumed.poll_acquire(cx)) {
                 Some(permit) => permit.forget(),
                 None => return Poll::Ready(Err(ErrorKind::BrokenPipe.into())),
@@ -220,8 +378,8 @@ impl Sink<Bytes> for Sender {
                 if let Some(not_ready_since) = this.not_ready_since.take() {
                     let elapsed = not_ready_since.elapsed().as_secs_f64();
                     if elapsed >= 0.1 {
-                        println!(
-                            "********* test channel was blocked for {:.2} s and last tried {:.2} s ago",
+                        tracing::debug!(
+                            "simulated channel was blocked for {:.2} s and last tried {:.2} s ago",
                             elapsed,
                             this.last_check.elapsed().as_secs_f64()
                         );
@@ -231,7 +389,6 @@ impl Sink<Bytes> for Sender {
             }
             Poll::Pending => {
                 if this.not_ready_since.is_none() {
-                    //println!("test channel NOT ready for sending");
                     this.not_ready_since = Some(Instant::now());
                 }
                 this.last_check = Instant::now();
@@ -271,7 +428,7 @@ impl Sink<Bytes> for Sender {
     }
 }
 
-/// Receiving half of test channel.
+/// Receiving half of a simulated channel.
 pub struct Receiver {
     rx: ReceiverStream<Packet>,
     buffer_size: Arc<AtomicUsize>,
@@ -304,3 +461,58 @@ impl Stream for Receiver {
         Poll::Ready(Some(Ok(packet.data)))
     }
 }
+
+/// One end of a simulated bidirectional link.
+pub struct Endpoint {
+    /// Sender towards the other end.
+    pub tx: Sender,
+    /// Receiver from the other end.
+    pub rx: Receiver,
+}
+
+/// Controls both directions of a simulated bidirectional link.
+#[derive(Clone)]
+pub struct LinkControl {
+    /// Control of the upstream direction, i.e. from the local to the remote end.
+    pub up: Control,
+    /// Control of the downstream direction, i.e. from the remote to the local end.
+    pub down: Control,
+}
+
+impl LinkControl {
+    /// Pauses both directions for the specified amount of time.
+    pub async fn pause_for(&self, duration: Duration) -> Result<(), Error> {
+        let (up, down) = future::join(self.up.pause_for(duration), self.down.pause_for(duration)).await;
+        up.and(down)
+    }
+
+    /// Sets the latency of both directions.
+    pub async fn set_latency(&self, latency: Option<Duration>) -> Result<(), Error> {
+        self.up.set_latency(latency).await?;
+        self.down.set_latency(latency).await
+    }
+
+    /// Disconnects both directions.
+    pub async fn disconnect(&self) -> Result<(), Error> {
+        let (up, down) = future::join(self.up.disconnect(), self.down.disconnect()).await;
+        up.and(down)
+    }
+}
+
+/// Creates a new simulated bidirectional link.
+///
+/// `up` configures the direction from the local to the remote end and
+/// `down` the direction from the remote to the local end, allowing for
+/// asymmetric links.
+///
+/// Returns the local end, the remote end and a handle for controlling the link.
+/// This spawns tasks and thus must be called from within a Tokio runtime.
+pub fn link(up: Cfg, down: Cfg) -> (Endpoint, Endpoint, LinkControl) {
+    let (up_tx, up_rx, up_control) = channel(up);
+    let (down_tx, down_rx, down_control) = channel(down);
+    (
+        Endpoint { tx: up_tx, rx: down_rx },
+        Endpoint { tx: down_tx, rx: up_rx },
+        LinkControl { up: up_control, down: down_control },
+    )
+}
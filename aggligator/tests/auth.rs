@@ -0,0 +1,88 @@
+//! Link authentication tests.
+
+use futures::join;
+use std::{future::IntoFuture, time::Duration};
+use tokio::time::timeout;
+
+use aggligator::{
+    cfg::{AuthToken, Cfg, LinkAuth},
+    connect::{connect, IncomingError, Server},
+    control::AddLinkError,
+    testing,
+};
+
+/// Establishes a single link using the specified configurations.
+async fn auth_link(server_cfg: Cfg, client_cfg: Cfg) -> (Result<(), IncomingError>, Result<(), AddLinkError>) {
+    let ch_cfg = testing::Cfg { latency: Some(Duration::from_millis(5)), ..Default::default() };
+    let (a_tx, a_rx, _a_control) = testing::channel(ch_cfg.clone());
+    let (b_tx, b_rx, _b_control) = testing::channel(ch_cfg);
+
+    let server = Server::new(server_cfg);
+    let mut listener = server.listen().unwrap();
+    let (task, _outgoing, control) = connect(client_cfg);
+    tokio::spawn(task.into_future());
+
+    let server_task = async {
+        server.add_incoming(b_tx, a_rx, (), &[]).await?;
+        let (task, ch, _control) = listener.next().await.unwrap().accept();
+        tokio::spawn(task.into_future());
+        Ok(ch)
+    };
+
+    let (server_res, client_res) =
+        timeout(Duration::from_secs(10), async { join!(server_task, control.add(a_tx, b_rx, (), &[])) })
+            .await
+            .unwrap();
+
+    (server_res.map(|_| ()), client_res.map(|_| ()))
+}
+
+fn psk(key: &[u8]) -> Cfg {
+    Cfg { link_auth: Some(LinkAuth::PreSharedKey(key.to_vec())), ..Default::default() }
+}
+
+fn tokens(tokens: &[(&str, &[u8])]) -> Cfg {
+    let tokens = tokens.iter().map(|(id, key)| AuthToken { id: id.to_string(), key: key.to_vec() }).collect();
+    Cfg { link_auth: Some(LinkAuth::Tokens(tokens)), ..Default::default() }
+}
+
+#[test_log::test(tokio::test)]
+async fn pre_shared_key() {
+    let (server_res, client_res) = auth_link(psk(b"secret"), psk(b"secret")).await;
+    server_res.unwrap();
+    client_res.unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn pre_shared_key_mismatch() {
+    let (server_res, client_res) = auth_link(psk(b"secret"), psk(b"wrong")).await;
+    assert!(matches!(server_res, Err(IncomingError::AuthenticationFailed)), "{server_res:?}");
+    assert!(matches!(client_res, Err(AddLinkError::AuthenticationFailed)), "{client_res:?}");
+}
+
+#[test_log::test(tokio::test)]
+async fn missing_authentication() {
+    let (server_res, client_res) = auth_link(psk(b"secret"), Cfg::default()).await;
+    assert!(matches!(server_res, Err(IncomingError::AuthenticationFailed)), "{server_res:?}");
+    assert!(matches!(client_res, Err(AddLinkError::AuthenticationFailed)), "{client_res:?}");
+
+    let (_server_res, client_res) = auth_link(Cfg::default(), psk(b"secret")).await;
+    assert!(matches!(client_res, Err(AddLinkError::AuthenticationFailed)), "{client_res:?}");
+}
+
+#[test_log::test(tokio::test)]
+async fn named_tokens() {
+    let server_cfg = || tokens(&[("alice", b"alice key"), ("bob", b"bob key")]);
+
+    let (server_res, client_res) = auth_link(server_cfg(), tokens(&[("bob", b"bob key")])).await;
+    server_res.unwrap();
+    client_res.unwrap();
+
+    let (server_res, client_res) = auth_link(server_cfg(), tokens(&[("bob", b"alice key")])).await;
+    assert!(matches!(server_res, Err(IncomingError::AuthenticationFailed)), "{server_res:?}");
+    assert!(matches!(client_res, Err(AddLinkError::AuthenticationFailed)), "{client_res:?}");
+
+    let (server_res, client_res) = auth_link(server_cfg(), tokens(&[("carol", b"carol key")])).await;
+    assert!(matches!(server_res, Err(IncomingError::AuthenticationFailed)), "{server_res:?}");
+    assert!(matches!(client_res, Err(AddLinkError::AuthenticationFailed)), "{client_res:?}");
+}
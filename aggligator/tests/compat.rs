@@ -0,0 +1,565 @@
+//! Compatibility tests using message streams of older protocol versions.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{future, join, Sink, SinkExt, StreamExt};
+use std::{
+    future::IntoFuture,
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::timeout;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+use aggligator::{
+    cfg::{Cfg, Compression, LinkAuth},
+    connect::{connect, IncomingError, Refusal, Server},
+    control::AddLinkError,
+    testing,
+};
+
+const MAGIC: &[u8] = b"LIAG\0";
+
+const MSG_WELCOME: u8 = 1;
+const MSG_CONNECT: u8 = 2;
+const MSG_ACCEPTED: u8 = 3;
+const MSG_REFUSED: u8 = 4;
+const MSG_PING: u8 = 5;
+const MSG_PONG: u8 = 6;
+const MSG_DATA: u8 = 7;
+const MSG_GOODBYE: u8 = 15;
+const MSG_DATAGRAM: u8 = 16;
+
+const EXT_CFG_SECTION: u32 = 1 << 6;
+const CFG_ENTRY_MAX_VERSION: u16 = 2;
+const ACCEPTED_VERSION: u8 = 1 << 2;
+
+const REFUSED_CONNECTION_REFUSED: u8 = 3;
+
+const RECV_BUFFER: u32 = 1_000_000;
+
+/// Welcome message of a server using the specified protocol version without extensions.
+fn welcome(version: u8) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_u8(MSG_WELCOME);
+    buf.put_slice(MAGIC);
+    buf.put_u8(version);
+    buf.put_u32(0);
+    buf.put_slice(&[1; 32]);
+    buf.put_u128(1);
+    buf.put_u16(0);
+    buf.put_u32(RECV_BUFFER);
+    buf.freeze()
+}
+
+/// Configuration section containing the highest supported protocol version.
+fn max_version_section(buf: &mut BytesMut, max_version: u8) {
+    buf.put_u8(1);
+    buf.put_u16(5);
+    buf.put_u16(CFG_ENTRY_MAX_VERSION);
+    buf.put_u16(1);
+    buf.put_u8(max_version);
+}
+
+/// Welcome message of a server supporting the specified range of protocol versions.
+fn welcome_range(min_version: u8, max_version: u8) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_u8(MSG_WELCOME);
+    buf.put_slice(MAGIC);
+    buf.put_u8(min_version);
+    buf.put_u32(EXT_CFG_SECTION);
+    buf.put_slice(&[1; 32]);
+    buf.put_u128(1);
+    buf.put_u16(0);
+    buf.put_u32(RECV_BUFFER);
+    max_version_section(&mut buf, max_version);
+    buf.freeze()
+}
+
+/// Connect message of a client supporting the specified range of protocol versions.
+fn connect_range(min_version: u8, max_version: u8) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_u8(MSG_CONNECT);
+    buf.put_slice(MAGIC);
+    buf.put_u8(min_version);
+    buf.put_u32(EXT_CFG_SECTION);
+    buf.put_slice(&[2; 32]);
+    buf.put_u128(0);
+    buf.put_u128(1234);
+    buf.put_u8(0);
+    buf.put_u16(0);
+    buf.put_u32(RECV_BUFFER);
+    max_version_section(&mut buf, max_version);
+    buf.freeze()
+}
+
+/// Accepted message choosing the specified protocol version.
+fn accepted_version(version: u8) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_u8(MSG_ACCEPTED);
+    buf.put_u128(0);
+    buf.put_u8(ACCEPTED_VERSION);
+    buf.put_u8(version);
+    buf.freeze()
+}
+
+/// Connect message of a protocol version 4 client without extensions.
+fn connect_v4() -> Bytes {
+    connect_v4_to(&[2; 32], 1234)
+}
+
+/// Connect message of a protocol version 4 client for a new connection with the specified
+/// encrypted connection id.
+fn connect_v4_to(public_key: &[u8; 32], connection_id: u128) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_u8(MSG_CONNECT);
+    buf.put_slice(MAGIC);
+    buf.put_u8(4);
+    buf.put_u32(0);
+    buf.put_slice(public_key);
+    buf.put_u128(0);
+    buf.put_u128(connection_id);
+    buf.put_u8(0);
+    buf.put_u16(0);
+    buf.put_u32(RECV_BUFFER);
+    buf.freeze()
+}
+
+/// Data message with the specified sequence number.
+fn data_msg(seq: u32) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_u8(MSG_DATA);
+    buf.put_u32(seq);
+    buf.freeze()
+}
+
+/// Records all messages sent over the sender.
+fn tap(
+    tx: testing::Sender, tapped: Arc<Mutex<Vec<Bytes>>>,
+) -> impl Sink<Bytes, Error = io::Error> + Send + Unpin + 'static {
+    tx.with(move |msg: Bytes| {
+        tapped.lock().unwrap().push(msg.clone());
+        future::ready(Ok::<_, io::Error>(msg))
+    })
+}
+
+/// Receives the next message, answering pings.
+async fn recv_msg(tx: &mut testing::Sender, rx: &mut testing::Receiver) -> Bytes {
+    loop {
+        let msg = rx.next().await.unwrap().unwrap();
+        match msg[0] {
+            MSG_PING => tx.send(Bytes::from_static(&[MSG_PONG])).await.unwrap(),
+            MSG_PONG => (),
+            _ => break msg,
+        }
+    }
+}
+
+/// Receives the next data packet.
+async fn recv_data(tx: &mut testing::Sender, rx: &mut testing::Receiver) -> Bytes {
+    loop {
+        if recv_msg(tx, rx).await[0] == MSG_DATA {
+            break rx.next().await.unwrap().unwrap();
+        }
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn v4_client() {
+    let (mut a_tx, a_rx, _a_control) = testing::channel(Default::default());
+    let (b_tx, mut b_rx, _b_control) = testing::channel(Default::default());
+
+    let cfg = Cfg { compression: Compression::Lz4, ..Default::default() };
+    let server = Server::new(cfg);
+    let mut listener = server.listen().unwrap();
+
+    timeout(Duration::from_secs(10), async {
+        let (link, ()) = join!(server.add_incoming(b_tx, a_rx, (), &[]), async {
+            let welcome = b_rx.next().await.unwrap().unwrap();
+            assert_eq!(welcome[0], MSG_WELCOME);
+            assert_eq!(&welcome[1..6], MAGIC);
+            assert_eq!(welcome[6], 4, "server must greet in protocol version 4");
+            a_tx.send(connect_v4()).await.unwrap();
+        });
+        let link = link.unwrap();
+        assert_eq!(link.protocol_version(), 4);
+
+        let (task, ch, _control) = listener.next().await.unwrap().accept();
+        tokio::spawn(task.into_future());
+        let (tx, mut rx) = ch.into_tx_rx();
+
+        let accepted = recv_msg(&mut a_tx, &mut b_rx).await;
+        assert_eq!(accepted.as_ref(), &[MSG_ACCEPTED]);
+
+        tx.send(Bytes::from_static(b"hello")).await.unwrap();
+        assert_eq!(recv_data(&mut a_tx, &mut b_rx).await.as_ref(), b"hello");
+
+        a_tx.send(data_msg(0)).await.unwrap();
+        a_tx.send(Bytes::from_static(b"world")).await.unwrap();
+        assert_eq!(rx.recv().await.unwrap().unwrap().as_ref(), b"world");
+    })
+    .await
+    .unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn v4_client_refused() {
+    let (mut a_tx, a_rx, _a_control) = testing::channel(Default::default());
+    let (b_tx, mut b_rx, _b_control) = testing::channel(Default::default());
+
+    let cfg = Cfg { link_auth: Some(LinkAuth::PreSharedKey(b"secret".to_vec())), ..Default::default() };
+    let server = Server::new(cfg);
+    let _listener = server.listen().unwrap();
+
+    timeout(Duration::from_secs(10), async {
+        let (res, ()) = join!(server.add_incoming(b_tx, a_rx, (), &[]), async {
+            b_rx.next().await.unwrap().unwrap();
+            a_tx.send(connect_v4()).await.unwrap();
+            let refused = b_rx.next().await.unwrap().unwrap();
+            assert_eq!(refused.as_ref(), &[MSG_REFUSED, REFUSED_CONNECTION_REFUSED]);
+        });
+        assert!(matches!(res, Err(IncomingError::AuthenticationFailed)), "{res:?}");
+    })
+    .await
+    .unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn v4_client_refused_with_reason() {
+    let (mut a_tx, a_rx, _a_control) = testing::channel(Default::default());
+    let (b_tx, mut b_rx, _b_control) = testing::channel(Default::default());
+
+    let server = Server::new(Cfg::default());
+    let mut listener = server.listen().unwrap();
+
+    timeout(Duration::from_secs(10), async {
+        let (link, ()) = join!(server.add_incoming(b_tx, a_rx, (), &[]), async {
+            b_rx.next().await.unwrap().unwrap();
+            a_tx.send(connect_v4()).await.unwrap();
+        });
+        link.unwrap();
+
+        let incoming = listener.next().await.unwrap();
+        incoming.refuse_with(Refusal::new(1, "go away")).await;
+        let refused = b_rx.next().await.unwrap().unwrap();
+        assert_eq!(refused.as_ref(), &[MSG_REFUSED, REFUSED_CONNECTION_REFUSED]);
+    })
+    .await
+    .unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn v4_server() {
+    let (a_tx, mut a_rx, _a_control) = testing::channel(Default::default());
+    let (mut b_tx, b_rx, _b_control) = testing::channel(Default::default());
+
+    let cfg = Cfg { compression: Compression::Lz4, ..Default::default() };
+    let (task, outgoing, control) = connect(cfg);
+    tokio::spawn(task.into_future());
+
+    timeout(Duration::from_secs(10), async {
+        let (link, ()) = join!(control.add(a_tx, b_rx, (), &[]), async {
+            b_tx.send(welcome(4)).await.unwrap();
+            let connect = a_rx.next().await.unwrap().unwrap();
+            assert_eq!(connect[0], MSG_CONNECT);
+            assert_eq!(connect[6], 4, "client must connect using protocol version 4");
+            b_tx.send(Bytes::from_static(&[MSG_ACCEPTED])).await.unwrap();
+        });
+        let link = link.unwrap();
+        assert_eq!(link.protocol_version(), 4);
+        assert!(control.extensions().unwrap().is_empty());
+
+        let (tx, mut rx) = outgoing.connect().await.unwrap().into_tx_rx();
+
+        // Data must be neither compressed nor encrypted.
+        tx.send(Bytes::from_static(b"hello")).await.unwrap();
+        assert_eq!(recv_data(&mut b_tx, &mut a_rx).await.as_ref(), b"hello");
+
+        b_tx.send(data_msg(0)).await.unwrap();
+        b_tx.send(Bytes::from_static(b"world")).await.unwrap();
+        assert_eq!(rx.recv().await.unwrap().unwrap().as_ref(), b"world");
+    })
+    .await
+    .unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn unsupported_server() {
+    let (a_tx, _a_rx, _a_control) = testing::channel(Default::default());
+    let (mut b_tx, b_rx, _b_control) = testing::channel(Default::default());
+
+    let (task, _outgoing, control) = connect(Cfg::default());
+    tokio::spawn(task.into_future());
+
+    b_tx.send(welcome(u8::MAX)).await.unwrap();
+    let res = timeout(Duration::from_secs(10), control.add(a_tx, b_rx, (), &[])).await.unwrap();
+    assert!(
+        matches!(res, Err(AddLinkError::UnsupportedProtocolVersion { min: u8::MAX, max: u8::MAX })),
+        "{res:?}"
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn highest_common_version() {
+    let (a_tx, a_rx, _a_control) = testing::channel(Default::default());
+    let (b_tx, b_rx, _b_control) = testing::channel(Default::default());
+
+    let server = Server::new(Cfg::default());
+    let mut listener = server.listen().unwrap();
+    let (task, _outgoing, control) = connect(Cfg::default());
+    tokio::spawn(task.into_future());
+
+    let (server_link, client_link) = timeout(Duration::from_secs(10), async {
+        join!(
+            async {
+                let link = server.add_incoming(b_tx, a_rx, (), &[]).await.unwrap();
+                let (task, _ch, _control) = listener.next().await.unwrap().accept();
+                tokio::spawn(task.into_future());
+                link
+            },
+            control.add(a_tx, b_rx, (), &[])
+        )
+    })
+    .await
+    .unwrap();
+
+    assert!(server_link.protocol_version() > 4);
+    assert_eq!(client_link.unwrap().protocol_version(), server_link.protocol_version());
+}
+
+#[test_log::test(tokio::test)]
+async fn foreign_client_without_resume_ticket() {
+    let (a_tx, a_rx, _a_control) = testing::channel(Default::default());
+    let (b_tx, b_rx, _b_control) = testing::channel(Default::default());
+    let (mut c_tx, c_rx, _c_control) = testing::channel(Default::default());
+    let (d_tx, mut d_rx, _d_control) = testing::channel(Default::default());
+
+    let cfg = Cfg { resume_window: Some(Duration::from_secs(10)), ..Default::default() };
+    let server = Server::new(cfg.clone());
+    let mut listener = server.listen().unwrap();
+    let (task, _outgoing, control) = connect(cfg);
+    tokio::spawn(task.into_future());
+
+    timeout(Duration::from_secs(10), async {
+        let (server_link, client_link) = join!(
+            async {
+                let link = server.add_incoming(b_tx, a_rx, (), &[]).await.unwrap();
+                let (task, _ch, _control) = listener.next().await.unwrap().accept();
+                tokio::spawn(task.into_future());
+                link
+            },
+            control.add(a_tx, b_rx, (), &[])
+        );
+        client_link.unwrap();
+        assert!(control.is_resumable());
+
+        // A foreign client that knows the connection id claims to establish a new connection.
+        let (res, ()) = join!(server.add_incoming(d_tx, c_rx, (), &[]), async {
+            let welcome = d_rx.next().await.unwrap().unwrap();
+            assert_eq!(welcome[0], MSG_WELCOME);
+            let server_public_key: [u8; 32] = welcome[11..43].try_into().unwrap();
+
+            let secret = EphemeralSecret::random_from_rng(rand_core::OsRng);
+            let public_key = PublicKey::from(&secret);
+            let shared_secret = secret.diffie_hellman(&PublicKey::from(server_public_key));
+            let key = u128::from_be_bytes(shared_secret.as_bytes()[..16].try_into().unwrap());
+
+            c_tx.send(connect_v4_to(public_key.as_bytes(), key ^ control.id().0)).await.unwrap();
+            let refused = d_rx.next().await.unwrap().unwrap();
+            assert_eq!(refused[0], MSG_REFUSED);
+        });
+        assert!(matches!(res, Err(IncomingError::InvalidResumeTicket)), "{res:?}");
+        assert_eq!(server_link.conn_id(), control.id());
+    })
+    .await
+    .unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn replayed_connect_refused() {
+    let (a_tx, a_rx, _a_control) = testing::channel(Default::default());
+    let (b_tx, b_rx, _b_control) = testing::channel(Default::default());
+    let (e_tx, e_rx, _e_control) = testing::channel(Default::default());
+    let (f_tx, f_rx, _f_control) = testing::channel(Default::default());
+    let (mut c_tx, c_rx, _c_control) = testing::channel(Default::default());
+    let (d_tx, mut d_rx, _d_control) = testing::channel(Default::default());
+
+    let cfg = Cfg { resume_window: Some(Duration::from_secs(10)), ..Default::default() };
+    let server = Server::new(cfg.clone());
+    let mut listener = server.listen().unwrap();
+    let (task, _outgoing, control) = connect(cfg);
+    tokio::spawn(task.into_future());
+
+    // Record the messages the client sends over the second link.
+    let sniffed = Arc::new(Mutex::new(Vec::new()));
+    let a_tx = tap(a_tx, Default::default());
+    let e_tx = tap(e_tx, sniffed.clone());
+
+    timeout(Duration::from_secs(10), async {
+        let (server_link, client_link) = join!(
+            async {
+                let link = server.add_incoming(b_tx, a_rx, (), &[]).await.unwrap();
+                let (task, _ch, _control) = listener.next().await.unwrap().accept();
+                tokio::spawn(task.into_future());
+                link
+            },
+            control.add(a_tx, b_rx, (), &[])
+        );
+        client_link.unwrap();
+        assert!(control.is_resumable());
+
+        let (res, client_link) =
+            join!(server.add_incoming(f_tx, e_rx, (), &[]), control.add(e_tx, f_rx, (), &[]));
+        res.unwrap();
+        client_link.unwrap();
+        let connect = sniffed.lock().unwrap()[0].clone();
+        assert_eq!(connect[0], MSG_CONNECT);
+
+        // A foreign client that knows the connection id replays the sniffed Connect message
+        // containing the resumption ticket.
+        let (res, ()) = join!(server.add_incoming(d_tx, c_rx, (), &[]), async {
+            let welcome = d_rx.next().await.unwrap().unwrap();
+            assert_eq!(welcome[0], MSG_WELCOME);
+            let server_public_key: [u8; 32] = welcome[11..43].try_into().unwrap();
+
+            let secret = EphemeralSecret::random_from_rng(rand_core::OsRng);
+            let public_key = PublicKey::from(&secret);
+            let shared_secret = secret.diffie_hellman(&PublicKey::from(server_public_key));
+            let key = u128::from_be_bytes(shared_secret.as_bytes()[..16].try_into().unwrap());
+
+            let mut replayed = BytesMut::from(&connect[..]);
+            replayed[11..43].copy_from_slice(public_key.as_bytes());
+            replayed[59..75].copy_from_slice(&(key ^ control.id().0).to_be_bytes());
+            c_tx.send(replayed.freeze()).await.unwrap();
+            let refused = d_rx.next().await.unwrap().unwrap();
+            assert_eq!(refused[0], MSG_REFUSED);
+        });
+        assert!(matches!(res, Err(IncomingError::InvalidResumeTicket)), "{res:?}");
+        assert_eq!(server_link.conn_id(), control.id());
+    })
+    .await
+    .unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn v4_client_exchange() {
+    const COUNT: u32 = 20;
+
+    let (mut a_tx, a_rx, _a_control) = testing::channel(Default::default());
+    let (b_tx, mut b_rx, _b_control) = testing::channel(Default::default());
+
+    let cfg = Cfg { compression: Compression::Lz4, datagram_max_size: 1000, ..Default::default() };
+    let server = Server::new(cfg);
+    let mut listener = server.listen().unwrap();
+
+    timeout(Duration::from_secs(10), async {
+        let (link, ()) = join!(server.add_incoming(b_tx, a_rx, (), &[]), async {
+            b_rx.next().await.unwrap().unwrap();
+            a_tx.send(connect_v4()).await.unwrap();
+        });
+        let link = link.unwrap();
+
+        let (task, mut ch, control) = listener.next().await.unwrap().accept();
+        tokio::spawn(task.into_future());
+        let (dg_tx, _dg_rx) = ch.datagrams().unwrap();
+        assert_eq!(dg_tx.max_size(), 0, "datagrams must not be sent to version 4 client");
+        assert!(control.extensions().unwrap().is_empty());
+        let (tx, mut rx) = ch.into_tx_rx();
+
+        let accepted = recv_msg(&mut a_tx, &mut b_rx).await;
+        assert_eq!(accepted.as_ref(), &[MSG_ACCEPTED]);
+
+        // Exchange data in both directions.
+        for i in 0..COUNT {
+            let packet = Bytes::from(format!("packet {i}"));
+            tx.send(packet.clone()).await.unwrap();
+            loop {
+                let msg = recv_msg(&mut a_tx, &mut b_rx).await;
+                assert!(msg[0] <= MSG_GOODBYE, "received message {} unknown to version 4", msg[0]);
+                if msg[0] == MSG_DATA {
+                    assert_eq!(b_rx.next().await.unwrap().unwrap(), packet, "data must not be compressed");
+                    break;
+                }
+            }
+
+            a_tx.send(data_msg(i)).await.unwrap();
+            a_tx.send(packet.clone()).await.unwrap();
+            assert_eq!(rx.recv().await.unwrap().unwrap(), packet);
+        }
+
+        // A message introduced after version 4 is a protocol violation.
+        a_tx.send(Bytes::from_static(&[MSG_DATAGRAM])).await.unwrap();
+        a_tx.send(Bytes::from_static(b"datagram")).await.unwrap();
+        link.disconnected().await;
+    })
+    .await
+    .unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn server_chooses_version() {
+    let (mut a_tx, a_rx, _a_control) = testing::channel(Default::default());
+    let (b_tx, mut b_rx, _b_control) = testing::channel(Default::default());
+
+    let server = Server::new(Cfg::default());
+    let mut listener = server.listen().unwrap();
+
+    timeout(Duration::from_secs(10), async {
+        let (link, ()) = join!(server.add_incoming(b_tx, a_rx, (), &[]), async {
+            b_rx.next().await.unwrap().unwrap();
+            a_tx.send(connect_range(4, 6)).await.unwrap();
+        });
+        let link = link.unwrap();
+        assert_eq!(link.protocol_version(), 6);
+
+        let (task, _ch, _control) = listener.next().await.unwrap().accept();
+        tokio::spawn(task.into_future());
+
+        let accepted = recv_msg(&mut a_tx, &mut b_rx).await;
+        assert_eq!(accepted, accepted_version(6), "server must echo chosen protocol version");
+    })
+    .await
+    .unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn client_uses_version_chosen_by_server() {
+    let (a_tx, mut a_rx, _a_control) = testing::channel(Default::default());
+    let (mut b_tx, b_rx, _b_control) = testing::channel(Default::default());
+
+    let (task, _outgoing, control) = connect(Cfg::default());
+    tokio::spawn(task.into_future());
+
+    timeout(Duration::from_secs(10), async {
+        let (link, ()) = join!(control.add(a_tx, b_rx, (), &[]), async {
+            b_tx.send(welcome_range(4, u8::MAX)).await.unwrap();
+            let connect = a_rx.next().await.unwrap().unwrap();
+            assert_eq!(connect[0], MSG_CONNECT);
+            assert_eq!(connect[6], 4, "client must connect with its lowest protocol version");
+            b_tx.send(accepted_version(5)).await.unwrap();
+        });
+        assert_eq!(link.unwrap().protocol_version(), 5);
+    })
+    .await
+    .unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn server_chooses_unsupported_version() {
+    let (a_tx, mut a_rx, _a_control) = testing::channel(Default::default());
+    let (mut b_tx, b_rx, _b_control) = testing::channel(Default::default());
+
+    let (task, _outgoing, control) = connect(Cfg::default());
+    tokio::spawn(task.into_future());
+
+    timeout(Duration::from_secs(10), async {
+        let (res, ()) = join!(control.add(a_tx, b_rx, (), &[]), async {
+            b_tx.send(welcome_range(4, u8::MAX)).await.unwrap();
+            a_rx.next().await.unwrap().unwrap();
+            b_tx.send(accepted_version(u8::MAX)).await.unwrap();
+        });
+        assert!(matches!(res, Err(AddLinkError::Io(_))), "{res:?}");
+    })
+    .await
+    .unwrap();
+}
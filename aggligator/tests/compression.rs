@@ -0,0 +1,201 @@
+//! Data compression tests.
+
+use bytes::Bytes;
+use futures::{future, join};
+use rand::{Rng, SeedableRng};
+use rand_xoshiro::Xoshiro256StarStar;
+use std::{future::IntoFuture, time::Duration};
+use tokio::time::{sleep, timeout};
+
+use aggligator::{
+    cfg::{Cfg, Compression},
+    connect::{connect, Server},
+    control::Stats,
+    testing,
+};
+
+const COUNT: usize = 200;
+
+/// Compressible packet.
+fn text_packet(i: usize) -> Bytes {
+    format!("packet {i}: ").repeat(100).into()
+}
+
+/// Incompressible packet.
+fn random_packet(i: usize) -> Bytes {
+    let mut rng = Xoshiro256StarStar::seed_from_u64(i as u64);
+    (0..1000).map(|_| rng.gen::<u8>()).collect::<Vec<_>>().into()
+}
+
+/// Sends packets from the client to the server and returns the statistics of both.
+async fn exchange(server_cfg: Cfg, client_cfg: Cfg, packet: fn(usize) -> Bytes) -> (Stats, Stats) {
+    let ch_cfg = testing::Cfg {
+        speed: 10_000_000,
+        latency: Some(Duration::from_millis(5)),
+        buffer_size: 1_000_000,
+        ..Default::default()
+    };
+
+    let server = Server::new(server_cfg);
+    let mut listener = server.listen().unwrap();
+    let (task, outgoing, control) = connect(client_cfg);
+    tokio::spawn(task.into_future());
+
+    let mut server_links = Vec::new();
+    let mut client_links = Vec::new();
+    for _ in 0..2 {
+        let (a_tx, a_rx, _) = testing::channel(ch_cfg.clone());
+        let (b_tx, b_rx, _) = testing::channel(ch_cfg.clone());
+        server_links.push((b_tx, a_rx));
+        client_links.push((a_tx, b_rx));
+    }
+
+    let ((server_ch, server_control), client_ch) = join!(
+        async {
+            for (n, (tx, rx)) in server_links.into_iter().enumerate() {
+                server.add_incoming(tx, rx, format!("{n}"), &[]).await.unwrap();
+            }
+            let (task, ch, control) = listener.next().await.unwrap().accept();
+            tokio::spawn(task.into_future());
+            (ch, control)
+        },
+        async {
+            future::try_join_all(
+                client_links
+                    .into_iter()
+                    .enumerate()
+                    .map(|(n, (tx, rx))| control.add(tx, rx, format!("{n}"), &[])),
+            )
+            .await
+            .unwrap();
+            outgoing.connect().await.unwrap()
+        }
+    );
+
+    let (_server_tx, mut server_rx) = server_ch.into_tx_rx();
+    let (client_tx, _client_rx) = client_ch.into_tx_rx();
+
+    timeout(Duration::from_secs(30), async {
+        join!(
+            async {
+                for i in 0..COUNT {
+                    client_tx.send(packet(i)).await.unwrap();
+                }
+                client_tx.flush().await.unwrap();
+            },
+            async {
+                for i in 0..COUNT {
+                    assert_eq!(server_rx.recv().await.unwrap().unwrap(), packet(i));
+                }
+            },
+        );
+    })
+    .await
+    .unwrap();
+
+    sleep(Duration::from_millis(500)).await;
+    (control.stats(), server_control.stats())
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn uncompressed() {
+    let (client_stats, server_stats) = exchange(Cfg::default(), Cfg::default(), text_packet).await;
+    assert_eq!(client_stats.compression_ratio(), None);
+    assert_eq!(server_stats.recved_compression_ratio(), None);
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn lz4() {
+    let cfg = Cfg { compression: Compression::Lz4, ..Default::default() };
+    let (client_stats, server_stats) = exchange(Cfg::default(), cfg, text_packet).await;
+
+    let ratio = client_stats.compression_ratio().unwrap();
+    println!("lz4 compression ratio: {ratio}");
+    assert!(ratio < 0.5, "data was not compressed");
+    assert_eq!(client_stats.sent_compressed_bytes, server_stats.recved_compressed_bytes);
+    assert_eq!(client_stats.sent_uncompressed_bytes, server_stats.recved_uncompressed_bytes);
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn lz4_incompressible() {
+    let cfg = Cfg { compression: Compression::Lz4, ..Default::default() };
+    let (client_stats, _server_stats) = exchange(Cfg::default(), cfg, random_packet).await;
+
+    let ratio = client_stats.compression_ratio().unwrap();
+    println!("lz4 compression ratio: {ratio}");
+    assert_eq!(client_stats.sent_compressed_bytes, client_stats.sent_uncompressed_bytes + COUNT as u64);
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn lz4_encrypted() {
+    let cfg = Cfg { compression: Compression::Lz4, data_encryption: true, ..Default::default() };
+    let (client_stats, _server_stats) = exchange(Cfg::default(), cfg, text_packet).await;
+    assert!(client_stats.compression_ratio().unwrap() < 0.5, "data was not compressed");
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn zstd() {
+    let cfg = Cfg { compression: Compression::Zstd(3), ..Default::default() };
+    let (client_stats, server_stats) = exchange(Cfg::default(), cfg, text_packet).await;
+
+    let ratio = client_stats.compression_ratio().unwrap();
+    println!("zstd compression ratio: {ratio}");
+    assert!(ratio < 0.5, "data was not compressed");
+    assert_eq!(client_stats.sent_compressed_bytes, server_stats.recved_compressed_bytes);
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn decompressed_flow_control() {
+    const RECV_BUFFER: u32 = 50_000;
+    const RECV_QUEUE: usize = 10;
+
+    let server_cfg = Cfg {
+        recv_buffer: RECV_BUFFER.try_into().unwrap(),
+        recv_queue: RECV_QUEUE.try_into().unwrap(),
+        ..Default::default()
+    };
+    let client_cfg = Cfg { compression: Compression::Lz4, ..Default::default() };
+    let ch_cfg = testing::Cfg { speed: 10_000_000, buffer_size: 1_000_000, ..Default::default() };
+
+    let server = Server::new(server_cfg);
+    let mut listener = server.listen().unwrap();
+    let (task, outgoing, control) = connect(client_cfg);
+    tokio::spawn(task.into_future());
+
+    let (a_tx, a_rx, _) = testing::channel(ch_cfg.clone());
+    let (b_tx, b_rx, _) = testing::channel(ch_cfg);
+
+    let ((_server_ch, server_control), client_ch) = join!(
+        async {
+            server.add_incoming(b_tx, a_rx, "0".to_string(), &[]).await.unwrap();
+            let (task, ch, control) = listener.next().await.unwrap().accept();
+            tokio::spawn(task.into_future());
+            (ch, control)
+        },
+        async {
+            control.add(a_tx, b_rx, "0".to_string(), &[]).await.unwrap();
+            outgoing.connect().await.unwrap()
+        }
+    );
+
+    // The server does not consume received data.
+    let (client_tx, _client_rx) = client_ch.into_tx_rx();
+    tokio::spawn(async move {
+        for i in 0..COUNT {
+            if client_tx.send(text_packet(i)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    sleep(Duration::from_secs(2)).await;
+    let client_stats = control.stats();
+    let server_stats = server_control.stats();
+    println!("client: sent {} uncompressed bytes", client_stats.sent_uncompressed_bytes);
+    println!("server: received {} uncompressed bytes", server_stats.recved_uncompressed_bytes);
+
+    let max_buffered = RECV_BUFFER as u64 + RECV_QUEUE as u64 * text_packet(COUNT).len() as u64;
+    assert!(server_stats.recved_uncompressed_bytes > 0, "no data received");
+    assert!(server_stats.recved_uncompressed_bytes <= max_buffered, "decompressed data exceeded receive buffer");
+    assert!(client_stats.sent_unconsumed <= RECV_BUFFER as usize);
+}
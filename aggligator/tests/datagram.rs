@@ -0,0 +1,126 @@
+//! Unreliable datagram tests.
+
+use bytes::Bytes;
+use futures::{future, join};
+use std::{future::IntoFuture, time::Duration};
+use tokio::time::{sleep, timeout};
+
+use aggligator::{
+    alc::{Channel, DatagramError},
+    cfg::Cfg,
+    connect::{connect, Server},
+    testing,
+};
+
+async fn channel_pair(server_cfg: Cfg, client_cfg: Cfg) -> (Channel, Channel) {
+    let ch_cfg = testing::Cfg {
+        speed: 10_000_000,
+        latency: Some(Duration::from_millis(5)),
+        buffer_size: 1_000_000,
+        ..Default::default()
+    };
+    let links: Vec<_> = (0..2)
+        .map(|_| {
+            let (a_tx, a_rx, _) = testing::channel(ch_cfg.clone());
+            let (b_tx, b_rx, _) = testing::channel(ch_cfg.clone());
+            ((b_tx, a_rx), (a_tx, b_rx))
+        })
+        .collect();
+    let (server_links, client_links): (Vec<_>, Vec<_>) = links.into_iter().unzip();
+
+    let server = Server::new(server_cfg);
+    let mut listener = server.listen().unwrap();
+    let (task, outgoing, control) = connect(client_cfg);
+    tokio::spawn(task.into_future());
+
+    join!(
+        async {
+            for (n, (tx, rx)) in server_links.into_iter().enumerate() {
+                server.add_incoming(tx, rx, format!("{n}"), &[]).await.unwrap();
+            }
+            let (task, ch, _control) = listener.next().await.unwrap().accept();
+            tokio::spawn(task.into_future());
+            ch
+        },
+        async {
+            future::try_join_all(
+                client_links
+                    .into_iter()
+                    .enumerate()
+                    .map(|(n, (tx, rx))| control.add(tx, rx, format!("{n}"), &[])),
+            )
+            .await
+            .unwrap();
+            outgoing.connect().await.unwrap()
+        }
+    )
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn datagrams_alongside_stream() {
+    const COUNT: u32 = 200;
+    const STREAM_LEN: usize = 1_000_000;
+
+    let server_cfg = Cfg { datagram_max_size: 1000, ..Default::default() };
+    let (mut server_ch, mut client_ch) = channel_pair(server_cfg, Cfg::default()).await;
+
+    let (server_dg_tx, mut server_dg_rx) = server_ch.datagrams().unwrap();
+    let (client_dg_tx, mut client_dg_rx) = client_ch.datagrams().unwrap();
+    assert!(client_ch.datagrams().is_none());
+    assert_eq!(client_dg_tx.max_size(), 1000);
+    assert_eq!(server_dg_tx.max_size(), Cfg::default().datagram_max_size as usize);
+    assert_eq!(client_dg_tx.send(Bytes::from(vec![0; 1001])), Err(DatagramError::TooBig));
+
+    let (server_tx, mut server_rx) = server_ch.into_tx_rx();
+    let (client_tx, _client_rx) = client_ch.into_tx_rx();
+
+    let stream = async {
+        let data: Vec<u8> = (0..STREAM_LEN).map(|i| i as u8).collect();
+        for chunk in data.chunks(8192) {
+            client_tx.send(Bytes::copy_from_slice(chunk)).await.unwrap();
+        }
+        client_tx.flush().await.unwrap();
+
+        let mut received = Vec::new();
+        while received.len() < STREAM_LEN {
+            received.extend_from_slice(&server_rx.recv().await.unwrap().unwrap());
+        }
+        assert_eq!(received, data);
+    };
+
+    let send_datagrams = async {
+        for i in 0..COUNT {
+            let _ = client_dg_tx.send(Bytes::copy_from_slice(&i.to_be_bytes()));
+            sleep(Duration::from_millis(5)).await;
+        }
+    };
+
+    let recv_datagrams = async {
+        let mut received = Vec::new();
+        while let Ok(Some(data)) = timeout(Duration::from_secs(1), server_dg_rx.recv()).await {
+            received.push(u32::from_be_bytes(data.as_ref().try_into().unwrap()));
+        }
+        received
+    };
+
+    let (_, _, received) = join!(stream, send_datagrams, recv_datagrams);
+    println!("server: received {} of {COUNT} datagrams", received.len());
+    assert!(received.len() > COUNT as usize / 4, "too many datagrams lost");
+    assert!(received.iter().all(|&i| i < COUNT));
+
+    println!("sending datagram in reverse direction");
+    server_dg_tx.send(Bytes::from_static(b"reply")).unwrap();
+    assert_eq!(client_dg_rx.recv().await.unwrap(), Bytes::from_static(b"reply"));
+
+    drop(server_tx);
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn datagrams_disabled() {
+    let server_cfg = Cfg { datagram_max_size: 0, ..Default::default() };
+    let (_server_ch, mut client_ch) = channel_pair(server_cfg, Cfg::default()).await;
+
+    let (client_dg_tx, _client_dg_rx) = client_ch.datagrams().unwrap();
+    assert_eq!(client_dg_tx.max_size(), 0);
+    assert_eq!(client_dg_tx.send(Bytes::from_static(b"data")), Err(DatagramError::Unsupported));
+}
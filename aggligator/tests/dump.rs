@@ -0,0 +1,142 @@
+//! Analysis dump tests.
+#![cfg(feature = "dump")]
+
+use futures::future;
+use std::{future::IntoFuture, time::Duration};
+use tokio::{io::AsyncReadExt, sync::mpsc, time::timeout};
+
+use aggligator::{
+    cfg::Cfg,
+    connect::{connect, Server},
+    dump::{dump_to_writer, ConnDump, DumpFormat, DUMP_VERSION},
+    testing,
+};
+
+const LINKS: usize = 12;
+
+/// Establishes a connection with many links and returns dump data of the client.
+async fn dumps_with_all_links() -> Vec<ConnDump> {
+    let server = Server::new(Cfg::default());
+    let mut listener = server.listen().unwrap();
+    let (mut task, outgoing, control) = connect(Cfg::default());
+    let (dump_tx, mut dump_rx) = mpsc::channel(16);
+    task.dump(dump_tx);
+    tokio::spawn(task.into_future());
+    tokio::spawn(async move {
+        let (task, ch, _control) = listener.next().await.unwrap().accept();
+        tokio::spawn(task.into_future());
+        let _ch = ch;
+        future::pending::<()>().await;
+    });
+    tokio::spawn(outgoing.connect());
+
+    let mut channel_controls = Vec::new();
+    let mut adds = Vec::new();
+    for n in 0..LINKS {
+        let (a_tx, a_rx, a_control) = testing::channel(Default::default());
+        let (b_tx, b_rx, b_control) = testing::channel(Default::default());
+        channel_controls.push((a_control, b_control));
+
+        let control = control.clone();
+        let server = server.clone();
+        adds.push(async move {
+            let (link, server_link) = futures::join!(
+                control.add(a_tx, b_rx, format!("link{n}"), &[]),
+                server.add_incoming(b_tx, a_rx, format!("link{n}"), &[])
+            );
+            link.unwrap();
+            server_link.unwrap();
+        });
+    }
+    timeout(Duration::from_secs(10), future::join_all(adds)).await.unwrap();
+
+    timeout(Duration::from_secs(10), async {
+        let mut dumps = Vec::new();
+        while let Some(dump) = dump_rx.recv().await {
+            let complete = dump.links.len() == LINKS;
+            dumps.push(dump);
+            if complete {
+                break;
+            }
+        }
+        dumps
+    })
+    .await
+    .unwrap()
+}
+
+#[test_log::test(tokio::test)]
+async fn more_than_ten_links() {
+    let dumps = dumps_with_all_links().await;
+    let dump = dumps.last().unwrap();
+    assert_eq!(dump.version, DUMP_VERSION);
+    assert_eq!(dump.links.len(), LINKS);
+
+    let mut tags: Vec<_> = dump.links.iter().map(|link| link.tag.clone()).collect();
+    tags.sort();
+    let mut expected: Vec<_> = (0..LINKS).map(|n| format!("{:?}", format!("link{n}"))).collect();
+    expected.sort();
+    assert_eq!(tags, expected);
+}
+
+#[test_log::test(tokio::test)]
+async fn stream_formats() {
+    let dumps = dumps_with_all_links().await;
+
+    let mut sizes = Vec::new();
+    for format in [DumpFormat::JsonLines, DumpFormat::Binary] {
+        let (tx, rx) = mpsc::channel(dumps.len());
+        for dump in &dumps {
+            tx.send(dump.clone()).await.unwrap();
+        }
+        drop(tx);
+
+        // Stream through a socket-like pipe.
+        let (writer, mut reader) = tokio::io::duplex(1024);
+        let writer_task = tokio::spawn(dump_to_writer(writer, format, rx));
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).await.unwrap();
+        writer_task.await.unwrap().unwrap();
+        println!("{format:?}: {} bytes for {} dumps", data.len(), dumps.len());
+        sizes.push(data.len());
+
+        let mut reader = &data[..];
+        let mut decoded = Vec::new();
+        while let Some(dump) = ConnDump::read_from(format, &mut reader).unwrap() {
+            decoded.push(dump);
+        }
+
+        assert_eq!(decoded.len(), dumps.len());
+        for (decoded, dump) in decoded.iter().zip(&dumps) {
+            assert_eq!(decoded.version, DUMP_VERSION);
+            assert_eq!(decoded.conn_id, dump.conn_id);
+            assert_eq!(decoded.tx_seq, dump.tx_seq);
+            let ids: Vec<_> = decoded.links.iter().map(|link| (link.link_id, &link.tag)).collect();
+            let expected: Vec<_> = dump.links.iter().map(|link| (link.link_id, &link.tag)).collect();
+            assert_eq!(ids, expected);
+        }
+    }
+
+    assert!(sizes[1] < sizes[0], "binary format is not more compact");
+}
+
+#[test]
+fn read_v1_dump() {
+    let mut reader = &include_bytes!("test_data/dump_v1.jsonl")[..];
+    let mut dumps = Vec::new();
+    while let Some(dump) = ConnDump::read_from(DumpFormat::JsonLines, &mut reader).unwrap() {
+        dumps.push(dump);
+    }
+
+    assert_eq!(dumps.len(), 2);
+    for dump in &dumps {
+        assert_eq!(dump.version, 1);
+        assert_eq!(dump.conn_id, 301927398463201);
+        assert_eq!(dump.send_buffer, 67108864);
+        let ids: Vec<_> = dump.links.iter().map(|link| link.link_id).collect();
+        assert_eq!(ids, [17, 19]);
+        assert_eq!(dump.links[1].total_sent, 19_000);
+        assert!(dump.links[1].tag.is_empty());
+    }
+    assert_eq!(dumps[1].links[0].roundtrip, 0.015);
+}
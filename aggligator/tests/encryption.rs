@@ -0,0 +1,176 @@
+//! End-to-end encryption tests.
+
+use bytes::Bytes;
+use futures::{future, join, SinkExt};
+use std::{
+    future::IntoFuture,
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::{sleep, timeout};
+
+use aggligator::{
+    alc::Channel,
+    cfg::{Cfg, LinkAuth},
+    connect::{connect, IncomingError, Server},
+    control::{AddLinkError, Link},
+    testing,
+};
+
+const MARKER: &[u8] = b"plaintext marker";
+
+/// Connects over two links, recording the packets sent by the client.
+///
+/// Returns the channels, the links of the client and the recorded packets.
+async fn tapped_channel_pair(
+    server_cfg: Cfg, client_cfg: Cfg,
+) -> (Channel, Channel, Vec<Link<String>>, Arc<Mutex<Vec<Bytes>>>) {
+    let ch_cfg = testing::Cfg {
+        speed: 10_000_000,
+        latency: Some(Duration::from_millis(5)),
+        buffer_size: 1_000_000,
+        ..Default::default()
+    };
+    let tapped = Arc::new(Mutex::new(Vec::new()));
+
+    let server = Server::new(server_cfg);
+    let mut listener = server.listen().unwrap();
+    let (task, outgoing, control) = connect(client_cfg);
+    tokio::spawn(task.into_future());
+
+    let mut server_links = Vec::new();
+    let mut client_links = Vec::new();
+    for _ in 0..2 {
+        let (a_tx, a_rx, _) = testing::channel(ch_cfg.clone());
+        let (b_tx, b_rx, _) = testing::channel(ch_cfg.clone());
+        let tapped = tapped.clone();
+        let a_tx = a_tx.with(move |packet: Bytes| {
+            tapped.lock().unwrap().push(packet.clone());
+            future::ready(Ok::<_, io::Error>(packet))
+        });
+        server_links.push((b_tx, a_rx));
+        client_links.push((a_tx, b_rx));
+    }
+
+    let (server_ch, (client_ch, links)) = join!(
+        async {
+            for (n, (tx, rx)) in server_links.into_iter().enumerate() {
+                server.add_incoming(tx, rx, format!("{n}"), &[]).await.unwrap();
+            }
+            let (task, ch, _control) = listener.next().await.unwrap().accept();
+            tokio::spawn(task.into_future());
+            ch
+        },
+        async {
+            let links = future::try_join_all(
+                client_links
+                    .into_iter()
+                    .enumerate()
+                    .map(|(n, (tx, rx))| control.add(tx, rx, format!("{n}"), &[])),
+            )
+            .await
+            .unwrap();
+            (outgoing.connect().await.unwrap(), links)
+        }
+    );
+
+    (server_ch, client_ch, links, tapped)
+}
+
+/// Exchanges data and datagrams and returns whether the marker was seen on the links.
+async fn exchange(server_cfg: Cfg, client_cfg: Cfg) -> bool {
+    const COUNT: usize = 200;
+
+    let (mut server_ch, mut client_ch, links, tapped) = tapped_channel_pair(server_cfg, client_cfg).await;
+    let (_server_dg_tx, mut server_dg_rx) = server_ch.datagrams().unwrap();
+    let (client_dg_tx, _client_dg_rx) = client_ch.datagrams().unwrap();
+    let (server_tx, mut server_rx) = server_ch.into_tx_rx();
+    let (client_tx, mut client_rx) = client_ch.into_tx_rx();
+
+    let packet = |i: usize| {
+        let mut data = MARKER.to_vec();
+        data.extend_from_slice(&i.to_be_bytes());
+        data.resize(100 + i * 10, i as u8);
+        Bytes::from(data)
+    };
+
+    timeout(Duration::from_secs(30), async {
+        join!(
+            async {
+                for i in 0..COUNT {
+                    client_tx.send(packet(i)).await.unwrap();
+                }
+                client_tx.flush().await.unwrap();
+            },
+            async {
+                for i in 0..COUNT {
+                    assert_eq!(server_rx.recv().await.unwrap().unwrap(), packet(i));
+                }
+            },
+            async {
+                for i in 0..COUNT {
+                    server_tx.send(packet(i)).await.unwrap();
+                }
+            },
+            async {
+                for i in 0..COUNT {
+                    assert_eq!(client_rx.recv().await.unwrap().unwrap(), packet(i));
+                }
+            },
+        );
+
+        // Datagrams are dropped when no link is ready, thus wait for confirmed, idle links.
+        while !links.iter().all(|link| link.is_working() && link.stats().sent_unacked == 0) {
+            sleep(Duration::from_millis(10)).await;
+        }
+        client_dg_tx.send(packet(0)).unwrap();
+        assert_eq!(server_dg_rx.recv().await.unwrap(), packet(0));
+    })
+    .await
+    .unwrap();
+
+    let tapped = tapped.lock().unwrap();
+    tapped.iter().any(|packet| packet.windows(MARKER.len()).any(|window| window == MARKER))
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn unencrypted() {
+    assert!(exchange(Cfg::default(), Cfg::default()).await, "tap did not observe plaintext");
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn encrypted() {
+    let cfg = Cfg { data_encryption: true, ..Default::default() };
+    assert!(!exchange(Cfg::default(), cfg).await, "plaintext was sent");
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn encrypted_with_pre_shared_key() {
+    let cfg = Cfg {
+        data_encryption: true,
+        link_auth: Some(LinkAuth::PreSharedKey(b"secret".to_vec())),
+        ..Default::default()
+    };
+    assert!(!exchange(cfg.clone(), cfg).await, "plaintext was sent");
+}
+
+#[test_log::test(tokio::test)]
+async fn encryption_required() {
+    let ch_cfg = testing::Cfg { latency: Some(Duration::from_millis(5)), ..Default::default() };
+    let (a_tx, a_rx, _a_control) = testing::channel(ch_cfg.clone());
+    let (b_tx, b_rx, _b_control) = testing::channel(ch_cfg);
+
+    let server = Server::new(Cfg { data_encryption: true, ..Default::default() });
+    let _listener = server.listen().unwrap();
+    let (task, _outgoing, control) = connect(Cfg::default());
+    tokio::spawn(task.into_future());
+
+    let (server_res, client_res) = timeout(Duration::from_secs(10), async {
+        join!(server.add_incoming(b_tx, a_rx, (), &[]), control.add(a_tx, b_rx, (), &[]))
+    })
+    .await
+    .unwrap();
+    assert!(matches!(server_res, Err(IncomingError::EncryptionRequired)), "{server_res:?}");
+    assert!(matches!(client_res, Err(AddLinkError::EncryptionRequired)), "{client_res:?}");
+}
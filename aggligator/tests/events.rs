@@ -0,0 +1,154 @@
+//! Connection event tests.
+
+use bytes::Bytes;
+use futures::{join, Stream, StreamExt};
+use std::{future::IntoFuture, num::NonZeroUsize, time::Duration};
+use tokio::time::{sleep, timeout};
+
+use aggligator::{
+    cfg::Cfg,
+    connect::{connect, Server},
+    control::{DisconnectReason, Event, EventKind},
+    testing,
+};
+
+/// Waits for the next event that matches the predicate, skipping all other events.
+async fn wait_for<S>(events: &mut S, name: &str, pred: impl Fn(&EventKind<()>) -> bool) -> Event<()>
+where
+    S: Stream<Item = Event<()>> + Unpin,
+{
+    timeout(Duration::from_secs(10), async {
+        loop {
+            let event = events.next().await.unwrap_or_else(|| panic!("{name}: event stream ended"));
+            println!("{name}: {}", event.kind);
+            if pred(&event.kind) {
+                break event;
+            }
+        }
+    })
+    .await
+    .unwrap()
+}
+
+#[test_log::test(tokio::test)]
+async fn link_and_connection_events() {
+    let ch_cfg = testing::Cfg { latency: Some(Duration::from_millis(5)), ..Default::default() };
+    let (a_tx, a_rx, _a_control) = testing::channel(ch_cfg.clone());
+    let (b_tx, b_rx, _b_control) = testing::channel(ch_cfg.clone());
+    let (c_tx, c_rx, _c_control) = testing::channel(ch_cfg.clone());
+    let (d_tx, d_rx, _d_control) = testing::channel(ch_cfg);
+
+    let server = Server::new(Cfg::default());
+    let mut listener = server.listen().unwrap();
+    let (task, outgoing, control) = connect(Cfg::default());
+    let mut events = control.events();
+    tokio::spawn(task.into_future());
+
+    let (link, ch, (server_ch, server_control)) = timeout(Duration::from_secs(10), async {
+        let (link, ch, server) = join!(control.add(a_tx, b_rx, (), &[]), outgoing.connect(), async {
+            server.add_incoming(b_tx, a_rx, (), &[]).await.unwrap();
+            let (task, ch, control) = listener.next().await.unwrap().accept();
+            tokio::spawn(task.into_future());
+            (ch, control)
+        });
+        (link.unwrap(), ch.unwrap(), server)
+    })
+    .await
+    .unwrap();
+    let mut server_events = server_control.events();
+
+    let added = wait_for(&mut events, "client", |kind| matches!(kind, EventKind::LinkAdded(_))).await;
+    let EventKind::LinkAdded(added_link) = added.kind else { unreachable!() };
+    assert_eq!(added_link, link);
+    wait_for(&mut events, "client", |kind| matches!(kind, EventKind::Established)).await;
+    let confirmed = wait_for(&mut events, "client", |kind| matches!(kind, EventKind::LinkConfirmed(_))).await;
+    assert!(confirmed.time >= added.time);
+
+    // Blocking is reported locally and remotely.
+    link.set_blocked(true);
+    wait_for(
+        &mut events,
+        "client",
+        |kind| matches!(kind, EventKind::LinkBlocked { link: l, remote: false } if *l == link),
+    )
+    .await;
+    wait_for(&mut server_events, "server", |kind| matches!(kind, EventKind::LinkBlocked { remote: true, .. }))
+        .await;
+
+    link.set_blocked(false);
+    wait_for(&mut events, "client", |kind| matches!(kind, EventKind::LinkUnblocked { remote: false, .. })).await;
+    wait_for(&mut server_events, "server", |kind| matches!(kind, EventKind::LinkUnblocked { remote: true, .. }))
+        .await;
+
+    // Disconnection of a link while another link remains.
+    let (link2, server_link2) = join!(control.add(c_tx, d_rx, (), &[]), server.add_incoming(d_tx, c_rx, (), &[]));
+    let (link2, _server_link2) = (link2.unwrap(), server_link2.unwrap());
+    wait_for(&mut events, "client", |kind| matches!(kind, EventKind::LinkConfirmed(l) if *l == link2)).await;
+
+    // Keep data flowing, so that both ends process the disconnection.
+    let (tx, rx) = ch.into_tx_rx();
+    let (server_tx, mut server_rx) = server_ch.into_tx_rx();
+    let sender = tokio::spawn(async move {
+        while tx.send(Bytes::from_static(b"data")).await.is_ok() {
+            sleep(Duration::from_millis(10)).await;
+        }
+    });
+    let receiver = tokio::spawn(async move { while let Ok(Some(_)) = server_rx.recv().await {} });
+
+    link.start_disconnect();
+    wait_for(&mut events, "client", |kind| {
+        matches!(kind, EventKind::LinkDisconnected { link: l, reason: DisconnectReason::LocallyRequested } if *l == link)
+    })
+    .await;
+    wait_for(&mut server_events, "server", |kind| {
+        matches!(kind, EventKind::LinkDisconnected { reason: DisconnectReason::RemotelyRequested, .. })
+    })
+    .await;
+
+    // Termination ends the event stream.
+    sender.abort();
+    let _ = sender.await;
+    drop((rx, server_tx));
+    receiver.await.unwrap();
+    wait_for(&mut events, "client", |kind| matches!(kind, EventKind::Terminated(_))).await;
+    assert!(timeout(Duration::from_secs(10), events.next()).await.unwrap().is_none());
+
+    // Subscribing after termination yields an empty stream.
+    control.terminated().await.ok();
+    assert!(control.events().next().await.is_none());
+}
+
+#[test_log::test(tokio::test)]
+async fn lagged_events() {
+    let (a_tx, a_rx, _a_control) = testing::channel(Default::default());
+    let (b_tx, b_rx, _b_control) = testing::channel(Default::default());
+
+    let server = Server::new(Cfg::default());
+    let mut listener = server.listen().unwrap();
+    let (task, outgoing, control) =
+        connect(Cfg { event_queue: NonZeroUsize::new(2).unwrap(), ..Default::default() });
+    let mut events = control.events();
+    tokio::spawn(task.into_future());
+
+    let (link, _ch, _server) = timeout(Duration::from_secs(10), async {
+        let (link, ch, server) = join!(control.add(a_tx, b_rx, (), &[]), outgoing.connect(), async {
+            server.add_incoming(b_tx, a_rx, (), &[]).await.unwrap();
+            let (task, ch, control) = listener.next().await.unwrap().accept();
+            tokio::spawn(task.into_future());
+            (ch, control)
+        });
+        (link.unwrap(), ch.unwrap(), server)
+    })
+    .await
+    .unwrap();
+
+    // Generate more events than are buffered without polling the stream.
+    for i in 0..10 {
+        link.set_blocked(i % 2 == 0);
+        sleep(Duration::from_millis(20)).await;
+    }
+
+    let lagged = wait_for(&mut events, "client", |kind| matches!(kind, EventKind::Lagged(_))).await;
+    let EventKind::Lagged(missed) = lagged.kind else { unreachable!() };
+    assert!(missed > 0);
+}
@@ -0,0 +1,52 @@
+//! Protocol extension negotiation tests.
+
+use futures::join;
+use std::{future::IntoFuture, time::Duration};
+use tokio::time::timeout;
+
+use aggligator::{
+    cfg::Cfg,
+    connect::{connect, Server},
+    ext::{Extension, Extensions},
+    testing,
+};
+
+#[test_log::test(tokio::test)]
+async fn agreed_extensions() {
+    let ch_cfg = testing::Cfg { latency: Some(Duration::from_millis(5)), ..Default::default() };
+    let (a_tx, a_rx, _a_control) = testing::channel(ch_cfg.clone());
+    let (b_tx, b_rx, _b_control) = testing::channel(ch_cfg);
+
+    let server = Server::new(Cfg::default());
+    let mut listener = server.listen().unwrap();
+    let (task, _outgoing, control) = connect(Cfg::default());
+    tokio::spawn(task.into_future());
+    assert_eq!(control.extensions(), None);
+
+    let (server_control, link) = timeout(Duration::from_secs(10), async {
+        join!(
+            async {
+                server.add_incoming(b_tx, a_rx, (), &[]).await.unwrap();
+                let (task, _ch, server_control) = listener.next().await.unwrap().accept();
+                tokio::spawn(task.into_future());
+                server_control
+            },
+            control.add(a_tx, b_rx, (), &[])
+        )
+    })
+    .await
+    .unwrap();
+    link.unwrap();
+
+    let extensions = control.extensions().unwrap();
+    println!("agreed extensions: {extensions}");
+    assert_eq!(extensions, Extensions::SUPPORTED);
+    assert_eq!(server_control.extensions(), Some(extensions));
+
+    for ext in
+        [Extension::Datagram, Extension::Resume, Extension::Compress, Extension::CfgSection, Extension::Metadata]
+    {
+        assert!(extensions.contains(ext), "{ext} not agreed");
+    }
+    assert_eq!(extensions.contains(Extension::Zstd), cfg!(feature = "zstd"));
+}
@@ -0,0 +1,138 @@
+//! Stream-based link wrapper tests.
+
+use bytes::{Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+use std::{
+    io,
+    io::IoSlice,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::AsyncWrite;
+use tokio_util::codec::Encoder;
+
+use aggligator::io::{IntegrityCodec, IoRx, IoTx};
+
+/// Writer recording written data, accepting at most `max_write` bytes per call.
+#[derive(Default)]
+struct RecordingWriter {
+    vectored: bool,
+    max_write: usize,
+    data: Vec<u8>,
+    writes: usize,
+    /// Addresses of the written slices.
+    addrs: Vec<usize>,
+}
+
+impl RecordingWriter {
+    fn new(vectored: bool, max_write: usize) -> Self {
+        Self { vectored, max_write, ..Default::default() }
+    }
+}
+
+impl AsyncWrite for RecordingWriter {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.poll_write_vectored(cx, &[IoSlice::new(buf)])
+    }
+
+    fn poll_write_vectored(self: Pin<&mut Self>, _cx: &mut Context, bufs: &[IoSlice]) -> Poll<io::Result<usize>> {
+        let this = Pin::into_inner(self);
+        this.writes += 1;
+
+        let mut written = 0;
+        for buf in bufs {
+            this.addrs.push(buf.as_ptr() as usize);
+            let n = buf.len().min(this.max_write - written);
+            this.data.extend_from_slice(&buf[..n]);
+            written += n;
+        }
+        Poll::Ready(Ok(written))
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.vectored
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Packets of small and large sizes.
+fn packets() -> Vec<Bytes> {
+    [0, 1, 5, 100, 256, 257, 1_000, 20_000, 3, 70_000, 10]
+        .into_iter()
+        .enumerate()
+        .map(|(i, size)| Bytes::from((0..size).map(|n| (n + i) as u8).collect::<Vec<_>>()))
+        .collect()
+}
+
+/// Encodes the packets by copying them into a single buffer.
+fn copy_encoded(packets: &[Bytes]) -> Vec<u8> {
+    let mut codec = IntegrityCodec::new();
+    let mut buf = BytesMut::new();
+    for packet in packets {
+        codec.encode(packet.clone(), &mut buf).unwrap();
+    }
+    buf.to_vec()
+}
+
+async fn send_packets(writer: RecordingWriter, packets: &[Bytes]) -> RecordingWriter {
+    let mut tx = IoTx::new(writer);
+    for packet in packets {
+        tx.feed(packet.clone()).await.unwrap();
+    }
+    tx.flush().await.unwrap();
+    tx.into_inner()
+}
+
+#[tokio::test]
+async fn vectored_write() {
+    let packets = packets();
+    let writer = send_packets(RecordingWriter::new(true, usize::MAX), &packets).await;
+    assert_eq!(writer.data, copy_encoded(&packets));
+
+    for packet in packets.iter().filter(|packet| packet.len() > 256) {
+        assert!(
+            writer.addrs.contains(&(packet.as_ptr() as usize)),
+            "packet of {} bytes was copied",
+            packet.len()
+        );
+    }
+
+    let mut rx = IoRx::new(&writer.data[..]);
+    for packet in &packets {
+        assert_eq!(&rx.next().await.unwrap().unwrap(), packet);
+    }
+    assert!(rx.next().await.is_none());
+}
+
+#[tokio::test]
+async fn partial_vectored_write() {
+    let packets = packets();
+    let writer = send_packets(RecordingWriter::new(true, 1_000), &packets).await;
+    println!("writes: {}", writer.writes);
+    assert_eq!(writer.data, copy_encoded(&packets));
+}
+
+#[tokio::test]
+async fn non_vectored_write() {
+    let packets = packets();
+    let writer = send_packets(RecordingWriter::new(false, 5_000), &packets).await;
+    assert_eq!(writer.data, copy_encoded(&packets));
+}
+
+#[tokio::test]
+async fn packet_too_big() {
+    let mut codec = IntegrityCodec::new();
+    codec.set_max_packet_size(100);
+    let mut tx = IoTx::with_codec(RecordingWriter::new(true, usize::MAX), codec);
+
+    tx.send(Bytes::from(vec![1; 100])).await.unwrap();
+    let err = tx.send(Bytes::from(vec![1; 101])).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
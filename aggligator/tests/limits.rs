@@ -0,0 +1,248 @@
+//! Server resource limit tests.
+
+use futures::{future, join};
+use std::{
+    future::IntoFuture,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr},
+    num::{NonZeroU64, NonZeroUsize},
+    time::Duration,
+};
+use tokio::{
+    task::JoinHandle,
+    time::{sleep, timeout},
+};
+
+use aggligator::{
+    cfg::{Cfg, IncomingRateLimit},
+    connect::{connect, IncomingError, Listener, Server},
+    control::{AddLinkError, Control},
+    testing,
+};
+
+type TestServer = Server<testing::Sender, testing::Receiver, ()>;
+type TestControl = Control<testing::Sender, testing::Receiver, ()>;
+type TestListener = Listener<testing::Sender, testing::Receiver, ()>;
+
+/// Adds a link to the connection and the server, optionally specifying the remote address.
+///
+/// Returns the result of the server and a handle to the result of the client.
+async fn add_link(
+    server: &TestServer, control: &TestControl, remote: Option<IpAddr>,
+) -> (JoinHandle<Result<(), AddLinkError>>, Result<(), IncomingError>) {
+    let (a_tx, a_rx, a_control) = testing::channel(Default::default());
+    let (b_tx, b_rx, b_control) = testing::channel(Default::default());
+
+    let control = control.clone();
+    let client = tokio::spawn(async move {
+        let _channel_controls = (a_control, b_control);
+        control.add(a_tx, b_rx, (), &[]).await.map(|_| ())
+    });
+
+    let server = timeout(Duration::from_secs(10), async {
+        match remote {
+            Some(remote) => server.add_incoming_from(b_tx, a_rx, (), &[], remote).await,
+            None => server.add_incoming(b_tx, a_rx, (), &[]).await,
+        }
+    })
+    .await
+    .unwrap();
+
+    (client, server.map(|_| ()))
+}
+
+/// Waits for the result of the client.
+async fn client_result(client: JoinHandle<Result<(), AddLinkError>>) -> Result<(), AddLinkError> {
+    timeout(Duration::from_secs(10), client).await.unwrap().unwrap()
+}
+
+/// Accepts all incoming connections.
+fn accept_all(mut listener: TestListener) {
+    tokio::spawn(async move {
+        let mut channels = Vec::new();
+        while let Ok(incoming) = listener.next().await {
+            let (task, ch, _control) = incoming.accept();
+            tokio::spawn(task.into_future());
+            channels.push(ch);
+        }
+    });
+}
+
+/// Starts a new outgoing connection.
+fn new_conn() -> TestControl {
+    let (task, outgoing, control) = connect(Cfg::default());
+    tokio::spawn(task.into_future());
+    tokio::spawn(outgoing.connect());
+    control
+}
+
+#[test_log::test(tokio::test)]
+async fn max_connections() {
+    let server = TestServer::new(Cfg { max_connections: NonZeroUsize::new(1), ..Default::default() });
+    accept_all(server.listen().unwrap());
+
+    let control1 = new_conn();
+    let (client, srv) = add_link(&server, &control1, None).await;
+    srv.unwrap();
+    client_result(client).await.unwrap();
+
+    let control2 = new_conn();
+    let (client, srv) = add_link(&server, &control2, None).await;
+    let client = client_result(client).await;
+    assert!(matches!(client, Err(AddLinkError::TooManyConnections)), "{client:?}");
+    assert!(matches!(srv, Err(IncomingError::TooManyConnections)), "{srv:?}");
+
+    let stats = server.stats();
+    println!("server stats: {stats:?}");
+    assert_eq!(stats.connections, 1);
+    assert_eq!(stats.refused_too_many_connections, 1);
+}
+
+#[test_log::test(tokio::test)]
+async fn max_links_per_connection() {
+    let server = TestServer::new(Cfg { max_links_per_connection: NonZeroUsize::new(1), ..Default::default() });
+    accept_all(server.listen().unwrap());
+
+    let control = new_conn();
+    let (client, srv) = add_link(&server, &control, None).await;
+    srv.unwrap();
+    client_result(client).await.unwrap();
+
+    let (client, srv) = add_link(&server, &control, None).await;
+    let client = client_result(client).await;
+    assert!(matches!(client, Err(AddLinkError::TooManyLinks)), "{client:?}");
+    assert!(matches!(srv, Err(IncomingError::TooManyLinks)), "{srv:?}");
+
+    let stats = server.stats();
+    println!("server stats: {stats:?}");
+    assert_eq!(stats.refused_too_many_links, 1);
+}
+
+#[test_log::test(tokio::test)]
+async fn max_links_per_connection_concurrent() {
+    let server = TestServer::new(Cfg {
+        max_links_per_connection: NonZeroUsize::new(2),
+        connect_queue: NonZeroUsize::new(1).unwrap(),
+        ..Default::default()
+    });
+    let mut listener = server.listen().unwrap();
+
+    let control = new_conn();
+    let (_client, srv) = add_link(&server, &control, None).await;
+    srv.unwrap();
+
+    // Joining links wait until the connection is accepted, since its link queue is full.
+    let joining = future::join_all((0..3).map(|_| add_link(&server, &control, None)));
+    let accepting = async {
+        sleep(Duration::from_millis(500)).await;
+        let (task, ch, _control) = listener.next().await.unwrap().accept();
+        tokio::spawn(task.into_future());
+        ch
+    };
+    let (results, _ch) = join!(joining, accepting);
+
+    let srvs: Vec<_> = results.into_iter().map(|(_, srv)| srv).collect();
+    println!("server results: {srvs:?}");
+    assert_eq!(srvs.iter().filter(|srv| srv.is_ok()).count(), 1);
+    assert_eq!(srvs.iter().filter(|srv| matches!(srv, Err(IncomingError::TooManyLinks))).count(), 2);
+}
+
+#[test_log::test(tokio::test)]
+async fn max_pending_incoming() {
+    let server = TestServer::new(Cfg { max_pending_incoming: NonZeroUsize::new(1), ..Default::default() });
+    let _listener = server.listen().unwrap();
+
+    let control1 = new_conn();
+    let (_client, srv) = add_link(&server, &control1, None).await;
+    srv.unwrap();
+
+    let control2 = new_conn();
+    let (client, srv) = add_link(&server, &control2, None).await;
+    let client = client_result(client).await;
+    assert!(matches!(client, Err(AddLinkError::TooManyPending)), "{client:?}");
+    assert!(matches!(srv, Err(IncomingError::TooManyPending)), "{srv:?}");
+
+    let stats = server.stats();
+    println!("server stats: {stats:?}");
+    assert_eq!(stats.connections, 1);
+    assert_eq!(stats.refused_too_many_pending, 1);
+}
+
+#[test_log::test(tokio::test)]
+async fn incoming_rate_limit() {
+    let rate_limit = IncomingRateLimit { rate: NonZeroU64::new(1).unwrap(), burst: NonZeroU64::new(1).unwrap() };
+    let server = TestServer::new(Cfg { incoming_rate_limit: Some(rate_limit), ..Default::default() });
+    accept_all(server.listen().unwrap());
+
+    let remote1 = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    let remote2 = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    let control1 = new_conn();
+    let (client, srv) = add_link(&server, &control1, Some(remote1)).await;
+    srv.unwrap();
+    client_result(client).await.unwrap();
+
+    let control2 = new_conn();
+    let (client, srv) = add_link(&server, &control2, Some(remote1)).await;
+    let client = client_result(client).await;
+    assert!(matches!(client, Err(AddLinkError::RateLimited)), "{client:?}");
+    assert!(matches!(srv, Err(IncomingError::RateLimited)), "{srv:?}");
+
+    let control3 = new_conn();
+    let (client, srv) = add_link(&server, &control3, Some(remote2)).await;
+    srv.unwrap();
+    client_result(client).await.unwrap();
+
+    let stats = server.stats();
+    println!("server stats: {stats:?}");
+    assert_eq!(stats.connections, 2);
+    assert_eq!(stats.refused_rate_limited, 1);
+}
+
+#[test_log::test(tokio::test)]
+async fn incoming_rate_limit_before_handshake() {
+    let rate_limit = IncomingRateLimit { rate: NonZeroU64::new(1).unwrap(), burst: NonZeroU64::new(1).unwrap() };
+    let server = TestServer::new(Cfg {
+        incoming_rate_limit: Some(rate_limit),
+        handshake_timeout: Duration::from_secs(60),
+        ..Default::default()
+    });
+    accept_all(server.listen().unwrap());
+
+    let remote = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    let control = new_conn();
+    let (client, srv) = add_link(&server, &control, Some(remote)).await;
+    srv.unwrap();
+    client_result(client).await.unwrap();
+
+    // The client never answers the welcome message, but the link must be refused right away.
+    let (_a_tx, a_rx, _a_control) = testing::channel(Default::default());
+    let (b_tx, _b_rx, _b_control) = testing::channel(Default::default());
+    let res =
+        timeout(Duration::from_secs(10), server.add_incoming_from(b_tx, a_rx, (), &[], remote)).await.unwrap();
+    assert!(matches!(res, Err(IncomingError::RateLimited)), "{res:?}");
+
+    let stats = server.stats();
+    println!("server stats: {stats:?}");
+    assert_eq!(stats.refused_rate_limited, 1);
+    assert_eq!(stats.handshake_timeouts, 0);
+}
+
+#[test_log::test(tokio::test)]
+async fn handshake_timeout() {
+    let (_a_tx, a_rx, _a_control) = testing::channel(Default::default());
+    let (b_tx, _b_rx, _b_control) = testing::channel(Default::default());
+
+    let server = Server::new(Cfg { handshake_timeout: Duration::from_millis(200), ..Default::default() });
+    let _listener = server.listen().unwrap();
+
+    // The client never answers the welcome message.
+    let res = timeout(Duration::from_secs(10), server.add_incoming(b_tx, a_rx, (), &[])).await.unwrap();
+    assert!(matches!(&res, Err(IncomingError::Io(err)) if err.kind() == ErrorKind::TimedOut), "{res:?}");
+
+    let stats = server.stats();
+    println!("server stats: {stats:?}");
+    assert_eq!(stats.handshake_timeouts, 1);
+    assert_eq!(stats.connections, 0);
+}
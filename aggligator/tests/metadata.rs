@@ -0,0 +1,108 @@
+//! Connection metadata tests.
+
+use futures::join;
+use std::{future::IntoFuture, time::Duration};
+use tokio::time::timeout;
+
+use aggligator::{
+    cfg::Cfg,
+    connect::{connect, ConnectError, Refusal, Server},
+    control::{Metadata, MetadataError},
+    testing,
+};
+
+fn metadata(tenant: &str) -> Metadata {
+    Metadata::from([
+        ("app".to_string(), "test".to_string()),
+        ("version".to_string(), "1.2.3".to_string()),
+        ("tenant".to_string(), tenant.to_string()),
+    ])
+}
+
+#[test_log::test(tokio::test)]
+async fn metadata_exchanged() {
+    let ch_cfg = testing::Cfg { latency: Some(Duration::from_millis(5)), ..Default::default() };
+    let (a1_tx, a1_rx, _a1_control) = testing::channel(ch_cfg.clone());
+    let (b1_tx, b1_rx, _b1_control) = testing::channel(ch_cfg.clone());
+    let (a2_tx, a2_rx, _a2_control) = testing::channel(ch_cfg.clone());
+    let (b2_tx, b2_rx, _b2_control) = testing::channel(ch_cfg);
+
+    let server = Server::new(Cfg::default());
+    let mut listener = server.listen().unwrap();
+    let (task, outgoing, control) = connect(Cfg::default());
+    tokio::spawn(task.into_future());
+    control.set_metadata(metadata("acme")).unwrap();
+    assert_eq!(control.metadata(), metadata("acme"));
+
+    let server_control = timeout(Duration::from_secs(10), async {
+        let (server_control, link, ch) = join!(
+            async {
+                server.add_incoming(b1_tx, a1_rx, (), &[]).await.unwrap();
+                let incoming = listener.next().await.unwrap();
+                assert_eq!(incoming.metadata(), &metadata("acme"));
+                let (task, _ch, server_control) = incoming.accept();
+                tokio::spawn(task.into_future());
+                server_control
+            },
+            control.add(a1_tx, b1_rx, (), &[]),
+            outgoing.connect(),
+        );
+        link.unwrap();
+        ch.unwrap();
+
+        // Links joining the established connection do not change the metadata.
+        control.set_metadata(metadata("other")).unwrap();
+        let (link, server_link) =
+            join!(control.add(a2_tx, b2_rx, (), &[]), server.add_incoming(b2_tx, a2_rx, (), &[]));
+        link.unwrap();
+        server_link.unwrap();
+
+        server_control
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(server_control.metadata(), metadata("acme"));
+}
+
+#[test_log::test(tokio::test)]
+async fn refused_by_metadata() {
+    let (a_tx, a_rx, _a_control) = testing::channel(Default::default());
+    let (b_tx, b_rx, _b_control) = testing::channel(Default::default());
+
+    let server = Server::new(Cfg::default());
+    let mut listener = server.listen().unwrap();
+    let (task, outgoing, control) = connect(Cfg::default());
+    tokio::spawn(task.into_future());
+    control.set_metadata(metadata("unknown")).unwrap();
+
+    let (_, res, ()) = timeout(Duration::from_secs(10), async {
+        join!(control.add(a_tx, b_rx, (), &[]), outgoing.connect(), async {
+            server.add_incoming(b_tx, a_rx, (), &[]).await.unwrap();
+            let incoming = listener.next().await.unwrap();
+            let tenant = incoming.metadata().get("tenant").cloned().unwrap_or_default();
+            incoming.refuse_with(Refusal::new(403, format!("unknown tenant {tenant}"))).await;
+        })
+    })
+    .await
+    .unwrap();
+
+    assert!(
+        matches!(&res, Err(ConnectError::Refused(refusal)) if refusal.message == "unknown tenant unknown"),
+        "{res:?}"
+    );
+}
+
+#[test]
+fn oversized_metadata() {
+    let (_task, _outgoing, control) = connect::<testing::Sender, testing::Receiver, ()>(Cfg::default());
+
+    let long = "x".repeat(u16::MAX as usize + 1);
+    let res = control.set_metadata(Metadata::from([("key".to_string(), long)]));
+    assert_eq!(res, Err(MetadataError::TooLong));
+
+    let many = (0..=u16::MAX as usize).map(|n| (n.to_string(), String::new())).collect();
+    assert_eq!(control.set_metadata(many), Err(MetadataError::TooManyEntries));
+
+    assert!(control.metadata().is_empty());
+}
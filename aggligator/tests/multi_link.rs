@@ -1,28 +1,30 @@
 //! Multi-link tests.
 
-use aggligator::control::DisconnectReason;
+use aggligator::control::{DisconnectReason, QuotaState};
 use futures::{future, join};
 use std::{
     future::IntoFuture,
     iter,
-    num::{NonZeroU32, NonZeroUsize},
+    num::{NonZeroU128, NonZeroU32, NonZeroU64, NonZeroUsize},
     time::Duration,
 };
-use tokio::time::{sleep, timeout};
+use tokio::time::{sleep, timeout, Instant};
 
-use crate::test_data::send_and_verify;
+use crate::test_data::{send_and_verify, Generator, Verifier};
 use aggligator::{
     alc::{RecvError, SendError},
-    cfg::{Cfg, LinkPing},
+    cfg::{BondingMode, Cfg, CongestionControl, LinkPing, Quota, RateLimit},
     connect::{connect, Server},
+    id::ServerId,
+    sched::{EarliestDelivery, FirstReady, LinkScheduler, LinkState, LowestRoundtrip, WeightedRoundRobin},
+    testing,
 };
 
-mod test_channel;
 mod test_data;
 
 #[derive(Debug, Clone, Default)]
 struct LinkDesc {
-    cfg: test_channel::Cfg,
+    cfg: testing::Cfg,
     pause: Option<(usize, Duration)>,
     fail: Option<usize>,
     block: Option<(usize, Duration)>,
@@ -37,8 +39,8 @@ async fn multi_link_test(
     let mut b_controls = Vec::new();
 
     for ld in link_descs {
-        let (link_a_tx, link_a_rx, link_a_control) = test_channel::channel(ld.cfg.clone());
-        let (link_b_tx, link_b_rx, link_b_control) = test_channel::channel(ld.cfg.clone());
+        let (link_a_tx, link_a_rx, link_a_control) = testing::channel(ld.cfg.clone());
+        let (link_b_tx, link_b_rx, link_b_control) = testing::channel(ld.cfg.clone());
 
         server_links.push((link_a_rx, link_b_tx));
         client_links.push((link_b_rx, link_a_tx));
@@ -323,10 +325,8 @@ async fn multi_link_test(
 
 #[test_log::test(tokio::test(flavor = "multi_thread"))]
 async fn five_x_unlimited_multi_thread() {
-    let link_desc = LinkDesc {
-        cfg: test_channel::Cfg { speed: 0, latency: None, ..Default::default() },
-        ..Default::default()
-    };
+    let link_desc =
+        LinkDesc { cfg: testing::Cfg { speed: 0, latency: None, ..Default::default() }, ..Default::default() };
     let link_descs: Vec<_> = iter::repeat(link_desc).take(5).collect();
     let alc_cfg = Cfg { ..Default::default() };
 
@@ -335,10 +335,8 @@ async fn five_x_unlimited_multi_thread() {
 
 #[test_log::test(tokio::test(flavor = "current_thread"))]
 async fn five_x_unlimited_current_thread() {
-    let link_desc = LinkDesc {
-        cfg: test_channel::Cfg { speed: 0, latency: None, ..Default::default() },
-        ..Default::default()
-    };
+    let link_desc =
+        LinkDesc { cfg: testing::Cfg { speed: 0, latency: None, ..Default::default() }, ..Default::default() };
     let link_descs: Vec<_> = iter::repeat(link_desc).take(5).collect();
     let alc_cfg = Cfg { ..Default::default() };
 
@@ -348,11 +346,12 @@ async fn five_x_unlimited_current_thread() {
 #[test_log::test(tokio::test(flavor = "multi_thread"))]
 async fn five_x_very_high_latency() {
     let link_desc = LinkDesc {
-        cfg: test_channel::Cfg {
+        cfg: testing::Cfg {
             speed: 10_000_000,
             latency: Some(Duration::from_millis(1000)),
             buffer_size: 10_000_000,
             buffer_items: 50_000,
+            ..Default::default()
         },
         ..Default::default()
     };
@@ -374,10 +373,8 @@ async fn five_x_very_high_latency() {
 
 #[test_log::test(tokio::test(flavor = "multi_thread"))]
 async fn five_x_blocked() {
-    let link_desc = LinkDesc {
-        cfg: test_channel::Cfg { speed: 0, latency: None, ..Default::default() },
-        ..Default::default()
-    };
+    let link_desc =
+        LinkDesc { cfg: testing::Cfg { speed: 0, latency: None, ..Default::default() }, ..Default::default() };
     let mut link_descs: Vec<_> = iter::repeat(link_desc).take(5).collect();
 
     link_descs[0].block = Some((0, Duration::from_secs(1)));
@@ -394,7 +391,7 @@ async fn five_x_blocked() {
 #[test_log::test(tokio::test(flavor = "multi_thread"))]
 async fn ten_x_hundert_kb_per_s() {
     let link_desc = LinkDesc {
-        cfg: test_channel::Cfg {
+        cfg: testing::Cfg {
             speed: 100_000,
             latency: Some(Duration::from_millis(10)),
             buffer_size: 4096,
@@ -412,7 +409,7 @@ async fn ten_x_hundert_kb_per_s() {
 #[test_log::test(tokio::test(flavor = "multi_thread"))]
 async fn ten_x_paused_link() {
     let link_desc = LinkDesc {
-        cfg: test_channel::Cfg {
+        cfg: testing::Cfg {
             speed: 1_000_000,
             latency: Some(Duration::from_millis(10)),
             buffer_size: 100_000,
@@ -433,7 +430,7 @@ async fn ten_x_paused_link() {
 #[test_log::test(tokio::test(flavor = "multi_thread"))]
 async fn ten_x_failed_link() {
     let link_desc = LinkDesc {
-        cfg: test_channel::Cfg {
+        cfg: testing::Cfg {
             speed: 1_000_000,
             latency: Some(Duration::from_millis(10)),
             buffer_size: 100_000,
@@ -464,7 +461,7 @@ async fn ten_x_failed_link() {
 #[test_log::test(tokio::test(flavor = "multi_thread"))]
 async fn ten_x_all_failed_link() {
     let link_desc = LinkDesc {
-        cfg: test_channel::Cfg {
+        cfg: testing::Cfg {
             speed: 1_000_000,
             latency: Some(Duration::from_millis(10)),
             buffer_size: 100_000,
@@ -493,7 +490,7 @@ async fn ten_x_all_failed_link() {
 #[test_log::test(tokio::test(flavor = "multi_thread"))]
 async fn ten_x_link_timeout() {
     let link_desc = LinkDesc {
-        cfg: test_channel::Cfg {
+        cfg: testing::Cfg {
             speed: 1_000_000,
             latency: Some(Duration::from_millis(10)),
             buffer_size: 100_000,
@@ -523,3 +520,1073 @@ async fn ten_x_link_timeout() {
         .await
         .unwrap();
 }
+
+type ChannelLink = (testing::Receiver, testing::Sender);
+
+/// Creates links with 1 MB/s and the specified latencies.
+///
+/// Returns the server and client ends of the links and the controls of the channels.
+fn channel_links(latencies: &[u64]) -> (Vec<ChannelLink>, Vec<ChannelLink>, Vec<[testing::Control; 2]>) {
+    let mut server_links = Vec::new();
+    let mut client_links = Vec::new();
+    let mut controls = Vec::new();
+
+    for &latency in latencies {
+        let ch_cfg = testing::Cfg {
+            speed: 1_000_000,
+            latency: Some(Duration::from_millis(latency)),
+            buffer_size: 1_000_000,
+            ..Default::default()
+        };
+        let (link_a_tx, link_a_rx, link_a_control) = testing::channel(ch_cfg.clone());
+        let (link_b_tx, link_b_rx, link_b_control) = testing::channel(ch_cfg);
+        server_links.push((link_a_rx, link_b_tx));
+        client_links.push((link_b_rx, link_a_tx));
+        controls.push([link_a_control, link_b_control]);
+    }
+
+    (server_links, client_links, controls)
+}
+
+async fn scheduler_test(
+    latencies: &[u64], cfg: Cfg, scheduler: impl LinkScheduler<String> + 'static, count: usize,
+) -> (Vec<u64>, usize) {
+    let (server_links, client_links, _controls) = channel_links(latencies);
+
+    let server_cfg = cfg.clone();
+    let server_task = async move {
+        let server = Server::new(server_cfg);
+        let mut listener = server.listen().unwrap();
+        for (n, (rx, tx)) in server_links.into_iter().enumerate() {
+            server.add_incoming(tx, rx, format!("{n}"), &[]).await.unwrap();
+        }
+
+        let (task, ch, _control) = listener.next().await.unwrap().accept();
+        let task = tokio::spawn(task.into_future());
+
+        let (_tx, mut rx) = ch.into_tx_rx();
+        let mut verifier = Verifier::new();
+        for _ in 0..count {
+            verifier.verify(rx.recv().await.unwrap().unwrap()).unwrap();
+        }
+        assert_eq!(rx.recv().await.unwrap(), None);
+
+        println!("server: received {} bytes", verifier.total());
+        drop(rx);
+        drop(_tx);
+        task.await.unwrap().unwrap();
+    };
+
+    let client_task = async move {
+        let (mut task, outgoing, control) = connect(cfg);
+        task.set_link_scheduler(scheduler);
+        let task = tokio::spawn(task.into_future());
+
+        let links = future::try_join_all(
+            client_links.into_iter().enumerate().map(|(n, (rx, tx))| control.add(tx, rx, format!("{n}"), &[])),
+        )
+        .await
+        .unwrap();
+
+        let (tx, _rx) = outgoing.connect().await.unwrap().into_tx_rx();
+        let mut gen = Generator::new(1000, 8000);
+        for i in 0..count {
+            if i % 100 == 0 {
+                println!("client: sending {i}");
+            }
+            tx.send(gen.packet()).await.unwrap();
+            sleep(Duration::from_millis(1)).await;
+        }
+        drop(tx);
+        drop(_rx);
+
+        task.await.unwrap().unwrap();
+        (links.iter().map(|link| link.stats().total_sent).collect::<Vec<_>>(), gen.total())
+    };
+
+    let ((), (sent, total)) = join!(server_task, client_task);
+    println!("sent {total} bytes over links: {sent:?}");
+    (sent, total)
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn weighted_round_robin_scheduler() {
+    let scheduler = WeightedRoundRobin::new(|link: &LinkState<String>| if link.tag == "0" { 1 } else { 4 });
+    let (sent, _) = scheduler_test(&[10, 10], Cfg::default(), scheduler, 1000).await;
+    assert!(sent[1] > sent[0] * 2, "weights not respected");
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn lowest_roundtrip_scheduler() {
+    let (sent, _) = scheduler_test(&[100, 5, 50], Cfg::default(), LowestRoundtrip, 1000).await;
+    assert!(sent[1] > sent[0] && sent[1] > sent[2], "fastest link not preferred");
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn earliest_delivery_scheduler() {
+    let (sent, _) = scheduler_test(&[100, 5, 50], Cfg::default(), EarliestDelivery, 1000).await;
+    assert!(sent[1] > sent[0], "fastest link not preferred");
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn broadcast_mode() {
+    let cfg = Cfg { bonding_mode: BondingMode::Broadcast, ..Default::default() };
+    let (sent, total) = scheduler_test(&[10, 30], cfg, FirstReady, 500).await;
+    assert!(sent.iter().all(|&sent| sent as usize > total * 9 / 10), "data not sent over all links");
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn active_backup_mode() {
+    const COUNT: usize = 1000;
+
+    let cfg = Cfg { bonding_mode: BondingMode::ActiveBackup, link_test_data_limit: 0, ..Default::default() };
+    let (server_links, client_links, mut controls) = channel_links(&[10, 10, 10]);
+    controls.truncate(2);
+    let mut primary_controls: Vec<_> = controls.into_iter().flatten().collect();
+
+    let server_cfg = cfg.clone();
+    let server_task = async move {
+        let server = Server::new(server_cfg);
+        let mut listener = server.listen().unwrap();
+        for (n, (rx, tx)) in server_links.into_iter().enumerate() {
+            server.add_incoming(tx, rx, format!("{n}"), &[]).await.unwrap();
+        }
+
+        let (task, ch, _control) = listener.next().await.unwrap().accept();
+        let task = tokio::spawn(task.into_future());
+
+        let (tx, mut rx) = ch.into_tx_rx();
+        let mut verifier = Verifier::new();
+        for _ in 0..COUNT {
+            verifier.verify(rx.recv().await.unwrap().unwrap()).unwrap();
+        }
+        assert_eq!(rx.recv().await.unwrap(), None);
+
+        println!("server: received {} bytes", verifier.total());
+        drop(rx);
+        drop(tx);
+        task.await.unwrap().unwrap();
+    };
+
+    let client_task = async move {
+        let (task, outgoing, control) = connect(cfg);
+        let task = tokio::spawn(task.into_future());
+
+        let links = future::try_join_all(
+            client_links.into_iter().enumerate().map(|(n, (rx, tx))| control.add(tx, rx, format!("{n}"), &[])),
+        )
+        .await
+        .unwrap();
+        let backup = links[2].clone();
+        backup.set_priority(1);
+        assert_eq!(backup.priority(), 1);
+
+        let (tx, _rx) = outgoing.connect().await.unwrap().into_tx_rx();
+        let mut gen = Generator::new(1000, 8000);
+        for i in 0..COUNT {
+            if i == COUNT / 2 {
+                sleep(Duration::from_millis(500)).await;
+                let backup_sent = backup.stats().total_sent;
+                println!("client: backup link sent {backup_sent} of {} bytes", gen.total());
+                assert!((backup_sent as usize) < gen.total() / 20, "backup link carried data");
+
+                println!("client: disconnecting primary links");
+                for control in primary_controls.drain(..) {
+                    // The reverse direction may already be gone after disconnecting one direction.
+                    let _ = control.disconnect().await;
+                }
+            }
+            tx.send(gen.packet()).await.unwrap();
+            sleep(Duration::from_millis(1)).await;
+        }
+        drop(tx);
+        drop(_rx);
+
+        task.await.unwrap().unwrap();
+        let backup_sent = backup.stats().total_sent;
+        println!("client: backup link sent {backup_sent} of {} bytes", gen.total());
+        assert!(backup_sent as usize > gen.total() / 3, "backup link did not take over");
+    };
+
+    timeout(Duration::from_secs(120), async { join!(server_task, client_task) }).await.unwrap();
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn link_quotas() {
+    const COUNT: usize = 1000;
+
+    let cfg = Cfg { link_test_data_limit: 0, ..Default::default() };
+    let (server_links, client_links, _controls) = channel_links(&[10, 10, 10]);
+
+    let server_cfg = cfg.clone();
+    let server_task = async move {
+        let server = Server::new(server_cfg);
+        let mut listener = server.listen().unwrap();
+        for (n, (rx, tx)) in server_links.into_iter().enumerate() {
+            server.add_incoming(tx, rx, format!("{n}"), &[]).await.unwrap();
+        }
+
+        let (task, ch, control) = listener.next().await.unwrap().accept();
+        let task = tokio::spawn(task.into_future());
+
+        let (tx, mut rx) = ch.into_tx_rx();
+        let mut verifier = Verifier::new();
+        for _ in 0..COUNT {
+            verifier.verify(rx.recv().await.unwrap().unwrap()).unwrap();
+        }
+
+        let links = control.links();
+        let hard_link = links.iter().find(|link| link.tag() == "0").unwrap();
+        assert!(hard_link.is_remotely_blocked(), "link exceeding hard quota not blocked remotely");
+
+        assert_eq!(rx.recv().await.unwrap(), None);
+        drop(rx);
+        drop(tx);
+        task.await.unwrap().unwrap();
+    };
+
+    let client_task = async move {
+        let (task, outgoing, mut control) = connect(cfg);
+        let task = tokio::spawn(task.into_future());
+
+        let hard_quota = Quota { soft: None, hard: Some(500_000) };
+        let soft_quota = Quota { soft: Some(300_000), hard: None };
+        control.set_tag_quota("1".to_string(), soft_quota);
+
+        let links = future::try_join_all(
+            client_links.into_iter().enumerate().map(|(n, (rx, tx))| control.add(tx, rx, format!("{n}"), &[])),
+        )
+        .await
+        .unwrap();
+        links[0].set_quota(hard_quota);
+        assert_eq!(links[0].quota(), hard_quota);
+        control.quota_update();
+
+        let (tx, _rx) = outgoing.connect().await.unwrap().into_tx_rx();
+        let mut gen = Generator::new(1000, 8000);
+        for _ in 0..COUNT {
+            tx.send(gen.packet()).await.unwrap();
+            sleep(Duration::from_millis(1)).await;
+        }
+        tx.flush().await.unwrap();
+        timeout(Duration::from_secs(1), control.quota_changed()).await.unwrap();
+
+        for link in &links {
+            println!("client: link {} has quota status {:?}", link.tag(), link.quota_status());
+        }
+        let tag_status = control.tag_quota_status(&"1".to_string()).unwrap();
+        println!("client: tag 1 has quota status {tag_status:?}");
+
+        assert_eq!(links[0].quota_state(), QuotaState::HardExceeded);
+        assert!(links[0].quota_status().used < 600_000, "hard quota not enforced");
+        assert!(links[0].is_blocked());
+        assert_eq!(links[1].quota_state(), QuotaState::SoftExceeded);
+        assert_eq!(tag_status.state, QuotaState::SoftExceeded);
+        assert!(tag_status.used < 500_000, "soft quota link not deprioritized");
+        assert_eq!(links[2].quota_state(), QuotaState::Within);
+        assert_eq!(control.quota_exceeded_links().len(), 2);
+
+        control.quota_update();
+        control.remove_tag_quota(&"1".to_string());
+        links[2].set_quota(hard_quota);
+        timeout(Duration::from_secs(1), control.quota_changed()).await.unwrap();
+        sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(links[1].quota_state(), QuotaState::Within);
+        assert_eq!(links[2].quota_state(), QuotaState::HardExceeded);
+        assert!(links[2].is_blocked());
+
+        drop(tx);
+        drop(_rx);
+        task.await.unwrap().unwrap();
+    };
+
+    timeout(Duration::from_secs(120), async { join!(server_task, client_task) }).await.unwrap();
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn rate_limits() {
+    const COUNT: usize = 250;
+    const SEND_RATE: u64 = 400_000;
+    const LINK_RATE: u64 = 50_000;
+
+    let send_rate_limit = RateLimit { rate: NonZeroU64::new(SEND_RATE).unwrap(), burst: 50_000 };
+    let cfg = Cfg { link_test_data_limit: 0, send_rate_limit: Some(send_rate_limit), ..Default::default() };
+    let (server_links, client_links, _controls) = channel_links(&[10, 10]);
+
+    let server_cfg = cfg.clone();
+    let server_task = async move {
+        let server = Server::new(server_cfg);
+        let mut listener = server.listen().unwrap();
+        for (n, (rx, tx)) in server_links.into_iter().enumerate() {
+            server.add_incoming(tx, rx, format!("{n}"), &[]).await.unwrap();
+        }
+
+        let (task, ch, _control) = listener.next().await.unwrap().accept();
+        let task = tokio::spawn(task.into_future());
+
+        let (tx, mut rx) = ch.into_tx_rx();
+        let mut verifier = Verifier::new();
+        for _ in 0..COUNT {
+            verifier.verify(rx.recv().await.unwrap().unwrap()).unwrap();
+        }
+
+        assert_eq!(rx.recv().await.unwrap(), None);
+        drop(rx);
+        drop(tx);
+        task.await.unwrap().unwrap();
+    };
+
+    let client_task = async move {
+        let (task, outgoing, control) = connect(cfg);
+        let task = tokio::spawn(task.into_future());
+
+        let links = future::try_join_all(
+            client_links.into_iter().enumerate().map(|(n, (rx, tx))| control.add(tx, rx, format!("{n}"), &[])),
+        )
+        .await
+        .unwrap();
+        let link_rate_limit = RateLimit { rate: NonZeroU64::new(LINK_RATE).unwrap(), burst: 10_000 };
+        links[0].set_rate_limit(Some(link_rate_limit));
+        assert_eq!(links[0].rate_limit(), Some(link_rate_limit));
+        assert_eq!(links[1].rate_limit(), None);
+        assert_eq!(control.send_rate_limit(), Some(send_rate_limit));
+
+        let (tx, _rx) = outgoing.connect().await.unwrap().into_tx_rx();
+        let start = Instant::now();
+        let mut gen = Generator::new(1000, 7000);
+        for _ in 0..COUNT {
+            tx.send(gen.packet()).await.unwrap();
+        }
+        tx.flush().await.unwrap();
+        let elapsed = start.elapsed();
+
+        let total = gen.total() as u64;
+        let link_sent = links[0].stats().total_sent;
+        println!("client: sent {total} bytes in {elapsed:?}, link 0 sent {link_sent} bytes");
+
+        let min_elapsed = (total - send_rate_limit.burst) as f64 / SEND_RATE as f64;
+        assert!(elapsed.as_secs_f64() > min_elapsed * 0.9, "send rate limit not enforced");
+        let max_link_sent = elapsed.as_secs_f64() * LINK_RATE as f64 + 20_000.0;
+        assert!((link_sent as f64) < max_link_sent, "link rate limit not enforced");
+
+        drop(tx);
+        drop(_rx);
+        task.await.unwrap().unwrap();
+    };
+
+    timeout(Duration::from_secs(60), async { join!(server_task, client_task) }).await.unwrap();
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn rate_limit_initial_burst() {
+    const COUNT: usize = 10;
+
+    let cfg = Cfg { link_test_data_limit: 0, ..Default::default() };
+    let (server_links, client_links, _controls) = channel_links(&[10]);
+
+    let server_cfg = cfg.clone();
+    let server_task = async move {
+        let server = Server::new(server_cfg);
+        let mut listener = server.listen().unwrap();
+        for (n, (rx, tx)) in server_links.into_iter().enumerate() {
+            server.add_incoming(tx, rx, format!("{n}"), &[]).await.unwrap();
+        }
+
+        let (task, ch, _control) = listener.next().await.unwrap().accept();
+        let task = tokio::spawn(task.into_future());
+
+        let (tx, mut rx) = ch.into_tx_rx();
+        let mut verifier = Verifier::new();
+        for _ in 0..COUNT {
+            verifier.verify(rx.recv().await.unwrap().unwrap()).unwrap();
+        }
+
+        assert_eq!(rx.recv().await.unwrap(), None);
+        drop(rx);
+        drop(tx);
+        task.await.unwrap().unwrap();
+    };
+
+    let client_task = async move {
+        let (task, outgoing, control) = connect(cfg);
+        let task = tokio::spawn(task.into_future());
+
+        let links = future::try_join_all(
+            client_links.into_iter().enumerate().map(|(n, (rx, tx))| control.add(tx, rx, format!("{n}"), &[])),
+        )
+        .await
+        .unwrap();
+
+        // Data within the burst size is sent without delay.
+        let link_rate_limit = RateLimit { rate: NonZeroU64::new(1_000).unwrap(), burst: 100_000 };
+        links[0].set_rate_limit(Some(link_rate_limit));
+
+        let (tx, _rx) = outgoing.connect().await.unwrap().into_tx_rx();
+        let mut gen = Generator::new(1000, 5000);
+        for _ in 0..COUNT {
+            tx.send(gen.packet()).await.unwrap();
+        }
+        timeout(Duration::from_secs(2), tx.flush()).await.expect("initial burst not permitted").unwrap();
+
+        drop(tx);
+        drop(_rx);
+        task.await.unwrap().unwrap();
+    };
+
+    timeout(Duration::from_secs(60), async { join!(server_task, client_task) }).await.unwrap();
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn rate_limit_passes_control_messages() {
+    for congestion_control in [CongestionControl::Adaptive, CongestionControl::Bbr] {
+        println!("congestion control: {congestion_control:?}");
+        rate_limit_passes_control_messages_with(congestion_control).await;
+    }
+}
+
+async fn rate_limit_passes_control_messages_with(congestion_control: CongestionControl) {
+    const COUNT: usize = 10;
+    const LINK_RATE: u64 = 10_000;
+
+    let server_cfg = Cfg { link_test_data_limit: 0, congestion_control, ..Default::default() };
+    let cfg = Cfg {
+        link_test_data_limit: 0,
+        congestion_control,
+        link_ack_timeout_min: Duration::from_millis(200),
+        link_ack_timeout_max: Duration::from_millis(200),
+        ..Default::default()
+    };
+    let (server_links, client_links, _controls) = channel_links(&[10]);
+
+    let server_task = async move {
+        let server = Server::new(server_cfg);
+        let mut listener = server.listen().unwrap();
+        for (n, (rx, tx)) in server_links.into_iter().enumerate() {
+            server.add_incoming(tx, rx, format!("{n}"), &[]).await.unwrap();
+        }
+
+        let (task, ch, control) = listener.next().await.unwrap().accept();
+        let task = tokio::spawn(task.into_future());
+
+        // Keep the rate limit of the link exhausted by sending data.
+        let link_rate_limit = RateLimit { rate: NonZeroU64::new(LINK_RATE).unwrap(), burst: 1_000 };
+        control.links()[0].set_rate_limit(Some(link_rate_limit));
+        let (tx, mut rx) = ch.into_tx_rx();
+        let mut gen = Generator::new(5000, 6000);
+        for _ in 0..COUNT {
+            tx.send(gen.packet()).await.unwrap();
+        }
+        tx.flush().await.unwrap();
+
+        let mut verifier = Verifier::new();
+        for _ in 0..4 * COUNT {
+            verifier.verify(rx.recv().await.unwrap().unwrap()).unwrap();
+        }
+
+        assert_eq!(rx.recv().await.unwrap(), None);
+        drop(rx);
+        drop(tx);
+        task.await.unwrap().unwrap();
+    };
+
+    let client_task = async move {
+        let (task, outgoing, control) = connect(cfg);
+        let task = tokio::spawn(task.into_future());
+
+        let links = future::try_join_all(
+            client_links.into_iter().enumerate().map(|(n, (rx, tx))| control.add(tx, rx, format!("{n}"), &[])),
+        )
+        .await
+        .unwrap();
+
+        // Acknowledgements from the server must not be delayed by its rate limit.
+        let (tx, mut rx) = outgoing.connect().await.unwrap().into_tx_rx();
+        let mut gen = Generator::new(1000, 2000);
+        for _ in 0..4 * COUNT {
+            tx.send(gen.packet()).await.unwrap();
+            sleep(Duration::from_millis(50)).await;
+        }
+        tx.flush().await.unwrap();
+        sleep(Duration::from_millis(500)).await;
+        let stats = links[0].stats();
+        println!("client: link roundtrip {:?} with {} hangs", stats.roundtrip, stats.hangs);
+        assert_eq!(stats.hangs, 0, "acknowledgements were delayed by rate limit");
+
+        let mut verifier = Verifier::new();
+        for _ in 0..COUNT {
+            verifier.verify(rx.recv().await.unwrap().unwrap()).unwrap();
+        }
+
+        drop(tx);
+        assert_eq!(rx.recv().await.unwrap(), None);
+        drop(rx);
+        task.await.unwrap().unwrap();
+    };
+
+    timeout(Duration::from_secs(60), async { join!(server_task, client_task) }).await.unwrap();
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn max_ping_under_load() {
+    const COUNT: usize = 1000;
+
+    let cfg =
+        Cfg { link_test_data_limit: 0, link_max_ping: Some(Duration::from_millis(100)), ..Default::default() };
+    let (server_links, client_links, _controls) = channel_links(&[10, 10]);
+
+    let server_cfg = cfg.clone();
+    let server_task = async move {
+        let server = Server::new(server_cfg);
+        let mut listener = server.listen().unwrap();
+        for (n, (rx, tx)) in server_links.into_iter().enumerate() {
+            server.add_incoming(tx, rx, format!("{n}"), &[]).await.unwrap();
+        }
+
+        let (task, ch, _control) = listener.next().await.unwrap().accept();
+        let task = tokio::spawn(task.into_future());
+
+        let (tx, mut rx) = ch.into_tx_rx();
+        let mut verifier = Verifier::new();
+        for _ in 0..COUNT {
+            verifier.verify(rx.recv().await.unwrap().unwrap()).unwrap();
+        }
+
+        assert_eq!(rx.recv().await.unwrap(), None);
+        drop(rx);
+        drop(tx);
+        task.await.unwrap().unwrap();
+    };
+
+    let client_task = async move {
+        let (task, outgoing, control) = connect(cfg);
+        let task = tokio::spawn(task.into_future());
+
+        let links = future::try_join_all(
+            client_links.into_iter().enumerate().map(|(n, (rx, tx))| control.add(tx, rx, format!("{n}"), &[])),
+        )
+        .await
+        .unwrap();
+
+        // Queueing delay of data must not make the links exceed the maximum ping.
+        let (tx, _rx) = outgoing.connect().await.unwrap().into_tx_rx();
+        let mut gen = Generator::new(1000, 8000);
+        for _ in 0..COUNT {
+            tx.send(gen.packet()).await.unwrap();
+        }
+        tx.flush().await.unwrap();
+        sleep(Duration::from_millis(500)).await;
+
+        let stats: Vec<_> = links.iter().map(|link| (link.stats().roundtrip, link.stats().hangs)).collect();
+        println!("client: roundtrips and hangs of links: {stats:?}");
+        assert!(stats.iter().all(|(_, hangs)| *hangs == 0), "links were unconfirmed");
+
+        drop(tx);
+        drop(_rx);
+        task.await.unwrap().unwrap();
+    };
+
+    timeout(Duration::from_secs(60), async { join!(server_task, client_task) }).await.unwrap();
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn redundant_sending() {
+    const COUNT: usize = 200;
+    const REDUNDANT_COUNT: usize = 20;
+
+    let cfg = Cfg { link_test_data_limit: 0, redundancy: NonZeroUsize::new(2).unwrap(), ..Default::default() };
+    let (server_links, client_links, _controls) = channel_links(&[10, 10, 100]);
+
+    let server_cfg = cfg.clone();
+    let server_task = async move {
+        let server = Server::new(server_cfg);
+        let mut listener = server.listen().unwrap();
+        for (n, (rx, tx)) in server_links.into_iter().enumerate() {
+            server.add_incoming(tx, rx, format!("{n}"), &[]).await.unwrap();
+        }
+
+        let (task, ch, control) = listener.next().await.unwrap().accept();
+        let task = tokio::spawn(task.into_future());
+
+        let (tx, mut rx) = ch.into_tx_rx();
+        let mut verifier = Verifier::new();
+        for _ in 0..COUNT + REDUNDANT_COUNT {
+            verifier.verify(rx.recv().await.unwrap().unwrap()).unwrap();
+        }
+
+        sleep(Duration::from_secs(1)).await;
+        let stats = control.stats();
+        println!(
+            "server: received {} duplicates of {} bytes",
+            stats.recved_duplicates, stats.recved_duplicate_bytes
+        );
+        assert!(stats.recved_duplicates > 0, "no duplicates received");
+
+        assert_eq!(rx.recv().await.unwrap(), None);
+        drop(rx);
+        drop(tx);
+        task.await.unwrap().unwrap();
+    };
+
+    let client_task = async move {
+        let (task, outgoing, control) = connect(cfg);
+        let task = tokio::spawn(task.into_future());
+
+        future::try_join_all(
+            client_links.into_iter().enumerate().map(|(n, (rx, tx))| control.add(tx, rx, format!("{n}"), &[])),
+        )
+        .await
+        .unwrap();
+
+        let (tx, _rx) = outgoing.connect().await.unwrap().into_tx_rx();
+        let mut gen = Generator::new(100, 1000);
+        for _ in 0..COUNT {
+            tx.send(gen.packet()).await.unwrap();
+            sleep(Duration::from_millis(2)).await;
+        }
+        tx.flush().await.unwrap();
+        sleep(Duration::from_millis(500)).await;
+        let stats = control.stats();
+        println!(
+            "client: sent {} redundant copies of {} bytes",
+            stats.sent_redundant, stats.sent_redundant_bytes
+        );
+        assert!(stats.sent_redundant > 0, "no redundant copies sent");
+        assert!(stats.sent_redundant as usize <= COUNT, "too many redundant copies sent");
+
+        for _ in 0..REDUNDANT_COUNT {
+            tx.send_redundant(gen.packet(), NonZeroUsize::new(3).unwrap()).await.unwrap();
+            sleep(Duration::from_millis(250)).await;
+        }
+        tx.flush().await.unwrap();
+        sleep(Duration::from_millis(500)).await;
+        let redundant_stats = control.stats();
+        println!(
+            "client: sent {} redundant copies of {} bytes",
+            redundant_stats.sent_redundant, redundant_stats.sent_redundant_bytes
+        );
+        assert!(
+            redundant_stats.sent_redundant > stats.sent_redundant + REDUNDANT_COUNT as u64,
+            "per-packet redundancy not applied"
+        );
+
+        drop(tx);
+        drop(_rx);
+        task.await.unwrap().unwrap();
+    };
+
+    timeout(Duration::from_secs(60), async { join!(server_task, client_task) }).await.unwrap();
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn redundant_sending_busy_links() {
+    const COUNT: usize = 200;
+    const SHORT_COUNT: usize = 20;
+
+    let cfg = Cfg { link_test_data_limit: 0, redundancy: NonZeroUsize::new(2).unwrap(), ..Default::default() };
+    let (server_links, client_links, _controls) = channel_links(&[10, 10]);
+
+    let server_cfg = cfg.clone();
+    let server_task = async move {
+        let server = Server::new(server_cfg);
+        let mut listener = server.listen().unwrap();
+        for (n, (rx, tx)) in server_links.into_iter().enumerate() {
+            server.add_incoming(tx, rx, format!("{n}"), &[]).await.unwrap();
+        }
+
+        let (task, ch, _control) = listener.next().await.unwrap().accept();
+        let task = tokio::spawn(task.into_future());
+
+        let (tx, mut rx) = ch.into_tx_rx();
+        let mut verifier = Verifier::new();
+        for _ in 0..COUNT + SHORT_COUNT {
+            verifier.verify(rx.recv().await.unwrap().unwrap()).unwrap();
+        }
+
+        assert_eq!(rx.recv().await.unwrap(), None);
+        drop(rx);
+        drop(tx);
+        task.await.unwrap().unwrap();
+    };
+
+    let client_task = async move {
+        let (task, outgoing, control) = connect(cfg);
+        let task = tokio::spawn(task.into_future());
+
+        future::try_join_all(
+            client_links.into_iter().enumerate().map(|(n, (rx, tx))| control.add(tx, rx, format!("{n}"), &[])),
+        )
+        .await
+        .unwrap();
+
+        // Send without pausing, so that the links are busy when copies are due.
+        let (tx, _rx) = outgoing.connect().await.unwrap().into_tx_rx();
+        let mut gen = Generator::new(1000, 8000);
+        for _ in 0..COUNT {
+            tx.send(gen.packet()).await.unwrap();
+        }
+        tx.flush().await.unwrap();
+        sleep(Duration::from_millis(500)).await;
+        let stats = control.stats();
+        println!(
+            "client: sent {} redundant copies of {} bytes, shortfall {}",
+            stats.sent_redundant, stats.sent_redundant_bytes, stats.sent_redundancy_shortfall
+        );
+        assert!(stats.sent_redundant as usize >= COUNT * 9 / 10, "redundant copies were skipped");
+
+        for _ in 0..SHORT_COUNT {
+            tx.send_redundant(gen.packet(), NonZeroUsize::new(3).unwrap()).await.unwrap();
+        }
+        tx.flush().await.unwrap();
+        sleep(Duration::from_millis(500)).await;
+        assert_eq!(
+            control.stats().sent_redundancy_shortfall - stats.sent_redundancy_shortfall,
+            SHORT_COUNT as u64,
+            "redundancy shortfall not reported"
+        );
+
+        drop(tx);
+        drop(_rx);
+        task.await.unwrap().unwrap();
+    };
+
+    timeout(Duration::from_secs(60), async { join!(server_task, client_task) }).await.unwrap();
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn redundant_copies_unacked() {
+    const COUNT: usize = 100;
+
+    let cfg = Cfg {
+        link_test_data_limit: 0,
+        link_ack_timeout_min: Duration::from_secs(10),
+        redundancy: NonZeroUsize::new(2).unwrap(),
+        ..Default::default()
+    };
+    let (server_links, client_links, controls) = channel_links(&[10, 10]);
+
+    let server_cfg = cfg.clone();
+    let server_task = async move {
+        let server = Server::new(server_cfg);
+        let mut listener = server.listen().unwrap();
+        for (n, (rx, tx)) in server_links.into_iter().enumerate() {
+            server.add_incoming(tx, rx, format!("{n}"), &[]).await.unwrap();
+        }
+
+        let (task, ch, _control) = listener.next().await.unwrap().accept();
+        let task = tokio::spawn(task.into_future());
+
+        let (tx, mut rx) = ch.into_tx_rx();
+        let mut verifier = Verifier::new();
+        for _ in 0..2 * COUNT {
+            verifier.verify(rx.recv().await.unwrap().unwrap()).unwrap();
+        }
+
+        assert_eq!(rx.recv().await.unwrap(), None);
+        drop(rx);
+        drop(tx);
+        task.await.unwrap().unwrap();
+    };
+
+    let client_task = async move {
+        let (task, outgoing, control) = connect(cfg);
+        let task = tokio::spawn(task.into_future());
+
+        let links = future::try_join_all(
+            client_links.into_iter().enumerate().map(|(n, (rx, tx))| control.add(tx, rx, format!("{n}"), &[])),
+        )
+        .await
+        .unwrap();
+
+        let (tx, _rx) = outgoing.connect().await.unwrap().into_tx_rx();
+        let mut gen = Generator::new(100, 1000);
+        for _ in 0..COUNT {
+            tx.send(gen.packet()).await.unwrap();
+        }
+        tx.flush().await.unwrap();
+        sleep(Duration::from_secs(1)).await;
+
+        // Copies sent over the paused link remain unacknowledged on it.
+        println!("client: pausing link 1");
+        for control in controls[1].clone() {
+            tokio::spawn(async move { control.pause_for(Duration::from_secs(3)).await });
+        }
+        for _ in 0..COUNT {
+            tx.send(gen.packet()).await.unwrap();
+            sleep(Duration::from_millis(5)).await;
+        }
+        sleep(Duration::from_secs(1)).await;
+        let unacked: Vec<_> = links.iter().map(|link| link.stats().sent_unacked).collect();
+        println!("client: unacked data of links: {unacked:?}");
+        assert_eq!(unacked[0], 0, "acknowledged data of link 0 not released");
+        assert!(unacked[1] > 0, "copies not accounted on link 1");
+
+        tx.flush().await.unwrap();
+        sleep(Duration::from_secs(3)).await;
+        let unacked: Vec<_> = links.iter().map(|link| link.stats().sent_unacked).collect();
+        println!("client: unacked data of links: {unacked:?}");
+        assert!(unacked.iter().all(|&unacked| unacked == 0), "unacked data of links not released");
+
+        drop(tx);
+        drop(_rx);
+        task.await.unwrap().unwrap();
+    };
+
+    timeout(Duration::from_secs(60), async { join!(server_task, client_task) }).await.unwrap();
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn resume_after_link_loss() {
+    const COUNT: usize = 400;
+
+    let cfg = Cfg {
+        link_test_data_limit: 0,
+        no_link_timeout: Duration::from_secs(1),
+        resume_window: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let (server_links, client_links, controls) = channel_links(&[10, 10]);
+    let (server_new_links, client_new_links, _new_controls) = channel_links(&[10]);
+
+    let server_cfg = cfg.clone();
+    let server_task = async move {
+        let server = Server::with_id(server_cfg, ServerId(NonZeroU128::new(1).unwrap()));
+        let mut listener = server.listen().unwrap();
+        for (n, (rx, tx)) in server_links.into_iter().enumerate() {
+            server.add_incoming(tx, rx, format!("{n}"), &[]).await.unwrap();
+        }
+
+        let (task, ch, _control) = listener.next().await.unwrap().accept();
+        let task = tokio::spawn(task.into_future());
+
+        let (tx, mut rx) = ch.into_tx_rx();
+        let recv_task = async {
+            let mut verifier = Verifier::new();
+            for _ in 0..COUNT {
+                verifier.verify(rx.recv().await.unwrap().unwrap()).unwrap();
+            }
+            assert_eq!(rx.recv().await.unwrap(), None);
+            println!("server: received {} bytes", verifier.total());
+        };
+        let add_task = async {
+            for (rx, tx) in server_new_links {
+                server.add_incoming(tx, rx, "new".to_string(), &[]).await.unwrap();
+            }
+        };
+        join!(recv_task, add_task);
+
+        drop(rx);
+        drop(tx);
+        task.await.unwrap().unwrap();
+    };
+
+    let client_task = async move {
+        let (task, outgoing, control) = connect(cfg);
+        let task = tokio::spawn(task.into_future());
+
+        future::try_join_all(
+            client_links.into_iter().enumerate().map(|(n, (rx, tx))| control.add(tx, rx, format!("{n}"), &[])),
+        )
+        .await
+        .unwrap();
+        assert!(control.is_resumable(), "no resumption ticket received");
+
+        let (tx, _rx) = outgoing.connect().await.unwrap().into_tx_rx();
+        let mut gen = Generator::new(1000, 8000);
+        for _ in 0..COUNT / 2 {
+            tx.send(gen.packet()).await.unwrap();
+            sleep(Duration::from_millis(1)).await;
+        }
+        tx.flush().await.unwrap();
+
+        println!("client: disconnecting all links");
+        for control in controls.into_iter().flatten() {
+            let _ = control.disconnect().await;
+        }
+        sleep(Duration::from_secs(2)).await;
+        assert!(!control.is_terminated(), "connection terminated within resume window");
+
+        println!("client: resuming connection");
+        for (rx, tx) in client_new_links {
+            control.add(tx, rx, "new".to_string(), &[]).await.unwrap();
+        }
+
+        for _ in COUNT / 2..COUNT {
+            tx.send(gen.packet()).await.unwrap();
+            sleep(Duration::from_millis(1)).await;
+        }
+        drop(tx);
+        drop(_rx);
+        task.await.unwrap().unwrap();
+    };
+
+    timeout(Duration::from_secs(60), async { join!(server_task, client_task) }).await.unwrap();
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn link_drain() {
+    const COUNT: usize = 1000;
+
+    let cfg = Cfg { link_test_data_limit: 0, ..Default::default() };
+    let (server_links, client_links, _controls) = channel_links(&[10, 10]);
+
+    let server_cfg = cfg.clone();
+    let server_task = async move {
+        let server = Server::new(server_cfg);
+        let mut listener = server.listen().unwrap();
+        for (n, (rx, tx)) in server_links.into_iter().enumerate() {
+            server.add_incoming(tx, rx, format!("{n}"), &[]).await.unwrap();
+        }
+
+        let (task, ch, _control) = listener.next().await.unwrap().accept();
+        let task = tokio::spawn(task.into_future());
+
+        let (tx, mut rx) = ch.into_tx_rx();
+        let mut verifier = Verifier::new();
+        for _ in 0..COUNT {
+            verifier.verify(rx.recv().await.unwrap().unwrap()).unwrap();
+        }
+        assert_eq!(rx.recv().await.unwrap(), None);
+
+        println!("server: received {} bytes", verifier.total());
+        drop(rx);
+        drop(tx);
+        task.await.unwrap().unwrap();
+    };
+
+    let client_task = async move {
+        let (task, outgoing, control) = connect(cfg);
+        let task = tokio::spawn(task.into_future());
+
+        let links = future::try_join_all(
+            client_links.into_iter().enumerate().map(|(n, (rx, tx))| control.add(tx, rx, format!("{n}"), &[])),
+        )
+        .await
+        .unwrap();
+
+        let (tx, _rx) = outgoing.connect().await.unwrap().into_tx_rx();
+        let mut gen = Generator::new(1000, 8000);
+        let mut drained = None;
+        for i in 0..COUNT {
+            if i == COUNT / 2 {
+                println!("client: draining link 0");
+                let link = links[0].clone();
+                drained = Some(tokio::spawn(async move { link.drain().await }));
+            }
+            tx.send(gen.packet()).await.unwrap();
+            sleep(Duration::from_millis(1)).await;
+        }
+
+        timeout(Duration::from_secs(10), drained.unwrap()).await.unwrap().unwrap();
+        assert!(matches!(links[0].disconnect_reason(), Some(DisconnectReason::LocallyRequested)));
+        assert!(!links[1].is_disconnected());
+
+        drop(tx);
+        drop(_rx);
+        task.await.unwrap().unwrap();
+        let sent: Vec<_> = links.iter().map(|link| link.stats().total_sent).collect();
+        println!("client: sent {} bytes over links: {sent:?}", gen.total());
+    };
+
+    timeout(Duration::from_secs(60), async { join!(server_task, client_task) }).await.unwrap();
+}
+
+/// Sends over the usable link with the lowest tag.
+struct PreferFirst;
+
+impl LinkScheduler<String> for PreferFirst {
+    fn select(&mut self, links: &[LinkState<String>], _size: usize) -> Option<usize> {
+        links
+            .iter()
+            .enumerate()
+            .filter(|(_, link)| link.is_usable())
+            .min_by_key(|(_, link)| link.tag)
+            .map(|(idx, _)| idx)
+    }
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn link_drain_with_scheduler() {
+    const COUNT: usize = 400;
+
+    // Keep the paused link confirmed while it is draining.
+    let cfg = Cfg {
+        link_test_data_limit: 0,
+        link_ack_timeout_min: Duration::from_secs(30),
+        link_ack_timeout_max: Duration::from_secs(30),
+        link_ping_timeout: Duration::from_secs(60),
+        ..Default::default()
+    };
+    let (server_links, client_links, controls) = channel_links(&[10, 10]);
+
+    let server_cfg = cfg.clone();
+    let server_task = async move {
+        let server = Server::new(server_cfg);
+        let mut listener = server.listen().unwrap();
+        for (n, (rx, tx)) in server_links.into_iter().enumerate() {
+            server.add_incoming(tx, rx, format!("{n}"), &[]).await.unwrap();
+        }
+
+        let (task, ch, _control) = listener.next().await.unwrap().accept();
+        let task = tokio::spawn(task.into_future());
+
+        let (tx, mut rx) = ch.into_tx_rx();
+        let mut verifier = Verifier::new();
+        for _ in 0..COUNT {
+            verifier.verify(rx.recv().await.unwrap().unwrap()).unwrap();
+        }
+        assert_eq!(rx.recv().await.unwrap(), None);
+
+        println!("server: received {} bytes", verifier.total());
+        drop(rx);
+        drop(tx);
+        task.await.unwrap().unwrap();
+    };
+
+    let client_task = async move {
+        let (mut task, outgoing, control) = connect(cfg);
+        task.set_link_scheduler(PreferFirst);
+        let task = tokio::spawn(task.into_future());
+
+        let links = future::try_join_all(
+            client_links.into_iter().enumerate().map(|(n, (rx, tx))| control.add(tx, rx, format!("{n}"), &[])),
+        )
+        .await
+        .unwrap();
+
+        let (tx, _rx) = outgoing.connect().await.unwrap().into_tx_rx();
+        let mut gen = Generator::new(1000, 8000);
+        for i in 0..COUNT {
+            if i == COUNT / 2 {
+                // Delay acknowledgements of link 0, so that draining takes a while.
+                println!("client: pausing link 0");
+                for control in controls[0].clone() {
+                    tokio::spawn(async move { control.pause_for(Duration::from_secs(15)).await });
+                }
+            }
+            if i == COUNT / 2 + 10 {
+                tx.flush().await.unwrap();
+                println!("client: draining link 0");
+                links[0].start_drain();
+            }
+
+            tx.send(gen.packet()).await.unwrap();
+
+            if i == COUNT / 2 + 30 {
+                // Data must be sent over the remaining link while link 0 is draining.
+                timeout(Duration::from_secs(5), tx.flush()).await.expect("sending stalled").unwrap();
+            }
+        }
+
+        timeout(Duration::from_secs(40), links[0].disconnected()).await.unwrap();
+        assert!(!links[1].is_disconnected());
+
+        drop(tx);
+        drop(_rx);
+        task.await.unwrap().unwrap();
+        let sent: Vec<_> = links.iter().map(|link| link.stats().total_sent).collect();
+        println!("client: sent {} bytes over links: {sent:?}", gen.total());
+    };
+
+    timeout(Duration::from_secs(90), async { join!(server_task, client_task) }).await.unwrap();
+}
@@ -0,0 +1,184 @@
+//! Stream multiplexer tests.
+
+use futures::{future, join};
+use std::{
+    future::IntoFuture,
+    io::ErrorKind,
+    num::{NonZeroU32, NonZeroU8},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    task::JoinHandle,
+    time::timeout,
+};
+
+use aggligator::{
+    alc::{Mux, MuxCfg, MuxError},
+    cfg::Cfg,
+    connect::{connect, Server},
+    testing,
+};
+
+type MuxTasks = (JoinHandle<Result<(), MuxError>>, JoinHandle<Result<(), MuxError>>);
+
+async fn mux_pair(mux_cfg: MuxCfg) -> (Mux, Mux, MuxTasks) {
+    let ch_cfg = testing::Cfg {
+        speed: 10_000_000,
+        latency: Some(Duration::from_millis(5)),
+        buffer_size: 1_000_000,
+        ..Default::default()
+    };
+    let (link_a_tx, link_a_rx, _link_a_control) = testing::channel(ch_cfg.clone());
+    let (link_b_tx, link_b_rx, _link_b_control) = testing::channel(ch_cfg);
+
+    let server = Server::new(Cfg::default());
+    let mut listener = server.listen().unwrap();
+    let (task, outgoing, control) = connect(Cfg::default());
+    tokio::spawn(task.into_future());
+
+    let (server_ch, client_ch) = join!(
+        async {
+            server.add_incoming(link_b_tx, link_a_rx, "incoming", &[]).await.unwrap();
+            let (task, ch, _control) = listener.next().await.unwrap().accept();
+            tokio::spawn(task.into_future());
+            ch
+        },
+        async {
+            control.add(link_a_tx, link_b_rx, "outgoing", &[]).await.unwrap();
+            outgoing.connect().await.unwrap()
+        }
+    );
+
+    let (server_task, server_mux) = server_ch.into_mux(mux_cfg.clone());
+    let (client_task, client_mux) = client_ch.into_mux(mux_cfg);
+
+    (server_mux, client_mux, (tokio::spawn(server_task.into_future()), tokio::spawn(client_task.into_future())))
+}
+
+fn test_data(stream: usize, len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + stream) as u8).collect()
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn streams_with_half_close() {
+    const STREAMS: usize = 8;
+    const LEN: usize = 300_000;
+
+    let (mut server_mux, client_mux, (server_task, client_task)) = mux_pair(MuxCfg::default()).await;
+
+    let server = async move {
+        let mut handlers = Vec::new();
+        for _ in 0..STREAMS {
+            let mut stream = server_mux.accept().await.unwrap();
+            assert!(!stream.id().is_local());
+            handlers.push(tokio::spawn(async move {
+                let mut received = Vec::new();
+                stream.read_to_end(&mut received).await.unwrap();
+                let n = received[0] as usize;
+                assert_eq!(received, test_data(n, LEN));
+
+                println!("server: stream {} received all data, replying", stream.id());
+                stream.write_all(&test_data(n + 1, LEN)).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }));
+        }
+        for handler in handlers {
+            handler.await.unwrap();
+        }
+    };
+
+    let client = async move {
+        let handlers: Vec<_> = (0..STREAMS)
+            .map(|n| {
+                let mut stream = client_mux.open().unwrap();
+                assert!(stream.id().is_local());
+                tokio::spawn(async move {
+                    let data = test_data(n * 2, LEN);
+                    assert_eq!(data[0] as usize, n * 2);
+                    stream.write_all(&data).await.unwrap();
+                    stream.shutdown().await.unwrap();
+                    assert!(stream.write_all(b"x").await.is_err());
+
+                    let mut received = Vec::new();
+                    stream.read_to_end(&mut received).await.unwrap();
+                    assert_eq!(received, test_data(n * 2 + 1, LEN));
+                    println!("client: stream {} done", stream.id());
+                })
+            })
+            .collect();
+        for handler in future::join_all(handlers).await {
+            handler.unwrap();
+        }
+    };
+
+    timeout(Duration::from_secs(60), async { join!(server, client) }).await.unwrap();
+
+    server_task.await.unwrap().unwrap();
+    client_task.await.unwrap().unwrap();
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn unread_stream_does_not_block_others() {
+    let mux_cfg = MuxCfg { stream_window: NonZeroU32::new(100_000).unwrap(), ..Default::default() };
+    let (mut server_mux, client_mux, _tasks) = mux_pair(mux_cfg).await;
+
+    let mut blocked = client_mux.open().unwrap();
+    let mut other = client_mux.open().unwrap();
+    other.set_priority(NonZeroU8::new(1).unwrap());
+    assert_eq!(other.priority().get(), 1);
+
+    let _blocked_remote = server_mux.accept().await.unwrap();
+    let mut other_remote = server_mux.accept().await.unwrap();
+
+    println!("filling flow control window of unread stream");
+    let res = timeout(Duration::from_secs(2), blocked.write_all(&[1; 1_000_000])).await;
+    assert!(res.is_err(), "write exceeding flow control window did not block");
+
+    println!("sending over other stream");
+    let data = test_data(1, 1_000_000);
+    let (_, received) = join!(
+        async {
+            other.write_all(&data).await.unwrap();
+            other.shutdown().await.unwrap();
+        },
+        async {
+            let mut received = Vec::new();
+            other_remote.read_to_end(&mut received).await.unwrap();
+            received
+        }
+    );
+    assert_eq!(received, data);
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn reset_and_stop() {
+    let (mut server_mux, client_mux, _tasks) = mux_pair(MuxCfg::default()).await;
+
+    println!("resetting stream");
+    let mut stream = client_mux.open().unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    stream.flush().await.unwrap();
+    stream.reset();
+
+    let mut remote = server_mux.accept().await.unwrap();
+    let mut buf = Vec::new();
+    let err = remote.read_to_end(&mut buf).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+
+    println!("dropping stream without reading");
+    let mut stream = client_mux.open().unwrap();
+    let remote = server_mux.accept().await.unwrap();
+    drop(remote);
+
+    let err = timeout(Duration::from_secs(10), async {
+        loop {
+            if let Err(err) = stream.write_all(&[0; 1000]).await {
+                break err;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+}
@@ -0,0 +1,169 @@
+//! Simulated network link tests.
+
+use bytes::Bytes;
+use futures::{join, SinkExt, StreamExt};
+use std::{future::IntoFuture, time::Duration};
+use tokio::time::{sleep, timeout, Instant};
+
+use aggligator::{
+    cfg::Cfg,
+    connect::{connect, Server},
+    control::DisconnectReason,
+    testing::{self, BandwidthTrace, Outage},
+};
+
+/// Sends packets over a channel and returns their delays in the order of arrival.
+async fn delays(cfg: testing::Cfg, count: usize) -> Vec<Duration> {
+    let (mut tx, mut rx, _control) = testing::channel(cfg);
+    let start = Instant::now();
+
+    let sender = async {
+        for i in 0..count {
+            tx.send(Bytes::from(vec![i as u8; 100])).await.unwrap();
+        }
+    };
+    let receiver = async {
+        let mut delays = Vec::new();
+        for i in 0..count {
+            let data = rx.next().await.unwrap().unwrap();
+            assert_eq!(data[0], i as u8, "packets were reordered");
+            delays.push(start.elapsed());
+        }
+        delays
+    };
+
+    join!(sender, receiver).1
+}
+
+/// Transfers data over a simulated channel and returns the required time.
+async fn transfer(mut tx: testing::Sender, mut rx: testing::Receiver, size: usize) -> Duration {
+    let start = Instant::now();
+    let sender = async move {
+        for _ in 0..size / 1000 {
+            tx.send(Bytes::from(vec![0; 1000])).await.unwrap();
+        }
+    };
+    let receiver = async move {
+        let mut total = 0;
+        while total < size {
+            total += rx.next().await.unwrap().unwrap().len();
+        }
+    };
+    join!(sender, receiver);
+    start.elapsed()
+}
+
+#[tokio::test(start_paused = true)]
+async fn jitter_is_reproducible() {
+    let cfg = |seed| testing::Cfg {
+        latency: Some(Duration::from_millis(10)),
+        jitter: Duration::from_millis(20),
+        seed,
+        ..Default::default()
+    };
+
+    let a = delays(cfg(1), 50).await;
+    let b = delays(cfg(1), 50).await;
+    let c = delays(cfg(2), 50).await;
+    println!("delays: {a:?}");
+
+    assert_eq!(a, b, "same seed gave different delays");
+    assert_ne!(a, c, "different seeds gave same delays");
+    assert!(a.iter().all(|d| *d >= Duration::from_millis(10) && *d <= Duration::from_millis(30)));
+    assert!(a.first() != a.last(), "no jitter");
+}
+
+#[tokio::test(start_paused = true)]
+async fn asymmetric_link() {
+    let up = testing::Cfg { speed: 100_000, ..Default::default() };
+    let down = testing::Cfg { speed: 10_000, ..Default::default() };
+    let (local, remote, _control) = testing::link(up, down);
+
+    let (up_time, down_time) =
+        join!(transfer(local.tx, remote.rx, 100_000), transfer(remote.tx, local.rx, 100_000));
+    println!("upstream: {up_time:?}, downstream: {down_time:?}");
+
+    assert!(up_time >= Duration::from_millis(900) && up_time <= Duration::from_millis(1100));
+    assert!(down_time >= Duration::from_millis(9900) && down_time <= Duration::from_millis(10100));
+}
+
+#[tokio::test(start_paused = true)]
+async fn bandwidth_trace() {
+    let trace = BandwidthTrace::new([(Duration::from_secs(1), 100_000), (Duration::from_secs(1), 10_000)]);
+    assert_eq!(trace.speed_at(Duration::from_millis(500)), 100_000);
+    assert_eq!(trace.speed_at(Duration::from_millis(1500)), 10_000);
+    assert_eq!(trace.speed_at(Duration::from_secs(5)), 10_000);
+    assert_eq!(trace.clone().repeating().speed_at(Duration::from_millis(2500)), 100_000);
+
+    let (tx, rx, _control) = testing::channel(testing::Cfg { trace: Some(trace), ..Default::default() });
+    let time = transfer(tx, rx, 110_000).await;
+    println!("transfer time: {time:?}");
+
+    assert!(time >= Duration::from_millis(1900) && time <= Duration::from_millis(2200));
+}
+
+#[test_log::test(tokio::test(start_paused = true))]
+async fn scheduled_outage_failover() {
+    let outage = testing::Cfg {
+        latency: Some(Duration::from_millis(10)),
+        outages: vec![Outage::Disconnect { at: Duration::from_secs(3) }],
+        ..Default::default()
+    };
+    let pause = testing::Cfg {
+        latency: Some(Duration::from_millis(30)),
+        outages: vec![Outage::Pause { at: Duration::from_secs(5), duration: Duration::from_secs(1) }],
+        ..Default::default()
+    };
+    let (a_local, a_remote, _a_control) = testing::link(outage.clone(), outage);
+    let (b_local, b_remote, _b_control) = testing::link(pause.clone(), pause);
+
+    let server = Server::new(Cfg::default());
+    let mut listener = server.listen().unwrap();
+    let (task, outgoing, control) = connect(Cfg::default());
+    tokio::spawn(task.into_future());
+
+    let (link_a, link_b, ch, server_ch) = timeout(Duration::from_secs(10), async {
+        let (link_a, link_b, ch, server_ch) = join!(
+            control.add(a_local.tx, a_local.rx, "a", &[]),
+            control.add(b_local.tx, b_local.rx, "b", &[]),
+            outgoing.connect(),
+            async {
+                server.add_incoming(a_remote.tx, a_remote.rx, "a", &[]).await.unwrap();
+                server.add_incoming(b_remote.tx, b_remote.rx, "b", &[]).await.unwrap();
+                let (task, ch, _control) = listener.next().await.unwrap().accept();
+                tokio::spawn(task.into_future());
+                ch
+            }
+        );
+        (link_a.unwrap(), link_b.unwrap(), ch.unwrap(), server_ch)
+    })
+    .await
+    .unwrap();
+
+    let (tx, _rx) = ch.into_tx_rx();
+    let (_server_tx, mut server_rx) = server_ch.into_tx_rx();
+    let recv_task = tokio::spawn(async move {
+        let mut count = 0usize;
+        while let Ok(Some(data)) = server_rx.recv().await {
+            assert!(data.iter().all(|b| *b == count as u8), "data corrupted");
+            count += 1;
+        }
+        count
+    });
+
+    let start = Instant::now();
+    let mut count = 0usize;
+    while start.elapsed() < Duration::from_secs(10) {
+        timeout(Duration::from_secs(10), tx.send(Bytes::from(vec![count as u8; 1000]))).await.unwrap().unwrap();
+        count += 1;
+        sleep(Duration::from_millis(10)).await;
+    }
+
+    assert!(matches!(link_a.disconnect_reason(), Some(DisconnectReason::IoError(_))));
+    assert!(link_b.disconnect_reason().is_none());
+    assert!(link_b.is_working());
+
+    drop(tx);
+    let received = timeout(Duration::from_secs(30), recv_task).await.unwrap().unwrap();
+    assert_eq!(received, count);
+}
@@ -1,29 +1,33 @@
 //! Single-link tests.
 
+use bytes::Bytes;
 use futures::join;
 use std::{
     future::IntoFuture,
     num::{NonZeroU32, NonZeroUsize},
+    sync::{Arc, Mutex},
     time::Duration,
 };
-use tokio::time::timeout;
+use tokio::time::{sleep, timeout, Instant};
 
 use crate::test_data::send_and_verify;
 use aggligator::{
-    alc::{RecvError, SendError},
-    cfg::Cfg,
-    connect::{connect, Server},
+    alc::{Channel, RecvError, SendError},
+    cc::{AckSample, Bbr, CongestionController},
+    cfg::{Cfg, CongestionControl, LinkPing},
+    connect::{connect, ConnectError, Refusal, Server},
+    control::{AddLinkError, Link},
+    testing, Task,
 };
 
-mod test_channel;
 mod test_data;
 
 async fn single_link_test(
-    channel_cfg: test_channel::Cfg, cfg: Cfg, max_size: usize, count: usize, expected_speed: usize,
+    channel_cfg: testing::Cfg, cfg: Cfg, max_size: usize, count: usize, expected_speed: usize,
     pause: Option<(usize, Duration)>, fail_link: Option<usize>,
 ) {
-    let (link_a_tx, link_a_rx, link_a_control) = test_channel::channel(channel_cfg.clone());
-    let (link_b_tx, link_b_rx, link_b_control) = test_channel::channel(channel_cfg);
+    let (link_a_tx, link_a_rx, link_a_control) = testing::channel(channel_cfg.clone());
+    let (link_b_tx, link_b_rx, link_b_control) = testing::channel(channel_cfg);
 
     let server_cfg = cfg.clone();
     let server_task = async move {
@@ -243,7 +247,7 @@ async fn single_link_test(
 
 #[test_log::test(tokio::test(flavor = "multi_thread"))]
 async fn termination() {
-    let ch_cfg = test_channel::Cfg { speed: 0, latency: None, ..Default::default() };
+    let ch_cfg = testing::Cfg { speed: 0, latency: None, ..Default::default() };
     let alc_cfg = Cfg { ..Default::default() };
 
     single_link_test(ch_cfg, alc_cfg, 16384, 10, 0, None, None).await;
@@ -251,7 +255,7 @@ async fn termination() {
 
 #[test_log::test(tokio::test(flavor = "multi_thread"))]
 async fn unlimited_multi_thread() {
-    let ch_cfg = test_channel::Cfg { speed: 0, latency: None, ..Default::default() };
+    let ch_cfg = testing::Cfg { speed: 0, latency: None, ..Default::default() };
     let alc_cfg = Cfg { ..Default::default() };
 
     single_link_test(ch_cfg, alc_cfg, 16384, 10000, 10_000_000, None, None).await;
@@ -259,7 +263,7 @@ async fn unlimited_multi_thread() {
 
 #[test_log::test(tokio::test(flavor = "current_thread"))]
 async fn unlimited_current_thread() {
-    let ch_cfg = test_channel::Cfg { speed: 0, latency: None, ..Default::default() };
+    let ch_cfg = testing::Cfg { speed: 0, latency: None, ..Default::default() };
     let alc_cfg = Cfg { ..Default::default() };
 
     single_link_test(ch_cfg, alc_cfg, 16384, 10000, 10_000_000, None, None).await;
@@ -267,11 +271,12 @@ async fn unlimited_current_thread() {
 
 #[test_log::test(tokio::test(flavor = "multi_thread"))]
 async fn very_high_latency() {
-    let ch_cfg = test_channel::Cfg {
+    let ch_cfg = testing::Cfg {
         speed: 10_000_000,
         latency: Some(Duration::from_millis(1000)),
         buffer_size: 10_000_000,
         buffer_items: 5000,
+        ..Default::default()
     };
     let alc_cfg = Cfg {
         send_buffer: NonZeroU32::new(20_000_000).unwrap(),
@@ -288,7 +293,7 @@ async fn very_high_latency() {
 
 #[test_log::test(tokio::test(flavor = "multi_thread"))]
 async fn one_mb_per_s() {
-    let ch_cfg = test_channel::Cfg {
+    let ch_cfg = testing::Cfg {
         speed: 1_000_000,
         latency: Some(Duration::from_millis(10)),
         buffer_size: 100_000,
@@ -305,11 +310,12 @@ async fn one_mb_per_s() {
 
 #[test_log::test(tokio::test(flavor = "multi_thread"))]
 async fn ten_mb_per_s() {
-    let ch_cfg = test_channel::Cfg {
+    let ch_cfg = testing::Cfg {
         speed: 10_000_000,
         latency: Some(Duration::from_millis(10)),
         buffer_size: 10_000_000,
         buffer_items: 5000,
+        ..Default::default()
     };
     let alc_cfg = Cfg {
         send_queue: NonZeroUsize::new(50).unwrap(),
@@ -322,7 +328,7 @@ async fn ten_mb_per_s() {
 
 #[test_log::test(tokio::test(flavor = "multi_thread"))]
 async fn paused_link() {
-    let ch_cfg = test_channel::Cfg {
+    let ch_cfg = testing::Cfg {
         speed: 1_000_000,
         latency: Some(Duration::from_millis(10)),
         buffer_size: 100_000,
@@ -335,7 +341,7 @@ async fn paused_link() {
 
 #[test_log::test(tokio::test(flavor = "multi_thread"))]
 async fn timed_out_link() {
-    let ch_cfg = test_channel::Cfg {
+    let ch_cfg = testing::Cfg {
         speed: 1_000_000,
         latency: Some(Duration::from_millis(10)),
         buffer_size: 100_000,
@@ -357,7 +363,7 @@ async fn timed_out_link() {
 
 #[test_log::test(tokio::test(flavor = "multi_thread"))]
 async fn failed_link() {
-    let ch_cfg = test_channel::Cfg {
+    let ch_cfg = testing::Cfg {
         speed: 1_000_000,
         latency: Some(Duration::from_millis(10)),
         buffer_size: 100_000,
@@ -376,3 +382,262 @@ async fn failed_link() {
 
     single_link_test(ch_cfg, alc_cfg, 16384, 1000, 0, None, Some(100)).await;
 }
+
+/// Refuses a connection and returns the results of adding the link and connecting.
+async fn refuse(refusal: Refusal) -> (Result<Link<()>, AddLinkError>, Result<Channel, ConnectError>) {
+    let (a_tx, a_rx, _a_control) = testing::channel(Default::default());
+    let (b_tx, b_rx, _b_control) = testing::channel(Default::default());
+
+    let server = Server::new(Cfg::default());
+    let mut listener = server.listen().unwrap();
+    let (task, outgoing, control) = connect(Cfg::default());
+    tokio::spawn(task.into_future());
+
+    let (add_res, connect_res, ()) = timeout(Duration::from_secs(10), async {
+        join!(control.add(a_tx, b_rx, (), &[]), outgoing.connect(), async {
+            server.add_incoming(b_tx, a_rx, (), &[]).await.unwrap();
+            listener.next().await.unwrap().refuse_with(refusal).await;
+        })
+    })
+    .await
+    .unwrap();
+
+    (add_res, connect_res)
+}
+
+#[test_log::test(tokio::test)]
+async fn refused_with_reason() {
+    let refusal = Refusal::new(503, "server full");
+    let (add_res, connect_res) = refuse(refusal.clone()).await;
+
+    assert!(matches!(&add_res, Err(AddLinkError::Refused(r)) if *r == refusal), "{add_res:?}");
+    assert!(matches!(&connect_res, Err(ConnectError::Refused(r)) if *r == refusal), "{connect_res:?}");
+}
+
+#[test_log::test(tokio::test)]
+async fn refused_with_long_reason() {
+    let refusal = Refusal { code: 1, message: "ä".repeat(200) };
+    let (_add_res, connect_res) = refuse(refusal.clone()).await;
+
+    let expected = Refusal::new(refusal.code, refusal.message);
+    assert!(matches!(&connect_res, Err(ConnectError::Refused(r)) if *r == expected), "{connect_res:?}");
+}
+
+#[test]
+fn refusal_message_truncated() {
+    let refusal = Refusal::new(1, "ä".repeat(200));
+    assert_eq!(refusal.message.len(), 254);
+}
+
+#[test_log::test(tokio::test)]
+async fn roundtrip_stats() {
+    let ch_cfg = testing::Cfg { latency: Some(Duration::from_millis(20)), ..Default::default() };
+    let (a_tx, a_rx, a_control) = testing::channel(ch_cfg.clone());
+    let (b_tx, b_rx, _b_control) = testing::channel(ch_cfg);
+
+    let cfg = Cfg { link_ping: LinkPing::Periodic(Duration::from_millis(50)), ..Default::default() };
+    let server = Server::new(cfg.clone());
+    let mut listener = server.listen().unwrap();
+    let (task, outgoing, control) = connect(cfg);
+    tokio::spawn(task.into_future());
+
+    let (link, _ch, _server_ch) = timeout(Duration::from_secs(10), async {
+        let (link, ch, server_ch) = join!(control.add(a_tx, b_rx, (), &[]), outgoing.connect(), async {
+            server.add_incoming(b_tx, a_rx, (), &[]).await.unwrap();
+            let (task, ch, _control) = listener.next().await.unwrap().accept();
+            tokio::spawn(task.into_future());
+            ch
+        });
+        (link.unwrap(), ch.unwrap(), server_ch)
+    })
+    .await
+    .unwrap();
+
+    // Alternate the latency of one direction to produce jitter.
+    for i in 0..20 {
+        let latency = if i % 2 == 0 { 5 } else { 60 };
+        a_control.set_latency(Some(Duration::from_millis(latency))).await.unwrap();
+        sleep(Duration::from_millis(100)).await;
+    }
+
+    let stats = link.stats();
+    println!(
+        "roundtrip {:?}, min {:?}, var {:?}, jitter {:?}",
+        stats.roundtrip, stats.min_roundtrip, stats.roundtrip_var, stats.jitter
+    );
+    assert!(stats.min_roundtrip >= Duration::from_millis(25));
+    assert!(stats.min_roundtrip <= stats.roundtrip);
+    assert!(stats.roundtrip <= Duration::from_millis(200));
+    assert!(stats.roundtrip_var > Duration::ZERO);
+    assert!(stats.jitter >= Duration::from_millis(5));
+}
+
+/// Establishes a connection over a single link and sends data over it for the specified duration.
+///
+/// Returns the client-side link and the number of bytes received by the server.
+async fn send_for(
+    ch_cfg: testing::Cfg, cfg: Cfg, duration: Duration,
+    setup: impl FnOnce(&mut Task<testing::Sender, testing::Receiver, ()>),
+) -> (Link<()>, usize) {
+    send_phases(ch_cfg, cfg, &[(duration, Duration::ZERO)], setup).await
+}
+
+/// Sends data over a single link in phases, each specified by its duration and the pause
+/// after each sent packet.
+async fn send_phases(
+    ch_cfg: testing::Cfg, cfg: Cfg, phases: &[(Duration, Duration)],
+    setup: impl FnOnce(&mut Task<testing::Sender, testing::Receiver, ()>),
+) -> (Link<()>, usize) {
+    let (a_tx, a_rx, _a_control) = testing::channel(ch_cfg.clone());
+    let (b_tx, b_rx, _b_control) = testing::channel(ch_cfg);
+
+    let server = Server::new(cfg.clone());
+    let mut listener = server.listen().unwrap();
+    let (mut task, outgoing, control) = connect(cfg);
+    setup(&mut task);
+    tokio::spawn(task.into_future());
+
+    let (link, ch, server_ch) = timeout(Duration::from_secs(10), async {
+        let (link, ch, server_ch) = join!(control.add(a_tx, b_rx, (), &[]), outgoing.connect(), async {
+            server.add_incoming(b_tx, a_rx, (), &[]).await.unwrap();
+            let (task, ch, _control) = listener.next().await.unwrap().accept();
+            tokio::spawn(task.into_future());
+            ch
+        });
+        (link.unwrap(), ch.unwrap(), server_ch)
+    })
+    .await
+    .unwrap();
+
+    let (tx, _rx) = ch.into_tx_rx();
+    let (_server_tx, mut server_rx) = server_ch.into_tx_rx();
+    let recv_task = tokio::spawn(async move {
+        let mut total = 0;
+        while let Ok(Some(data)) = server_rx.recv().await {
+            total += data.len();
+        }
+        total
+    });
+
+    let data = Bytes::from(vec![0; 8192]);
+    for &(duration, pause) in phases {
+        let end = Instant::now() + duration;
+        while Instant::now() < end {
+            timeout(Duration::from_secs(10), tx.send(data.clone())).await.unwrap().unwrap();
+            if !pause.is_zero() {
+                sleep(pause).await;
+            }
+        }
+    }
+
+    let stats = link.stats();
+    println!(
+        "roundtrip {:?}, min {:?}, unacked {} of {}",
+        stats.roundtrip, stats.min_roundtrip, stats.sent_unacked, stats.unacked_limit
+    );
+
+    drop(tx);
+    let total = timeout(Duration::from_secs(30), recv_task).await.unwrap().unwrap();
+    println!("received {total} bytes");
+
+    (link, total)
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn bbr_deep_buffer() {
+    let ch_cfg = testing::Cfg {
+        speed: 1_000_000,
+        latency: Some(Duration::from_millis(20)),
+        buffer_size: 10_000_000,
+        buffer_items: 5000,
+        ..Default::default()
+    };
+    let cfg = Cfg {
+        congestion_control: CongestionControl::Bbr,
+        link_ping: LinkPing::Periodic(Duration::from_millis(100)),
+        ..Default::default()
+    };
+
+    let (link, total) = send_for(ch_cfg, cfg, Duration::from_secs(5), |_| ()).await;
+
+    // The queue in the deep link buffer must be kept short.
+    let stats = link.stats();
+    assert!(stats.roundtrip <= Duration::from_millis(500), "roundtrip too high: {:?}", stats.roundtrip);
+    assert!(stats.unacked_limit <= 1_000_000, "unacked limit too high: {}", stats.unacked_limit);
+    assert!(total >= 2_000_000, "too little data transferred: {total}");
+}
+
+/// BBR congestion controller that can be inspected while in use.
+struct SharedBbr(Arc<Mutex<Bbr>>);
+
+impl CongestionController for SharedBbr {
+    fn on_sent(&mut self, now: Instant, size: usize, unacked: usize) {
+        self.0.lock().unwrap().on_sent(now, size, unacked)
+    }
+
+    fn on_ack(&mut self, sample: &AckSample) {
+        self.0.lock().unwrap().on_ack(sample)
+    }
+
+    fn on_app_limited(&mut self, now: Instant) {
+        self.0.lock().unwrap().on_app_limited(now)
+    }
+
+    fn on_timeout(&mut self, now: Instant) {
+        self.0.lock().unwrap().on_timeout(now)
+    }
+
+    fn window(&self) -> usize {
+        self.0.lock().unwrap().window()
+    }
+
+    fn pacing_rate(&self) -> Option<u64> {
+        self.0.lock().unwrap().pacing_rate()
+    }
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn bbr_bursty_traffic() {
+    let ch_cfg =
+        testing::Cfg { speed: 1_000_000, latency: Some(Duration::from_millis(20)), ..Default::default() };
+    let cfg = Cfg { send_queue: NonZeroUsize::new(4).unwrap(), ..Default::default() };
+    let bbr = Arc::new(Mutex::new(Bbr::new(cfg.link_unacked_init.get(), 4 * cfg.io_write_size.get())));
+
+    // A burst saturating the link is followed by sparse packets.
+    let phases = [(Duration::from_secs(2), Duration::ZERO), (Duration::from_secs(3), Duration::from_millis(100))];
+    let (_link, total) = send_phases(ch_cfg, cfg, &phases, |task| {
+        let bbr = bbr.clone();
+        task.set_congestion_controller(move |_| Some(Box::new(SharedBbr(bbr.clone()))))
+    })
+    .await;
+    assert!(total > 0);
+
+    // Sparse packets must not lower the bandwidth estimate.
+    let bandwidth = bbr.lock().unwrap().bandwidth();
+    println!("estimated bandwidth: {bandwidth}");
+    assert!(bandwidth >= 500_000.0, "bandwidth estimate too low: {bandwidth}");
+}
+
+/// Congestion controller with a fixed window.
+struct FixedWindow(usize);
+
+impl CongestionController for FixedWindow {
+    fn on_ack(&mut self, _sample: &AckSample) {}
+
+    fn window(&self) -> usize {
+        self.0
+    }
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn custom_congestion_controller() {
+    let ch_cfg = testing::Cfg { latency: Some(Duration::from_millis(10)), ..Default::default() };
+
+    let (link, total) = send_for(ch_cfg, Cfg::default(), Duration::from_secs(1), |task| {
+        task.set_congestion_controller(|_| Some(Box::new(FixedWindow(16384))))
+    })
+    .await;
+
+    assert_eq!(link.stats().unacked_limit, 16384);
+    assert!(total > 0);
+}